@@ -0,0 +1,67 @@
+//! Uploads a DuckyScript and runs it with the Bad USB app
+//!
+//! `cargo run --example serial-badusb --features flipper,fs-write,fs-createdir,transport-serial-optimized -- script.txt`
+//!
+//! The Flipper types the script on whatever computer it is plugged into, which is usually the one
+//! running this example.
+
+use flipper_rpc::{
+    FlipperZero,
+    error::{Error, Result},
+    proto::{self, app::StartRequest, gui::InputKey},
+    rpc::req::Request,
+    transport::{
+        CommandIndex, Transport, TransportRaw,
+        serial::{list_flipper_ports, rpc::SerialRpcTransport},
+    },
+};
+
+/// Directory the Bad USB app loads scripts from
+pub const SCRIPT_DIR: &str = "/ext/badusb";
+
+/// Name the Bad USB app is started by
+pub const APP_NAME: &str = "Bad USB";
+
+/// Uploads `script` as `name`, opens it in the Bad USB app and presses OK to run it. Returns the
+/// path of the uploaded script.
+pub fn run_script<T>(flipper: &mut FlipperZero<T>, name: &str, script: &str) -> Result<String>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let path = format!("{SCRIPT_DIR}/{name}");
+
+    flipper.fs().create_dir(SCRIPT_DIR)?;
+    flipper.fs().write(&path, script)?;
+
+    flipper
+        .get_mut()
+        .send_and_receive(Request::AppStart(StartRequest {
+            name: APP_NAME.to_string(),
+            args: path.clone(),
+        }))?;
+
+    flipper.gui().press(InputKey::Ok)?;
+
+    Ok(path)
+}
+
+fn main() -> Result<()> {
+    let Some(file) = std::env::args().nth(1) else {
+        eprintln!("usage: serial-badusb <script.txt>");
+        std::process::exit(2);
+    };
+
+    let script = std::fs::read_to_string(&file)?;
+    let name = std::path::Path::new(&file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("script.txt");
+
+    let ports = list_flipper_ports()?;
+
+    let mut flipper = FlipperZero::new(SerialRpcTransport::new(&ports[0].port_name)?);
+
+    println!("running {}", run_script(&mut flipper, name, &script)?);
+
+    Ok(())
+}
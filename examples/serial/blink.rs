@@ -0,0 +1,52 @@
+//! Blinks an LED wired to a GPIO pin
+//!
+//! `cargo run --example serial-blink --features flipper,transport-serial-optimized`
+//!
+//! Connect an LED and a resistor between pin 7 (PC3) and GND.
+
+use std::time::Duration;
+
+use flipper_rpc::{
+    FlipperZero,
+    error::{Error, Result},
+    proto::{
+        self,
+        gpio::{GpioPin, GpioPinMode},
+    },
+    transport::{
+        CommandIndex, TransportRaw,
+        serial::{list_flipper_ports, rpc::SerialRpcTransport},
+    },
+};
+
+/// Toggles `pin` on and off `times` times, one `period` per blink, then releases it as an input
+pub fn blink<T>(
+    flipper: &mut FlipperZero<T>,
+    pin: GpioPin,
+    times: usize,
+    period: Duration,
+) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let mut gpio = flipper.gpio();
+
+    gpio.set_pin_mode(pin, GpioPinMode::Output)?;
+
+    for _ in 0..times {
+        gpio.write(pin, true)?;
+        std::thread::sleep(period / 2);
+        gpio.write(pin, false)?;
+        std::thread::sleep(period / 2);
+    }
+
+    gpio.set_pin_mode(pin, GpioPinMode::Input)
+}
+
+fn main() -> Result<()> {
+    let ports = list_flipper_ports()?;
+
+    let mut flipper = FlipperZero::new(SerialRpcTransport::new(&ports[0].port_name)?);
+
+    blink(&mut flipper, GpioPin::Pc3, 10, Duration::from_millis(500))
+}
@@ -0,0 +1,72 @@
+//! Mirrors the Flipper's screen to the terminal
+//!
+//! `cargo run --example serial-mirror --features flipper,transport-serial-optimized`
+
+use std::io::Write;
+
+use flipper_rpc::{
+    FlipperZero,
+    error::{Error, Result},
+    proto,
+    transport::{
+        CommandIndex, TransportRaw,
+        serial::{list_flipper_ports, rpc::SerialRpcTransport},
+    },
+};
+
+/// Screen width in pixels
+pub const WIDTH: usize = 128;
+/// Screen height in pixels
+pub const HEIGHT: usize = 64;
+
+/// Streams `frames` screen frames to `out`, redrawing in place
+pub fn mirror<T>(flipper: &mut FlipperZero<T>, frames: usize, out: &mut impl Write) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    flipper.gui().start_screen_stream()?;
+
+    for _ in 0..frames {
+        let frame = flipper.gui().next_frame()?;
+
+        // Cursor home, then the frame
+        write!(out, "\x1b[H{}", render(&frame.data))?;
+        out.flush()?;
+    }
+
+    flipper.gui().stop_screen_stream()
+}
+
+/// Renders a frame as text, two pixel rows per line. Each byte of `data` holds a column of 8
+/// pixels, least significant bit on top.
+pub fn render(data: &[u8]) -> String {
+    let pixel = |x: usize, y: usize| {
+        data.get(x + (y / 8) * WIDTH)
+            .is_some_and(|byte| byte >> (y % 8) & 1 == 1)
+    };
+
+    let mut text = String::with_capacity((WIDTH + 1) * HEIGHT / 2 * 3);
+
+    for y in (0..HEIGHT).step_by(2) {
+        for x in 0..WIDTH {
+            text.push(match (pixel(x, y), pixel(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+fn main() -> Result<()> {
+    let ports = list_flipper_ports()?;
+
+    let mut flipper = FlipperZero::new(SerialRpcTransport::new(&ports[0].port_name)?);
+
+    print!("\x1b[2J");
+    mirror(&mut flipper, usize::MAX, &mut std::io::stdout())
+}
@@ -0,0 +1,118 @@
+//! Uploads the files of a local directory that are missing or changed on the Flipper
+//!
+//! `cargo run --example serial-sync --features flipper,fs-readdir,fs-write,fs-createdir,transport-serial-optimized -- ./nfc /ext/nfc`
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc;
+
+use flipper_rpc::{
+    FlipperZero,
+    error::{Error, Result},
+    proto,
+    rpc::res::ReadDirItem,
+    transport::{
+        CommandIndex, TransportRaw,
+        serial::{list_flipper_ports, rpc::SerialRpcTransport},
+    },
+};
+
+/// Files read and hashed ahead of the one being uploaded. Bounds the memory the reader holds.
+const READ_AHEAD: usize = 2;
+
+/// A local file, read and hashed
+struct LocalFile {
+    name: String,
+    data: Vec<u8>,
+    md5: String,
+}
+
+/// Uploads every file directly in `local` whose MD5 differs from its copy in `remote`, creating
+/// `remote` if needed. Returns the names of the uploaded files, sorted.
+///
+/// A worker thread reads and hashes the next files while the current one is uploaded, so on a
+/// slow link the host-side work is hidden behind the transfer.
+pub fn sync<T>(flipper: &mut FlipperZero<T>, local: &Path, remote: &str) -> Result<Vec<String>>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    flipper.fs().create_dir(remote)?;
+
+    let remote_md5 = flipper
+        .fs()
+        .read_dir(remote, true)?
+        .filter_map(|item| match item {
+            ReadDirItem::File(name, _, md5) => Some((name, md5?)),
+            ReadDirItem::Dir(_) => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut uploaded = Vec::new();
+
+    std::thread::scope(|scope| {
+        let (files, next_file) = mpsc::sync_channel(READ_AHEAD);
+        let reader = scope.spawn(move || read_files(local, files));
+
+        // Returning early drops the receiver, which stops the reader at its next file
+        for file in next_file {
+            if remote_md5.get(&file.name) == Some(&file.md5) {
+                continue;
+            }
+
+            flipper
+                .fs()
+                .write(format!("{remote}/{}", file.name), &file.data)?;
+            uploaded.push(file.name);
+        }
+
+        reader.join().expect("the reader does not panic")?;
+
+        Ok::<_, Error>(())
+    })?;
+
+    uploaded.sort();
+
+    Ok(uploaded)
+}
+
+/// Reads and hashes the files directly in `local` into `files`, until the receiver hangs up
+fn read_files(local: &Path, files: mpsc::SyncSender<LocalFile>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(local)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let Ok(name) = entry.file_name().into_string() else {
+            eprintln!("skipping {:?}: not UTF-8", entry.file_name());
+            continue;
+        };
+
+        let data = std::fs::read(entry.path())?;
+        let md5 = format!("{:x}", md5::compute(&data));
+
+        if files.send(LocalFile { name, data, md5 }).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(local), Some(remote)) = (args.next(), args.next()) else {
+        eprintln!("usage: serial-sync <local dir> <remote dir>");
+        std::process::exit(2);
+    };
+
+    let ports = list_flipper_ports()?;
+
+    let mut flipper = FlipperZero::new(SerialRpcTransport::new(&ports[0].port_name)?);
+
+    for name in sync(&mut flipper, Path::new(&local), &remote)? {
+        println!("uploaded {name}");
+    }
+
+    Ok(())
+}
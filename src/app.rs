@@ -0,0 +1,215 @@
+//! Typed messaging with running apps over `AppDataExchange`
+//!
+//! `Request::AppDataExchange` only moves raw bytes. Most apps put a small framing protocol on
+//! top, so [`DataChannel`] pairs a transport with an [`AppCodec`] that turns those bytes into
+//! typed messages. Outgoing messages are split into chunks that fit in a single RPC message and
+//! incoming bytes are buffered until the codec can decode a full message.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::app::{DataChannel, Tlv, TlvCodec};
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! let mut channel = DataChannel::new(&mut rpc, TlvCodec);
+//!
+//! channel.send(&Tlv { tag: 1, value: b"hello".to_vec() })?;
+//! let reply = channel.receive()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::logging::trace;
+
+use crate::transport::CommandIndex;
+use crate::transport::Transport;
+use crate::{
+    error::{Error, Result},
+    proto::{self, app::DataExchangeRequest},
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Largest payload sent in a single `AppDataExchange` message by default
+pub const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+/// Encodes and decodes typed messages carried over `AppDataExchange`
+pub trait AppCodec {
+    /// Message type produced and consumed by this codec
+    type Message;
+
+    /// Appends the encoded form of `message` to `buf`.
+    fn encode(&mut self, message: &Self::Message, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Tries to decode a single message from the start of `buf`.
+    ///
+    /// Returns the message and the number of bytes it used, or `None` if `buf` does not contain
+    /// a full message yet.
+    fn decode(&mut self, buf: &[u8]) -> Result<Option<(Self::Message, usize)>>;
+}
+
+/// A tag-length-value record, as used by [`TlvCodec`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    /// Record type
+    pub tag: u8,
+    /// Record payload, at most `u16::MAX` bytes
+    pub value: Vec<u8>,
+}
+
+/// A minimal TLV codec: one tag byte, a little endian `u16` length, then the value
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlvCodec;
+
+impl AppCodec for TlvCodec {
+    type Message = Tlv;
+
+    fn encode(&mut self, message: &Tlv, buf: &mut Vec<u8>) -> Result<()> {
+        let len = u16::try_from(message.value.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "tlv value too long")
+        })?;
+
+        buf.push(message.tag);
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&message.value);
+
+        Ok(())
+    }
+
+    fn decode(&mut self, buf: &[u8]) -> Result<Option<(Tlv, usize)>> {
+        let [tag, lo, hi, ..] = *buf else {
+            return Ok(None);
+        };
+
+        let end = 3 + u16::from_le_bytes([lo, hi]) as usize;
+
+        if buf.len() < end {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            Tlv {
+                tag,
+                value: buf[3..end].to_vec(),
+            },
+            end,
+        )))
+    }
+}
+
+/// A typed, chunked `AppDataExchange` channel to the currently running app
+#[derive(Debug)]
+pub struct DataChannel<'a, T, C> {
+    transport: &'a mut T,
+    codec: C,
+    chunk_size: usize,
+    rx: Vec<u8>,
+}
+
+impl<'a, T, C> DataChannel<'a, T, C>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+    C: AppCodec,
+{
+    /// Creates a channel over `transport` using [`DEFAULT_CHUNK_SIZE`]
+    pub fn new(transport: &'a mut T, codec: C) -> Self {
+        Self {
+            transport,
+            codec,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rx: Vec::new(),
+        }
+    }
+
+    /// Sets the largest payload sent in a single RPC message. Zero is treated as one.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+
+        self
+    }
+
+    /// Encodes and sends a message, splitting it over as many RPC messages as needed.
+    pub fn send(&mut self, message: &C::Message) -> Result<()> {
+        let mut buf = Vec::new();
+        self.codec.encode(message, &mut buf)?;
+
+        trace!(len = buf.len(), "data exchange send");
+
+        for chunk in buf.chunks(self.chunk_size) {
+            self.transport
+                .send_and_receive(Request::AppDataExchange(DataExchangeRequest {
+                    data: chunk.to_vec(),
+                }))?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the app to send a full message and decodes it.
+    ///
+    /// Bytes that belong to the next message are kept for the following call.
+    pub fn receive(&mut self) -> Result<C::Message> {
+        loop {
+            if let Some((message, used)) = self.codec.decode(&self.rx)? {
+                self.rx.drain(..used);
+
+                return Ok(message);
+            }
+
+            let response: DataExchangeRequest = self.transport.receive()?.try_into()?;
+            trace!(len = response.data.len(), "data exchange receive");
+
+            self.rx.extend_from_slice(&response.data);
+        }
+    }
+
+    /// Returns the codec
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Returns the underlying transport
+    pub fn into_inner(self) -> &'a mut T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tlv_round_trip() {
+        let message = Tlv {
+            tag: 7,
+            value: b"flipper".to_vec(),
+        };
+
+        let mut buf = Vec::new();
+        TlvCodec.encode(&message, &mut buf).unwrap();
+
+        assert_eq!(buf[..3], [7, 7, 0]);
+        assert_eq!(TlvCodec.decode(&buf).unwrap(), Some((message, buf.len())));
+    }
+
+    #[test]
+    fn tlv_waits_for_full_message() {
+        let mut buf = Vec::new();
+        TlvCodec
+            .encode(
+                &Tlv {
+                    tag: 1,
+                    value: vec![0; 10],
+                },
+                &mut buf,
+            )
+            .unwrap();
+
+        assert_eq!(TlvCodec.decode(&buf[..2]).unwrap(), None);
+        assert_eq!(TlvCodec.decode(&buf[..12]).unwrap(), None);
+        assert!(TlvCodec.decode(&buf).unwrap().is_some());
+    }
+}
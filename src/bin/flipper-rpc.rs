@@ -0,0 +1,402 @@
+//! `flipper-rpc`, a command line tool over the library
+//!
+//! ```text
+//! flipper-rpc [--port PORT] [--json] <command> [arguments]
+//! ```
+//!
+//! With `--json` every command prints one JSON document to stdout instead of text, and errors,
+//! including bad command lines, are printed as `{"error": "..."}`.
+
+use std::process::ExitCode;
+
+use serde::Serialize;
+
+use flipper_rpc::diagnostics::bench::{BenchConfig, BenchReport, bench_transfer};
+use flipper_rpc::diagnostics::probe;
+use flipper_rpc::fs::{FsRead, FsReadDir, FsWrite, ReadOptions, TransferSummary, WriteOptions};
+use flipper_rpc::rpc::res::ReadDirItem;
+use flipper_rpc::transport::serial::{list_flipper_ports, rpc::SerialRpcTransport};
+
+const USAGE: &str = "\
+usage: flipper-rpc [--port PORT] [--json] <command> [arguments]
+
+commands:
+  probe                   check that the port answers the CLI and RPC
+  ls PATH [--md5]         list a directory on the device
+  read REMOTE LOCAL       download a file
+  write LOCAL REMOTE      upload a file
+  bench [options]         upload, hash, download and remove files, timing every phase
+
+options:
+  --port PORT             serial port, the first Flipper found by default
+  --json                  print results as JSON
+
+bench options:
+  --sizes LIST            file sizes, with optional k or m suffixes (default 4k,64k,256k)
+  --chunk-sizes LIST      upload chunk sizes to sweep, in bytes (default 1024)
+  --repeat N              runs per size and chunk size (default 1)
+  --dir DIR               directory on the device to write to (default /ext/.bench)
+";
+
+/// The parsed command line
+#[derive(Debug, Default)]
+struct Options {
+    /// `-h` or `--help` was given, everything else is ignored
+    help: bool,
+    port: Option<String>,
+    json: bool,
+    md5: bool,
+    bench: BenchConfig,
+    /// The command and its positional arguments
+    args: Vec<String>,
+}
+
+/// A listed entry, flattened for scripts
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    md5: Option<String>,
+}
+
+/// A finished transfer and the paths it was between
+#[derive(Debug, Serialize)]
+struct Transfer<'a> {
+    from: &'a str,
+    to: &'a str,
+    #[serde(flatten)]
+    summary: TransferSummary,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_options(args.iter().cloned()) {
+        Ok(options) if options.help => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(options) => options,
+        // Parsing stopped at the error, so look for `--json` in the whole command line
+        Err(error) if args.iter().any(|arg| arg == "--json") => {
+            println!("{}", error_json(&error));
+            return ExitCode::FAILURE;
+        }
+        Err(error) => {
+            eprintln!("error: {error}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => ExitCode::SUCCESS,
+        // The output already says what failed
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) if options.json => {
+            println!("{}", error_json(&error));
+            ExitCode::FAILURE
+        }
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The error document printed with `--json`
+fn error_json(error: &str) -> serde_json::Value {
+    serde_json::json!({ "error": error })
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("`{arg}` needs a value"));
+
+        match arg.as_str() {
+            "-h" | "--help" => {
+                options.help = true;
+                return Ok(options);
+            }
+            "--port" => options.port = Some(value()?),
+            "--json" => options.json = true,
+            "--md5" => options.md5 = true,
+            "--sizes" => options.bench.sizes = parse_list(&value()?)?,
+            "--chunk-sizes" => options.bench.chunk_sizes = parse_list(&value()?)?,
+            "--repeat" => {
+                options.bench.repeat = value()?
+                    .parse()
+                    .map_err(|_| "`--repeat` needs a number".to_string())?;
+            }
+            "--dir" => options.bench.dir = value()?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ => options.args.push(arg),
+        }
+    }
+
+    if options.args.is_empty() {
+        return Err("no command given".to_string());
+    }
+
+    Ok(options)
+}
+
+/// `4k,1m,100` into bytes
+fn parse_list(list: &str) -> Result<Vec<usize>, String> {
+    list.split(',')
+        .map(|size| {
+            let size = size.trim().to_ascii_lowercase();
+            let (digits, unit) = match size.strip_suffix('k') {
+                Some(digits) => (digits, 1024),
+                None => match size.strip_suffix('m') {
+                    Some(digits) => (digits, 1024 * 1024),
+                    None => (size.as_str(), 1),
+                },
+            };
+
+            digits
+                .parse::<usize>()
+                .map(|n| n * unit)
+                .map_err(|_| format!("`{size}` is not a size"))
+        })
+        .collect()
+}
+
+/// Runs the command, returning whether it succeeded
+fn run(options: &Options) -> Result<bool, String> {
+    let args: Vec<&str> = options.args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["probe"] => run_probe(options),
+        ["ls", path] => ls(options, path),
+        ["read", remote, local] => read(options, remote, local),
+        ["write", local, remote] => write(options, local, remote),
+        ["bench"] => bench(options),
+        [command, ..] => Err(format!(
+            "unknown command `{command}` or wrong arguments, see --help"
+        )),
+        [] => unreachable!("checked by parse_options"),
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<bool, String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    println!("{json}");
+
+    Ok(true)
+}
+
+fn port_name(port: Option<&str>) -> Result<String, String> {
+    match port {
+        Some(port) => Ok(port.to_string()),
+        None => Ok(list_flipper_ports()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .ok_or("no Flipper found, pass --port")?
+            .port_name),
+    }
+}
+
+fn open(port: Option<&str>) -> Result<SerialRpcTransport, String> {
+    let port = port_name(port)?;
+
+    SerialRpcTransport::new(&port).map_err(|e| format!("{port}: {e}"))
+}
+
+fn run_probe(options: &Options) -> Result<bool, String> {
+    let report = probe(&port_name(options.port.as_deref())?);
+
+    if options.json {
+        print_json(&report)?;
+    } else {
+        println!("{report}");
+    }
+
+    Ok(report.ok())
+}
+
+fn ls(options: &Options, path: &str) -> Result<bool, String> {
+    let mut rpc = open(options.port.as_deref())?;
+    let entries: Vec<Entry> = rpc
+        .fs_read_dir(path, options.md5)
+        .map_err(|e| e.to_string())?
+        .map(|item| match item {
+            ReadDirItem::Dir(name) => Entry {
+                name,
+                kind: "dir",
+                size: 0,
+                md5: None,
+            },
+            ReadDirItem::File(name, size, md5) => Entry {
+                name,
+                kind: "file",
+                size: u64::from(size),
+                md5,
+            },
+        })
+        .collect();
+
+    if options.json {
+        return print_json(&entries);
+    }
+
+    for entry in entries {
+        match entry.kind {
+            "dir" => println!("{:>10}  {}/", "", entry.name),
+            _ => println!(
+                "{:>10}  {}{}",
+                entry.size,
+                entry.name,
+                entry.md5.map(|md5| format!("  {md5}")).unwrap_or_default()
+            ),
+        }
+    }
+
+    Ok(true)
+}
+
+fn read(options: &Options, remote: &str, local: &str) -> Result<bool, String> {
+    let mut rpc = open(options.port.as_deref())?;
+    let (data, summary) = rpc
+        .fs_read_with(remote, ReadOptions::default())
+        .map_err(|e| e.to_string())?;
+    std::fs::write(local, &data).map_err(|e| format!("{local}: {e}"))?;
+
+    print_transfer(options, remote, local, summary)
+}
+
+fn write(options: &Options, local: &str, remote: &str) -> Result<bool, String> {
+    let data = std::fs::read(local).map_err(|e| format!("{local}: {e}"))?;
+    let mut rpc = open(options.port.as_deref())?;
+    let summary = rpc
+        .fs_write_with(remote, &data, WriteOptions::default())
+        .map_err(|e| e.to_string())?;
+
+    print_transfer(options, local, remote, summary)
+}
+
+fn print_transfer(
+    options: &Options,
+    from: &str,
+    to: &str,
+    summary: TransferSummary,
+) -> Result<bool, String> {
+    if options.json {
+        return print_json(&Transfer { from, to, summary });
+    }
+
+    println!(
+        "{from} -> {to}: {} bytes in {:.1?}, {:.1} KiB/s",
+        summary.bytes,
+        summary.duration,
+        summary.avg_rate / 1024.0
+    );
+    if let Some(digests) = summary.digests {
+        println!("md5 {}  sha256 {}", digests.md5_hex(), digests.sha256_hex());
+    }
+
+    Ok(true)
+}
+
+fn bench(options: &Options) -> Result<bool, String> {
+    let mut rpc = open(options.port.as_deref())?;
+    let report = bench_transfer(&mut rpc, &options.bench).map_err(|e| e.to_string())?;
+
+    if options.json {
+        print_json(&report)?;
+    } else {
+        print_bench(&report);
+    }
+
+    Ok(report.verified())
+}
+
+fn print_bench(report: &BenchReport) {
+    println!(
+        "{:>10} {:>6} {:>10} {:>10} {:>10} {:>10} {:>12} {:>12}  ok",
+        "size", "chunk", "upload", "md5", "download", "remove", "up KiB/s", "down KiB/s"
+    );
+
+    for run in &report.runs {
+        println!(
+            "{:>10} {:>6} {:>10.1?} {:>10.1?} {:>10.1?} {:>10.1?} {:>12.1} {:>12.1}  {}",
+            run.size,
+            run.chunk_size,
+            run.upload,
+            run.md5,
+            run.download,
+            run.remove,
+            run.upload_rate() / 1024.0,
+            run.download_rate() / 1024.0,
+            if run.verified { "yes" } else { "NO" }
+        );
+    }
+
+    let best = report.best_by_chunk_size();
+    if best.len() > 1 {
+        println!("\nfastest upload per chunk size:");
+        for run in best {
+            println!(
+                "{:>6} bytes: {:.1} KiB/s",
+                run.chunk_size,
+                run.upload_rate() / 1024.0
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_options(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options_around_the_command() {
+        let options = parse(&["--json", "ls", "/ext", "--md5", "--port", "COM3"]).unwrap();
+
+        assert!(options.json);
+        assert!(options.md5);
+        assert!(!options.help);
+        assert_eq!(options.port.as_deref(), Some("COM3"));
+        assert_eq!(options.args, ["ls", "/ext"]);
+
+        let options = parse(&["bench", "--sizes", "4k,1m", "--repeat", "2"]).unwrap();
+        assert_eq!(options.bench.sizes, [4 * 1024, 1024 * 1024]);
+        assert_eq!(options.bench.repeat, 2);
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["ls", "--help", "--bogus"]).unwrap().help);
+    }
+
+    #[test]
+    fn rejects_bad_command_lines() {
+        assert_eq!(parse(&["--bogus"]).unwrap_err(), "unknown option `--bogus`");
+        assert_eq!(
+            parse(&["ls", "--port"]).unwrap_err(),
+            "`--port` needs a value"
+        );
+        assert_eq!(parse(&["--json"]).unwrap_err(), "no command given");
+        assert_eq!(
+            error_json("no command given").to_string(),
+            r#"{"error":"no command given"}"#
+        );
+    }
+
+    #[test]
+    fn dispatches_on_the_positional_arguments() {
+        for args in [&["frobnicate"][..], &["ls"], &["read", "/ext/a.txt"]] {
+            let error = run(&parse(args).unwrap()).unwrap_err();
+            assert!(error.starts_with(&format!("unknown command `{}`", args[0])));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! One cancellation source for every long running operation
+//!
+//! A [`CancelToken`] is handed to file transfers ([`ReadOptions`](crate::fs::ReadOptions),
+//! [`WriteOptions`](crate::fs::WriteOptions)), to an [`RpcSession`](crate::session::RpcSession),
+//! which covers every chained operation run on it and its keepalive thread, and to a
+//! [`RemoteControl`](crate::remote_control::RemoteControl) screen stream. Cancelling it, e.g.
+//! from a Ctrl-C handler or a UI button, stops all of them.
+//!
+//! Operations check the token between messages, so a cancelled operation still finishes the
+//! message it is waiting for. Chains are wound down the same way as with
+//! [`AbortHandle`](crate::session::AbortHandle): a write chain is closed and a read chain is
+//! drained, so the stream stays in sync. The operation then fails with an
+//! [`std::io::ErrorKind::Interrupted`] error.
+//!
+//! Unlike an `AbortHandle`, which aborts a single operation, a token stays cancelled.
+//!
+//! # Examples
+//!
+//! ```
+//! use flipper_rpc::CancelToken;
+//!
+//! let cancel = CancelToken::new();
+//! let handler = cancel.clone();
+//!
+//! // e.g. from a Ctrl-C handler
+//! std::thread::spawn(move || handler.cancel()).join().unwrap();
+//!
+//! assert!(cancel.is_cancelled());
+//! ```
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// A shared cancellation flag, see the [module docs](self)
+///
+/// Cheap to clone; all clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A token that is not cancelled yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every operation the token was given to
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`CancelToken::cancel`] was called
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fails with an [`std::io::ErrorKind::Interrupted`] error once cancelled
+    pub fn check(&self) -> crate::error::Result<()> {
+        if self.is_cancelled() {
+            return Err(cancelled());
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `token` is set and cancelled
+#[cfg_attr(
+    not(any(
+        feature = "fs-read",
+        feature = "fs-readdir",
+        feature = "fs-write",
+        feature = "session"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn is_cancelled(token: Option<&CancelToken>) -> bool {
+    token.is_some_and(CancelToken::is_cancelled)
+}
+
+/// The error returned by operations stopped through a [`CancelToken`]
+pub(crate) fn cancelled() -> crate::error::Error {
+    std::io::Error::new(std::io::ErrorKind::Interrupted, "operation cancelled").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+
+        clone.cancel();
+
+        assert!(token.is_cancelled());
+        assert!(is_cancelled(Some(&token)));
+        assert!(!is_cancelled(None));
+        assert!(matches!(
+            token.check(),
+            Err(crate::error::Error::Io(e)) if e.kind() == std::io::ErrorKind::Interrupted
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+//! Length-delimited framing of RPC messages
+//!
+//! On every link the Flipper speaks RPC over, each [`proto::Main`] is sent as a varint length
+//! followed by the message. [`FrameCodec`] turns messages into such frames and cuts complete
+//! frames off a growing [`BytesMut`], so a transport only has to move bytes. The serial
+//! transport, the port probe and [`EmulatedFlipper`](crate::testing::EmulatedFlipper) share it.
+//!
+//! With the `async` feature it also implements tokio-util's `Decoder` and `Encoder`, for framing
+//! async byte streams with `tokio_util::codec::Framed`.
+//!
+//! ```
+//! use flipper_rpc::codec::FrameCodec;
+//! use flipper_rpc::proto::{self, system::PingRequest};
+//! use flipper_rpc::proto::prost::bytes::BytesMut;
+//!
+//! let codec = FrameCodec::new();
+//! let ping = proto::Main::new(PingRequest { data: vec![1, 2] }).with_command_id(1);
+//!
+//! let mut buf = BytesMut::new();
+//! codec.encode(&ping, &mut buf).unwrap();
+//! codec.encode(&ping, &mut buf).unwrap();
+//!
+//! assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping.clone()));
+//! assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping));
+//! assert_eq!(codec.decode(&mut buf).unwrap(), None);
+//! ```
+
+use prost::Message;
+use prost::bytes::BytesMut;
+
+use crate::error::{Error, Result};
+use crate::logging::warn;
+use crate::proto;
+
+/// Default [`FrameCodec::max_len`], far above anything the firmware sends (screen frames are
+/// 1 KiB, storage chunks 512 bytes)
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Encodes and decodes length-delimited [`proto::Main`] frames, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCodec {
+    max_len: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameCodec {
+    /// A codec accepting message bodies up to [`DEFAULT_MAX_FRAME_LEN`] bytes
+    pub const fn new() -> Self {
+        Self::with_max_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// A codec accepting message bodies up to `max_len` bytes. Longer length prefixes fail with
+    /// [`Error::FrameTooLarge`] before anything is allocated for them.
+    pub const fn with_max_len(max_len: usize) -> Self {
+        Self { max_len }
+    }
+
+    /// The longest message body the codec accepts
+    pub const fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Appends `message` to `dst` as one frame
+    pub fn encode(&self, message: &proto::Main, dst: &mut BytesMut) -> Result<()> {
+        let len = message.encoded_len();
+        dst.reserve(prost::length_delimiter_len(len) + len);
+        message.encode_length_delimited(dst)?;
+
+        Ok(())
+    }
+
+    /// Splits the frame at the front of `src` off and decodes it. Payloads such as file data and
+    /// screen frames are sliced out of `src`'s allocation instead of copied.
+    ///
+    /// Returns `None`, leaving `src` untouched, if `src` does not hold a complete frame yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::FrameTooLarge`] or [`Error::InvalidFrame`] for a bad length prefix, and
+    /// [`Error::ProtoDecode`] for a body that is not a message. The bytes stay in `src` in both
+    /// cases, e.g. for spotting a CLI banner in their place.
+    pub fn decode(&self, src: &mut BytesMut) -> Result<Option<proto::Main>> {
+        let Some((header_len, body_len)) = self.frame_len(src)? else {
+            return Ok(None);
+        };
+
+        if src.len() < header_len + body_len {
+            return Ok(None);
+        }
+
+        let frame = src.split_to(header_len + body_len).freeze();
+        match proto::Main::decode(frame.slice(header_len..)) {
+            Ok(main) => Ok(Some(main)),
+            Err(e) => {
+                // Put the bytes back for the caller to inspect
+                let mut restored = BytesMut::from(&frame[..]);
+                restored.unsplit(core::mem::take(src));
+                *src = restored;
+
+                Err(e.into())
+            }
+        }
+    }
+
+    /// The length prefix at the front of `src`, as `(header_len, body_len)`. `None` if it is not
+    /// complete yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::FrameTooLarge`] if the body is longer than [`FrameCodec::max_len`], and
+    /// [`Error::InvalidFrame`] if the prefix is longer than a varint can be.
+    pub fn frame_len(&self, src: &[u8]) -> Result<Option<(usize, usize)>> {
+        let Some((header_len, body_len)) = crate::proto_ext::frame_len(src)? else {
+            return Ok(None);
+        };
+        self.check_len(body_len)?;
+
+        Ok(Some((header_len, body_len)))
+    }
+
+    /// Fails with [`Error::FrameTooLarge`] if a body of `len` bytes is above
+    /// [`FrameCodec::max_len`]
+    pub fn check_len(&self, len: usize) -> Result<()> {
+        if len > self.max_len {
+            warn!(len, "length prefix above the maximum message length");
+            return Err(Error::FrameTooLarge(len));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = proto::Main;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<proto::Main>> {
+        let frame = FrameCodec::decode(self, src)?;
+
+        // Room for the rest of the frame, so the next read can complete it
+        if frame.is_none() {
+            if let Some((header_len, body_len)) = self.frame_len(src)? {
+                src.reserve((header_len + body_len).saturating_sub(src.len()));
+            }
+        }
+
+        Ok(frame)
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio_util::codec::Encoder<proto::Main> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: proto::Main, dst: &mut BytesMut) -> Result<()> {
+        FrameCodec::encode(self, &item, dst)
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio_util::codec::Encoder<&proto::Main> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &proto::Main, dst: &mut BytesMut) -> Result<()> {
+        FrameCodec::encode(self, item, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{gui::ScreenFrame, system::PingRequest};
+
+    fn ping(command_id: u32) -> proto::Main {
+        proto::Main::new(PingRequest {
+            data: vec![command_id as u8; 3],
+        })
+        .with_command_id(command_id)
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let codec = FrameCodec::new();
+        let mut wire = BytesMut::new();
+        codec.encode(&ping(1), &mut wire).unwrap();
+        codec.encode(&ping(2), &mut wire).unwrap();
+
+        let mut buf = BytesMut::new();
+        let mut decoded = alloc::vec::Vec::new();
+        for byte in wire.iter() {
+            buf.extend_from_slice(&[*byte]);
+            while let Some(main) = codec.decode(&mut buf).unwrap() {
+                decoded.push(main);
+            }
+        }
+
+        assert_eq!(decoded, [ping(1), ping(2)]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn slices_payloads_out_of_the_buffer() {
+        let codec = FrameCodec::new();
+        let frame = proto::Main::new(ScreenFrame {
+            data: alloc::vec![7; 1024].into(),
+            ..Default::default()
+        });
+        let mut buf = BytesMut::new();
+        codec.encode(&frame, &mut buf).unwrap();
+        let start = buf.as_ptr() as usize;
+        let end = start + buf.len();
+
+        let Some(proto::main::Content::GuiScreenFrame(decoded)) = codec
+            .decode(&mut buf)
+            .unwrap()
+            .and_then(|main| main.content)
+        else {
+            panic!("not a screen frame");
+        };
+
+        let data = decoded.data.as_ptr() as usize;
+        assert!((start..end).contains(&data));
+    }
+
+    #[test]
+    fn rejects_oversized_frames_without_consuming_them() {
+        let codec = FrameCodec::with_max_len(8);
+        let mut buf = BytesMut::new();
+        FrameCodec::new().encode(&ping(1), &mut buf).unwrap();
+        buf[0] = 9;
+
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(Error::FrameTooLarge(9))
+        ));
+        assert_eq!(buf[0], 9);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn frames_through_tokio_util() {
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        Encoder::encode(&mut codec, ping(1), &mut buf).unwrap();
+        Encoder::encode(&mut codec, &ping(2), &mut buf).unwrap();
+        let second = buf.split_off(buf.len() - 2);
+
+        assert_eq!(
+            Decoder::decode(&mut codec, &mut buf).unwrap(),
+            Some(ping(1))
+        );
+        assert_eq!(Decoder::decode(&mut codec, &mut buf).unwrap(), None);
+        assert!(buf.capacity() >= buf.len() + second.len());
+
+        buf.unsplit(second);
+        assert_eq!(
+            Decoder::decode(&mut codec, &mut buf).unwrap(),
+            Some(ping(2))
+        );
+    }
+}
@@ -0,0 +1,469 @@
+//! Desktop lock helpers
+//!
+//! `Request::DesktopUnlock` only dismisses the plain lock screen. Devices locked with a PIN need
+//! the code typed in, which [`unlock_with_pin`] does by emulating hardware button presses.
+//!
+//! Long unattended runs can also be stalled by the desktop auto-locking itself, with a PIN
+//! nobody is there to type. [`KeepUnlocked`] wraps a transport to prevent that, see
+//! [`AutolockPolicy`].
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::logging::{debug, trace, warn};
+
+use crate::proto::CommandStatus;
+use crate::proto::gui::{InputKey, InputType, SendInputEventRequest};
+use crate::proto::main::Content;
+use crate::transport::Transport;
+use crate::transport::{CommandIndex, check_status};
+use crate::{
+    error::{Error, Result},
+    proto::{
+        self,
+        desktop::{
+            IsLockedRequest, StatusSubscribeRequest, StatusUnsubscribeRequest, UnlockRequest,
+        },
+    },
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Pause between emulated key presses so the PIN screen keeps up
+const KEY_DELAY: Duration = Duration::from_millis(50);
+
+/// Time the desktop gets to check the PIN before the lock state is queried again
+const UNLOCK_DELAY: Duration = Duration::from_millis(500);
+
+/// A button that can be part of a desktop PIN. The firmware only accepts the arrow keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    /// Up arrow
+    Up,
+    /// Down arrow
+    Down,
+    /// Left arrow
+    Left,
+    /// Right arrow
+    Right,
+}
+
+impl From<Button> for InputKey {
+    fn from(value: Button) -> Self {
+        match value {
+            Button::Up => InputKey::Up,
+            Button::Down => InputKey::Down,
+            Button::Left => InputKey::Left,
+            Button::Right => InputKey::Right,
+        }
+    }
+}
+
+/// Checks whether the desktop is locked
+///
+/// The firmware answers `OK` when locked and a generic `ERROR` when it is not.
+pub fn is_locked<T>(transport: &mut T) -> Result<bool>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    match transport.send_and_receive(Request::DesktopIsLocked(IsLockedRequest {})) {
+        Ok(_) => Ok(true),
+        Err(e) if e.rpc_status() == Some(CommandStatus::Error) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Unlocks a desktop that is locked without a PIN
+pub fn unlock<T>(transport: &mut T) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    transport.send_and_receive(Request::DesktopUnlock(UnlockRequest {}))?;
+
+    Ok(())
+}
+
+/// Unlocks a PIN locked desktop by typing `pin` on the emulated keypad and confirming with OK.
+///
+/// Returns `true` if the desktop is unlocked afterwards. Does nothing if the desktop is not
+/// locked to begin with.
+pub fn unlock_with_pin<T>(transport: &mut T, pin: &[Button]) -> Result<bool>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    if !is_locked(transport)? {
+        debug!("desktop already unlocked");
+        return Ok(true);
+    }
+
+    debug!(len = pin.len(), "entering pin");
+
+    // Wakes the PIN prompt. OK with an empty PIN is ignored if the prompt is already open.
+    press(transport, InputKey::Ok)?;
+
+    for &button in pin {
+        press(transport, button.into())?;
+    }
+
+    press(transport, InputKey::Ok)?;
+
+    std::thread::sleep(UNLOCK_DELAY);
+
+    Ok(!is_locked(transport)?)
+}
+
+/// Emulates a full short press: press, short, release
+fn press<T>(transport: &mut T, key: InputKey) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    for r#type in [InputType::Press, InputType::Short, InputType::Release] {
+        transport.send_and_receive(Request::GuiSendInputEvent(SendInputEventRequest {
+            key: key.into(),
+            r#type: r#type.into(),
+        }))?;
+    }
+
+    std::thread::sleep(KEY_DELAY);
+
+    Ok(())
+}
+
+/// How often [`KeepUnlocked`] refreshes the desktop's activity timer. The shortest auto-lock
+/// delay the firmware offers is 10 seconds.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What [`KeepUnlocked`] does about the desktop auto-lock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AutolockPolicy {
+    /// Resets the auto-lock timer while messages are being sent, and warns if the desktop
+    /// locks anyway
+    #[default]
+    Refresh,
+    /// Only warns when the desktop locks
+    Warn,
+}
+
+/// A transport decorator that keeps the desktop from auto-locking during long operations
+///
+/// It subscribes to desktop status updates, which it consumes and logs instead of passing them
+/// on. With [`AutolockPolicy::Refresh`], it also resets the auto-lock timer whenever it is due
+/// before a send, by emulating a lone key release: the desktop counts it as activity and the GUI
+/// discards it, because no press came before it. Answers to earlier requests that arrive while
+/// it waits for the refresh are queued and handed back by the next receives.
+///
+/// # Examples
+///
+/// ```no_run
+/// use flipper_rpc::desktop::{AutolockPolicy, KeepUnlocked};
+/// use flipper_rpc::error::Result;
+/// use flipper_rpc::fs::FsWrite;
+/// use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+///
+/// # fn main() -> Result<()> {
+/// let rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+/// let mut rpc = KeepUnlocked::new(rpc, AutolockPolicy::Refresh)?;
+///
+/// rpc.fs_write_with("/ext/big.bin", vec![0; 1 << 20], Default::default())?;
+/// if rpc.saw_lock() {
+///     eprintln!("the desktop locked during the upload");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct KeepUnlocked<T> {
+    inner: T,
+    policy: AutolockPolicy,
+    refresh_interval: Duration,
+    last_refresh: Instant,
+    locked: bool,
+    saw_lock: bool,
+    /// Messages read while waiting for a refresh, not received yet
+    pending: VecDeque<proto::Main>,
+}
+
+impl<T> KeepUnlocked<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Wraps `inner`, subscribing to desktop status updates
+    pub fn new(mut inner: T, policy: AutolockPolicy) -> Result<Self> {
+        inner.send_and_receive(Request::DesktopStatusSubscribe(StatusSubscribeRequest {}))?;
+        debug!(?policy, "subscribed to desktop status");
+
+        Ok(Self {
+            inner,
+            policy,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            last_refresh: Instant::now(),
+            locked: false,
+            saw_lock: false,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Sets how often the auto-lock timer is refreshed, [`DEFAULT_REFRESH_INTERVAL`] by
+    /// default. Keep it below the device's auto-lock delay.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Unsubscribes from desktop status updates and returns the wrapped transport. Receive every
+    /// answer first: messages still queued are lost.
+    pub fn into_inner(mut self) -> Result<T> {
+        let id = self.inner.reserve_range(1).start;
+        self.inner.send_raw(
+            Request::DesktopStatusUnsubscribe(StatusUnsubscribeRequest {}).into_rpc(id),
+        )?;
+        self.receive_answer(id)?;
+
+        if !self.pending.is_empty() {
+            warn!(
+                count = self.pending.len(),
+                "unwrapped with answers that were not received"
+            );
+        }
+
+        Ok(self.inner)
+    }
+
+    /// Resets the auto-lock timer if it is due
+    fn refresh(&mut self) -> Result<()> {
+        if self.policy != AutolockPolicy::Refresh
+            || self.last_refresh.elapsed() < self.refresh_interval
+        {
+            return Ok(());
+        }
+
+        trace!("refreshing desktop activity");
+        let id = self.inner.reserve_range(1).start;
+        self.inner.send_raw(
+            Request::GuiSendInputEvent(SendInputEventRequest {
+                key: InputKey::Back.into(),
+                r#type: InputType::Release.into(),
+            })
+            .into_rpc(id),
+        )?;
+        self.receive_answer(id)?;
+        self.last_refresh = Instant::now();
+
+        Ok(())
+    }
+
+    /// Receives until the answer to `id`, handling status updates on the way. Anything else,
+    /// like a write chain's early error or the answer to a pipelined request, is queued for
+    /// [`TransportRaw::receive_raw`].
+    fn receive_answer(&mut self, id: u32) -> Result<()> {
+        loop {
+            // Unchecked, so failed answers keep their command id
+            let main = self.inner.receive_raw_unchecked()?;
+
+            if self.handle_status(&main) {
+                continue;
+            }
+            if main.command_id == id {
+                return check_status(main).map(drop);
+            }
+            self.pending.push_back(main);
+        }
+    }
+}
+
+impl<T> KeepUnlocked<T> {
+    /// Whether the desktop locked at any point since it was wrapped
+    pub fn saw_lock(&self) -> bool {
+        self.saw_lock
+    }
+
+    /// Whether the last status update said the desktop is locked
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Records a desktop status update. Returns `false` for any other message.
+    fn handle_status(&mut self, main: &proto::Main) -> bool {
+        let Some(Content::DesktopStatus(status)) = &main.content else {
+            return false;
+        };
+
+        if status.locked && !self.locked {
+            warn!("desktop locked during the operation");
+            self.saw_lock = true;
+        }
+        self.locked = status.locked;
+
+        true
+    }
+}
+
+impl<T> TransportRaw<proto::Main> for KeepUnlocked<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    type Err = Error;
+
+    fn send_raw(&mut self, value: proto::Main) -> Result<()> {
+        self.refresh()?;
+        self.inner.send_raw(value)
+    }
+
+    fn send_raw_buf(&mut self, value: proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+        self.refresh()?;
+        self.inner.send_raw_buf(value, buf)
+    }
+
+    fn receive_raw(&mut self) -> Result<proto::Main> {
+        check_status(self.receive_raw_unchecked()?)
+    }
+
+    fn receive_raw_unchecked(&mut self) -> Result<proto::Main> {
+        if let Some(main) = self.pending.pop_front() {
+            return Ok(main);
+        }
+
+        loop {
+            let main = self.inner.receive_raw_unchecked()?;
+
+            if !self.handle_status(&main) {
+                return Ok(main);
+            }
+        }
+    }
+
+    fn try_receive_raw(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw_unchecked()?
+            .map(check_status)
+            .transpose()
+    }
+
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<proto::Main>> {
+        if let Some(main) = self.pending.pop_front() {
+            return Ok(Some(main));
+        }
+
+        while let Some(main) = self.inner.try_receive_raw_unchecked()? {
+            if !self.handle_status(&main) {
+                return Ok(Some(main));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn take_abort(&mut self) -> bool {
+        self.inner.take_abort()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn decode_mode(&self) -> crate::rpc::res::DecodeMode {
+        self.inner.decode_mode()
+    }
+}
+
+impl<T> CommandIndex for KeepUnlocked<T>
+where
+    T: CommandIndex,
+{
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        self.inner.increment_command_index(by)
+    }
+
+    fn command_index(&mut self) -> u32 {
+        self.inner.command_index()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::proto::desktop::Status;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn status_updates_are_consumed_and_activity_refreshed() {
+        let mut rpc = KeepUnlocked::new(EmulatedFlipper::new(), AutolockPolicy::Refresh)
+            .unwrap()
+            .refresh_interval(Duration::ZERO);
+
+        rpc.get_mut()
+            .push_event(Content::DesktopStatus(Status { locked: true }));
+        rpc.send_and_receive(Request::Ping(vec![1])).unwrap();
+        assert!(rpc.saw_lock());
+        assert!(rpc.is_locked());
+
+        let flipper = rpc.into_inner().unwrap();
+        let refreshes = flipper
+            .requests()
+            .iter()
+            .filter(|main| matches!(main.content, Some(Content::GuiSendInputEventRequest(_))))
+            .count();
+        assert_eq!(refreshes, 1);
+    }
+
+    #[test]
+    fn answers_read_during_a_refresh_are_handed_back() {
+        let mut rpc = KeepUnlocked::new(EmulatedFlipper::new(), AutolockPolicy::Refresh)
+            .unwrap()
+            .refresh_interval(Duration::ZERO);
+
+        let first = rpc.reserve_range(1).start;
+        rpc.send_raw(Request::Ping(vec![1]).into_rpc(first))
+            .unwrap();
+        let missing = rpc.reserve_range(1).start;
+        rpc.send_raw(Request::StorageMetadata("/ext/missing".into()).into_rpc(missing))
+            .unwrap();
+        let last = rpc.reserve_range(1).start;
+        rpc.send_raw(Request::Ping(vec![3]).into_rpc(last)).unwrap();
+
+        assert_eq!(rpc.receive_raw().unwrap().command_id, first);
+        assert!(rpc.receive_raw().is_err());
+        assert_eq!(rpc.receive_raw().unwrap().command_id, last);
+        assert_eq!(rpc.try_receive_raw().unwrap(), None);
+    }
+
+    #[test]
+    fn unlocks_the_plain_lock_screen() {
+        let mut flipper = EmulatedFlipper::new();
+        assert!(!is_locked(&mut flipper).unwrap());
+
+        flipper.lock(&[]);
+        assert!(is_locked(&mut flipper).unwrap());
+
+        unlock(&mut flipper).unwrap();
+        assert!(!is_locked(&mut flipper).unwrap());
+    }
+
+    #[test]
+    fn types_the_pin() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.lock(&[InputKey::Up, InputKey::Left, InputKey::Up]);
+
+        // `DesktopUnlock` does not get past a PIN
+        unlock(&mut flipper).unwrap();
+        assert!(flipper.is_locked());
+
+        assert!(!unlock_with_pin(&mut flipper, &[Button::Up, Button::Up]).unwrap());
+        assert!(unlock_with_pin(&mut flipper, &[Button::Up, Button::Left, Button::Up]).unwrap());
+        assert!(!flipper.is_locked());
+
+        // Already unlocked, nothing is typed
+        let sent = flipper.requests().len();
+        assert!(unlock_with_pin(&mut flipper, &[Button::Down]).unwrap());
+        assert_eq!(flipper.requests().len(), sent + 1);
+    }
+}
@@ -0,0 +1,15 @@
+//! Diagnostics
+//!
+//! Routines that exercise a device and report what they found, for validating hardware and for
+//! attaching to bug reports.
+
+pub mod bench;
+pub use bench::{BenchConfig, BenchReport, BenchRun, bench_transfer};
+
+pub mod storage;
+pub use storage::{FileCheck, SELFTEST_SIZES, StorageReport, storage_selftest};
+
+#[cfg(feature = "transport-serial")]
+pub mod probe;
+#[cfg(feature = "transport-serial")]
+pub use probe::{ProbeReport, probe};
@@ -0,0 +1,265 @@
+//! End-to-end transfer benchmark
+//!
+//! [`bench_transfer`] uploads files of the configured sizes once per upload chunk size, has the
+//! device hash them, downloads them again and removes them, timing every phase. The
+//! [`BenchReport`] is meant for tuning chunk sizes and for reproducible performance reports; the
+//! `flipper-rpc bench` command prints it.
+//!
+//! Download chunks are always 512 bytes, the firmware picks their size.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::diagnostics::bench::{BenchConfig, bench_transfer};
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let config = BenchConfig {
+//!     chunk_sizes: vec![256, 512, 1024],
+//!     ..BenchConfig::default()
+//! };
+//! for run in bench_transfer(&mut rpc, &config)?.runs {
+//!     println!(
+//!         "{} bytes in {} byte chunks: {:.1} KiB/s up",
+//!         run.size,
+//!         run.chunk_size,
+//!         run.upload_rate() / 1024.0
+//!     );
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::logging::{debug, warn};
+
+use crate::fs::{FsCreateDir, FsMd5, FsRead, FsRemove, FsWrite, WriteOptions};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+use super::storage::pseudo_random;
+
+/// What [`bench_transfer`] measures
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BenchConfig {
+    /// File sizes to transfer, in bytes
+    pub sizes: Vec<usize>,
+    /// Upload chunk sizes to sweep, in bytes, see [`WriteOptions::chunk_size`]
+    pub chunk_sizes: Vec<usize>,
+    /// How often every size and chunk size combination runs
+    pub repeat: u32,
+    /// Directory on the device the files are written to, created if needed
+    pub dir: String,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![4 * 1024, 64 * 1024, 256 * 1024],
+            chunk_sizes: vec![1024],
+            repeat: 1,
+            dir: "/ext/.bench".to_string(),
+        }
+    }
+}
+
+/// Timings of one file's round trip
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BenchRun {
+    /// Size of the file in bytes
+    pub size: usize,
+    /// Upload chunk size in bytes
+    pub chunk_size: usize,
+    /// Time taken by the upload
+    pub upload: Duration,
+    /// Time the device took to hash the file
+    pub md5: Duration,
+    /// Time taken by the download
+    pub download: Duration,
+    /// Time taken to remove the file
+    pub remove: Duration,
+    /// Whether the device's MD5 and the downloaded data match the uploaded data
+    pub verified: bool,
+}
+
+impl BenchRun {
+    /// Upload speed in bytes per second
+    pub fn upload_rate(&self) -> f64 {
+        rate(self.size, self.upload)
+    }
+
+    /// Download speed in bytes per second
+    pub fn download_rate(&self) -> f64 {
+        rate(self.size, self.download)
+    }
+}
+
+/// Results of [`bench_transfer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BenchReport {
+    /// One entry per transfer, by size, then chunk size, then repetition
+    pub runs: Vec<BenchRun>,
+}
+
+impl BenchReport {
+    /// Whether every file survived its round trip
+    pub fn verified(&self) -> bool {
+        self.runs.iter().all(|run| run.verified)
+    }
+
+    /// The run with the fastest upload for every chunk size, in the order they were swept
+    pub fn best_by_chunk_size(&self) -> Vec<&BenchRun> {
+        let mut best: Vec<&BenchRun> = Vec::new();
+
+        for run in &self.runs {
+            match best.iter_mut().find(|b| b.chunk_size == run.chunk_size) {
+                Some(b) if run.upload_rate() > b.upload_rate() => *b = run,
+                Some(_) => {}
+                None => best.push(run),
+            }
+        }
+
+        best
+    }
+}
+
+fn rate(bytes: usize, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+
+    bytes as f64 / time.as_secs_f64()
+}
+
+/// Runs the benchmark described by `config`. Every file is removed after its run, the directory
+/// is kept.
+///
+/// # Errors
+///
+/// Returns the first transfer error. A file named `bench.bin` in the directory is overwritten.
+pub fn bench_transfer<T>(transport: &mut T, config: &BenchConfig) -> Result<BenchReport>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let dir = config.dir.trim_end_matches('/');
+    transport.fs_create_dir(dir)?;
+    let path = format!("{dir}/bench.bin");
+
+    let mut runs = Vec::new();
+    for &size in &config.sizes {
+        let data = pseudo_random(size);
+        let md5 = format!("{:x}", md5::compute(&data));
+
+        for &chunk_size in &config.chunk_sizes {
+            for _ in 0..config.repeat {
+                let run = run_once(transport, &path, &data, &md5, chunk_size);
+                if run.is_err() {
+                    // Best effort, the error of the run is what matters
+                    if let Err(_e) = transport.fs_remove(&path, false) {
+                        warn!(path, error = %_e, "bench cleanup failed");
+                    }
+                }
+
+                let run = run?;
+                debug!(
+                    size,
+                    chunk_size,
+                    upload_rate = run.upload_rate(),
+                    download_rate = run.download_rate(),
+                    "bench run finished"
+                );
+                runs.push(run);
+            }
+        }
+    }
+
+    Ok(BenchReport { runs })
+}
+
+/// Uploads, hashes, downloads and removes `data` once
+fn run_once<T>(
+    transport: &mut T,
+    path: &str,
+    data: &[u8],
+    md5: &str,
+    chunk_size: usize,
+) -> Result<BenchRun>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let start = Instant::now();
+    transport.fs_write_with(path, data, WriteOptions::default().chunk_size(chunk_size))?;
+    let upload = start.elapsed();
+
+    let start = Instant::now();
+    let device_md5 = transport.fs_md5(path)?;
+    let md5_time = start.elapsed();
+
+    let start = Instant::now();
+    let read = transport.fs_read(path)?;
+    let download = start.elapsed();
+
+    let start = Instant::now();
+    transport.fs_remove(path, false)?;
+    let remove = start.elapsed();
+
+    Ok(BenchRun {
+        size: data.len(),
+        chunk_size,
+        upload,
+        md5: md5_time,
+        download,
+        remove,
+        verified: device_md5 == md5 && read.as_ref() == data,
+    })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::proto::main::Content;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn sweeps_chunk_sizes_and_cleans_up() {
+        let mut flipper = EmulatedFlipper::new();
+        let config = BenchConfig {
+            sizes: vec![0, 3000],
+            chunk_sizes: vec![512, 1024],
+            repeat: 2,
+            dir: "/ext/bench/".to_string(),
+        };
+
+        let report = bench_transfer(&mut flipper, &config).unwrap();
+        assert!(report.verified());
+        assert_eq!(report.runs.len(), 8);
+        assert_eq!(
+            report
+                .best_by_chunk_size()
+                .iter()
+                .map(|run| run.chunk_size)
+                .collect::<Vec<_>>(),
+            [512, 1024]
+        );
+        assert_eq!(flipper.file("/ext/bench/bench.bin"), None);
+
+        // 3000 bytes go out as 6 chunks of 512, then 3 of 1024
+        let chunks = flipper
+            .requests()
+            .iter()
+            .filter(|main| matches!(main.content, Some(Content::StorageWriteRequest(_))))
+            .count();
+        assert_eq!(chunks, 2 * (1 + 1 + 6 + 3));
+    }
+}
@@ -0,0 +1,172 @@
+//! Connection probe
+//!
+//! When a connection fails it is hard to tell from the error alone whether the wrong port was
+//! picked, the CLI is busy or the RPC session misbehaves. [`probe`] walks through the steps of
+//! connecting one at a time and records how far it got in a [`ProbeReport`], whose `Display`
+//! output is meant to be pasted into bug reports.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::diagnostics::probe;
+//!
+//! let report = probe("/dev/ttyACM0");
+//! println!("{report}");
+//! ```
+
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::logging::debug;
+
+use crate::error::Result;
+use crate::rpc::{req::Request, res::Response};
+use crate::transport::{
+    CommandIndex, Transport, TransportRaw,
+    serial::{
+        DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_PROMPT, DEFAULT_READ_TIMEOUT,
+        helpers::{drain_until, drain_until_str},
+        open_port,
+        rpc::SerialRpcTransport,
+    },
+};
+
+/// Data sent with the probe's ping
+const PING_DATA: [u8; 4] = [1, 2, 3, 4];
+
+/// What [`probe`] found on a port
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProbeReport {
+    /// The probed port
+    pub port: String,
+    /// Whether the port could be opened
+    pub opened: bool,
+    /// Whether the CLI prompt appeared
+    pub prompt_found: bool,
+    /// Whether an RPC session started and answered a request
+    pub rpc_handshake: bool,
+    /// Protobuf schema version of the firmware, as major and minor
+    pub protobuf_version: Option<(u32, u32)>,
+    /// Round trip time of a ping
+    pub ping_latency: Option<Duration>,
+    /// The error that stopped the probe, if any
+    pub error: Option<String>,
+}
+
+impl ProbeReport {
+    /// Whether every step succeeded
+    pub fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for ProbeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn yes_no(value: bool) -> &'static str {
+            if value { "yes" } else { "no" }
+        }
+
+        writeln!(f, "port:             {}", self.port)?;
+        writeln!(f, "opened:           {}", yes_no(self.opened))?;
+        writeln!(f, "prompt found:     {}", yes_no(self.prompt_found))?;
+        writeln!(f, "rpc handshake:    {}", yes_no(self.rpc_handshake))?;
+
+        match self.protobuf_version {
+            Some((major, minor)) => writeln!(f, "protobuf version: {major}.{minor}")?,
+            None => writeln!(f, "protobuf version: unknown")?,
+        }
+        match self.ping_latency {
+            Some(latency) => writeln!(f, "ping latency:     {latency:?}")?,
+            None => writeln!(f, "ping latency:     unknown")?,
+        }
+
+        write!(f, "crate version:    {}", env!("CARGO_PKG_VERSION"))?;
+        if let Some(error) = &self.error {
+            write!(f, "\nerror:            {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Probes the Flipper on `port`. Never fails: errors end the probe and are recorded in the
+/// report.
+///
+/// The RPC session is stopped again afterwards, leaving the device at the CLI prompt.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn probe(port: &str) -> ProbeReport {
+    let mut report = ProbeReport {
+        port: port.to_string(),
+        ..Default::default()
+    };
+
+    if let Err(e) = run(&mut report) {
+        report.error = Some(e.to_string());
+    }
+    debug!(ok = report.ok(), "probe finished");
+
+    report
+}
+
+/// Fills in `report` step by step, stopping at the first error
+fn run(report: &mut ProbeReport) -> Result<()> {
+    let mut port = open_port(&report.port, DEFAULT_READ_TIMEOUT)?;
+    report.opened = true;
+
+    // A fresh prompt, in case the banner was printed before the port was opened
+    port.write_all(b"\r")?;
+    port.flush()?;
+    drain_until_str(&mut port, DEFAULT_PROMPT, DEFAULT_HANDSHAKE_TIMEOUT)?;
+    report.prompt_found = true;
+
+    port.write_all(b"start_rpc_session\r")?;
+    port.flush()?;
+    drain_until(&mut port, b'\n', DEFAULT_HANDSHAKE_TIMEOUT)?;
+
+    let mut rpc = SerialRpcTransport::from_port(port)?;
+
+    let version = rpc.send_and_receive(Request::SystemProtobufVersion)?;
+    report.rpc_handshake = true;
+    if let Response::SystemProtobufVersion(version) = version {
+        report.protobuf_version = Some((version.major, version.minor));
+    }
+
+    let start = Instant::now();
+    let pong = rpc.send_and_receive(Request::Ping(PING_DATA.to_vec()))?;
+    if pong != Response::Ping(PING_DATA.to_vec()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unexpected ping response: {pong:?}"),
+        )
+        .into());
+    }
+    report.ping_latency = Some(start.elapsed());
+
+    let id = rpc.command_index();
+    rpc.send_raw(Request::StopSession.into_rpc(id))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_partial_reports() {
+        let report = ProbeReport {
+            port: "/dev/ttyACM0".to_string(),
+            opened: true,
+            error: Some("timed out".to_string()),
+            ..Default::default()
+        };
+
+        let text = report.to_string();
+        assert!(!report.ok());
+        assert!(text.contains("prompt found:     no"));
+        assert!(text.contains("protobuf version: unknown"));
+        assert!(text.ends_with("error:            timed out"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Storage self-test
+//!
+//! [`storage_selftest`] writes files of pseudo-random data with sizes around the chunk
+//! boundaries, reads them back, checks them against the device's MD5 and the original data, and
+//! removes them again. The [`StorageReport`] tells whether everything survived the round trip
+//! and how fast it went, which catches both failing SD cards and chunking bugs.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::diagnostics::storage_selftest;
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let report = storage_selftest(&mut rpc, "/ext/.selftest")?;
+//! println!(
+//!     "{}: {:.1} KiB/s write, {:.1} KiB/s read",
+//!     if report.passed() { "passed" } else { "FAILED" },
+//!     report.write_throughput() / 1024.0,
+//!     report.read_throughput() / 1024.0,
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::logging::{debug, warn};
+
+use crate::error::Result;
+use crate::fs::StorageBackend;
+
+/// File sizes [`storage_selftest`] writes: empty, around one and several 1 KiB chunks, and large
+pub const SELFTEST_SIZES: [usize; 8] = [0, 1, 1023, 1024, 1025, 4096, 10_000, 65_536];
+
+/// Outcome of one file of the self-test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCheck {
+    /// Path the file was written to
+    pub path: String,
+    /// Size of the file in bytes
+    pub size: usize,
+    /// Time taken to write the file
+    pub write_time: Duration,
+    /// Time taken to read the file back
+    pub read_time: Duration,
+    /// Whether the MD5 calculated by the device matches the written data
+    pub md5_matches: bool,
+    /// Whether the data read back equals the written data
+    pub data_matches: bool,
+}
+
+impl FileCheck {
+    /// Whether the file survived the round trip
+    pub fn passed(&self) -> bool {
+        self.md5_matches && self.data_matches
+    }
+}
+
+/// Results of [`storage_selftest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageReport {
+    /// Directory the test ran in
+    pub dir: String,
+    /// One entry per file, in the order of [`SELFTEST_SIZES`]
+    pub files: Vec<FileCheck>,
+    /// Whether every test file, and the directory if the test created it, was removed
+    pub cleaned_up: bool,
+}
+
+impl StorageReport {
+    /// Whether every file survived the round trip
+    pub fn passed(&self) -> bool {
+        self.files.iter().all(FileCheck::passed)
+    }
+
+    /// Total number of bytes written, and read back
+    pub fn bytes(&self) -> usize {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    /// Write speed over all files, in bytes per second
+    pub fn write_throughput(&self) -> f64 {
+        throughput(
+            self.bytes(),
+            self.files.iter().map(|file| file.write_time).sum(),
+        )
+    }
+
+    /// Read speed over all files, in bytes per second
+    pub fn read_throughput(&self) -> f64 {
+        throughput(
+            self.bytes(),
+            self.files.iter().map(|file| file.read_time).sum(),
+        )
+    }
+}
+
+fn throughput(bytes: usize, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+
+    bytes as f64 / time.as_secs_f64()
+}
+
+/// Runs the storage self-test in `dir`, creating it if needed. `storage` is usually a transport,
+/// see [`StorageBackend`].
+///
+/// Corrupted files are reported in the [`StorageReport`], not as errors. Test files are removed
+/// even when a transfer fails.
+///
+/// # Errors
+///
+/// Returns the first transfer error. Existing files named `selftest-*.bin` in `dir` are
+/// overwritten.
+pub fn storage_selftest<B>(storage: &mut B, dir: &str) -> Result<StorageReport>
+where
+    B: StorageBackend,
+{
+    let dir = dir.trim_end_matches('/').to_string();
+    let existed = storage.create_dir(&dir)?;
+
+    let mut written = Vec::with_capacity(SELFTEST_SIZES.len());
+    let mut files = Vec::with_capacity(SELFTEST_SIZES.len());
+    let mut result = Ok(());
+
+    for (i, &size) in SELFTEST_SIZES.iter().enumerate() {
+        let path = format!("{dir}/selftest-{i}.bin");
+        // Remembered before the transfer, so a failed write is still cleaned up
+        written.push(path.clone());
+
+        match check_file(storage, path, size) {
+            Ok(check) => {
+                debug!(
+                    path = check.path,
+                    size,
+                    passed = check.passed(),
+                    "selftest file checked"
+                );
+                files.push(check);
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    let cleaned_up = cleanup(storage, &dir, &written, existed);
+    result?;
+
+    Ok(StorageReport {
+        dir,
+        files,
+        cleaned_up,
+    })
+}
+
+/// Writes `size` bytes to `path`, reads them back and compares
+fn check_file<B>(storage: &mut B, path: String, size: usize) -> Result<FileCheck>
+where
+    B: StorageBackend,
+{
+    let data = pseudo_random(size);
+
+    let start = Instant::now();
+    storage.write(&path, &data)?;
+    let write_time = start.elapsed();
+
+    let md5_matches = storage.md5(&path)? == format!("{:x}", md5::compute(&data));
+
+    let start = Instant::now();
+    let read = storage.read(&path)?;
+    let read_time = start.elapsed();
+
+    Ok(FileCheck {
+        data_matches: read == data,
+        path,
+        size,
+        write_time,
+        read_time,
+        md5_matches,
+    })
+}
+
+/// Removes the test files, and `dir` if the test created it. Returns whether all of it went.
+fn cleanup<B>(storage: &mut B, dir: &str, files: &[String], existed: bool) -> bool
+where
+    B: StorageBackend,
+{
+    let mut cleaned_up = true;
+
+    let dir_to_remove = (!existed).then_some(dir);
+    for path in files.iter().map(String::as_str).chain(dir_to_remove) {
+        if let Err(_e) = storage.remove(path, false) {
+            warn!(path, error = %_e, "selftest cleanup failed");
+            cleaned_up = false;
+        }
+    }
+
+    cleaned_up
+}
+
+/// Deterministic xorshift bytes, so a failing size can be reproduced
+pub(crate) fn pseudo_random(size: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_u32 ^ size as u32;
+
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryBackend;
+
+    #[test]
+    fn passes_on_memory_storage() {
+        let mut storage = MemoryBackend::new();
+        storage.create_dir("/ext/selftest").unwrap();
+
+        let report = storage_selftest(&mut storage, "/ext/selftest").unwrap();
+
+        assert!(report.passed());
+        assert!(report.cleaned_up);
+        // The directory existed before, so it is kept
+        assert!(storage.is_dir("/ext/selftest"));
+        assert_eq!(storage.list("/ext/selftest").unwrap(), []);
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod device_tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn round_trips_and_cleans_up() {
+        let mut flipper = EmulatedFlipper::new();
+
+        let report = storage_selftest(&mut flipper, "/ext/selftest/").unwrap();
+
+        assert!(report.passed());
+        assert!(report.cleaned_up);
+        assert_eq!(report.files.len(), SELFTEST_SIZES.len());
+        assert_eq!(report.bytes(), SELFTEST_SIZES.iter().sum::<usize>());
+        assert!(!flipper.is_dir("/ext/selftest"));
+    }
+
+    #[test]
+    fn cleans_up_after_a_failed_transfer() {
+        let mut flipper = EmulatedFlipper::new();
+        // A directory in the way of the third file makes its write fail
+        flipper.insert_file("/ext/selftest/selftest-2.bin/keep", b"keep".to_vec());
+
+        assert!(storage_selftest(&mut flipper, "/ext/selftest").is_err());
+
+        assert_eq!(flipper.file("/ext/selftest/selftest-0.bin"), None);
+        assert_eq!(flipper.file("/ext/selftest/selftest-1.bin"), None);
+        assert_eq!(
+            flipper.file("/ext/selftest/selftest-2.bin/keep"),
+            Some(&b"keep"[..])
+        );
+    }
+}
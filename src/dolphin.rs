@@ -0,0 +1,197 @@
+//! Dolphin level and XP
+//!
+//! Companion apps commonly show the dolphin's state next to the device name. Firmwares that
+//! expose it answer a `PropertyGet` for the `dolphin` key with one `has_next` message per value,
+//! keyed `dolphin.level`, `dolphin.xp` and so on. [`stats`] collects them into
+//! [`DolphinStats`], or returns `None` when the firmware does not know the key.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::dolphin;
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! match dolphin::stats(&mut rpc)? {
+//!     Some(stats) => println!("level {}, {} xp", stats.level, stats.xp),
+//!     None => println!("this firmware does not report dolphin stats"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::logging::{debug, trace};
+
+use crate::proto::CommandStatus;
+use crate::rpc::res::Response;
+use crate::transport::{CommandIndex, Transport};
+use crate::{
+    error::{Error, Result},
+    proto::{self, property::GetRequest, property::GetResponse},
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Property key the dolphin values are listed under
+pub const DOLPHIN_KEY: &str = "dolphin";
+
+/// State of the dolphin, as reported by the firmware
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DolphinStats {
+    /// Current level, starting at 1
+    pub level: u32,
+    /// Experience collected in total
+    pub xp: u32,
+    /// Experience still needed for the next level, 0 at the maximum level
+    pub xp_to_level_up: u32,
+    /// Mood penalty, higher is grumpier
+    pub butthurt: u32,
+    /// Unix time of the last state change
+    pub timestamp: u64,
+}
+
+/// Reads the dolphin state.
+///
+/// Returns `None` if the firmware does not expose the `dolphin` property.
+///
+/// # Errors
+///
+/// Returns an error if the request fails for another reason, or a value is not a number.
+pub fn stats<T>(transport: &mut T) -> Result<Option<DolphinStats>>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    match read_properties(transport) {
+        Ok(properties) => parse(&properties),
+        Err(e) if is_unsupported(&e) => {
+            debug!(error = %e, "dolphin property not supported");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the `has_next` chain answering a `PropertyGet` for [`DOLPHIN_KEY`]
+fn read_properties<T>(transport: &mut T) -> Result<BTreeMap<String, String>>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    transport.send(Request::PropertyGet(GetRequest {
+        key: DOLPHIN_KEY.to_string(),
+    }))?;
+
+    let mut properties = BTreeMap::new();
+
+    loop {
+        let response = transport.receive_raw()?;
+        let has_next = response.has_next;
+
+        let GetResponse { key, value } = Response::try_from(response)?.try_into()?;
+        trace!(key, value, "dolphin property");
+        properties.insert(key, value);
+
+        if !has_next {
+            break;
+        }
+    }
+
+    Ok(properties)
+}
+
+/// Builds the stats from `dolphin.*` properties, `None` if there are none
+fn parse(properties: &BTreeMap<String, String>) -> Result<Option<DolphinStats>> {
+    let mut stats = DolphinStats::default();
+    let mut found = false;
+
+    for (key, value) in properties {
+        let Some(name) = key
+            .strip_prefix(DOLPHIN_KEY)
+            .and_then(|name| name.strip_prefix('.'))
+        else {
+            continue;
+        };
+
+        let value = value.trim();
+        match name {
+            "level" => stats.level = number(value)?,
+            "xp" | "icounter" => stats.xp = number(value)?,
+            "xp_to_level_up" => stats.xp_to_level_up = number(value)?,
+            "butthurt" => stats.butthurt = number(value)?,
+            "timestamp" => stats.timestamp = number(value)?,
+            // Newer firmwares may add values, which are not worth failing over
+            _ => continue,
+        }
+        found = true;
+    }
+
+    Ok(found.then_some(stats))
+}
+
+fn number<N: std::str::FromStr>(value: &str) -> Result<N> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidRpcPayload("dolphin property is not a number"))
+}
+
+/// Whether an error means the firmware does not know the `dolphin` property
+fn is_unsupported(error: &Error) -> bool {
+    matches!(
+        error.rpc_status(),
+        Some(CommandStatus::ErrorNotImplemented | CommandStatus::ErrorInvalidParameters)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_dolphin_properties() {
+        let stats = parse(&properties(&[
+            ("dolphin.level", "2"),
+            ("dolphin.xp", "450"),
+            ("dolphin.xp_to_level_up", "1550"),
+            ("dolphin.butthurt", "4"),
+            ("dolphin.timestamp", "1700000000"),
+            ("dolphin.mood", "happy"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            stats,
+            Some(DolphinStats {
+                level: 2,
+                xp: 450,
+                xp_to_level_up: 1550,
+                butthurt: 4,
+                timestamp: 1_700_000_000,
+            })
+        );
+
+        assert_eq!(
+            parse(&properties(&[("devinfo.model", "F7")])).unwrap(),
+            None
+        );
+        assert!(parse(&properties(&[("dolphin.level", "high")])).is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn unsupported_firmware_gives_none() {
+        let mut flipper = crate::testing::EmulatedFlipper::new();
+
+        assert_eq!(stats(&mut flipper).unwrap(), None);
+    }
+}
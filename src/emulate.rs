@@ -0,0 +1,212 @@
+//! NFC and RFID emulation from host-generated files
+//!
+//! The NFC and 125 kHz RFID apps start emulating right away when they are opened with the path of
+//! a saved card. [`emulate`] uploads a `.nfc` or `.rfid` file, opens the matching app on it and
+//! returns an [`Emulation`] guard that closes the app when dropped.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::emulate::{CardKind, emulate};
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let card = "Filetype: Flipper RFID key\nVersion: 1\nKey type: EM4100\nData: 01 23 45 67 89\n";
+//! let emulation = emulate(&mut rpc, CardKind::Rfid, "badge", card)?;
+//!
+//! std::thread::sleep(std::time::Duration::from_secs(30));
+//! emulation.stop()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::logging::{debug, warn};
+
+use crate::fs::{DB_LFRFID, DB_NFC, FsCreateDir, FsWrite};
+use crate::proto::app::{AppExitRequest, StartRequest};
+use crate::transport::CommandIndex;
+use crate::transport::Transport;
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// The kind of card to emulate, which decides the app and the file type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CardKind {
+    /// 13.56 MHz NFC, a `.nfc` file opened in the NFC app
+    Nfc,
+    /// 125 kHz RFID, a `.rfid` file opened in the 125 kHz RFID app
+    Rfid,
+}
+
+impl CardKind {
+    /// Name the app is started by
+    pub fn app_name(self) -> &'static str {
+        match self {
+            Self::Nfc => "NFC",
+            Self::Rfid => "125 kHz RFID",
+        }
+    }
+
+    /// Directory the app keeps its cards in
+    pub fn dir(self) -> &'static str {
+        match self {
+            Self::Nfc => DB_NFC,
+            Self::Rfid => DB_LFRFID,
+        }
+    }
+
+    /// File extension of a saved card, with the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Nfc => ".nfc",
+            Self::Rfid => ".rfid",
+        }
+    }
+}
+
+/// A running emulation. Dropping it closes the app; use [`Emulation::stop`] to see errors.
+#[derive(Debug)]
+pub struct Emulation<'a, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    transport: &'a mut T,
+    path: String,
+    running: bool,
+}
+
+/// Uploads `contents` as the card `name` and starts emulating it.
+///
+/// The card is saved to the app's directory, so it also shows up in the app's list of saved
+/// cards. The extension is added to `name` if missing.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error if `name` is empty or contains a `/`,
+/// or the device's error if the upload or the app start fails, e.g. because another app is
+/// running.
+pub fn emulate<'a, T>(
+    transport: &'a mut T,
+    kind: CardKind,
+    name: &str,
+    contents: impl AsRef<[u8]>,
+) -> Result<Emulation<'a, T>>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    if name.is_empty() || name.contains('/') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "card name must be a non-empty file name",
+        )
+        .into());
+    }
+
+    let mut path = format!("{}/{name}", kind.dir());
+    if !path.ends_with(kind.extension()) {
+        path.push_str(kind.extension());
+    }
+
+    transport.fs_create_dir(kind.dir())?;
+    transport.fs_write(
+        &path,
+        contents,
+        #[cfg(feature = "fs-write-progress-mpsc")]
+        None,
+    )?;
+
+    transport.send_and_receive(Request::AppStart(StartRequest {
+        name: kind.app_name().to_string(),
+        args: path.clone(),
+    }))?;
+    debug!(path, "emulation started");
+
+    Ok(Emulation {
+        transport,
+        path,
+        running: true,
+    })
+}
+
+impl<T> Emulation<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Path of the uploaded card on the device
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Closes the app, ending the emulation
+    pub fn stop(mut self) -> Result<()> {
+        self.running = false;
+
+        self.exit()
+    }
+
+    fn exit(&mut self) -> Result<()> {
+        self.transport
+            .send_and_receive(Request::AppExit(AppExitRequest {}))?;
+        debug!(path = self.path, "emulation stopped");
+
+        Ok(())
+    }
+}
+
+impl<T> Drop for Emulation<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        if self.running {
+            if let Err(_e) = self.exit() {
+                warn!(error = %_e, "failed to stop emulation");
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn uploads_and_stops_on_drop() {
+        let mut flipper = EmulatedFlipper::new();
+
+        {
+            let emulation = emulate(&mut flipper, CardKind::Nfc, "door", "Filetype: NFC").unwrap();
+            assert_eq!(emulation.path(), "/ext/nfc/door.nfc");
+        }
+
+        assert_eq!(
+            flipper.file("/ext/nfc/door.nfc"),
+            Some(&b"Filetype: NFC"[..])
+        );
+        assert_eq!(flipper.running_app(), None);
+
+        let emulation = emulate(&mut flipper, CardKind::Rfid, "badge.rfid", "").unwrap();
+        std::mem::forget(emulation);
+        assert_eq!(
+            flipper.running_app(),
+            Some(("125 kHz RFID", "/ext/lfrfid/badge.rfid"))
+        );
+    }
+
+    #[test]
+    fn rejects_paths_as_names() {
+        let mut flipper = EmulatedFlipper::new();
+
+        assert!(emulate(&mut flipper, CardKind::Nfc, "../x", "").is_err());
+        assert!(flipper.requests().is_empty());
+    }
+}
@@ -7,6 +7,7 @@ use thiserror::Error;
 /// Global error type for all rpc and io errors
 pub enum Error {
     #[error("io: {0}")]
+    #[cfg(feature = "std")]
     /// An IO error, based on std::io::Error
     Io(#[from] std::io::Error),
 
@@ -20,6 +21,23 @@ pub enum Error {
     /// A Serialport error, based on serialport::Error
     Serialport(#[from] serialport::Error),
 
+    #[error("serial port {port} is in use: {hint}")]
+    #[cfg(feature = "transport-serial")]
+    /// The port is held by another program, e.g. qFlipper or lab.flipper.net in a browser.
+    /// See [`crate::transport::serial::wait_for_port_free`].
+    PortInUse {
+        /// The port that could not be opened
+        port: String,
+        /// What to do about it
+        hint: &'static str,
+    },
+
+    #[error("serial port is locked by process {0}")]
+    #[cfg(feature = "transport-serial")]
+    /// Another process using this crate holds the lock on the port. See
+    /// [`crate::transport::serial::lock`].
+    PortLockedByPid(u32),
+
     #[error("prost: decode: {0}")]
     #[cfg(feature = "proto")]
     /// A protobuf decode error, based on prost::DecodeError
@@ -30,6 +48,22 @@ pub enum Error {
     /// A protobuf encode error, based on prost::EncodeError
     ProtoEncode(#[from] prost::EncodeError),
 
+    #[error("invalid frame: {0}")]
+    #[cfg(feature = "proto")]
+    /// A length-delimited frame that can't be split off the byte stream.
+    InvalidFrame(&'static str),
+
+    #[error("frame too large: {0} bytes")]
+    #[cfg(feature = "proto")]
+    /// A length prefix above the transport's maximum message length, most likely a corrupted
+    /// varint. Nothing is allocated for it.
+    FrameTooLarge(usize),
+
+    #[error("device rebooted: the rpc session is gone, reconnect")]
+    /// The device started over, e.g. after a firmware assert, and dropped the RPC session.
+    /// Transports that detect this fail every later call with this error too.
+    DeviceRebooted,
+
     #[error("invalid command status value: {0}")]
     /// A command status integer that is not defined by the Flipper protobuf schema.
     InvalidCommandStatus(i32),
@@ -55,11 +89,352 @@ pub enum Error {
         actual: &'static str,
     },
 
+    #[error("checksum mismatch for {path}: device has {expected}, read {actual}")]
+    #[cfg(feature = "fs-read-verified")]
+    /// The MD5 of downloaded data differs from the one the device calculated
+    ChecksumMismatch {
+        /// Path of the file on the device
+        path: String,
+        /// MD5 calculated by the device, as lowercase hex
+        expected: String,
+        /// MD5 of the downloaded data, as lowercase hex
+        actual: String,
+    },
+
+    #[error("update package: {0}")]
+    #[cfg(feature = "update")]
+    /// An update package failed validation or was rejected by the device
+    InvalidBundle(#[from] crate::update::BundleError),
+
+    #[error("json: {0}")]
+    #[cfg(any(feature = "fs-write-json", feature = "fs-manifest"))]
+    /// A value could not be serialized to or parsed from JSON, based on serde_json::Error
+    Json(#[from] serde_json::Error),
+
+    #[error("toml: {0}")]
+    #[cfg(feature = "fs-write-toml")]
+    /// A value could not be serialized to TOML, based on toml::ser::Error
+    Toml(#[from] toml::ser::Error),
+
+    #[error("script: {0}")]
+    #[cfg(feature = "scripting")]
+    /// A script failed to compile or threw an error, see [`crate::scripting`]
+    Script(String),
+
+    #[error("{op} (op {op_id}): {error}")]
+    #[cfg(feature = "std")]
+    /// A failed filesystem operation, tagged with the `op_id` its log events carry. Use
+    /// [`Error::root`] to match on the underlying error.
+    Operation {
+        /// The operation, e.g. `fs_write`
+        op: &'static str,
+        /// The id of the operation's log span
+        op_id: u64,
+        /// The error the operation failed with
+        #[source]
+        error: Box<Error>,
+    },
+
     #[error("mpsc: {0}")]
-    #[cfg(feature = "fs-progress-mpsc")]
+    #[cfg(any(feature = "fs-read-progress-mpsc", feature = "fs-write-progress-mpsc"))]
     /// MPSC Error in the storage module when using progress-mpsc
     MpscSend(#[from] std::sync::mpsc::SendError<usize>),
 }
 
 /// Result type based on error::Error
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The category of an [`Error`](enum@Error), see [`Error::kind`]. Like [`std::io::ErrorKind`],
+/// more kinds may be added, so matches need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The device did not answer in time
+    Timeout,
+    /// The operation was aborted or cancelled
+    Interrupted,
+    /// The file, directory, app or port does not exist
+    NotFound,
+    /// The file or directory already exists
+    AlreadyExists,
+    /// The device refused the operation, e.g. while it is locked
+    PermissionDenied,
+    /// The device or the port is busy with something else
+    Busy,
+    /// The device rejected a parameter of the request, e.g. an invalid file name
+    InvalidInput,
+    /// The firmware does not implement the request
+    Unsupported,
+    /// The device rebooted and the session is gone
+    Disconnected,
+    /// A message could not be framed, encoded or decoded, or was not the expected one
+    Protocol,
+    /// Downloaded data does not match the device's checksum
+    Checksum,
+    /// Any other error reported by the device
+    Device,
+    /// An error of the port or the OS
+    Io,
+    /// Anything else
+    Other,
+}
+
+impl Error {
+    /// The category of the error, so callers can match on it without destructuring the nested
+    /// rpc error enums
+    ///
+    /// ```
+    /// use flipper_rpc::error::{Error, ErrorKind};
+    ///
+    /// assert_eq!(Error::DeviceRebooted.kind(), ErrorKind::Disconnected);
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Operation { error, .. } => error.kind(),
+            #[cfg(feature = "std")]
+            Error::Io(error) => match error.kind() {
+                std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+                std::io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+                _ => ErrorKind::Io,
+            },
+            #[cfg(feature = "easy-rpc")]
+            Error::Rpc(error) => rpc_kind(error),
+            #[cfg(feature = "transport-serial")]
+            Error::Serialport(error) => match error.kind() {
+                serialport::ErrorKind::NoDevice => ErrorKind::NotFound,
+                _ => ErrorKind::Io,
+            },
+            #[cfg(feature = "transport-serial")]
+            Error::PortInUse { .. } | Error::PortLockedByPid(_) => ErrorKind::Busy,
+            #[cfg(feature = "proto")]
+            Error::ProtoDecode(_)
+            | Error::ProtoEncode(_)
+            | Error::InvalidFrame(_)
+            | Error::FrameTooLarge(_) => ErrorKind::Protocol,
+            Error::DeviceRebooted => ErrorKind::Disconnected,
+            Error::InvalidCommandStatus(_)
+            | Error::InvalidStorageFileType(_)
+            | Error::UnsupportedRpcContent
+            | Error::InvalidRpcPayload(_)
+            | Error::UnexpectedResponse { .. } => ErrorKind::Protocol,
+            #[cfg(feature = "fs-read-verified")]
+            Error::ChecksumMismatch { .. } => ErrorKind::Checksum,
+            #[cfg(feature = "update")]
+            Error::InvalidBundle(crate::update::BundleError::Read { .. }) => ErrorKind::Io,
+            #[cfg(feature = "update")]
+            Error::InvalidBundle(_) => ErrorKind::InvalidInput,
+            #[cfg(any(feature = "fs-write-json", feature = "fs-manifest"))]
+            Error::Json(_) => ErrorKind::InvalidInput,
+            #[cfg(feature = "fs-write-toml")]
+            Error::Toml(_) => ErrorKind::InvalidInput,
+            #[cfg(feature = "scripting")]
+            Error::Script(_) => ErrorKind::Other,
+            #[cfg(any(feature = "fs-read-progress-mpsc", feature = "fs-write-progress-mpsc"))]
+            Error::MpscSend(_) => ErrorKind::Other,
+        }
+    }
+
+    /// The error without the operation context of [`Error::Operation`]
+    pub fn root(&self) -> &Error {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Operation { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// Like [`Error::root`], by value
+    pub fn into_root(self) -> Error {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Operation { error, .. } => error.into_root(),
+            error => error,
+        }
+    }
+
+    /// The id of the failed operation, as carried by its log events
+    pub fn op_id(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Operation { op_id, .. } => Some(*op_id),
+            _ => None,
+        }
+    }
+
+    /// The status the device answered with, if this is an rpc error. Looks through
+    /// [`Error::Operation`] and the content snapshot, so callers can match on the status alone.
+    ///
+    /// ```
+    /// use flipper_rpc::error::Error;
+    /// use flipper_rpc::proto::CommandStatus;
+    /// use flipper_rpc::rpc::error::StorageError;
+    ///
+    /// let error = Error::from(flipper_rpc::rpc::error::Error::from(StorageError::NotFound));
+    /// assert_eq!(error.rpc_status(), Some(CommandStatus::ErrorStorageNotExist));
+    /// assert_eq!(Error::DeviceRebooted.rpc_status(), None);
+    /// ```
+    #[cfg(feature = "easy-rpc")]
+    pub fn rpc_status(&self) -> Option<crate::proto::CommandStatus> {
+        match self.root() {
+            Error::Rpc(error) => Some(error.status()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "easy-rpc")]
+fn rpc_kind(error: &crate::rpc::error::Error) -> ErrorKind {
+    use crate::rpc::error::{ApplicationError, CommandError, Error as RpcError, StorageError};
+
+    match error.root() {
+        RpcError::CommandError(CommandError::Busy)
+        | RpcError::StorageError(StorageError::AlreadyOpen) => ErrorKind::Busy,
+        RpcError::CommandError(CommandError::NotImplemented)
+        | RpcError::StorageError(StorageError::NotImplemented) => ErrorKind::Unsupported,
+        RpcError::CommandError(CommandError::InvalidParameters)
+        | RpcError::StorageError(StorageError::InvalidParameter | StorageError::InvalidName)
+        | RpcError::GPIOError(_) => ErrorKind::InvalidInput,
+        RpcError::CommandError(CommandError::Decode) => ErrorKind::Protocol,
+        RpcError::CommandError(CommandError::ContinuousCommandInterrupted) => {
+            ErrorKind::Interrupted
+        }
+        RpcError::StorageError(StorageError::NotFound) => ErrorKind::NotFound,
+        RpcError::StorageError(StorageError::AlreadyExists) => ErrorKind::AlreadyExists,
+        RpcError::StorageError(StorageError::PermissionDenied)
+        | RpcError::ApplicationError(ApplicationError::SystemLocked) => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Device,
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error {
+    /// The closest [`std::io::ErrorKind`], used when converting into a [`std::io::Error`].
+    /// Device errors map by meaning, e.g. a missing file to [`std::io::ErrorKind::NotFound`],
+    /// and malformed messages to [`std::io::ErrorKind::InvalidData`].
+    pub fn io_kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+
+        match self {
+            Error::Operation { error, .. } => error.io_kind(),
+            Error::Io(error) => error.kind(),
+            #[cfg(feature = "easy-rpc")]
+            Error::Rpc(error) => error.io_kind(),
+            #[cfg(feature = "transport-serial")]
+            Error::Serialport(error) => match error.kind() {
+                serialport::ErrorKind::NoDevice => ErrorKind::NotFound,
+                serialport::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+                serialport::ErrorKind::Io(kind) => kind,
+                serialport::ErrorKind::Unknown => ErrorKind::Other,
+            },
+            #[cfg(feature = "transport-serial")]
+            Error::PortInUse { .. } | Error::PortLockedByPid(_) => ErrorKind::ResourceBusy,
+            #[cfg(feature = "proto")]
+            Error::ProtoDecode(_) | Error::InvalidFrame(_) | Error::FrameTooLarge(_) => {
+                ErrorKind::InvalidData
+            }
+            #[cfg(feature = "proto")]
+            Error::ProtoEncode(_) => ErrorKind::InvalidInput,
+            Error::DeviceRebooted => ErrorKind::ConnectionReset,
+            Error::InvalidCommandStatus(_)
+            | Error::InvalidStorageFileType(_)
+            | Error::UnsupportedRpcContent
+            | Error::InvalidRpcPayload(_)
+            | Error::UnexpectedResponse { .. } => ErrorKind::InvalidData,
+            #[cfg(feature = "fs-read-verified")]
+            Error::ChecksumMismatch { .. } => ErrorKind::InvalidData,
+            #[cfg(feature = "update")]
+            Error::InvalidBundle(crate::update::BundleError::Read { source, .. }) => source.kind(),
+            #[cfg(feature = "update")]
+            Error::InvalidBundle(crate::update::BundleError::MissingManifest(_))
+            | Error::InvalidBundle(crate::update::BundleError::MissingFile { .. }) => {
+                ErrorKind::NotFound
+            }
+            #[cfg(feature = "update")]
+            Error::InvalidBundle(_) => ErrorKind::InvalidData,
+            #[cfg(any(feature = "fs-write-json", feature = "fs-manifest"))]
+            Error::Json(_) => ErrorKind::InvalidInput,
+            #[cfg(feature = "fs-write-toml")]
+            Error::Toml(_) => ErrorKind::InvalidInput,
+            #[cfg(feature = "scripting")]
+            Error::Script(_) => ErrorKind::Other,
+            #[cfg(any(feature = "fs-read-progress-mpsc", feature = "fs-write-progress-mpsc"))]
+            Error::MpscSend(_) => ErrorKind::BrokenPipe,
+        }
+    }
+}
+
+/// Converts into an io error of kind [`Error::io_kind`], for `std::io::Read`/`Write` adapters
+/// and other code that expects io errors. Io errors are returned as they are; any other error is
+/// kept as the source and can be recovered with [`std::io::Error::downcast`].
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => error,
+            error => std::io::Error::new(error.io_kind(), error),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_into_io_errors() {
+        let error = std::io::Error::from(Error::DeviceRebooted);
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+        assert!(matches!(
+            error.downcast::<Error>(),
+            Ok(Error::DeviceRebooted)
+        ));
+
+        let timeout = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        let error = std::io::Error::from(Error::from(timeout));
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(error.get_ref().is_none());
+    }
+
+    #[test]
+    fn kinds_flatten_nested_errors() {
+        let timeout = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert_eq!(Error::from(timeout).kind(), ErrorKind::Timeout);
+        #[cfg(feature = "proto")]
+        assert_eq!(
+            Error::InvalidFrame("bad varint").kind(),
+            ErrorKind::Protocol
+        );
+
+        #[cfg(feature = "easy-rpc")]
+        {
+            use crate::rpc::error::{CommandError, StorageError};
+
+            let missing = Error::from(crate::rpc::error::Error::from(StorageError::NotFound));
+            assert_eq!(missing.kind(), ErrorKind::NotFound);
+            let busy = Error::from(crate::rpc::error::Error::from(CommandError::Busy));
+            assert_eq!(busy.kind(), ErrorKind::Busy);
+        }
+    }
+
+    #[cfg(feature = "easy-rpc")]
+    #[test]
+    fn storage_errors_keep_their_meaning() {
+        use crate::rpc::error::StorageError;
+
+        for (storage, kind) in [
+            (StorageError::NotFound, std::io::ErrorKind::NotFound),
+            (
+                StorageError::PermissionDenied,
+                std::io::ErrorKind::PermissionDenied,
+            ),
+            (
+                StorageError::AlreadyExists,
+                std::io::ErrorKind::AlreadyExists,
+            ),
+        ] {
+            let error = Error::from(crate::rpc::error::Error::from(storage));
+            assert_eq!(std::io::Error::from(error).kind(), kind);
+        }
+    }
+}
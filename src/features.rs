@@ -0,0 +1,75 @@
+//! Compile-time checks of the feature graph
+//!
+//! `Cargo.toml` already enables every dependency listed here. The checks turn a broken edit of
+//! the feature list, or features set by hand with `--cfg`, into one error naming the missing
+//! feature instead of dozens of unresolved imports.
+
+/// Fails the build if `$feature` is enabled without each of the following features
+macro_rules! requires {
+    ($($feature:literal => [$($dep:literal),+ $(,)?]),* $(,)?) => {
+        $($(
+            #[cfg(all(feature = $feature, not(feature = $dep)))]
+            compile_error!(concat!("feature `", $feature, "` requires feature `", $dep, "`"));
+        )+)*
+    };
+}
+
+requires! {
+    "easy-rpc" => ["proto"],
+    "script" => ["easy-rpc"],
+    "async" => ["proto", "std"],
+    "transport-any" => ["proto", "std"],
+    "transport-serial" => ["transport-any", "easy-rpc"],
+    "transport-serial-optimized" => ["transport-serial"],
+    "transport-serial-optimized-large-stack-limit" => ["transport-serial-optimized"],
+
+    "fs-any" => ["easy-rpc", "transport-any"],
+    "fs-read" => ["fs-any"],
+    "fs-read-metadata" => ["fs-read"],
+    "fs-read-progress-mpsc" => ["fs-read-metadata"],
+    "fs-read-verified" => ["fs-read", "fs-md5"],
+    "fs-read-digests" => ["fs-read"],
+    "fs-write" => ["fs-any"],
+    "fs-write-progress-mpsc" => ["fs-write"],
+    "fs-write-json" => ["fs-write"],
+    "fs-write-toml" => ["fs-write"],
+    "fs-readdir" => ["fs-any"],
+    "fs-remove" => ["fs-any"],
+    "fs-createdir" => ["fs-any"],
+    "fs-metadata" => ["fs-any"],
+    "fs-timestamp" => ["fs-any"],
+    "fs-tempfile" => ["fs-remove"],
+    "fs-cache" => ["fs-readdir", "fs-metadata", "fs-write", "fs-remove", "fs-createdir"],
+    "fs-usage" => ["fs-readdir"],
+    "fs-manifest" => ["fs-readdir"],
+    "fs-md5" => ["fs-any"],
+    "fs-tar-extract" => ["fs-any"],
+    "fs-backend" => ["fs-read", "fs-write", "fs-readdir", "fs-createdir", "fs-remove", "fs-md5"],
+    "fs-upload-dir" => ["fs-write", "fs-createdir", "fs-remove", "fs-tar-extract"],
+    "cli-fallback" => ["fs-read", "fs-write", "transport-serial"],
+    "subghz" => ["transport-serial"],
+    "infrared" => ["transport-serial"],
+    "apps" => ["transport-serial"],
+    "cli-info" => ["transport-serial"],
+    "diagnostics" => ["fs-backend"],
+    "bin" => ["diagnostics", "fs-read-digests", "fs-readdir", "serde", "transport-serial-optimized"],
+
+    "app" => ["easy-rpc", "transport-any"],
+    "desktop" => ["easy-rpc", "transport-any"],
+    "system" => ["easy-rpc", "transport-any"],
+    "system-log" => ["system", "transport-serial"],
+    "system-name" => ["system", "settings"],
+    "session" => ["system"],
+    "flipper" => ["system"],
+    "remote-control" => ["easy-rpc", "transport-any"],
+    "gui-macro" => ["remote-control"],
+    "scripting" => ["flipper", "fs-read", "fs-write", "fs-readdir", "fs-createdir", "fs-remove", "fs-metadata", "fs-md5"],
+    "dolphin" => ["easy-rpc", "transport-any"],
+    "settings" => ["fs-read", "fs-write"],
+    "emulate" => ["fs-write", "fs-createdir"],
+    "update" => ["settings", "fs-createdir", "fs-remove", "fs-tar-extract"],
+    "notes" => ["fs-read", "fs-write", "fs-createdir"],
+    "testing" => ["easy-rpc", "transport-any"],
+    "tracing" => ["std"],
+    "serde" => ["std"],
+}
@@ -0,0 +1,121 @@
+//! Firmware fork detection
+//!
+//! Custom firmwares extend the protocol and change CLI behavior, so helpers that depend on such
+//! quirks need to know which fork they are talking to. [`FirmwareFlavor`] classifies it from
+//! what the device reports: the `firmware.origin.fork` device info key where the firmware sets
+//! it, otherwise the version string, whose prefix names the fork (`mntm-008`, `unlshd-080`).
+
+use alloc::string::{String, ToString};
+
+/// Firmware fork a device runs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FirmwareFlavor {
+    /// flipperdevices/flipperzero-firmware
+    Official,
+    /// DarkFlippers/unleashed-firmware
+    Unleashed,
+    /// Next-Flip/Momentum-Firmware
+    Momentum,
+    /// RogueMaster/flipperzero-firmware-wPlugins
+    RogueMaster,
+    /// Flipper-XFW/Xtreme-Firmware, discontinued in favor of Momentum
+    Xtreme,
+    /// A fork that reported this name but is not known to the crate
+    Other(String),
+    /// Nothing the device reported identifies the fork
+    Unknown,
+}
+
+/// Version prefixes of the known forks, lowercase
+const VERSION_PREFIXES: [(&str, FirmwareFlavor); 4] = [
+    ("mntm", FirmwareFlavor::Momentum),
+    ("unlshd", FirmwareFlavor::Unleashed),
+    ("rm", FirmwareFlavor::RogueMaster),
+    ("xfw", FirmwareFlavor::Xtreme),
+];
+
+impl FirmwareFlavor {
+    /// Classifies the fork from the `firmware.origin.fork` device info value, if the firmware
+    /// reports one, and the firmware version
+    pub fn detect(origin_fork: Option<&str>, version: &str) -> Self {
+        match origin_fork.map(str::trim).filter(|fork| !fork.is_empty()) {
+            Some(fork) => Self::from_fork_name(fork),
+            None => Self::from_version(version),
+        }
+    }
+
+    /// Classifies a fork name as reported in `firmware.origin.fork`, e.g. `Official` or
+    /// `Momentum`
+    pub fn from_fork_name(fork: &str) -> Self {
+        match fork.trim().to_ascii_lowercase().as_str() {
+            "official" => Self::Official,
+            "unleashed" => Self::Unleashed,
+            "momentum" => Self::Momentum,
+            "roguemaster" => Self::RogueMaster,
+            "xtreme" => Self::Xtreme,
+            _ => Self::Other(fork.trim().to_string()),
+        }
+    }
+
+    /// Guesses the fork from a version string: plain version numbers such as `1.0.1` are
+    /// official, custom firmwares prefix theirs with a tag
+    pub fn from_version(version: &str) -> Self {
+        let version = version.trim().to_ascii_lowercase();
+
+        if version.starts_with(|c: char| c.is_ascii_digit()) {
+            return Self::Official;
+        }
+
+        VERSION_PREFIXES
+            .iter()
+            .find(|(prefix, _)| version.starts_with(prefix))
+            .map_or(Self::Unknown, |(_, flavor)| flavor.clone())
+    }
+
+    /// Display name of the fork, `None` if it is [`FirmwareFlavor::Unknown`]
+    pub fn name(&self) -> Option<&str> {
+        Some(match self {
+            Self::Official => "Official",
+            Self::Unleashed => "Unleashed",
+            Self::Momentum => "Momentum",
+            Self::RogueMaster => "RogueMaster",
+            Self::Xtreme => "Xtreme",
+            Self::Other(name) => name,
+            Self::Unknown => return None,
+        })
+    }
+
+    /// Whether the device runs a custom firmware rather than the official one
+    pub fn is_custom(&self) -> bool {
+        !matches!(self, Self::Official | Self::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_forks_by_origin_and_version() {
+        assert_eq!(
+            FirmwareFlavor::detect(Some("Momentum"), "1.0.0"),
+            FirmwareFlavor::Momentum
+        );
+        assert_eq!(
+            FirmwareFlavor::detect(None, "unlshd-080"),
+            FirmwareFlavor::Unleashed
+        );
+        assert_eq!(
+            FirmwareFlavor::detect(Some(" "), "1.0.1"),
+            FirmwareFlavor::Official
+        );
+        assert_eq!(
+            FirmwareFlavor::detect(Some("MyFork"), "dev"),
+            FirmwareFlavor::Other("MyFork".to_string())
+        );
+        assert_eq!(FirmwareFlavor::from_version("dev"), FirmwareFlavor::Unknown);
+        assert!(FirmwareFlavor::RogueMaster.is_custom());
+        assert_eq!(FirmwareFlavor::Unknown.name(), None);
+    }
+}
@@ -0,0 +1,449 @@
+//! One entry point for every capability
+//!
+//! The helpers in this crate are spread over several traits and modules. [`FlipperZero`] wraps a
+//! transport and groups them into namespaces, so the API can be explored through completion:
+//! [`FlipperZero::fs`], [`FlipperZero::system`], [`FlipperZero::gui`] and [`FlipperZero::gpio`].
+//! The namespaced methods are thin wrappers; the underlying traits and functions remain
+//! available.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::FlipperZero;
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut flipper = FlipperZero::new(SerialRpcTransport::new("/dev/ttyACM0")?);
+//!
+//! let (major, minor) = flipper.system().protobuf_version()?;
+//! println!("protobuf {major}.{minor}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+#[cfg(feature = "fs-any")]
+use std::{borrow::Cow, path::Path};
+
+#[cfg(feature = "fs-any")]
+use crate::fs;
+use crate::proto::gpio::{
+    GetOtgMode, GetOtgModeResponse, GetPinMode, GetPinModeResponse, GpioInputPull, GpioOtgMode,
+    GpioPin, GpioPinMode, ReadPin, ReadPinResponse, SetInputPull, SetOtgMode, SetPinMode, WritePin,
+};
+use crate::proto::gui::{
+    InputKey, InputType, ScreenFrame, SendInputEventRequest, StartScreenStreamRequest,
+    StopScreenStreamRequest,
+};
+use crate::proto::system::{DateTime, ProtobufVersionResponse, reboot_request::RebootMode};
+use crate::rpc::{req::Request, res::Response};
+use crate::system::{self, Confirmation};
+use crate::transport::CommandIndex;
+use crate::transport::Transport;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// A Flipper Zero reached over the transport `T`
+#[derive(Debug)]
+pub struct FlipperZero<T> {
+    transport: T,
+}
+
+impl<T> FlipperZero<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Wraps `transport`. Any RPC transport works, including sessions and throttled transports.
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Storage operations
+    #[cfg(feature = "fs-any")]
+    pub fn fs(&mut self) -> Fs<'_, T> {
+        Fs(&mut self.transport)
+    }
+
+    /// Device information, time, and power
+    pub fn system(&mut self) -> System<'_, T> {
+        System(&mut self.transport)
+    }
+
+    /// Screen streaming and input emulation
+    pub fn gui(&mut self) -> Gui<'_, T> {
+        Gui(&mut self.transport)
+    }
+
+    /// GPIO pins and 5V output
+    pub fn gpio(&mut self) -> Gpio<'_, T> {
+        Gpio(&mut self.transport)
+    }
+}
+
+impl<T> FlipperZero<T> {
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the wrapped transport
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns the wrapped transport
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+/// Storage operations, see [`FlipperZero::fs`]
+#[cfg(feature = "fs-any")]
+#[derive(Debug)]
+pub struct Fs<'a, T>(&'a mut T);
+
+#[cfg(feature = "fs-any")]
+impl<T> Fs<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Reads a file, see [`fs::FsRead::fs_read`]
+    #[cfg(feature = "fs-read")]
+    pub fn read(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+        fs::FsRead::fs_read(self.0, path)
+    }
+
+    /// Reads a file with explicit options, see [`fs::FsRead::fs_read_with`]
+    #[cfg(feature = "fs-read")]
+    pub fn read_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: fs::ReadOptions,
+    ) -> Result<(Cow<'static, [u8]>, fs::TransferSummary)> {
+        fs::FsRead::fs_read_with(self.0, path, options)
+    }
+
+    /// Reads a file and checks it against the device's MD5, see [`fs::FsRead::fs_read_verified`]
+    #[cfg(feature = "fs-read-verified")]
+    pub fn read_verified(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+        fs::FsRead::fs_read_verified(self.0, path)
+    }
+
+    /// Reads a UTF-8 file, see [`fs::FsRead::fs_read_to_string`]
+    #[cfg(feature = "fs-read")]
+    pub fn read_to_string(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, str>> {
+        fs::FsRead::fs_read_to_string(self.0, path)
+    }
+
+    /// Writes a file, replacing it if it exists, see [`fs::FsWrite::fs_write_with`]
+    #[cfg(feature = "fs-write")]
+    pub fn write(&mut self, path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> Result<()> {
+        self.write_with(path, data, fs::WriteOptions::default())?;
+
+        Ok(())
+    }
+
+    /// Writes a string as UTF-8, see [`fs::FsWrite::fs_write_str`]
+    #[cfg(feature = "fs-write")]
+    pub fn write_str(&mut self, path: impl AsRef<Path>, contents: &str) -> Result<()> {
+        fs::FsWrite::fs_write_str(self.0, path, contents)
+    }
+
+    /// Writes a file with explicit options, see [`fs::FsWrite::fs_write_with`]
+    #[cfg(feature = "fs-write")]
+    pub fn write_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        data: impl AsRef<[u8]>,
+        options: fs::WriteOptions,
+    ) -> Result<fs::TransferSummary> {
+        fs::FsWrite::fs_write_with(self.0, path, data, options)
+    }
+
+    /// Lists a directory, see [`fs::FsReadDir::fs_read_dir`]
+    #[cfg(feature = "fs-readdir")]
+    pub fn read_dir(
+        &mut self,
+        path: impl AsRef<Path>,
+        include_md5: bool,
+    ) -> Result<impl Iterator<Item = crate::rpc::res::ReadDirItem>> {
+        fs::FsReadDir::fs_read_dir(self.0, path, include_md5)
+    }
+
+    /// Lists a directory entry by entry, see [`fs::FsReadDir::fs_read_dir_with`]
+    #[cfg(feature = "fs-readdir")]
+    pub fn read_dir_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: fs::ReadDirOptions,
+        on_entry: impl FnMut(crate::rpc::res::ReadDirItem) -> std::ops::ControlFlow<()>,
+    ) -> Result<usize> {
+        fs::FsReadDir::fs_read_dir_with(self.0, path, options, on_entry)
+    }
+
+    /// Creates a directory, see [`fs::FsCreateDir::fs_create_dir`]
+    #[cfg(feature = "fs-createdir")]
+    pub fn create_dir(&mut self, path: impl AsRef<Path>) -> Result<bool> {
+        fs::FsCreateDir::fs_create_dir(self.0, path)
+    }
+
+    /// Creates a directory and its missing parents, see [`fs::FsCreateDir::fs_create_dir_all`]
+    #[cfg(feature = "fs-createdir")]
+    pub fn create_dir_all(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        fs::FsCreateDir::fs_create_dir_all(self.0, path)
+    }
+
+    /// Removes a file or directory, see [`fs::FsRemove::fs_remove`]
+    #[cfg(feature = "fs-remove")]
+    pub fn remove(&mut self, path: impl AsRef<Path>, recursive: bool) -> Result<()> {
+        fs::FsRemove::fs_remove(self.0, path, recursive)
+    }
+
+    /// Gets the size of a file, see [`fs::FsMetadata::fs_metadata`]
+    #[cfg(feature = "fs-metadata")]
+    pub fn metadata(&mut self, path: impl AsRef<Path>) -> Result<u32> {
+        fs::FsMetadata::fs_metadata(self.0, path)
+    }
+
+    /// Hashes a file on the device, see [`fs::FsMd5::fs_md5`]
+    #[cfg(feature = "fs-md5")]
+    pub fn md5(&mut self, path: impl AsRef<Path>) -> Result<String> {
+        fs::FsMd5::fs_md5(self.0, path)
+    }
+
+    /// Extracts a tar archive on the device, see [`fs::FsTarExtract::fs_extract_tar`]
+    #[cfg(feature = "fs-tar-extract")]
+    pub fn extract_tar(&mut self, path: impl AsRef<Path>, out: impl AsRef<Path>) -> Result<()> {
+        fs::FsTarExtract::fs_extract_tar(self.0, path, out)
+    }
+}
+
+/// Device information, time, and power, see [`FlipperZero::system`]
+#[derive(Debug)]
+pub struct System<'a, T>(&'a mut T);
+
+impl<T> System<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Sends `data` and returns what the device echoes back
+    pub fn ping(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.0.send_and_receive(Request::Ping(data))?.try_into()
+    }
+
+    /// Reads the device info map, see [`system::device_info`]
+    pub fn device_info(&mut self) -> Result<BTreeMap<String, String>> {
+        system::device_info(self.0)
+    }
+
+    /// The RPC protobuf schema version as `(major, minor)`
+    pub fn protobuf_version(&mut self) -> Result<(u32, u32)> {
+        let ProtobufVersionResponse { major, minor } = self
+            .0
+            .send_and_receive(Request::SystemProtobufVersion)?
+            .try_into()?;
+
+        Ok((major, minor))
+    }
+
+    /// The device's current date and time
+    pub fn datetime(&mut self) -> Result<Option<DateTime>> {
+        self.0
+            .send_and_receive(Request::SystemGetDatetime)?
+            .try_into()
+    }
+
+    /// Sets the device's date and time
+    pub fn set_datetime(&mut self, datetime: DateTime) -> Result<()> {
+        self.0
+            .send_and_receive(Request::SystemSetDatetime(datetime))?;
+
+        Ok(())
+    }
+
+    /// Flashes the screen, vibrates and beeps
+    pub fn play_alert(&mut self) -> Result<()> {
+        self.0.send_and_receive(Request::PlayAvAlert)?;
+
+        Ok(())
+    }
+
+    /// Reboots the device. It does not answer, so the transport has to be reopened afterwards.
+    pub fn reboot(&mut self, mode: RebootMode) -> Result<()> {
+        self.0.send(Request::Reboot(mode))
+    }
+
+    /// Erases all user data, see [`system::factory_reset`]
+    pub fn factory_reset(&mut self, confirmation: Confirmation) -> Result<()> {
+        system::factory_reset(self.0, confirmation)
+    }
+}
+
+/// Screen streaming and input emulation, see [`FlipperZero::gui`]
+#[derive(Debug)]
+pub struct Gui<'a, T>(&'a mut T);
+
+impl<T> Gui<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Starts streaming the screen. Frames arrive as [`Response::GuiScreenFrame`]; read them with
+    /// [`Gui::next_frame`].
+    pub fn start_screen_stream(&mut self) -> Result<()> {
+        self.0
+            .send_and_receive(Request::GuiStartScreenStream(StartScreenStreamRequest {}))?;
+
+        Ok(())
+    }
+
+    /// Waits for the next streamed screen frame, skipping other messages
+    pub fn next_frame(&mut self) -> Result<ScreenFrame> {
+        loop {
+            if let Response::GuiScreenFrame(frame) = self.0.receive()? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Stops streaming the screen, discarding frames that were already in flight
+    pub fn stop_screen_stream(&mut self) -> Result<()> {
+        self.0
+            .send(Request::GuiStopScreenStream(StopScreenStreamRequest {}))?;
+
+        while let Response::GuiScreenFrame(_) = self.0.receive()? {}
+
+        Ok(())
+    }
+
+    /// Emulates a hardware input event
+    pub fn send_input(&mut self, key: InputKey, r#type: InputType) -> Result<()> {
+        self.0
+            .send_and_receive(Request::GuiSendInputEvent(SendInputEventRequest {
+                key: key.into(),
+                r#type: r#type.into(),
+            }))?;
+
+        Ok(())
+    }
+
+    /// Emulates a short button press: press, short, release
+    pub fn press(&mut self, key: InputKey) -> Result<()> {
+        for r#type in [InputType::Press, InputType::Short, InputType::Release] {
+            self.send_input(key, r#type)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// GPIO pins and 5V output, see [`FlipperZero::gpio`]
+#[derive(Debug)]
+pub struct Gpio<'a, T>(&'a mut T);
+
+impl<T> Gpio<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Configures `pin` as an input or output
+    pub fn set_pin_mode(&mut self, pin: GpioPin, mode: GpioPinMode) -> Result<()> {
+        self.0
+            .send_and_receive(Request::GpioSetPinMode(SetPinMode {
+                pin: pin.into(),
+                mode: mode.into(),
+            }))?;
+
+        Ok(())
+    }
+
+    /// The current mode of `pin`
+    pub fn pin_mode(&mut self, pin: GpioPin) -> Result<GpioPinMode> {
+        let GetPinModeResponse { mode } = self
+            .0
+            .send_and_receive(Request::GpioGetPinMode(GetPinMode { pin: pin.into() }))?
+            .try_into()?;
+
+        GpioPinMode::try_from(mode).map_err(|_| Error::InvalidRpcPayload("unknown gpio pin mode"))
+    }
+
+    /// Sets the pull resistor of an input pin
+    pub fn set_input_pull(&mut self, pin: GpioPin, pull: GpioInputPull) -> Result<()> {
+        self.0
+            .send_and_receive(Request::GpioSetInputPull(SetInputPull {
+                pin: pin.into(),
+                pull_mode: pull.into(),
+            }))?;
+
+        Ok(())
+    }
+
+    /// Reads the level of an input pin
+    pub fn read(&mut self, pin: GpioPin) -> Result<bool> {
+        let ReadPinResponse { value } = self
+            .0
+            .send_and_receive(Request::GpioReadPin(ReadPin { pin: pin.into() }))?
+            .try_into()?;
+
+        Ok(value != 0)
+    }
+
+    /// Drives an output pin high or low
+    pub fn write(&mut self, pin: GpioPin, high: bool) -> Result<()> {
+        self.0.send_and_receive(Request::GpioWritePin(WritePin {
+            pin: pin.into(),
+            value: high.into(),
+        }))?;
+
+        Ok(())
+    }
+
+    /// Whether 5V is enabled on the GPIO header
+    pub fn otg(&mut self) -> Result<bool> {
+        let GetOtgModeResponse { mode } = self
+            .0
+            .send_and_receive(Request::GpioGetOtgMode(GetOtgMode {}))?
+            .try_into()?;
+
+        Ok(mode == i32::from(GpioOtgMode::On))
+    }
+
+    /// Enables or disables 5V on the GPIO header
+    pub fn set_otg(&mut self, enabled: bool) -> Result<()> {
+        let mode = if enabled {
+            GpioOtgMode::On
+        } else {
+            GpioOtgMode::Off
+        };
+
+        self.0
+            .send_and_receive(Request::GpioSetOtgMode(SetOtgMode { mode: mode.into() }))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "fs-write", feature = "fs-read"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn namespaces_reach_the_device() {
+        let mut flipper = FlipperZero::new(EmulatedFlipper::new());
+
+        assert_eq!(flipper.system().ping(vec![4, 2]).unwrap(), [4, 2]);
+        assert_eq!(
+            flipper.system().protobuf_version().unwrap(),
+            crate::testing::PROTOBUF_VERSION
+        );
+
+        flipper.fs().write("/ext/hello.txt", b"hello").unwrap();
+        assert_eq!(
+            flipper.fs().read_to_string("/ext/hello.txt").unwrap(),
+            "hello"
+        );
+    }
+}
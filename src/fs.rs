@@ -28,6 +28,9 @@ pub const DB_SUBGHZ: &str = "/ext/subghz";
 /// Path to the NFC database stored on external storage.
 pub const DB_NFC: &str = "/ext/nfc";
 
+/// Path to the documents directory on external storage.
+pub const DB_DOCS: &str = "/ext/docs";
+
 /// Path to the update directory on external storage.
 pub const UPDATE_DIR: &str = "/ext/update";
 
@@ -39,12 +42,17 @@ pub use create_dir::FsCreateDir;
 #[cfg(feature = "fs-read")]
 pub mod read;
 #[cfg(feature = "fs-read")]
-pub use read::FsRead;
+pub use read::{FsRead, ReadOptions, ReadOutcome};
+
+#[cfg(feature = "fs-read")]
+pub mod reader;
+#[cfg(feature = "fs-read")]
+pub use reader::FileReader;
 
 #[cfg(feature = "fs-readdir")]
 pub mod read_dir;
 #[cfg(feature = "fs-readdir")]
-pub use read_dir::FsReadDir;
+pub use read_dir::{FsReadDir, ReadDirOptions};
 
 #[cfg(feature = "fs-remove")]
 pub mod remove;
@@ -55,13 +63,23 @@ pub use remove::FsRemove;
 #[cfg(feature = "fs-write")]
 pub mod write;
 #[cfg(feature = "fs-write")]
-pub use write::FsWrite;
+pub use write::{FsWrite, WriteOptions};
+
+#[cfg(feature = "fs-write")]
+pub mod writer;
+#[cfg(feature = "fs-write")]
+pub use writer::FileWriter;
 
 #[cfg(feature = "fs-metadata")]
 pub mod metadata;
 #[cfg(feature = "fs-metadata")]
 pub use metadata::FsMetadata;
 
+#[cfg(feature = "fs-cache")]
+pub mod cache;
+#[cfg(feature = "fs-cache")]
+pub use cache::CachedFs;
+
 #[cfg(feature = "fs-md5")]
 pub mod md5;
 #[cfg(feature = "fs-md5")]
@@ -72,6 +90,55 @@ pub mod tar;
 #[cfg(feature = "fs-tar-extract")]
 pub use tar::FsTarExtract;
 
+#[cfg(feature = "fs-tempfile")]
+pub mod temp;
+#[cfg(feature = "fs-tempfile")]
+pub use temp::TempFile;
+
+#[cfg(feature = "fs-usage")]
+pub mod usage;
+#[cfg(feature = "fs-usage")]
+pub use usage::{DirUsage, FsUsage};
+
+#[cfg(feature = "fs-manifest")]
+pub mod manifest;
+#[cfg(feature = "fs-manifest")]
+pub use manifest::{FsSnapshot, Manifest, ManifestDiff, ManifestEntry};
+
+#[cfg(feature = "fs-timestamp")]
+pub mod timestamp;
+#[cfg(feature = "fs-timestamp")]
+pub use timestamp::FsTimestamp;
+
+#[cfg(feature = "fs-backend")]
+pub mod backend;
+#[cfg(feature = "fs-backend")]
+pub use backend::{MemoryBackend, StorageBackend};
+
+#[cfg(feature = "fs-upload-dir")]
+pub mod upload_dir;
+#[cfg(feature = "fs-upload-dir")]
+pub use upload_dir::{BatchMode, FsUploadDir, UploadDirOptions, UploadDirSummary};
+
+#[cfg(feature = "cli-fallback")]
+pub mod compat;
+#[cfg(feature = "cli-fallback")]
+pub use compat::FsCompat;
+
 pub mod helpers;
 
-pub(crate) const CHUNK_SIZE: usize = 1024;
+#[cfg(any(
+    feature = "fs-usage",
+    feature = "fs-manifest",
+    feature = "fs-upload-dir"
+))]
+mod walk;
+
+#[cfg(any(feature = "fs-read", feature = "fs-write"))]
+pub mod transfer;
+#[cfg(any(feature = "fs-read", feature = "fs-write"))]
+pub use transfer::{Digests, ProgressCallback, TransferProgress, TransferSummary};
+
+/// Bytes per chunk of a write chain, unless [`WriteOptions::chunk_size`] sets another size
+#[cfg(feature = "fs-write")]
+pub const CHUNK_SIZE: usize = 1024;
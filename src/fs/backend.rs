@@ -0,0 +1,284 @@
+//! Storage backends
+//!
+//! [`StorageBackend`] is the set of file operations higher-level helpers such as
+//! [`storage_selftest`](crate::diagnostics::storage_selftest) are written against. Every
+//! transport is a backend, talking to the device through the fs traits, and [`MemoryBackend`]
+//! keeps the files in memory instead, for unit tests of that logic and for dry runs of tools
+//! that would otherwise change a device.
+//!
+//! Backends report failures with the same errors the device would, e.g.
+//! [`StorageError::NotFound`] for a missing file, so callers handle both alike.
+
+use std::collections::BTreeMap;
+
+use crate::fs::{FsCreateDir, FsMd5, FsRead, FsReadDir, FsRemove, FsWrite};
+use crate::rpc::error::StorageError;
+use crate::rpc::res::ReadDirItem;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// File operations on a Flipper-like storage. Paths are absolute and `/` separated.
+pub trait StorageBackend {
+    /// Reads the whole file at `path`
+    fn read(&mut self, path: &str) -> Result<Vec<u8>>;
+
+    /// Writes `data` to `path`, replacing the file if it exists. The parent must exist.
+    fn write(&mut self, path: &str, data: &[u8]) -> Result<()>;
+
+    /// Lists the directory at `path`
+    fn list(&mut self, path: &str) -> Result<Vec<ReadDirItem>>;
+
+    /// Creates a directory. Returns whether it already existed.
+    fn create_dir(&mut self, path: &str) -> Result<bool>;
+
+    /// Removes a file or directory. Non-empty directories need `recursive`.
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()>;
+
+    /// MD5 of the file at `path`, as lowercase hex
+    fn md5(&mut self, path: &str) -> Result<String>;
+}
+
+impl<T> StorageBackend for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        Ok(self.fs_read(path)?.into_owned())
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        self.fs_write(
+            path,
+            data,
+            #[cfg(feature = "fs-write-progress-mpsc")]
+            None,
+        )
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<ReadDirItem>> {
+        Ok(self.fs_read_dir(path, false)?.collect())
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<bool> {
+        self.fs_create_dir(path)
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        self.fs_remove(path, recursive)
+    }
+
+    fn md5(&mut self, path: &str) -> Result<String> {
+        Ok(self.fs_md5(path)?.to_ascii_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// A [`StorageBackend`] in memory, starting with empty `/ext` and `/int` directories
+#[derive(Debug, Clone)]
+pub struct MemoryBackend {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::from([
+                ("/".to_string(), Entry::Dir),
+                ("/ext".to_string(), Entry::Dir),
+                ("/int".to_string(), Entry::Dir),
+            ]),
+        }
+    }
+}
+
+impl MemoryBackend {
+    /// An empty storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Contents of the file at `path`, if there is one
+    pub fn file(&self, path: &str) -> Option<&[u8]> {
+        match self.entries.get(&normalize(path)) {
+            Some(Entry::File(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Whether `path` is a directory
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.entries.get(&normalize(path)) == Some(&Entry::Dir)
+    }
+
+    /// Paths of all files and directories, sorted
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    fn require_parent(&self, path: &str) -> Result<()> {
+        match parent(path).and_then(|parent| self.entries.get(parent)) {
+            Some(Entry::Dir) => Ok(()),
+            Some(Entry::File(_)) => Err(storage_error(StorageError::InvalidName)),
+            None => Err(storage_error(StorageError::NotFound)),
+        }
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        match self.entries.get(&normalize(path)) {
+            Some(Entry::File(data)) => Ok(data.clone()),
+            Some(Entry::Dir) => Err(storage_error(StorageError::InvalidName)),
+            None => Err(storage_error(StorageError::NotFound)),
+        }
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let path = normalize(path);
+        self.require_parent(&path)?;
+
+        if self.entries.get(&path) == Some(&Entry::Dir) {
+            return Err(storage_error(StorageError::InvalidName));
+        }
+        self.entries.insert(path, Entry::File(data.to_vec()));
+
+        Ok(())
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<ReadDirItem>> {
+        let path = normalize(path);
+        match self.entries.get(&path) {
+            Some(Entry::Dir) => {}
+            Some(Entry::File(_)) => return Err(storage_error(StorageError::InvalidName)),
+            None => return Err(storage_error(StorageError::NotFound)),
+        }
+
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(key, _)| *key != "/" && parent(key) == Some(path.as_str()))
+            .map(|(key, entry)| {
+                let name = key.rsplit('/').next().unwrap_or_default().to_string();
+
+                match entry {
+                    Entry::Dir => ReadDirItem::Dir(name),
+                    Entry::File(data) => ReadDirItem::File(name, data.len() as u32, None),
+                }
+            })
+            .collect())
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<bool> {
+        let path = normalize(path);
+
+        match self.entries.get(&path) {
+            Some(Entry::Dir) => Ok(true),
+            Some(Entry::File(_)) => Err(storage_error(StorageError::AlreadyExists)),
+            None => {
+                self.require_parent(&path)?;
+                self.entries.insert(path, Entry::Dir);
+
+                Ok(false)
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        let path = normalize(path);
+        if !self.entries.contains_key(&path) {
+            return Err(storage_error(StorageError::NotFound));
+        }
+
+        let has_children = self
+            .entries
+            .keys()
+            .any(|key| parent(key) == Some(path.as_str()));
+        if has_children && !recursive {
+            return Err(storage_error(StorageError::DirectoryNotEmpty));
+        }
+
+        self.entries.retain(|key, _| !is_within(key, &path));
+
+        Ok(())
+    }
+
+    fn md5(&mut self, path: &str) -> Result<String> {
+        Ok(format!("{:x}", md5::compute(self.read(path)?)))
+    }
+}
+
+fn storage_error(error: StorageError) -> Error {
+    crate::rpc::error::Error::from(error).into()
+}
+
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn parent(path: &str) -> Option<&str> {
+    match path.rsplit_once('/')? {
+        ("", "") => None,
+        ("", _) => Some("/"),
+        (parent, _) => Some(parent),
+    }
+}
+
+/// Whether `path` is `root` or lies below it
+fn is_within(path: &str, root: &str) -> bool {
+    path == root
+        || path
+            .strip_prefix(root)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_backend_behaves_like_the_device() {
+        let mut storage = MemoryBackend::new();
+
+        assert!(!storage.create_dir("/ext/apps").unwrap());
+        assert!(storage.create_dir("/ext/apps/").unwrap());
+        storage.write("/ext/apps/a.fap", b"fap").unwrap();
+
+        assert_eq!(storage.read("/ext/apps/a.fap").unwrap(), b"fap");
+        assert_eq!(
+            storage.md5("/ext/apps/a.fap").unwrap(),
+            format!("{:x}", md5::compute(b"fap"))
+        );
+        assert_eq!(
+            storage.list("/ext").unwrap(),
+            [ReadDirItem::Dir("apps".to_string())]
+        );
+
+        let error = storage.write("/ext/missing/a.fap", b"").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Rpc(crate::rpc::error::Error::StorageError(
+                StorageError::NotFound
+            ))
+        ));
+        assert!(storage.remove("/ext/apps", false).is_err());
+
+        storage.remove("/ext/apps", true).unwrap();
+        assert!(!storage.is_dir("/ext/apps"));
+        assert_eq!(storage.file("/ext/apps/a.fap"), None);
+    }
+}
@@ -0,0 +1,420 @@
+//! CachedFs module. Host-side caching of directory listings and file sizes.
+//!
+//! File browsers list the same directories over and over while the user moves around, and every
+//! listing is a round trip that grows with the directory. [`CachedFs`] remembers the results of
+//! [`FsReadDir`] and [`FsMetadata`] and answers repeated calls without asking the device.
+//!
+//! The cache only sees changes made through the same handle: writes, removals and new
+//! directories drop the affected entries. Anything else, the device's own apps, another
+//! connection or requests sent through [`CachedFs::get_mut`], leaves stale entries behind until
+//! [`CachedFs::invalidate`] or [`CachedFs::clear`] is called.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::fs::{CachedFs, FsReadDir, FsRemove};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut fs = CachedFs::new(SerialRpcTransport::new("/dev/ttyACM0")?);
+//!
+//! let first = fs.fs_read_dir("/ext/nfc", false)?.count();
+//! // Answered from the cache
+//! let again = fs.fs_read_dir("/ext/nfc", false)?.count();
+//! assert_eq!(first, again);
+//!
+//! // Drops the cached listing of /ext/nfc, the next one asks the device
+//! fs.fs_remove("/ext/nfc/old.nfc", false)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use crate::logging::trace;
+
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::{
+    FsCreateDir, FsMetadata, FsReadDir, FsRemove, FsWrite, ReadDirOptions, TransferSummary,
+    WriteOptions,
+};
+use crate::rpc::res::ReadDirItem;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto::{self},
+    transport::TransportRaw,
+};
+
+/// A listing and whether it has MD5s
+#[derive(Debug)]
+struct Listing {
+    include_md5: bool,
+    items: Vec<ReadDirItem>,
+}
+
+/// A transport wrapper that caches directory listings and file sizes, see the
+/// [module docs](self)
+#[derive(Debug)]
+pub struct CachedFs<T> {
+    inner: T,
+    listings: HashMap<String, Listing>,
+    sizes: HashMap<String, u32>,
+}
+
+impl<T> CachedFs<T> {
+    /// Wraps `inner` with an empty cache
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            listings: HashMap::new(),
+            sizes: HashMap::new(),
+        }
+    }
+
+    /// Drops everything cached for `path` and below it, and the listing of its parent
+    pub fn invalidate(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = normalize(os_str_to_str(path.as_ref().as_os_str())?);
+        self.invalidate_normalized(path);
+
+        Ok(())
+    }
+
+    /// Drops the whole cache
+    pub fn clear(&mut self) {
+        self.listings.clear();
+        self.sizes.clear();
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport. Changes made through it are not
+    /// seen by the cache.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the inner transport, dropping the cache
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn invalidate_normalized(&mut self, path: &str) {
+        trace!(path, "invalidating cache");
+
+        let below = |key: &String| is_below(key, path);
+        self.listings.retain(|key, _| !below(key));
+        self.sizes.retain(|key, _| !below(key));
+
+        if let Some((parent, _)) = split(path) {
+            self.listings.remove(parent);
+        }
+    }
+}
+
+impl<T> CachedFs<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// [`FsWrite::fs_write_with`] on the wrapped transport, dropping the cached size of `path`
+    /// and the listing of its directory
+    pub fn fs_write_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        data: impl AsRef<[u8]>,
+        options: WriteOptions,
+    ) -> Result<TransferSummary> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        // Dropped even on failure, a failed upload can leave a partial file behind
+        let result = self.inner.fs_write_with(path, data, options);
+        self.invalidate_normalized(normalize(path));
+
+        result
+    }
+
+    /// [`FsWrite::fs_write_str`] on the wrapped transport, see [`CachedFs::fs_write_with`]
+    pub fn fs_write_str(&mut self, path: impl AsRef<Path>, contents: &str) -> Result<()> {
+        self.fs_write_with(path, contents, WriteOptions::default())?;
+
+        Ok(())
+    }
+
+    /// Lists `path` through the wrapped transport and caches the full listing
+    fn fetch(&mut self, path: &str, options: &ReadDirOptions) -> Result<()> {
+        let mut items = Vec::new();
+        self.inner.fs_read_dir_with(
+            path,
+            ReadDirOptions {
+                filter_max_size: 0,
+                ..options.clone()
+            },
+            |item| {
+                items.push(item);
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        trace!(path, count = items.len(), "listing cached");
+        self.listings.insert(
+            path.to_string(),
+            Listing {
+                include_md5: options.include_md5,
+                items,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+impl<T> FsReadDir for CachedFs<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Lists `path` from the cache, asking the device on a miss. A listing cached without MD5s
+    /// does not answer a call with `include_md5`.
+    fn fs_read_dir(
+        &mut self,
+        path: impl AsRef<Path>,
+        include_md5: bool,
+    ) -> Result<impl Iterator<Item = ReadDirItem>> {
+        let mut items = Vec::new();
+
+        self.fs_read_dir_with(
+            path,
+            ReadDirOptions::default().include_md5(include_md5),
+            |item| {
+                items.push(item);
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        Ok(items.into_iter())
+    }
+
+    /// Like [`CachedFs::fs_read_dir`]. The cache keeps whole listings, so a miss always lists
+    /// every file and `filter_max_size` is applied on the host.
+    fn fs_read_dir_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: ReadDirOptions,
+        mut on_entry: impl FnMut(ReadDirItem) -> ControlFlow<()>,
+    ) -> Result<usize> {
+        let path = normalize(os_str_to_str(path.as_ref().as_os_str())?);
+
+        let hit = self
+            .listings
+            .get(path)
+            .is_some_and(|listing| listing.include_md5 || !options.include_md5);
+        if !hit {
+            self.fetch(path, &options)?;
+        }
+
+        let Some(listing) = self.listings.get(path) else {
+            unreachable!("fetched above");
+        };
+
+        let mut count = 0;
+        for item in &listing.items {
+            let item = match item {
+                ReadDirItem::File(_, size, _)
+                    if options.filter_max_size != 0 && *size > options.filter_max_size =>
+                {
+                    continue;
+                }
+                ReadDirItem::File(name, size, md5) => ReadDirItem::File(
+                    name.clone(),
+                    *size,
+                    md5.clone().filter(|_| options.include_md5),
+                ),
+                ReadDirItem::Dir(name) => ReadDirItem::Dir(name.clone()),
+            };
+
+            count += 1;
+            if on_entry(item).is_break() {
+                break;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+impl<T> FsMetadata for CachedFs<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Returns the size of a file from the cache, or from a cached listing of its directory,
+    /// asking the device on a miss
+    fn fs_metadata(&mut self, path: impl AsRef<Path>) -> Result<u32> {
+        let path = normalize(os_str_to_str(path.as_ref().as_os_str())?);
+
+        if let Some(&size) = self.sizes.get(path) {
+            return Ok(size);
+        }
+
+        let listed = split(path).and_then(|(parent, name)| {
+            self.listings
+                .get(parent)?
+                .items
+                .iter()
+                .find_map(|item| match item {
+                    ReadDirItem::File(file, size, _) if file == name => Some(*size),
+                    _ => None,
+                })
+        });
+
+        let size = match listed {
+            Some(size) => size,
+            None => self.inner.fs_metadata(path)?,
+        };
+        self.sizes.insert(path.to_string(), size);
+
+        Ok(size)
+    }
+}
+
+impl<T> FsRemove for CachedFs<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Removes through the wrapped transport and drops the cache for `path` and below it
+    fn fs_remove(&mut self, path: impl AsRef<Path>, recursive: bool) -> Result<()> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        // A failed recursive removal may have removed part of the tree
+        let result = self.inner.fs_remove(path, recursive);
+        self.invalidate_normalized(normalize(path));
+
+        result
+    }
+}
+
+impl<T> FsCreateDir for CachedFs<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn fs_create_dir(&mut self, path: impl AsRef<Path>) -> Result<bool> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        let existed = self.inner.fs_create_dir(path)?;
+        if !existed {
+            self.invalidate_normalized(normalize(path));
+        }
+
+        Ok(existed)
+    }
+
+    fn fs_create_dir_all(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        let result = self.inner.fs_create_dir_all(path);
+
+        // Any of the parents may be new
+        let mut dir = normalize(path);
+        loop {
+            self.invalidate_normalized(dir);
+            match split(dir) {
+                Some((parent, _)) if parent != "/" => dir = parent,
+                _ => break,
+            }
+        }
+
+        result
+    }
+}
+
+/// Trims trailing slashes, the root stays `/`
+fn normalize(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+/// Splits a normalized path into its parent and name, `None` for the root
+fn split(path: &str) -> Option<(&str, &str)> {
+    match path.rsplit_once('/')? {
+        (_, "") => None,
+        ("", name) => Some(("/", name)),
+        (parent, name) => Some((parent, name)),
+    }
+}
+
+/// Whether `key` is `path` or inside it
+fn is_below(key: &str, path: &str) -> bool {
+    path == "/"
+        || key
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_paths() {
+        assert_eq!(split("/ext/nfc"), Some(("/ext", "nfc")));
+        assert_eq!(split("/ext"), Some(("/", "ext")));
+        assert_eq!(split("/"), None);
+        assert_eq!(normalize("/ext/"), "/ext");
+        assert!(is_below("/ext/nfc/a", "/ext/nfc"));
+        assert!(!is_below("/ext/nfcx", "/ext/nfc"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn answers_from_the_cache_until_changed() {
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/nfc/card.nfc", b"card".to_vec());
+        flipper.insert_file("/ext/nfc/assets/keys.dict", b"keys".to_vec());
+        let mut fs = CachedFs::new(flipper);
+
+        let first = fs.fs_read_dir("/ext/nfc/", false).unwrap().count();
+        let sent = fs.get_ref().requests().len();
+        assert_eq!(fs.fs_read_dir("/ext/nfc", false).unwrap().count(), first);
+        assert_eq!(fs.fs_metadata("/ext/nfc/card.nfc").unwrap(), 4);
+        assert_eq!(fs.get_ref().requests().len(), sent);
+
+        // MD5s were not cached, the device is asked
+        let md5 = fs
+            .fs_read_dir("/ext/nfc", true)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(
+            md5.iter()
+                .any(|item| matches!(item, ReadDirItem::File(_, _, Some(_))))
+        );
+        let sent = fs.get_ref().requests().len();
+        assert!(
+            fs.fs_read_dir("/ext/nfc", false)
+                .unwrap()
+                .all(|item| !matches!(item, ReadDirItem::File(_, _, Some(_))))
+        );
+        assert_eq!(fs.get_ref().requests().len(), sent);
+
+        fs.fs_write_str("/ext/nfc/card.nfc", "longer card").unwrap();
+        fs.fs_remove("/ext/nfc/assets", true).unwrap();
+        assert_eq!(fs.fs_metadata("/ext/nfc/card.nfc").unwrap(), 11);
+        assert_eq!(
+            fs.fs_read_dir("/ext/nfc", false)
+                .unwrap()
+                .collect::<Vec<_>>(),
+            [ReadDirItem::File("card.nfc".to_string(), 11, None)]
+        );
+
+        fs.fs_create_dir_all("/ext/nfc/a/b").unwrap();
+        assert_eq!(fs.fs_read_dir("/ext/nfc/a", false).unwrap().count(), 1);
+        assert_eq!(fs.fs_read_dir("/ext/nfc", false).unwrap().count(), 2);
+    }
+}
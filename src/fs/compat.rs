@@ -0,0 +1,71 @@
+//! FsCompat module. CLI fallbacks for firmwares without RPC storage commands.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use crate::logging::warn;
+
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::{FsRead, FsWrite};
+use crate::proto::CommandStatus;
+use crate::{
+    error::{Error, Result},
+    transport::serial::rpc::SerialRpcTransport,
+};
+
+/// Read/write traits that fall back to the text CLI when RPC storage commands are missing
+pub trait FsCompat {
+    /// Reads a file like [`FsRead::fs_read`], falling back to `storage read_chunks` over the CLI if
+    /// the firmware does not implement the RPC command.
+    fn fs_read_compat(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>>;
+
+    /// Writes a file like [`FsWrite::fs_write`], falling back to `storage write_chunk` over the CLI
+    /// if the firmware does not implement the RPC command.
+    fn fs_write_compat(&mut self, path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> Result<()>;
+}
+
+impl<const STACK_LIMIT: usize> FsCompat for SerialRpcTransport<STACK_LIMIT> {
+    fn fs_read_compat(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+        let path = path.as_ref();
+
+        match self.fs_read(path) {
+            Err(e) if is_not_implemented(&e) => {
+                warn!("rpc storage read not implemented, falling back to cli");
+
+                let path = os_str_to_str(path.as_os_str())?;
+                let data = self.with_cli(|cli| cli.storage_read(path))?;
+
+                Ok(data.into())
+            }
+            other => other,
+        }
+    }
+
+    fn fs_write_compat(&mut self, path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> Result<()> {
+        let path = path.as_ref();
+        let data = data.as_ref();
+
+        match self.fs_write(
+            path,
+            data,
+            #[cfg(feature = "fs-write-progress-mpsc")]
+            None,
+        ) {
+            Err(e) if is_not_implemented(&e) => {
+                warn!("rpc storage write not implemented, falling back to cli");
+
+                let path = os_str_to_str(path.as_os_str())?;
+                self.with_cli(|cli| cli.storage_write(path, data))
+            }
+            other => other,
+        }
+    }
+}
+
+/// Whether an error means the firmware lacks the requested RPC command
+fn is_not_implemented(error: &Error) -> bool {
+    matches!(
+        error.rpc_status(),
+        Some(CommandStatus::ErrorNotImplemented | CommandStatus::ErrorStorageNotImplemented)
+    )
+}
@@ -1,12 +1,13 @@
 //! FsCreateDir module
 
-use std::path::Path;
+use std::path::{Component, Path};
 
-use crate::logging::debug;
+use crate::logging::{debug, operation};
 
 use crate::fs::helpers::os_str_to_str;
+use crate::proto::CommandStatus;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto::{self},
@@ -18,6 +19,16 @@ use crate::{
 pub trait FsCreateDir {
     /// Creates a directory at a path. Returns weather the path existed. False = did not exist; True = Already existed.
     fn fs_create_dir(&mut self, path: impl AsRef<Path>) -> Result<bool>;
+
+    /// Creates a directory and all of its missing parents, like [`std::fs::create_dir_all`].
+    /// Directories that already exist, including ones created concurrently, are not an error.
+    /// The storage root (`/ext`, `/int`) is assumed to exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`std::io::ErrorKind::InvalidInput`] error, before creating anything, if the
+    /// path has a component other than the root and plain names, such as `..`.
+    fn fs_create_dir_all(&mut self, path: impl AsRef<Path>) -> Result<()>;
 }
 
 impl<T> FsCreateDir for T
@@ -26,17 +37,88 @@ where
 {
     #[doc(alias = "fs_mkdir")]
     fn fs_create_dir(&mut self, path: impl AsRef<Path>) -> Result<bool> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        operation("fs_create_dir", path, || {
+            debug!("creating directory at {path}");
+
+            match self.send_and_receive(Request::StorageMkdir(path.to_string())) {
+                Ok(_) => Ok(false),
+                Err(e) if e.rpc_status() == Some(CommandStatus::ErrorStorageExist) => Ok(true),
+
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    #[doc(alias = "fs_mkdir_p")]
+    fn fs_create_dir_all(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let absolute = path.has_root();
+
+        let names = path
+            .components()
+            .filter(|component| *component != Component::RootDir)
+            .map(|component| match component {
+                Component::Normal(name) => os_str_to_str(name),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "path component not allowed on the device: {}",
+                        path.display()
+                    ),
+                )
+                .into()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut current = String::new();
+        for (i, name) in names.into_iter().enumerate() {
+            if absolute || i > 0 {
+                current.push('/');
+            }
+            current.push_str(name);
+
+            // The first component of an absolute path is the storage, which cannot be created
+            if absolute && i == 0 {
+                continue;
+            }
 
-        debug!("creating directory at {path}");
+            self.fs_create_dir(&current)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn creates_missing_parents() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fs_create_dir("/ext/apps").unwrap();
+
+        flipper.fs_create_dir_all("/ext/apps/nfc/assets/").unwrap();
+        flipper.fs_create_dir_all("/ext/apps/nfc").unwrap();
+
+        assert!(flipper.is_dir("/ext/apps/nfc/assets"));
+        assert!(flipper.fs_create_dir_all("/nope/a").is_err());
+    }
 
-        match self.send_and_receive(Request::StorageMkdir(path)) {
-            Ok(_) => Ok(false),
-            Err(Error::Rpc(crate::rpc::error::Error::StorageError(
-                crate::rpc::error::StorageError::AlreadyExists,
-            ))) => Ok(true),
+    #[test]
+    fn rejects_relative_components() {
+        let mut flipper = EmulatedFlipper::new();
 
-            Err(e) => Err(e),
+        for path in ["/ext/apps/../nfc", "./ext/apps"] {
+            let error = flipper.fs_create_dir_all(path).unwrap_err();
+            assert!(
+                matches!(error.root(), Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput)
+            );
         }
+        assert!(!flipper.is_dir("/ext/apps"));
+        assert!(flipper.requests().is_empty());
     }
 }
@@ -10,3 +10,9 @@ pub fn os_str_to_str(path: &OsStr) -> Result<&str> {
         std::io::Error::new(std::io::ErrorKind::InvalidData, "Path is not UTF-8").into()
     })
 }
+
+/// The error returned by chained operations stopped through `TransportRaw::take_abort`
+#[cfg(any(feature = "fs-read", feature = "fs-readdir", feature = "fs-write"))]
+pub(crate) fn aborted() -> crate::error::Error {
+    std::io::Error::new(std::io::ErrorKind::Interrupted, "operation aborted").into()
+}
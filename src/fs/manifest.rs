@@ -0,0 +1,254 @@
+//! FsSnapshot module. Manifests of directory trees, for change detection without the content.
+//!
+//! [`FsSnapshot::fs_snapshot`] lists a tree with the device's MD5 of every file and returns a
+//! [`Manifest`]. Saved as JSON between runs, two manifests tell a backup tool which files were
+//! added, removed or changed with [`Manifest::diff`], so only those have to be downloaded.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::fs::{FsSnapshot, Manifest};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let last = Manifest::from_json(&std::fs::read_to_string("nfc.manifest.json")?)?;
+//! let now = rpc.fs_snapshot("/ext/nfc")?;
+//! for name in last.diff(&now).changed.keys() {
+//!     println!("{name} changed since the last backup");
+//! }
+//! std::fs::write("nfc.manifest.json", now.to_json()?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::logging::{debug, operation};
+
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::walk::{DeviceTree, Visit, walk};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto::{self},
+    transport::TransportRaw,
+};
+
+/// A file in a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Size in bytes
+    pub size: u64,
+    /// MD5 as lowercase hex, `None` if the firmware did not report it
+    pub md5: Option<String>,
+}
+
+/// The files below a directory, without their content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Full path of the directory the manifest was taken of
+    pub root: String,
+    /// Every file below the root, by path relative to it with `/` separators, e.g.
+    /// `assets/keys.dict`. Directories are only implied by the paths, empty ones are not kept.
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+/// What changed between two manifests, see [`Manifest::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestDiff {
+    /// Files only in the newer manifest
+    pub added: BTreeMap<String, ManifestEntry>,
+    /// Files only in the older manifest, with their old entry
+    pub removed: BTreeMap<String, ManifestEntry>,
+    /// Files in both whose size or MD5 differs, with their new entry
+    pub changed: BTreeMap<String, ManifestEntry>,
+}
+
+impl ManifestDiff {
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Manifest {
+    /// The changes from `self` to the newer `other`.
+    ///
+    /// A file counts as changed if its size differs, or if both manifests have its MD5 and they
+    /// differ. Without MD5s a change that keeps the size goes unnoticed.
+    pub fn diff(&self, other: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+
+        for (name, old) in &self.files {
+            match other.files.get(name) {
+                None => {
+                    diff.removed.insert(name.clone(), old.clone());
+                }
+                Some(new) if is_changed(old, new) => {
+                    diff.changed.insert(name.clone(), new.clone());
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (name, new) in &other.files {
+            if !self.files.contains_key(name) {
+                diff.added.insert(name.clone(), new.clone());
+            }
+        }
+
+        diff
+    }
+
+    /// Total size of the files in bytes
+    pub fn size(&self) -> u64 {
+        self.files.values().map(|entry| entry.size).sum()
+    }
+
+    /// Serializes the manifest to pretty-printed JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`] if serialization fails, which it does not for manifests built by
+    /// this crate.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a manifest written by [`Manifest::to_json`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Json`] if `json` is not a manifest.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+fn is_changed(old: &ManifestEntry, new: &ManifestEntry) -> bool {
+    match (&old.md5, &new.md5) {
+        (Some(old_md5), Some(new_md5)) if old_md5 != new_md5 => true,
+        _ => old.size != new.size,
+    }
+}
+
+/// Snapshot traits for flipper filesystem
+pub trait FsSnapshot {
+    /// Lists the tree at `path` with the MD5 of every file. Costs one listing round trip per
+    /// directory, and the device hashes every file, so large trees take a while.
+    fn fs_snapshot(&mut self, path: impl AsRef<Path>) -> Result<Manifest>;
+}
+
+impl<T> FsSnapshot for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn fs_snapshot(&mut self, path: impl AsRef<Path>) -> Result<Manifest> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+        let root = match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+
+        operation("fs_snapshot", root, || {
+            let mut manifest = Manifest {
+                root: root.to_string(),
+                files: BTreeMap::new(),
+            };
+            walk(&mut DeviceTree::new(self, root, true), &mut |visit| {
+                if let Visit::File(path, (size, md5)) = visit {
+                    manifest.files.insert(
+                        path.to_string(),
+                        ManifestEntry {
+                            size: u64::from(size),
+                            md5: md5.filter(|md5| !md5.is_empty()),
+                        },
+                    );
+                }
+
+                Ok(())
+            })?;
+            debug!(files = manifest.files.len(), "snapshot taken");
+
+            Ok(manifest)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, md5: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            size,
+            md5: md5.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn diff_sorts_files_into_sets() {
+        let old = Manifest {
+            root: "/ext".to_string(),
+            files: BTreeMap::from([
+                ("same".to_string(), entry(1, Some("a"))),
+                ("edited".to_string(), entry(1, Some("a"))),
+                ("resized".to_string(), entry(1, None)),
+                ("gone".to_string(), entry(1, None)),
+            ]),
+        };
+        let new = Manifest {
+            root: "/ext".to_string(),
+            files: BTreeMap::from([
+                ("same".to_string(), entry(1, Some("a"))),
+                ("edited".to_string(), entry(1, Some("b"))),
+                ("resized".to_string(), entry(2, Some("c"))),
+                ("new".to_string(), entry(3, None)),
+            ]),
+        };
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["new"]);
+        assert_eq!(diff.removed.keys().collect::<Vec<_>>(), ["gone"]);
+        assert_eq!(
+            diff.changed.keys().collect::<Vec<_>>(),
+            ["edited", "resized"]
+        );
+        assert_eq!(diff.changed["resized"], entry(2, Some("c")));
+        assert!(new.diff(&new).is_empty());
+
+        assert_eq!(Manifest::from_json(&new.to_json().unwrap()).unwrap(), new);
+        assert!(Manifest::from_json("{}").is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn snapshots_nested_trees() {
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/nfc/card.nfc", b"card".to_vec());
+        flipper.insert_file("/ext/nfc/assets/keys.dict", b"keys".to_vec());
+
+        let before = flipper.fs_snapshot("/ext/nfc/").unwrap();
+        assert_eq!(before.root, "/ext/nfc");
+        assert_eq!(
+            before.files["assets/keys.dict"],
+            entry(4, Some(&format!("{:x}", md5::compute(b"keys"))))
+        );
+        assert_eq!(before.size(), 8);
+
+        flipper.insert_file("/ext/nfc/card.nfc", b"CARD".to_vec());
+        flipper.insert_file("/ext/nfc/other.nfc", b"x".to_vec());
+        let diff = before.diff(&flipper.fs_snapshot("/ext/nfc").unwrap());
+        assert_eq!(diff.changed.keys().collect::<Vec<_>>(), ["card.nfc"]);
+        assert_eq!(diff.added.keys().collect::<Vec<_>>(), ["other.nfc"]);
+        assert!(diff.removed.is_empty());
+    }
+}
@@ -2,11 +2,11 @@
 
 use std::path::Path;
 
-use crate::logging::debug;
+use crate::logging::{debug, operation};
 
 use crate::fs::helpers::os_str_to_str;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto::{self},
@@ -25,16 +25,18 @@ where
     T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
 {
     fn fs_md5(&mut self, path: impl AsRef<Path>) -> Result<String> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
 
-        debug!(path, "MD5 request for");
+        operation("fs_md5", path, || {
+            debug!(path, "MD5 request for");
 
-        let response: String = self
-            .send_and_receive(Request::StorageMd5sum(path))?
-            .try_into()?;
+            let response: String = self
+                .send_and_receive(Request::StorageMd5sum(path.to_string()))?
+                .try_into()?;
 
-        debug!(response, "MD5");
+            debug!(response, "MD5");
 
-        Ok(response)
+            Ok(response)
+        })
     }
 }
@@ -2,11 +2,11 @@
 
 use std::path::Path;
 
-use crate::logging::{debug, trace};
+use crate::logging::{debug, operation, trace};
 
 use crate::fs::helpers::os_str_to_str;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto::{self},
@@ -27,18 +27,20 @@ where
 {
     #[doc(alias = "fs_stat")]
     fn fs_metadata(&mut self, path: impl AsRef<Path>) -> Result<u32> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
 
-        debug!("reading metadata for {path}");
+        operation("fs_metadata", path, || {
+            debug!("reading metadata for {path}");
 
-        let response: Option<u32> = self
-            .send_and_receive(Request::StorageMetadata(path))?
-            .try_into()?;
+            let response: Option<u32> = self
+                .send_and_receive(Request::StorageMetadata(path.to_string()))?
+                .try_into()?;
 
-        trace!("response collected");
+            trace!("response collected");
 
-        let size = response.ok_or_else(|| std::io::Error::other("Failed to read file"))?;
+            let size = response.ok_or_else(|| std::io::Error::other("Failed to read file"))?;
 
-        Ok(size)
+            Ok(size)
+        })
     }
 }
@@ -3,12 +3,19 @@
 use std::borrow::Cow;
 use std::path::Path;
 
-use crate::logging::debug;
+use prost::bytes::Bytes;
 
-use crate::fs::helpers::os_str_to_str;
+use crate::cancel::{CancelToken, is_cancelled};
+use crate::logging::{debug, operation, warn};
+
+use crate::fs::helpers::{aborted, os_str_to_str};
+use crate::fs::reader::{DEFAULT_PREFETCH, FileReader};
+use crate::fs::transfer::{ProgressCallback, RateMeter, TransferProgress, TransferSummary};
+use crate::proto::CommandStatus;
+use crate::proto::storage::ListRequest;
 use crate::rpc::res::Response;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto,
@@ -16,10 +23,79 @@ use crate::{
     transport::TransportRaw,
 };
 
+/// What [`FsRead::fs_read_file`] found at a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadOutcome {
+    /// The path is a file with these contents
+    File(Cow<'static, [u8]>),
+    /// The path is a directory
+    IsDirectory,
+}
+
+/// Options for [`FsRead::fs_read_with`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ReadOptions {
+    /// Called with the bytes received so far and the current rate after every chunk
+    pub on_progress: Option<ProgressCallback>,
+    /// Stops the read between chunks once cancelled
+    pub cancel: Option<CancelToken>,
+}
+
+impl ReadOptions {
+    /// Calls `f` with the progress and current rate after every chunk. The total is only known
+    /// with the `fs-read-metadata` feature.
+    pub fn on_progress(mut self, f: impl Fn(TransferProgress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(ProgressCallback::new(f));
+
+        self
+    }
+
+    /// Stops the read once `token` is cancelled. The rest of the chain is drained and the read
+    /// fails with an [`std::io::ErrorKind::Interrupted`] error.
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+
+        self
+    }
+}
+
 /// Read traits for flipper filesystem
 pub trait FsRead {
     /// Reads a file on the flipper zero from src
-    fn fs_read(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>>;
+    fn fs_read(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+        self.fs_read_with(path, ReadOptions::default())
+            .map(|(data, _)| data)
+    }
+
+    /// Like [`FsRead::fs_read`], with explicit [`ReadOptions`]. Also returns how much was
+    /// received and how fast.
+    fn fs_read_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: ReadOptions,
+    ) -> Result<(Cow<'static, [u8]>, TransferSummary)>;
+
+    /// Opens `path` for streaming through [`std::io::Read`], receiving chunks in the background
+    /// while the caller consumes the current one. See [`FileReader`].
+    fn fs_open_read(self, path: impl AsRef<Path>) -> Result<FileReader<Self>>
+    where
+        Self: Sized + Send + 'static;
+
+    /// Like [`FsRead::fs_read`], but reports a directory as [`ReadOutcome::IsDirectory`] instead
+    /// of an error, so it can be told apart from real read failures.
+    fn fs_read_file(&mut self, path: impl AsRef<Path>) -> Result<ReadOutcome> {
+        self.fs_read(path).map(ReadOutcome::File)
+    }
+
+    /// Like [`FsRead::fs_read`], then asks the device for the file's MD5 and checks the
+    /// downloaded data against it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ChecksumMismatch`] with both hashes if the data was corrupted on the way.
+    #[cfg(feature = "fs-read-verified")]
+    fn fs_read_verified(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>>;
 
     /// Reads to a string
     fn fs_read_to_string(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, str>> {
@@ -79,59 +155,256 @@ impl<T> FsRead for T
 where
     T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
 {
-    fn fs_read(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+    fn fs_read_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: ReadOptions,
+    ) -> Result<(Cow<'static, [u8]>, TransferSummary)> {
         // Convert the path to a string
         let path = os_str_to_str(path.as_ref().as_os_str())?;
 
-        // Optionally fetch metadata if the "fs-read-metadata" feature is enabled
-        #[cfg(feature = "fs-read-metadata")]
-        let size: Option<u32> = self
-            .send_and_receive(Request::StorageMetadata(path.to_string()))?
-            .try_into()?;
-
-        // Initialize buffer for storing the file contents
-        #[cfg(feature = "fs-read-metadata")]
-        let mut buf = match size {
-            Some(size) => Vec::with_capacity(size as usize), // Pre-allocate buffer if size is known
-            None => vec![],
-        };
-
-        #[cfg(not(feature = "fs-read-metadata"))]
-        let mut buf = vec![]; // Default to an empty buffer if metadata isn't fetched
-
-        debug!("init read chain");
-        // Send the initial request to start the read chain
-        self.send(Request::StorageRead(path.to_string()))?;
-
-        loop {
-            // Receive the next chunk of data (raw response to check for has_next flag)
-            let response = self.receive_raw()?;
-            debug!("read rpc chunk");
-
-            // Check if there are more chunks to read
-            let has_next = response.has_next;
-
-            // Convert the raw response into usable data (Cow<[u8]>)
-            let response: Option<Cow<'static, [u8]>> = Response::try_from(response)?.try_into()?;
-
-            match response {
-                // If no data was received, return an error
-                None => {
-                    return Err(std::io::Error::other("Failed to read file").into());
+        operation("fs_read", path, || {
+            // Optionally fetch metadata if the "fs-read-metadata" feature is enabled
+            #[cfg(feature = "fs-read-metadata")]
+            let size: Option<u32> = self
+                .send_and_receive(Request::StorageMetadata(path.to_string()))?
+                .try_into()?;
+
+            // Initialize buffer for storing the file contents
+            #[cfg(feature = "fs-read-metadata")]
+            let mut buf = match size {
+                Some(size) => Vec::with_capacity(size as usize), // Pre-allocate buffer if size is known
+                None => vec![],
+            };
+
+            #[cfg(not(feature = "fs-read-metadata"))]
+            let mut buf = vec![]; // Default to an empty buffer if metadata isn't fetched
+
+            #[cfg(feature = "fs-read-metadata")]
+            let mut meter = RateMeter::start(size.map(|size| size as usize));
+            #[cfg(not(feature = "fs-read-metadata"))]
+            let mut meter = RateMeter::start(None);
+
+            debug!("init read chain");
+            // Send the initial request to start the read chain
+            self.send(Request::StorageRead(path.to_string()))?;
+
+            #[cfg(feature = "fs-read-digests")]
+            let mut hasher = crate::fs::transfer::Hasher::new();
+
+            let mut abort = false;
+
+            loop {
+                // The device drives read chains, so an abort still has to drain the remaining
+                // chunks to keep the stream in sync
+                abort |= self.take_abort() || is_cancelled(options.cancel.as_ref());
+
+                // Receive the next chunk of data (raw response to check for has_next flag)
+                let response = self.receive_raw()?;
+                debug!("read rpc chunk");
+
+                // Check if there are more chunks to read
+                let has_next = response.has_next;
+
+                // Convert the raw response into usable data, a slice of the received message
+                let response: Option<Bytes> = Response::try_from(response)?.try_into()?;
+
+                match response {
+                    // If no data was received, return an error
+                    None => {
+                        return Err(std::io::Error::other("Failed to read file").into());
+                    }
+                    // Aborted, discard the rest of the chain
+                    Some(_) if abort => {}
+                    // Otherwise, add the data to the buffer
+                    Some(data) => {
+                        buf.extend_from_slice(data.as_ref());
+                        #[cfg(feature = "fs-read-digests")]
+                        hasher.update(data.as_ref());
+
+                        let progress = meter.record(data.len());
+                        if let Some(ref on_progress) = options.on_progress {
+                            on_progress.call(progress);
+                        }
+                    }
                 }
-                // Otherwise, add the data to the buffer
-                Some(data) => {
-                    buf.extend_from_slice(data.as_ref());
+
+                // If this is the last chunk, stop reading
+                if !has_next {
+                    break;
                 }
             }
 
-            // If this is the last chunk, stop reading
-            if !has_next {
-                break;
+            if abort {
+                warn!("read aborted");
+                return Err(aborted());
             }
+
+            let summary = meter.finish();
+            #[cfg(feature = "fs-read-digests")]
+            let summary = TransferSummary {
+                digests: Some(hasher.finish()),
+                ..summary
+            };
+
+            // Return the entire contents as a Cow<[u8]> (static lifetime)
+            Ok((buf.into(), summary))
+        })
+    }
+
+    fn fs_open_read(self, path: impl AsRef<Path>) -> Result<FileReader<Self>>
+    where
+        Self: Sized + Send + 'static,
+    {
+        FileReader::open(self, path, DEFAULT_PREFETCH)
+    }
+
+    #[cfg(feature = "fs-read-verified")]
+    fn fs_read_verified(&mut self, path: impl AsRef<Path>) -> Result<Cow<'static, [u8]>> {
+        let path = path.as_ref();
+        let data = self.fs_read(path)?;
+
+        let expected = crate::fs::FsMd5::fs_md5(self, path)?.to_ascii_lowercase();
+        let actual = format!("{:x}", md5::compute(&data));
+
+        if expected != actual {
+            let path = os_str_to_str(path.as_os_str())?.to_string();
+            warn!(path, expected, actual, "checksum mismatch");
+
+            return Err(Error::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            });
         }
 
-        // Return the entire contents as a Cow<[u8]> (static lifetime)
-        Ok(buf.into())
+        Ok(data)
+    }
+
+    fn fs_read_file(&mut self, path: impl AsRef<Path>) -> Result<ReadOutcome> {
+        let path = path.as_ref();
+
+        match self.fs_read(path) {
+            Ok(data) => Ok(ReadOutcome::File(data)),
+            // The firmware answers reads of directories with InvalidName, which it also uses for
+            // malformed paths. Only a directory can be listed.
+            Err(e)
+                if e.rpc_status() == Some(CommandStatus::ErrorStorageInvalidName)
+                    && is_dir(self, path)? =>
+            {
+                Ok(ReadOutcome::IsDirectory)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether `path` can be listed, i.e. is a directory
+fn is_dir<T>(transport: &mut T, path: &Path) -> Result<bool>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let path = os_str_to_str(path.as_os_str())?.to_string();
+
+    transport.send(Request::StorageList(ListRequest {
+        path,
+        include_md5: false,
+        filter_max_size: 0,
+    }))?;
+
+    loop {
+        match transport.receive_raw() {
+            Ok(response) if response.has_next => {}
+            Ok(_) => return Ok(true),
+            Err(Error::Rpc(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn read_file_tells_directories_apart() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/apps/a.fap", b"fap".to_vec());
+
+        assert_eq!(
+            flipper.fs_read_file("/ext/apps").unwrap(),
+            ReadOutcome::IsDirectory
+        );
+        assert_eq!(
+            flipper.fs_read_file("/ext/apps/a.fap").unwrap(),
+            ReadOutcome::File(Cow::Borrowed(b"fap"))
+        );
+        assert!(flipper.fs_read_file("/ext/missing").is_err());
+    }
+
+    #[cfg(feature = "fs-read-digests")]
+    #[test]
+    fn read_reports_host_digests() {
+        let mut flipper = EmulatedFlipper::new();
+        // Spans several read chunks
+        flipper.insert_file("/ext/big.bin", vec![b'a'; 1500]);
+        flipper.insert_file("/ext/abc.txt", b"abc".to_vec());
+
+        let (_, summary) = flipper
+            .fs_read_with("/ext/abc.txt", ReadOptions::default())
+            .unwrap();
+        let digests = summary.digests.unwrap();
+        assert_eq!(digests.md5_hex(), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            digests.sha256_hex(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let (data, summary) = flipper
+            .fs_read_with("/ext/big.bin", ReadOptions::default())
+            .unwrap();
+        assert_eq!(
+            summary.digests.unwrap().md5_hex(),
+            format!("{:x}", md5::compute(&data))
+        );
+    }
+
+    #[cfg(feature = "fs-read-verified")]
+    #[test]
+    fn verified_read_detects_corruption() {
+        use crate::transport::faults::{FaultPlan, FaultyTransport};
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/a.txt", b"hello".to_vec());
+
+        assert_eq!(
+            flipper.fs_read_verified("/ext/a.txt").unwrap().as_ref(),
+            b"hello"
+        );
+
+        let mut corrupting = FaultyTransport::new(flipper, FaultPlan::default());
+        // Flips the first byte of every read chunk
+        corrupting.on_receive(|main| {
+            if let Some(proto::main::Content::StorageReadResponse(response)) = &mut main.content {
+                if let Some(file) = &mut response.file {
+                    if !file.data.is_empty() {
+                        let mut data = file.data.to_vec();
+                        data[0] ^= 0xff;
+                        file.data = data.into();
+                    }
+                }
+            }
+        });
+
+        let error = corrupting
+            .fs_read_verified("/ext/a.txt")
+            .expect_err("corrupted data should not verify");
+
+        assert!(matches!(
+            error,
+            Error::ChecksumMismatch { expected, actual, .. }
+                if expected == format!("{:x}", md5::compute(b"hello")) && expected != actual
+        ));
     }
 }
@@ -1,13 +1,15 @@
 //! FsReadDir module
 
-use std::path::Path;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 
-use crate::logging::trace;
+use crate::cancel::{CancelToken, is_cancelled};
+use crate::logging::{debug, operation, trace, warn};
 
-use crate::fs::helpers::os_str_to_str;
+use crate::fs::helpers::{aborted, os_str_to_str};
 use crate::rpc::res::{ReadDirItem, Response};
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto::{self, storage::ListRequest},
@@ -23,6 +25,147 @@ pub trait FsReadDir {
         path: impl AsRef<Path>,
         include_md5: bool,
     ) -> Result<impl Iterator<Item = ReadDirItem>>;
+
+    /// Lists a directory, handing each entry to `on_entry` as soon as its chunk arrives instead
+    /// of collecting them first. Returns how many entries were handed over.
+    ///
+    /// `on_entry` returns [`ControlFlow::Break`] to stop early. The device still sends the rest
+    /// of the listing, which is drained and discarded to keep the stream in sync.
+    fn fs_read_dir_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: ReadDirOptions,
+        on_entry: impl FnMut(ReadDirItem) -> ControlFlow<()>,
+    ) -> Result<usize>;
+}
+
+/// Options for [`FsReadDir::fs_read_dir_with`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ReadDirOptions {
+    /// Ask the device for the MD5 of every file. Slow on large directories.
+    pub include_md5: bool,
+    /// Skip files larger than this many bytes. Zero (the default) lists every file.
+    pub filter_max_size: u32,
+    /// Stops the listing between chunks once cancelled
+    pub cancel: Option<CancelToken>,
+}
+
+impl ReadDirOptions {
+    /// Asks for the MD5 of every file
+    pub fn include_md5(mut self, include_md5: bool) -> Self {
+        self.include_md5 = include_md5;
+
+        self
+    }
+
+    /// Skips files larger than `size` bytes
+    pub fn filter_max_size(mut self, size: u32) -> Self {
+        self.filter_max_size = size;
+
+        self
+    }
+
+    /// Stops the listing once `token` is cancelled, failing with an
+    /// [`std::io::ErrorKind::Interrupted`] error
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+
+        self
+    }
+}
+
+/// Kind of a listed entry, like [`std::fs::FileType`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// A directory
+    Dir,
+    /// A regular file
+    File,
+}
+
+impl FileType {
+    /// Whether this is a directory
+    pub fn is_dir(&self) -> bool {
+        *self == Self::Dir
+    }
+
+    /// Whether this is a regular file
+    pub fn is_file(&self) -> bool {
+        *self == Self::File
+    }
+}
+
+impl From<&ReadDirItem> for FileType {
+    fn from(item: &ReadDirItem) -> Self {
+        match item {
+            ReadDirItem::Dir(_) => Self::Dir,
+            ReadDirItem::File(..) => Self::File,
+        }
+    }
+}
+
+/// What a listing tells about an entry, with the accessors of [`std::fs::Metadata`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    file_type: FileType,
+    len: u64,
+    md5: Option<String>,
+}
+
+impl Metadata {
+    /// Kind of the entry
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Whether the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    /// Whether the entry is a regular file
+    pub fn is_file(&self) -> bool {
+        self.file_type.is_file()
+    }
+
+    /// Size in bytes, 0 for directories
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// MD5 as hex, if the listing asked for it with [`ReadDirOptions::include_md5`]
+    pub fn md5(&self) -> Option<&str> {
+        self.md5.as_deref()
+    }
+}
+
+impl From<&ReadDirItem> for Metadata {
+    fn from(item: &ReadDirItem) -> Self {
+        match item {
+            ReadDirItem::Dir(_) => Self {
+                file_type: FileType::Dir,
+                len: 0,
+                md5: None,
+            },
+            ReadDirItem::File(_, size, md5) => Self {
+                file_type: FileType::File,
+                len: u64::from(*size),
+                md5: md5.clone(),
+            },
+        }
+    }
+}
+
+/// The entry's name, relative to the listed directory. Join it onto the directory for a full
+/// path.
+impl From<&ReadDirItem> for PathBuf {
+    fn from(item: &ReadDirItem) -> Self {
+        match item {
+            ReadDirItem::Dir(name) | ReadDirItem::File(name, ..) => PathBuf::from(name),
+        }
+    }
 }
 
 impl<T> FsReadDir for T
@@ -34,34 +177,139 @@ where
         path: impl AsRef<Path>,
         include_md5: bool,
     ) -> Result<impl Iterator<Item = ReadDirItem>> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
-
         let mut items = Vec::new();
 
-        trace!("init readdir chain");
-        // Send the initial request to start the chain
-        self.send(Request::StorageList(ListRequest {
+        self.fs_read_dir_with(
             path,
-            include_md5,
-            filter_max_size: 0,
-        }))?;
-
-        loop {
-            // Receive the next list items
-            let response = self.receive_raw()?;
-            trace!("readdir chunk");
-            let has_next = response.has_next;
-
-            // Convert the raw response into usable data (Vec<ReadDirItem>)
-            let chunk: Vec<ReadDirItem> = Response::try_from(response)?.try_into()?;
-            items.extend(chunk);
-
-            // If this is the last chunk, stop reading
-            if !has_next {
-                break;
+            ReadDirOptions::default().include_md5(include_md5),
+            |item| {
+                items.push(item);
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        Ok(items.into_iter())
+    }
+
+    fn fs_read_dir_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: ReadDirOptions,
+        mut on_entry: impl FnMut(ReadDirItem) -> ControlFlow<()>,
+    ) -> Result<usize> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        operation("fs_read_dir", path, || {
+            let mut count = 0;
+            let mut stopped = false;
+            let mut abort = false;
+
+            trace!("init readdir chain");
+            // Send the initial request to start the chain
+            self.send(Request::StorageList(ListRequest {
+                path: path.to_string(),
+                include_md5: options.include_md5,
+                filter_max_size: options.filter_max_size,
+            }))?;
+
+            loop {
+                // Like reads, listings are driven by the device and have to be drained
+                abort |= self.take_abort() || is_cancelled(options.cancel.as_ref());
+
+                // Receive the next list items
+                let response = self.receive_raw()?;
+                trace!("readdir chunk");
+                let has_next = response.has_next;
+
+                // Convert the raw response into usable data (Vec<ReadDirItem>)
+                let chunk: Vec<ReadDirItem> = Response::try_from(response)?.try_into()?;
+
+                if !stopped && !abort {
+                    for item in chunk {
+                        count += 1;
+
+                        if on_entry(item).is_break() {
+                            debug!(count, "readdir stopped early");
+                            stopped = true;
+                            break;
+                        }
+                    }
+                }
+
+                // If this is the last chunk, stop reading
+                if !has_next {
+                    break;
+                }
+            }
+
+            if abort {
+                warn!("readdir aborted");
+                return Err(aborted());
             }
+
+            Ok(count)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn converts_items_to_std_like_types() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/nfc/card.nfc", b"card".to_vec());
+        flipper.insert_file("/ext/nfc/assets/keys.dict", b"".to_vec());
+
+        let items = flipper.fs_read_dir("/ext/nfc", true).unwrap();
+        let mut entries = items
+            .map(|item| (PathBuf::from(&item), Metadata::from(&item)))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let (path, metadata) = &entries[0];
+        assert_eq!(path, Path::new("assets"));
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.len(), 0);
+
+        let (path, metadata) = &entries[1];
+        assert_eq!(
+            Path::new("/ext/nfc").join(path),
+            Path::new("/ext/nfc/card.nfc")
+        );
+        assert_eq!(metadata.file_type(), FileType::File);
+        assert_eq!(metadata.len(), 4);
+        assert_eq!(metadata.md5(), Some("5dd2199ad68327cc76d583b057aee7d5"));
+    }
+
+    #[test]
+    fn read_dir_with_stops_early_and_drains() {
+        let mut flipper = EmulatedFlipper::new();
+        for i in 0..250 {
+            flipper.insert_file(&format!("/ext/subghz/{i:03}.sub"), b"sub".to_vec());
         }
 
-        Ok(items.into_iter())
+        let mut seen = Vec::new();
+        let count = flipper
+            .fs_read_dir_with("/ext/subghz", ReadDirOptions::default(), |item| {
+                seen.push(item);
+                if seen.len() == 3 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(seen.len(), 3);
+
+        // The rest of the listing was drained, so the next request gets its own answer
+        assert_eq!(
+            flipper.fs_read_dir("/ext/subghz", false).unwrap().count(),
+            250
+        );
     }
 }
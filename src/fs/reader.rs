@@ -0,0 +1,195 @@
+//! Streaming file reads with background prefetch
+//!
+//! [`FsRead::fs_read`](super::FsRead::fs_read) collects a whole file before returning it.
+//! [`FileReader`] hands it out as it arrives instead, through [`std::io::Read`]. The device
+//! pushes a read chain without waiting for acknowledgements, so a background thread receives the
+//! chunks into a small queue while the consumer works on the current one, hiding the serial
+//! latency behind the consumer's processing time.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::fs::FsRead;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut reader = SerialRpcTransport::new("/dev/ttyACM0")?.fs_open_read("/ext/big.bin")?;
+//! std::io::copy(&mut reader, &mut std::io::sink())?;
+//!
+//! let transport = reader.into_inner()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::JoinHandle;
+
+use prost::bytes::{Buf, Bytes};
+
+use crate::fs::helpers::os_str_to_str;
+use crate::logging::debug;
+use crate::rpc::res::Response;
+use crate::transport::{CommandIndex, Transport};
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Chunks [`FsRead::fs_open_read`](super::FsRead::fs_open_read) receives ahead of the consumer
+pub const DEFAULT_PREFETCH: usize = 4;
+
+/// A file being read from the device, see the [module docs](self)
+///
+/// The reader owns the transport while the read chain runs. [`FileReader::into_inner`] returns
+/// it once the chain is over; dropping the reader early still drains the chain in the
+/// background, but the transport is lost.
+#[derive(Debug)]
+pub struct FileReader<T> {
+    chunks: Receiver<Result<Bytes>>,
+    current: Bytes,
+    receiver: JoinHandle<T>,
+}
+
+impl<T> FileReader<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>
+        + CommandIndex
+        + std::fmt::Debug
+        + Send
+        + 'static,
+{
+    /// Starts reading `path`, receiving up to `prefetch` chunks ahead of the consumer
+    pub fn open(mut transport: T, path: impl AsRef<Path>, prefetch: usize) -> Result<Self> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        debug!(path, prefetch, "init prefetching read chain");
+        transport.send(Request::StorageRead(path.to_string()))?;
+
+        let (tx, chunks) = sync_channel(prefetch);
+        let receiver = std::thread::spawn(move || {
+            receive_chain(&mut transport, &tx);
+            transport
+        });
+
+        Ok(Self {
+            chunks,
+            current: Bytes::new(),
+            receiver,
+        })
+    }
+
+    /// Waits for the read chain to end and returns the transport. Unread data is discarded.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the receiving thread panicked.
+    pub fn into_inner(self) -> Result<T> {
+        // Unblocks the receiving thread, which then drains the rest of the chain
+        drop(self.chunks);
+
+        self.receiver
+            .join()
+            .map_err(|_| std::io::Error::other("read chain receiver panicked").into())
+    }
+}
+
+/// Receives the chain into `tx` until its last chunk. Keeps draining it if the reader is gone,
+/// so the stream stays in sync for the next request.
+fn receive_chain<T>(transport: &mut T, tx: &SyncSender<Result<Bytes>>)
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+{
+    let mut forward = true;
+
+    loop {
+        let chunk = transport.receive_raw().and_then(|response| {
+            let has_next = response.has_next;
+            let data: Option<Bytes> = Response::try_from(response)?.try_into()?;
+            let data = data.ok_or_else(|| std::io::Error::other("Failed to read file"))?;
+
+            Ok((data, has_next))
+        });
+
+        let (chunk, has_next) = match chunk {
+            Ok((data, has_next)) => (Ok(data), has_next),
+            // The chain is broken, nothing more will arrive for it
+            Err(e) => (Err(e), false),
+        };
+
+        if forward && tx.send(chunk).is_err() {
+            debug!("reader dropped, draining read chain");
+            forward = false;
+        }
+
+        if !has_next {
+            break;
+        }
+    }
+}
+
+impl<T> std::io::Read for FileReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.recv() {
+                Ok(chunk) => self.current = chunk?,
+                // The chain is over
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current[..len]);
+        self.current.advance(len);
+
+        Ok(len)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::io::Read;
+
+    use crate::fs::FsRead;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn streams_the_file_and_returns_the_transport() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/big.bin", data.clone());
+        flipper.insert_file("/ext/small.txt", b"small".to_vec());
+
+        let mut reader = flipper.fs_open_read("/ext/big.bin").unwrap();
+        let mut streamed = vec![];
+        reader.read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, data);
+
+        // Stopping early drains the chain, so the transport stays usable
+        let mut reader = reader
+            .into_inner()
+            .unwrap()
+            .fs_open_read("/ext/big.bin")
+            .unwrap();
+        reader.read_exact(&mut [0; 10]).unwrap();
+        let mut flipper = reader.into_inner().unwrap();
+
+        assert_eq!(
+            flipper.fs_read("/ext/small.txt").unwrap().as_ref(),
+            b"small"
+        );
+    }
+
+    #[test]
+    fn missing_files_fail_the_read() {
+        let mut reader = EmulatedFlipper::new()
+            .fs_open_read("/ext/missing.bin")
+            .unwrap();
+
+        let error = reader.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -2,12 +2,12 @@
 
 use std::path::Path;
 
-use crate::logging::debug;
+use crate::logging::{debug, operation};
 
 use crate::fs::helpers::os_str_to_str;
 use crate::proto::storage::DeleteRequest;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto,
@@ -27,13 +27,18 @@ where
 {
     #[doc(alias = "fs_rm")]
     fn fs_remove(&mut self, path: impl AsRef<Path>, recursive: bool) -> Result<()> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
 
-        debug!("removing file {path:?}");
-        let rm_req = Request::StorageDelete(DeleteRequest { path, recursive });
+        operation("fs_remove", path, || {
+            debug!("removing file {path:?}");
+            let rm_req = Request::StorageDelete(DeleteRequest {
+                path: path.to_string(),
+                recursive,
+            });
 
-        self.send_and_receive(rm_req)?;
+            self.send_and_receive(rm_req)?;
 
-        Ok(())
+            Ok(())
+        })
     }
 }
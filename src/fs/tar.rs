@@ -2,11 +2,11 @@
 
 use std::path::Path;
 
-use crate::logging::debug;
+use crate::logging::{debug, operation};
 
 use crate::fs::helpers::os_str_to_str;
+use crate::transport::CommandIndex;
 use crate::transport::Transport;
-use crate::transport::serial::rpc::CommandIndex;
 use crate::{
     error::{Error, Result},
     proto::{self},
@@ -25,13 +25,15 @@ where
     T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
 {
     fn fs_extract_tar(&mut self, path: impl AsRef<Path>, out: impl AsRef<Path>) -> Result<()> {
-        let path = os_str_to_str(path.as_ref().as_os_str())?.to_string();
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
         let out = os_str_to_str(out.as_ref().as_os_str())?.to_string();
 
-        debug!("extracting {path} into {out}");
+        operation("fs_extract_tar", path, || {
+            debug!("extracting {path} into {out}");
 
-        self.send_and_receive(Request::StorageTarExtract(path, out))?;
+            self.send_and_receive(Request::StorageTarExtract(path.to_string(), out))?;
 
-        Ok(())
+            Ok(())
+        })
     }
 }
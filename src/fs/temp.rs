@@ -0,0 +1,160 @@
+//! TempFile module. Device paths that clean up after themselves.
+//!
+//! Staged writes, tar uploads and tests all need a scratch path on the device that does not
+//! collide with anything and does not outlive its use. [`TempFile`] picks a unique name and
+//! removes whatever ended up there when it is dropped.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::fs::{FsWrite, TempFile};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let mut temp = TempFile::new(&mut rpc, "/ext")?;
+//! let path = temp.path().to_string();
+//! temp.transport().fs_write(
+//!     &path,
+//!     b"scratch",
+//!     #[cfg(feature = "fs-write-progress-mpsc")]
+//!     None,
+//! )?;
+//! // Removed here
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::SystemTime;
+
+use crate::fs::FsRemove;
+use crate::fs::helpers::os_str_to_str;
+use crate::logging::{debug, warn};
+use crate::proto::CommandStatus;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// Prefix of temporary file names, so leftovers of a crashed process are easy to find
+pub const TEMP_PREFIX: &str = ".flipper-rpc-";
+
+/// Distinguishes temp files created by this process within the same instant
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A unique path on the device that is removed on drop, see the [module docs](self)
+///
+/// The path is only reserved by name, nothing is created on the device until the caller writes
+/// to it. Removal is recursive, so the path may also be used as a scratch directory. Failing to
+/// remove it is logged and otherwise ignored.
+#[derive(Debug)]
+pub struct TempFile<'a, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    transport: &'a mut T,
+    path: String,
+    keep: bool,
+}
+
+impl<'a, T> TempFile<'a, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Allocates a unique path inside `dir`, which must exist
+    pub fn new(transport: &'a mut T, dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = os_str_to_str(dir.as_ref().as_os_str())?.trim_end_matches('/');
+
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_nanos());
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = format!(
+            "{dir}/{TEMP_PREFIX}{:x}-{nanos:x}-{count}",
+            std::process::id()
+        );
+
+        debug!(path, "allocated temp path");
+        Ok(Self {
+            transport,
+            path,
+            keep: false,
+        })
+    }
+
+    /// The reserved path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The transport, to work with the path while the temp file holds the borrow
+    pub fn transport(&mut self) -> &mut T {
+        self.transport
+    }
+
+    /// Keeps whatever is at the path instead of removing it, e.g. after it was renamed into
+    /// place. Returns the path.
+    pub fn keep(mut self) -> String {
+        self.keep = true;
+
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl<T> Drop for TempFile<'_, T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        match self.transport.fs_remove(&self.path, true) {
+            Ok(()) => {}
+            // Never written to
+            Err(e) if e.rpc_status() == Some(CommandStatus::ErrorStorageNotExist) => {}
+            Err(_e) => {
+                warn!(path = %self.path, error = %_e, "failed to remove temp file");
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "fs-write"))]
+mod tests {
+    use super::*;
+    use crate::fs::FsWrite;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn removes_the_path_on_drop() {
+        let mut flipper = EmulatedFlipper::new();
+
+        let mut temp = TempFile::new(&mut flipper, "/ext/").unwrap();
+        let path = temp.path().to_string();
+        let other = TempFile::new(temp.transport(), "/ext").unwrap().keep();
+        assert_ne!(path, other);
+        assert!(path.starts_with("/ext/.flipper-rpc-"));
+
+        temp.transport()
+            .fs_write_with(&path, b"data", Default::default())
+            .unwrap();
+        drop(temp);
+        assert_eq!(flipper.file(&path), None);
+
+        let mut temp = TempFile::new(&mut flipper, "/ext").unwrap();
+        let kept = temp.path().to_string();
+        temp.transport()
+            .fs_write_with(&kept, b"data", Default::default())
+            .unwrap();
+        temp.keep();
+        assert_eq!(flipper.file(&kept), Some(&b"data"[..]));
+    }
+}
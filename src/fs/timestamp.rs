@@ -0,0 +1,201 @@
+//! FsTimestamp module. Modification times of files on the device.
+//!
+//! The device answers `StorageTimestamp` with a bare `u32`: seconds since 1970-01-01 on its own
+//! clock. That clock has no notion of timezones, it runs in whatever zone it was last set to,
+//! which is the host's local time when qFlipper or the mobile app synced it. Treating the value
+//! as UTC is therefore off by the UTC offset of that zone.
+//!
+//! [`FsTimestamp::fs_timestamp`] corrects for this by reading the device's clock along with the
+//! timestamp and comparing it to the host's, see [`utc_offset`].
+
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::logging::{debug, operation, warn};
+
+use crate::fs::helpers::os_str_to_str;
+use crate::proto::storage::{TimestampRequest, TimestampResponse};
+use crate::proto::system::DateTime;
+use crate::transport::CommandIndex;
+use crate::transport::Transport;
+use crate::{
+    error::{Error, Result},
+    proto::{self},
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Granularity of UTC offsets, every timezone in use is a multiple of a quarter hour
+const OFFSET_STEP: i64 = 15 * 60;
+
+/// Largest UTC offset in use, UTC+14 on the Line Islands
+const MAX_OFFSET: i64 = 14 * 3_600;
+
+/// Timestamp traits for flipper filesystem
+pub trait FsTimestamp {
+    /// Modification time of `path` as the device reports it, seconds since 1970-01-01 on the
+    /// device's clock. See the [module docs](self) for why this is not UTC.
+    fn fs_timestamp_raw(&mut self, path: impl AsRef<Path>) -> Result<u32>;
+
+    /// Modification time of `path`. Reads the device's clock as well to find the timezone the
+    /// timestamp is in, so this costs two round trips.
+    fn fs_timestamp(&mut self, path: impl AsRef<Path>) -> Result<SystemTime>;
+}
+
+impl<T> FsTimestamp for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    #[doc(alias = "fs_mtime")]
+    fn fs_timestamp_raw(&mut self, path: impl AsRef<Path>) -> Result<u32> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+
+        operation("fs_timestamp", path, || {
+            debug!("reading timestamp of {path}");
+
+            let TimestampResponse { timestamp } = self
+                .send_and_receive(Request::StorageTimestamp(TimestampRequest {
+                    path: path.to_string(),
+                }))?
+                .try_into()?;
+
+            Ok(timestamp)
+        })
+    }
+
+    fn fs_timestamp(&mut self, path: impl AsRef<Path>) -> Result<SystemTime> {
+        let raw = self.fs_timestamp_raw(path)?;
+
+        let device_now: Option<DateTime> = self
+            .send_and_receive(Request::SystemGetDatetime)?
+            .try_into()?;
+        let device_now =
+            device_now.ok_or_else(|| std::io::Error::other("Failed to read the device clock"))?;
+
+        Ok(from_device_seconds(
+            raw,
+            utc_offset(&device_now, SystemTime::now()),
+        ))
+    }
+}
+
+/// Converts seconds on a device clock running `utc_offset` seconds ahead of UTC to a
+/// [`SystemTime`]
+pub fn from_device_seconds(raw: u32, utc_offset: i32) -> SystemTime {
+    let utc = i64::from(raw) - i64::from(utc_offset);
+
+    match u64::try_from(utc) {
+        Ok(secs) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        Err(_) => SystemTime::UNIX_EPOCH - Duration::from_secs(utc.unsigned_abs()),
+    }
+}
+
+/// Seconds since 1970-01-01 of a device date and time, in the device's timezone
+pub fn datetime_to_device_seconds(datetime: &DateTime) -> i64 {
+    let days = days_from_civil(
+        i64::from(datetime.year),
+        i64::from(datetime.month),
+        i64::from(datetime.day),
+    );
+
+    days * 86_400
+        + i64::from(datetime.hour) * 3_600
+        + i64::from(datetime.minute) * 60
+        + i64::from(datetime.second)
+}
+
+/// Offset of the device's clock from UTC in seconds, found by comparing its current time to the
+/// host's and rounding to a quarter hour. Clocks that drifted apart by more than a few minutes
+/// round to the wrong offset.
+///
+/// Real offsets stay within ±14 hours. A larger difference means the device clock is not set,
+/// so it tells nothing about the timezone and the offset is taken as 0, i.e. UTC.
+pub fn utc_offset(device_now: &DateTime, host_now: SystemTime) -> i32 {
+    let host = match host_now.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let offset = datetime_to_device_seconds(device_now) - host;
+    let rounded = (offset as f64 / OFFSET_STEP as f64).round() as i64 * OFFSET_STEP;
+
+    if rounded.abs() > MAX_OFFSET {
+        warn!(
+            offset,
+            "device clock is too far from the host's for a timezone, assuming UTC"
+        );
+        return 0;
+    }
+
+    rounded as i32
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(year: u32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime {
+        DateTime {
+            hour,
+            minute,
+            second: 0,
+            day,
+            month,
+            year,
+            weekday: 1,
+        }
+    }
+
+    #[test]
+    fn converts_device_clock_to_system_time() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(
+            datetime_to_device_seconds(&datetime(2024, 2, 29, 12, 30)),
+            1_709_209_800
+        );
+
+        // Device set to UTC+2, host clock a few seconds behind
+        let host = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_209_800 - 2 * 3_600 - 7);
+        let offset = utc_offset(&datetime(2024, 2, 29, 12, 30), host);
+        assert_eq!(offset, 2 * 3_600);
+
+        assert_eq!(
+            from_device_seconds(1_709_209_800, offset),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_202_600)
+        );
+        assert_eq!(
+            from_device_seconds(0, 3_600),
+            SystemTime::UNIX_EPOCH - Duration::from_secs(3_600)
+        );
+    }
+
+    #[test]
+    fn unset_clocks_are_taken_as_utc() {
+        let host = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_209_800);
+
+        // Still a timezone at the edge
+        assert_eq!(
+            utc_offset(&datetime(2024, 2, 29, 12 + 11, 30), host),
+            11 * 3_600
+        );
+        assert_eq!(
+            utc_offset(&datetime(2024, 2, 29, 12 - 12, 30), host),
+            -12 * 3_600
+        );
+
+        // A device that booted with its clock at the firmware's default date
+        assert_eq!(utc_offset(&datetime(2000, 1, 1, 0, 0), host), 0);
+        // Just past UTC+14
+        assert_eq!(utc_offset(&datetime(2024, 3, 1, 2, 45), host), 0);
+    }
+}
@@ -0,0 +1,229 @@
+//! Transfer speed measurement for reads and writes
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Span the current rate in [`TransferProgress::rate`] is averaged over
+pub const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Progress of a running transfer, passed to a [`ProgressCallback`] after every chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferProgress {
+    /// Bytes moved so far
+    pub bytes: usize,
+    /// Size of the whole transfer, if known
+    pub total: Option<usize>,
+    /// Bytes per second over the last [`RATE_WINDOW`]
+    pub rate: f64,
+}
+
+/// Totals of a finished transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TransferSummary {
+    /// Bytes moved
+    pub bytes: usize,
+    /// Time from the first request to the last answer
+    pub duration: Duration,
+    /// Bytes per second over the whole transfer
+    pub avg_rate: f64,
+    /// Host-side hashes of the downloaded data, with the `fs-read-digests` feature
+    #[cfg_attr(all(feature = "serde", not(feature = "fs-read-digests")), serde(skip))]
+    pub digests: Option<Digests>,
+}
+
+/// Hashes of transferred data, calculated on the host
+///
+/// The device can only calculate MD5, which is fine for catching transfer errors but too weak
+/// to record as proof of integrity, so SHA-256 is calculated alongside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Digests {
+    /// MD5, comparable with [`FsMd5::fs_md5`](crate::fs::FsMd5)
+    pub md5: [u8; 16],
+    /// SHA-256
+    pub sha256: [u8; 32],
+}
+
+#[cfg(feature = "fs-read-digests")]
+impl Digests {
+    /// MD5 as lowercase hex, the format the device reports it in
+    pub fn md5_hex(&self) -> String {
+        hex::encode(self.md5)
+    }
+
+    /// SHA-256 as lowercase hex
+    pub fn sha256_hex(&self) -> String {
+        hex::encode(self.sha256)
+    }
+}
+
+/// Serialized as hex strings, like [`Digests::md5_hex`] and [`Digests::sha256_hex`]
+#[cfg(all(feature = "serde", feature = "fs-read-digests"))]
+impl serde::Serialize for Digests {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut digests = serializer.serialize_struct("Digests", 2)?;
+        digests.serialize_field("md5", &self.md5_hex())?;
+        digests.serialize_field("sha256", &self.sha256_hex())?;
+        digests.end()
+    }
+}
+
+/// Calculates [`Digests`] chunk by chunk
+#[cfg(feature = "fs-read-digests")]
+pub(crate) struct Hasher {
+    md5: md5::Context,
+    sha256: sha2::Sha256,
+}
+
+#[cfg(feature = "fs-read-digests")]
+impl Hasher {
+    pub(crate) fn new() -> Self {
+        use sha2::Digest;
+
+        Self {
+            md5: md5::Context::new(),
+            sha256: sha2::Sha256::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        self.md5.consume(data);
+        self.sha256.update(data);
+    }
+
+    pub(crate) fn finish(self) -> Digests {
+        use sha2::Digest;
+
+        Digests {
+            md5: self.md5.finalize().0,
+            sha256: self.sha256.finalize().into(),
+        }
+    }
+}
+
+/// Receives a [`TransferProgress`] after every chunk of a transfer
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(TransferProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    /// Wraps `f`
+    pub fn new(f: impl Fn(TransferProgress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub(crate) fn call(&self, progress: TransferProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback(..)")
+    }
+}
+
+/// Measures a transfer: the total so far and a sliding window for the current rate
+#[derive(Debug)]
+pub(crate) struct RateMeter {
+    start: Instant,
+    bytes: usize,
+    total: Option<usize>,
+    /// Time and running byte count of recent chunks, oldest first
+    samples: VecDeque<(Instant, usize)>,
+}
+
+impl RateMeter {
+    pub(crate) fn start(total: Option<usize>) -> Self {
+        Self::start_at(Instant::now(), total)
+    }
+
+    fn start_at(start: Instant, total: Option<usize>) -> Self {
+        Self {
+            start,
+            bytes: 0,
+            total,
+            samples: VecDeque::from([(start, 0)]),
+        }
+    }
+
+    /// Records `bytes` more moved, returning the progress to report
+    pub(crate) fn record(&mut self, bytes: usize) -> TransferProgress {
+        self.record_at(Instant::now(), bytes)
+    }
+
+    fn record_at(&mut self, now: Instant, bytes: usize) -> TransferProgress {
+        self.bytes += bytes;
+        self.samples.push_back((now, self.bytes));
+
+        // Keep one sample at or before the window start, so the window is always covered
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(at, _)| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let (since, bytes_then) = self.samples[0];
+
+        TransferProgress {
+            bytes: self.bytes,
+            total: self.total,
+            rate: rate(self.bytes - bytes_then, now.duration_since(since)),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> TransferSummary {
+        self.finish_at(Instant::now())
+    }
+
+    fn finish_at(&self, now: Instant) -> TransferSummary {
+        let duration = now.duration_since(self.start);
+
+        TransferSummary {
+            bytes: self.bytes,
+            duration,
+            avg_rate: rate(self.bytes, duration),
+            digests: None,
+        }
+    }
+}
+
+fn rate(bytes: usize, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+
+    bytes as f64 / time.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_follows_the_recent_window() {
+        let start = Instant::now();
+        let mut meter = RateMeter::start_at(start, Some(4096));
+
+        // A fast first second, then a slow one
+        let progress = meter.record_at(start + Duration::from_secs(1), 3072);
+        assert_eq!(progress.rate, 3072.0);
+
+        meter.record_at(start + Duration::from_secs(2), 512);
+        let progress = meter.record_at(start + Duration::from_secs(3), 512);
+        assert_eq!(progress.bytes, 4096);
+        assert_eq!(progress.total, Some(4096));
+        assert_eq!(progress.rate, 512.0);
+
+        let summary = meter.finish_at(start + Duration::from_secs(4));
+        assert_eq!(summary.bytes, 4096);
+        assert_eq!(summary.duration, Duration::from_secs(4));
+        assert_eq!(summary.avg_rate, 1024.0);
+    }
+}
@@ -0,0 +1,491 @@
+//! FsUploadDir module. Uploads a local directory tree.
+//!
+//! Every file costs a write chain of its own, and with many small files the per-file round trips
+//! dominate the transfer time. [`FsUploadDir::fs_upload_dir`] therefore packs such trees into a
+//! single tar, uploads that and lets the device unpack it with `TarExtract`.
+//!
+//! The archive is sent uncompressed: the device's `TarExtract` takes a plain tar, see
+//! [`FsTarExtract`].
+//!
+//! Trees uploaded file by file are read on a worker thread, up to [`READ_AHEAD`] files ahead of
+//! the one being written, so reading the next file overlaps with sending the current one.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Instant;
+
+use crate::logging::{debug, warn};
+
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::walk::{HostTree, Visit, walk};
+use crate::fs::{FsCreateDir, FsRemove, FsTarExtract, FsWrite, TransferSummary, WriteOptions};
+use crate::proto::CommandStatus;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto::{self},
+    transport::TransportRaw,
+};
+
+/// Smallest number of files [`BatchMode::Auto`] packs into a tar
+pub const BATCH_MIN_FILES: usize = 8;
+
+/// Largest average file size [`BatchMode::Auto`] packs into a tar. Bigger files spend their time
+/// on data, not round trips, so packing them gains nothing.
+pub const BATCH_MAX_AVERAGE_SIZE: usize = 4 * 1024;
+
+/// Longest path inside the tar the device's extractor can handle, directories including their
+/// trailing `/`: the 100 byte ustar name field less its NUL. The extractor ignores the ustar prefix.
+pub const TAR_MAX_NAME_LEN: usize = 99;
+
+/// Files read ahead of the one being written when uploading file by file. Bounds the memory the
+/// reader holds.
+pub const READ_AHEAD: usize = 2;
+
+/// Name of the archive while it is on the device, inside the target directory
+const ARCHIVE_NAME: &str = ".upload.tar";
+
+const BLOCK: usize = 512;
+
+/// Whether [`FsUploadDir::fs_upload_dir_with`] packs the files into a tar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Pack trees of at least [`BATCH_MIN_FILES`] files averaging at most
+    /// [`BATCH_MAX_AVERAGE_SIZE`] bytes, and fall back to single files if the firmware cannot
+    /// extract tars
+    #[default]
+    Auto,
+    /// Always pack, failing if the tree cannot be
+    Always,
+    /// Upload every file on its own
+    Never,
+}
+
+/// Options for [`FsUploadDir::fs_upload_dir_with`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct UploadDirOptions {
+    /// When to pack the files into a tar
+    pub batch: BatchMode,
+}
+
+impl UploadDirOptions {
+    /// Sets the [`BatchMode`]
+    pub fn batch(mut self, batch: BatchMode) -> Self {
+        self.batch = batch;
+
+        self
+    }
+}
+
+/// Totals of a finished directory upload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UploadDirSummary {
+    /// Number of files uploaded
+    pub files: usize,
+    /// Whether the files went up as one tar
+    pub batched: bool,
+    /// Bytes of file data, time taken and average rate
+    pub transfer: TransferSummary,
+}
+
+/// Directory upload trait for flipper filesystem
+pub trait FsUploadDir {
+    /// Uploads the contents of the local directory `local` into `remote`, creating it and any
+    /// subdirectories. Existing files are overwritten.
+    fn fs_upload_dir(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+    ) -> Result<UploadDirSummary> {
+        self.fs_upload_dir_with(local, remote, UploadDirOptions::default())
+    }
+
+    /// Like [`FsUploadDir::fs_upload_dir`], with [`UploadDirOptions`]
+    fn fs_upload_dir_with(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        options: UploadDirOptions,
+    ) -> Result<UploadDirSummary>;
+}
+
+impl<T> FsUploadDir for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn fs_upload_dir_with(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        options: UploadDirOptions,
+    ) -> Result<UploadDirSummary> {
+        let remote = os_str_to_str(remote.as_ref().as_os_str())?.trim_end_matches('/');
+
+        let tree = LocalTree::scan(local.as_ref())?;
+        let bytes = tree.bytes();
+        let start = Instant::now();
+
+        let batched = match options.batch {
+            BatchMode::Never => false,
+            BatchMode::Always => true,
+            BatchMode::Auto => tree.worth_batching() && tree.fits_tar(),
+        };
+        debug!(
+            files = tree.files.len(),
+            bytes, batched, "uploading directory"
+        );
+
+        self.fs_create_dir(remote)?;
+
+        let batched = match batched {
+            true => match upload_tar(self, &tree, remote) {
+                Ok(()) => true,
+                Err(e) if options.batch == BatchMode::Auto && is_tar_unsupported(&e) => {
+                    warn!("tar extraction not supported, uploading files one by one");
+                    upload_files(self, &tree, remote, |local| std::fs::read(local))?;
+                    false
+                }
+                Err(e) => return Err(e),
+            },
+            false => {
+                upload_files(self, &tree, remote, |local| std::fs::read(local))?;
+                false
+            }
+        };
+
+        let duration = start.elapsed();
+        Ok(UploadDirSummary {
+            files: tree.files.len(),
+            batched,
+            transfer: TransferSummary {
+                bytes,
+                duration,
+                avg_rate: if duration.is_zero() {
+                    0.0
+                } else {
+                    bytes as f64 / duration.as_secs_f64()
+                },
+                digests: None,
+            },
+        })
+    }
+}
+
+fn upload_tar<T>(transport: &mut T, tree: &LocalTree, remote: &str) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let archive = tree.to_tar()?;
+    let archive_path = format!("{remote}/{ARCHIVE_NAME}");
+
+    transport.fs_write_with(&archive_path, &archive, WriteOptions::default())?;
+    let extracted = transport.fs_extract_tar(&archive_path, remote);
+
+    // Removed even if the extraction failed, so no half-uploaded archive is left behind
+    if let Err(_e) = transport.fs_remove(&archive_path, false) {
+        warn!(path = archive_path, error = %_e, "could not remove upload archive");
+    }
+
+    extracted
+}
+
+/// Writes the files one by one, while a worker reads the next ones with `read`
+fn upload_files<T>(
+    transport: &mut T,
+    tree: &LocalTree,
+    remote: &str,
+    read: impl Fn(&Path) -> std::io::Result<Vec<u8>> + Send,
+) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    std::thread::scope(|scope| {
+        let (files, next_file) = mpsc::sync_channel(READ_AHEAD);
+        scope.spawn(move || {
+            for file in &tree.files {
+                // Stops once the uploader hangs up after an error
+                if files.send((&file.path, read(&file.local))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for dir in &tree.dirs {
+            transport.fs_create_dir(format!("{remote}/{dir}"))?;
+        }
+        // Returning early drops the receiver, which stops the reader at its next file
+        for (path, data) in next_file {
+            transport.fs_write_with(format!("{remote}/{path}"), &data?, WriteOptions::default())?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Whether an error means the firmware cannot extract tars
+fn is_tar_unsupported(error: &Error) -> bool {
+    matches!(
+        error.rpc_status(),
+        Some(CommandStatus::ErrorNotImplemented | CommandStatus::ErrorStorageNotImplemented)
+    )
+}
+
+/// A local directory tree, with `/` separated paths relative to its root
+#[derive(Debug, Default)]
+struct LocalTree {
+    /// Subdirectories, parents before children
+    dirs: Vec<String>,
+    files: Vec<LocalFile>,
+}
+
+/// A file of a [`LocalTree`], read only when it is uploaded
+#[derive(Debug)]
+struct LocalFile {
+    /// Path relative to the root
+    path: String,
+    /// Path on the host
+    local: PathBuf,
+    /// Size when the tree was scanned
+    size: usize,
+}
+
+impl LocalTree {
+    fn scan(root: &Path) -> Result<Self> {
+        let mut tree = Self::default();
+        walk(&mut HostTree::new(root), &mut |visit| {
+            match visit {
+                Visit::Enter(path) => tree.dirs.push(path.to_string()),
+                Visit::File(path, local) => tree.files.push(LocalFile {
+                    path: path.to_string(),
+                    size: std::fs::metadata(&local)?.len() as usize,
+                    local,
+                }),
+                Visit::Leave => {}
+            }
+
+            Ok(())
+        })?;
+
+        Ok(tree)
+    }
+
+    fn bytes(&self) -> usize {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    fn worth_batching(&self) -> bool {
+        self.files.len() >= BATCH_MIN_FILES
+            && self.bytes() / self.files.len() <= BATCH_MAX_AVERAGE_SIZE
+    }
+
+    fn fits_tar(&self) -> bool {
+        // Directories are stored with a trailing `/`
+        self.dirs.iter().all(|dir| dir.len() < TAR_MAX_NAME_LEN)
+            && self
+                .files
+                .iter()
+                .all(|file| file.path.len() <= TAR_MAX_NAME_LEN)
+    }
+
+    /// Reads the files and packs the tree into a ustar archive
+    fn to_tar(&self) -> Result<Vec<u8>> {
+        let mut archive = Vec::with_capacity(self.bytes() + (self.files.len() + 2) * 2 * BLOCK);
+
+        for dir in &self.dirs {
+            archive.extend_from_slice(&tar_header(&format!("{dir}/"), 0, b'5')?);
+        }
+        for file in &self.files {
+            let data = std::fs::read(&file.local)?;
+            archive.extend_from_slice(&tar_header(&file.path, data.len(), b'0')?);
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+        }
+        // End of archive marker
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+
+        Ok(archive)
+    }
+}
+
+/// A ustar header block for an entry of type `kind`
+fn tar_header(name: &str, size: usize, kind: u8) -> Result<[u8; BLOCK]> {
+    if name.len() > TAR_MAX_NAME_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("path too long for the device's tar extractor: {name}"),
+        )
+        .into());
+    }
+
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+
+    let mode = if kind == b'5' { 0o755 } else { 0o644 };
+    octal(&mut header[100..108], mode);
+    octal(&mut header[108..116], 0); // uid
+    octal(&mut header[116..124], 0); // gid
+    octal(&mut header[124..136], size as u64);
+    octal(&mut header[136..148], 0); // mtime
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is calculated with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    octal(&mut header[148..155], u64::from(checksum));
+
+    Ok(header)
+}
+
+/// Writes `value` as zero padded octal, followed by a NUL
+fn octal(field: &mut [u8], value: u64) {
+    let (digits, nul) = field.split_at_mut(field.len() - 1);
+    digits.copy_from_slice(format!("{value:0width$o}", width = digits.len()).as_bytes());
+    nul[0] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_ustar_archives() {
+        let local = std::env::temp_dir().join(format!("flipper-rpc-tar-{}", std::process::id()));
+        std::fs::create_dir_all(&local).unwrap();
+        std::fs::write(local.join("a.txt"), b"hello").unwrap();
+
+        let tree = LocalTree {
+            dirs: vec!["sub".to_string()],
+            files: vec![LocalFile {
+                path: "sub/a.txt".to_string(),
+                local: local.join("a.txt"),
+                size: 5,
+            }],
+        };
+        let archive = tree.to_tar().unwrap();
+        std::fs::remove_dir_all(&local).unwrap();
+
+        assert_eq!(archive.len(), 5 * BLOCK);
+        assert_eq!(&archive[..4], b"sub/");
+        assert_eq!(archive[156], b'5');
+        assert_eq!(&archive[BLOCK..BLOCK + 9], b"sub/a.txt");
+        assert_eq!(&archive[BLOCK + 124..BLOCK + 136], b"00000000005\0");
+        assert_eq!(&archive[2 * BLOCK..2 * BLOCK + 5], b"hello");
+        assert!(archive[3 * BLOCK..].iter().all(|&byte| byte == 0));
+
+        // Checksum of the file header, over the header with the checksum field as spaces
+        let mut header = archive[BLOCK..2 * BLOCK].to_vec();
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        assert_eq!(
+            &archive[BLOCK + 148..BLOCK + 155],
+            format!("{checksum:06o}\0").as_bytes()
+        );
+
+        assert!(tar_header(&"x".repeat(100), 0, b'0').is_err());
+    }
+
+    #[test]
+    fn names_fit_the_tar_up_to_the_limit() {
+        let tree = |dir: usize, file: usize| LocalTree {
+            dirs: vec!["d".repeat(dir)],
+            files: vec![LocalFile {
+                path: "f".repeat(file),
+                local: PathBuf::new(),
+                size: 0,
+            }],
+        };
+
+        assert!(tree(TAR_MAX_NAME_LEN - 1, TAR_MAX_NAME_LEN).fits_tar());
+        assert!(!tree(TAR_MAX_NAME_LEN, TAR_MAX_NAME_LEN).fits_tar());
+        assert!(!tree(1, TAR_MAX_NAME_LEN + 1).fits_tar());
+
+        assert!(tar_header(&"f".repeat(TAR_MAX_NAME_LEN), 0, b'0').is_ok());
+        assert!(tar_header(&format!("{}/", "d".repeat(TAR_MAX_NAME_LEN - 1)), 0, b'5').is_ok());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn uploads_small_files_as_one_tar() {
+        use crate::testing::EmulatedFlipper;
+
+        let local = std::env::temp_dir().join(format!("flipper-rpc-upload-{}", std::process::id()));
+        std::fs::create_dir_all(local.join("sub")).unwrap();
+        for i in 0..BATCH_MIN_FILES {
+            std::fs::write(local.join("sub").join(format!("{i}.txt")), i.to_string()).unwrap();
+        }
+
+        let mut flipper = EmulatedFlipper::new();
+        let summary = flipper.fs_upload_dir(&local, "/ext/upload/").unwrap();
+        let mut single = EmulatedFlipper::new();
+        let unbatched = single
+            .fs_upload_dir_with(
+                &local,
+                "/ext/upload",
+                UploadDirOptions::default().batch(BatchMode::Never),
+            )
+            .unwrap();
+        std::fs::remove_dir_all(&local).unwrap();
+
+        assert!(summary.batched);
+        assert!(!unbatched.batched);
+        assert_eq!(summary.files, BATCH_MIN_FILES);
+        assert_eq!(summary.transfer.bytes, BATCH_MIN_FILES);
+        for device in [&flipper, &single] {
+            assert_eq!(device.file("/ext/upload/sub/3.txt"), Some(&b"3"[..]));
+            assert_eq!(device.file(&format!("/ext/upload/{ARCHIVE_NAME}")), None);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn reads_the_next_file_while_writing_the_current_one() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        use crate::testing::EmulatedFlipper;
+        use crate::transport::faults::{FaultPlan, FaultyTransport};
+
+        let tree = LocalTree {
+            dirs: Vec::new(),
+            files: (0..4)
+                .map(|i| LocalFile {
+                    path: format!("{i}.txt"),
+                    local: PathBuf::from(i.to_string()),
+                    size: 1,
+                })
+                .collect(),
+        };
+
+        let (read_second, second_read) = mpsc::channel();
+        let read_second = Mutex::new(read_second);
+        let mut flipper = FaultyTransport::new(EmulatedFlipper::new(), FaultPlan::default());
+        // Holds the first write until the reader has moved on to the next file
+        flipper.on_send(move |main| {
+            if let Some(proto::main::Content::StorageWriteRequest(request)) = &main.content {
+                if request.path.ends_with("/0.txt") && !main.has_next {
+                    second_read
+                        .recv_timeout(Duration::from_secs(5))
+                        .expect("the next file is read during the first write");
+                }
+            }
+        });
+        upload_files(&mut flipper, &tree, "/ext", |local| {
+            if local == Path::new("1") {
+                read_second.lock().unwrap().send(()).unwrap();
+            }
+
+            Ok(local.as_os_str().as_encoded_bytes().to_vec())
+        })
+        .unwrap();
+
+        for i in 0..4 {
+            assert_eq!(
+                flipper.get_ref().file(&format!("/ext/{i}.txt")),
+                Some(i.to_string().as_bytes())
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! FsUsage module. Disk usage per directory, like `du` or `ncdu`.
+//!
+//! The device has no call that sizes a directory, so [`FsUsage::fs_usage_by_dir`] lists the
+//! whole tree and adds the file sizes up on the host. Every directory costs a listing round
+//! trip, so sizing a full SD card takes a while.
+
+use std::path::Path;
+
+use crate::logging::{debug, operation};
+
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::walk::{DeviceTree, Visit, join, walk};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto::{self},
+    transport::TransportRaw,
+};
+
+/// Aggregated size of a directory tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DirUsage {
+    /// Full path of the directory
+    pub path: String,
+    /// Bytes in all files below the directory
+    pub size: u64,
+    /// Files below the directory
+    pub files: u64,
+    /// Directories below the directory
+    pub dirs: u64,
+    /// Usage of the subdirectories, largest first. Empty below the requested depth, the
+    /// totals above still count them.
+    pub children: Vec<DirUsage>,
+    own_size: u64,
+}
+
+impl DirUsage {
+    /// The last component of [`DirUsage::path`]
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Bytes in files directly inside the directory, not in its subdirectories
+    pub fn own_size(&self) -> u64 {
+        self.own_size
+    }
+}
+
+/// Usage traits for flipper filesystem
+pub trait FsUsage {
+    /// Sizes the tree at `path`, keeping a [`DirUsage`] node for every directory up to `depth`
+    /// levels below it. A depth of 0 only returns the totals of `path`.
+    fn fs_usage_by_dir(&mut self, path: impl AsRef<Path>, depth: usize) -> Result<DirUsage>;
+}
+
+impl<T> FsUsage for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    #[doc(alias = "fs_du")]
+    fn fs_usage_by_dir(&mut self, path: impl AsRef<Path>, depth: usize) -> Result<DirUsage> {
+        let path = os_str_to_str(path.as_ref().as_os_str())?;
+        let path = match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+
+        operation("fs_usage_by_dir", path, || {
+            let usage = sum_tree(self, path, depth)?;
+            debug!(size = usage.size, files = usage.files, "usage collected");
+
+            Ok(usage)
+        })
+    }
+}
+
+/// Sums the tree at `root` up while walking it. A stack holds the directories being walked;
+/// each one is added to its parent when the walk leaves it.
+fn sum_tree<T>(transport: &mut T, root: &str, depth: usize) -> Result<DirUsage>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let mut stack = vec![DirUsage {
+        path: root.to_string(),
+        ..Default::default()
+    }];
+
+    walk(&mut DeviceTree::new(transport, root, false), &mut |visit| {
+        match visit {
+            Visit::Enter(path) => stack.push(DirUsage {
+                path: join(root, path),
+                ..Default::default()
+            }),
+            Visit::File(_, (size, _)) => {
+                let dir = stack.last_mut().expect("the root is never left");
+                dir.size += u64::from(size);
+                dir.own_size += u64::from(size);
+                dir.files += 1;
+            }
+            Visit::Leave => {
+                let mut child = stack.pop().expect("entered before");
+                sort_children(&mut child);
+                // The root is level 0, the child one below its parent
+                let level = stack.len();
+                let parent = stack.last_mut().expect("the root is never left");
+
+                parent.size += child.size;
+                parent.files += child.files;
+                parent.dirs += child.dirs + 1;
+                if level <= depth {
+                    parent.children.push(child);
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    let mut usage = stack.pop().expect("the root is never left");
+    sort_children(&mut usage);
+
+    Ok(usage)
+}
+
+/// Largest first, ties by path
+fn sort_children(usage: &mut DirUsage) {
+    usage
+        .children
+        .sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn aggregates_sizes_per_directory() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/top.txt", vec![0; 10]);
+        flipper.insert_file("/ext/nfc/card.nfc", vec![0; 100]);
+        flipper.insert_file("/ext/nfc/assets/keys.dict", vec![0; 1000]);
+        flipper.insert_file("/ext/subghz/a.sub", vec![0; 50]);
+
+        let usage = flipper.fs_usage_by_dir("/ext/", 1).unwrap();
+        assert_eq!(usage.path, "/ext");
+        assert_eq!((usage.size, usage.files, usage.dirs), (1160, 4, 3));
+        assert_eq!(usage.own_size(), 10);
+
+        let nfc = &usage.children[0];
+        assert_eq!(nfc.name(), "nfc");
+        assert_eq!((nfc.size, nfc.files, nfc.dirs), (1100, 2, 1));
+        assert!(nfc.children.is_empty());
+        assert_eq!(nfc.own_size(), 100);
+        assert_eq!(usage.children[1].path, "/ext/subghz");
+
+        let totals = flipper.fs_usage_by_dir("/ext", 0).unwrap();
+        assert_eq!(totals.size, 1160);
+        assert!(totals.children.is_empty());
+    }
+}
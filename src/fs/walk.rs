@@ -0,0 +1,226 @@
+//! Depth-first walks over directory trees, on the device or on the host
+//!
+//! [`walk`] visits a tree through a [`ListDir`] that lists one directory at a time, so the
+//! helpers that size, snapshot or upload whole trees share one traversal. Paths handed to the
+//! visitor are relative to the root and joined with `/`.
+
+use crate::error::Result;
+
+/// An entry of a listed directory
+pub(crate) enum Entry<F> {
+    /// A subdirectory, by name
+    Dir(String),
+    /// A file, by name, with whatever the lister knows about it
+    File(String, F),
+}
+
+/// What [`walk`] meets, with paths relative to the root
+#[allow(dead_code)] // each walk reads only the paths it needs
+pub(crate) enum Visit<'a, F> {
+    /// A directory, before anything inside it
+    Enter(&'a str),
+    /// The end of the directory entered last, after everything inside it
+    Leave,
+    /// A file
+    File(&'a str, F),
+}
+
+/// Lists the directories of one tree
+pub(crate) trait ListDir {
+    /// What a listing tells about a file
+    type File;
+
+    /// The entries of `dir`, relative to the root; the root itself is `""`
+    fn list(&mut self, dir: &str) -> Result<Vec<Entry<Self::File>>>;
+}
+
+/// Visits every entry below the root of `tree`, parents before their contents. The root itself
+/// is not visited. Each listing is collected before its subdirectories are listed, so it is over
+/// before the next one starts.
+pub(crate) fn walk<L: ListDir>(
+    tree: &mut L,
+    visit: &mut impl FnMut(Visit<'_, L::File>) -> Result<()>,
+) -> Result<()> {
+    walk_dir(tree, "", visit)
+}
+
+fn walk_dir<L: ListDir>(
+    tree: &mut L,
+    dir: &str,
+    visit: &mut impl FnMut(Visit<'_, L::File>) -> Result<()>,
+) -> Result<()> {
+    for entry in tree.list(dir)? {
+        match entry {
+            Entry::File(name, file) => visit(Visit::File(&join(dir, &name), file))?,
+            Entry::Dir(name) => {
+                let path = join(dir, &name);
+                visit(Visit::Enter(&path))?;
+                walk_dir(tree, &path, visit)?;
+                visit(Visit::Leave)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `name` inside the directory `dir`, where `""` is the root
+pub(crate) fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        "/" => format!("/{name}"),
+        dir => format!("{dir}/{name}"),
+    }
+}
+
+#[cfg(feature = "fs-upload-dir")]
+pub(crate) use host::HostTree;
+
+#[cfg(feature = "fs-upload-dir")]
+mod host {
+    use std::path::PathBuf;
+
+    use super::{Entry, ListDir};
+
+    use crate::error::Result;
+    use crate::fs::helpers::os_str_to_str;
+
+    /// A directory on the host. Entries are sorted by name, so walks do not depend on the host
+    /// filesystem's order, and files are reported with their full path.
+    pub(crate) struct HostTree {
+        root: PathBuf,
+    }
+
+    impl HostTree {
+        pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+            Self { root: root.into() }
+        }
+    }
+
+    impl ListDir for HostTree {
+        type File = PathBuf;
+
+        fn list(&mut self, dir: &str) -> Result<Vec<Entry<PathBuf>>> {
+            let mut entries =
+                std::fs::read_dir(self.root.join(dir))?.collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let name = os_str_to_str(&entry.file_name())?.to_string();
+
+                    Ok(if entry.file_type()?.is_dir() {
+                        Entry::Dir(name)
+                    } else {
+                        Entry::File(name, entry.path())
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(feature = "fs-readdir")]
+pub(crate) use device::DeviceTree;
+
+#[cfg(feature = "fs-readdir")]
+mod device {
+    use super::{Entry, ListDir, join};
+
+    use crate::error::{Error, Result};
+    use crate::fs::FsReadDir;
+    use crate::proto;
+    use crate::rpc::res::ReadDirItem;
+    use crate::transport::{CommandIndex, TransportRaw};
+
+    /// A directory on the device, listed with `StorageList`. Files are reported with their size
+    /// and, if requested, the device's MD5.
+    pub(crate) struct DeviceTree<'a, T> {
+        transport: &'a mut T,
+        root: &'a str,
+        md5: bool,
+    }
+
+    impl<'a, T> DeviceTree<'a, T> {
+        pub(crate) fn new(transport: &'a mut T, root: &'a str, md5: bool) -> Self {
+            Self {
+                transport,
+                root,
+                md5,
+            }
+        }
+    }
+
+    impl<T> ListDir for DeviceTree<'_, T>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+    {
+        type File = (u32, Option<String>);
+
+        fn list(&mut self, dir: &str) -> Result<Vec<Entry<Self::File>>> {
+            let path = match dir {
+                "" => self.root.to_string(),
+                dir => join(self.root, dir),
+            };
+
+            Ok(self
+                .transport
+                .fs_read_dir(&path, self.md5)?
+                .map(|item| match item {
+                    ReadDirItem::Dir(name) => Entry::Dir(name),
+                    ReadDirItem::File(name, size, md5) => Entry::File(name, (size, md5)),
+                })
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(dir, entries)` pairs, subdirectories marked with a trailing `/`
+    struct Listings(&'static [(&'static str, &'static [&'static str])]);
+
+    impl ListDir for Listings {
+        type File = ();
+
+        fn list(&mut self, dir: &str) -> Result<Vec<Entry<()>>> {
+            let (_, entries) = self.0.iter().find(|(d, _)| *d == dir).expect("listed");
+
+            Ok(entries
+                .iter()
+                .map(|entry| match entry.strip_suffix('/') {
+                    Some(name) => Entry::Dir(name.to_string()),
+                    None => Entry::File(entry.to_string(), ()),
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn visits_parents_before_their_contents() {
+        let mut tree = Listings(&[
+            ("", &["a/", "top.txt"]),
+            ("a", &["b/", "one.txt"]),
+            ("a/b", &[]),
+        ]);
+
+        let mut seen = Vec::new();
+        walk(&mut tree, &mut |visit| {
+            seen.push(match visit {
+                Visit::Enter(path) => format!("> {path}"),
+                Visit::Leave => "<".to_string(),
+                Visit::File(path, ()) => path.to_string(),
+            });
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(seen, ["> a", "> a/b", "<", "a/one.txt", "<", "top.txt"]);
+        assert_eq!(join("/", "ext"), "/ext");
+        assert_eq!(join("/ext", "nfc"), "/ext/nfc");
+    }
+}
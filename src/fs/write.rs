@@ -3,18 +3,28 @@
 use std::path::Path;
 #[cfg(feature = "fs-write-progress-mpsc")]
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use crate::logging::debug;
+use prost::bytes::Bytes;
+
+use crate::cancel::{CancelToken, is_cancelled};
+use crate::fs::writer::{DEFAULT_WRITE_BEHIND, FileWriter};
+use crate::logging::{debug, operation, trace, warn};
+use crate::proto::CommandStatus;
 
 use crate::{
     error::{Error, Result},
-    fs::{CHUNK_SIZE, helpers::os_str_to_str},
+    fs::{
+        CHUNK_SIZE,
+        helpers::{aborted, os_str_to_str},
+        transfer::{ProgressCallback, RateMeter, TransferProgress, TransferSummary},
+    },
     proto::{
         self,
         storage::{File, WriteRequest, file::FileType},
     },
     rpc::req::Request,
-    transport::{TransportRaw, serial::rpc::CommandIndex},
+    transport::{CommandIndex, TransportRaw},
 };
 
 /// Write traits for flipper filesystem
@@ -25,100 +35,546 @@ pub trait FsWrite {
         path: impl AsRef<Path>,
         data: impl AsRef<[u8]>,
         #[cfg(feature = "fs-write-progress-mpsc")] tx: Option<Sender<usize>>,
-    ) -> Result<()>;
+    ) -> Result<()> {
+        let options = WriteOptions::default();
+
+        #[cfg(feature = "fs-write-progress-mpsc")]
+        let options = WriteOptions {
+            progress: tx,
+            ..options
+        };
+
+        self.fs_write_with(path, data, options)?;
+
+        Ok(())
+    }
+
+    /// Writes a string as UTF-8, the counterpart of
+    /// [`FsRead::fs_read_to_string`](crate::fs::FsRead::fs_read_to_string)
+    fn fs_write_str(&mut self, path: impl AsRef<Path>, contents: &str) -> Result<()> {
+        self.fs_write_with(path, contents, WriteOptions::default())?;
+
+        Ok(())
+    }
+
+    /// Serializes `value` as pretty-printed JSON and writes it
+    #[cfg(feature = "fs-write-json")]
+    fn fs_write_json<V>(&mut self, path: impl AsRef<Path>, value: &V) -> Result<()>
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        let contents = serde_json::to_string_pretty(value)?;
+
+        self.fs_write_str(path, &contents)
+    }
+
+    /// Serializes `value` as TOML and writes it
+    #[cfg(feature = "fs-write-toml")]
+    fn fs_write_toml<V>(&mut self, path: impl AsRef<Path>, value: &V) -> Result<()>
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        let contents = toml::to_string_pretty(value)?;
+
+        self.fs_write_str(path, &contents)
+    }
+
+    /// Like [`FsWrite::fs_write`], with explicit [`WriteOptions`]. Returns how much was sent and
+    /// how fast, measured over the attempt that succeeded.
+    fn fs_write_with(
+        &mut self,
+        path: impl AsRef<Path>,
+        data: impl AsRef<[u8]>,
+        options: WriteOptions,
+    ) -> Result<TransferSummary>;
+
+    /// Opens `path` for writing through [`std::io::Write`]. Chunks are encoded as they are
+    /// written and sent by a background thread, see [`FileWriter`].
+    fn fs_open_write(self, path: impl AsRef<Path>) -> Result<FileWriter<Self>>
+    where
+        Self: Sized + Send + 'static;
 }
 
-/// I did a few tests and this number came out to ~67.2 KiB/s for my machine, rounding down to 50
-/// for most machines
-pub(crate) const THROUGHPUT_KIB: usize = 50;
+/// Options for [`FsWrite::fs_write_with`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct WriteOptions {
+    /// How many times a write that fails with a transient storage error is retried. Zero (the
+    /// default) fails on the first error.
+    pub retries: u32,
+    /// Receives the number of bytes sent so far after every chunk
+    #[cfg(feature = "fs-write-progress-mpsc")]
+    pub progress: Option<Sender<usize>>,
+    /// Called with the bytes sent so far and the current rate after every chunk
+    pub on_progress: Option<ProgressCallback>,
+    /// Stops the upload between chunks once cancelled
+    pub cancel: Option<CancelToken>,
+    /// Leave the MD5 of every chunk out, see [`WriteOptions::skip_chunk_md5`]
+    pub skip_chunk_md5: bool,
+    /// Bytes per chunk, [`CHUNK_SIZE`] when `None`, see [`WriteOptions::chunk_size`]
+    pub chunk_size: Option<usize>,
+}
+
+impl WriteOptions {
+    /// Sets the retry budget for the whole upload.
+    ///
+    /// With retries enabled, the device's answers are polled between chunks with
+    /// [`TransportRaw::try_receive_raw`], so a failed chunk is noticed right away. The transport
+    /// must implement it without blocking, as the built-in transports do.
+    ///
+    /// A retry resends the file from the first chunk under a fresh command id: the firmware
+    /// opens every new write chain with `CREATE_ALWAYS`, so resuming at the failed chunk would
+    /// truncate everything before it.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+
+        self
+    }
+
+    /// Calls `f` with the progress and current rate after every chunk
+    pub fn on_progress(mut self, f: impl Fn(TransferProgress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(ProgressCallback::new(f));
+
+        self
+    }
+
+    /// Stops the upload once `token` is cancelled. The write chain is closed, leaving a partial
+    /// file, and the write fails with an [`std::io::ErrorKind::Interrupted`] error.
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+
+        self
+    }
+
+    /// Leaves the MD5 of every chunk out of the chain. The firmware only checks a chunk's MD5
+    /// when one is sent, and hashing each chunk of [`CHUNK_SIZE`] bytes is a noticeable part of
+    /// the host's work on fast links; compare [`TransferSummary::avg_rate`] with and without it.
+    ///
+    /// Without per-chunk hashes a corrupted chunk goes unnoticed. Check the whole file once
+    /// afterwards with [`FsMd5::fs_md5`](crate::fs::FsMd5) instead, if that matters.
+    pub fn skip_chunk_md5(mut self, skip: bool) -> Self {
+        self.skip_chunk_md5 = skip;
+
+        self
+    }
+
+    /// Sends the data in chunks of `chunk_size` bytes instead of [`CHUNK_SIZE`], e.g. to
+    /// measure the effect on throughput. Zero is treated as one. The firmware decodes each chunk
+    /// into its RPC buffer, so chunks above the default may be rejected.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size.max(1));
+
+        self
+    }
+
+    /// Reports progress on `tx`
+    #[cfg(feature = "fs-write-progress-mpsc")]
+    pub fn progress(mut self, tx: Sender<usize>) -> Self {
+        self.progress = Some(tx);
+
+        self
+    }
+}
+
+/// How long an upload may go without a ping
+pub(crate) const MAX_PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shortest time between pings, also used before the first ping has measured the link
+pub(crate) const MIN_PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pings are spaced this many round trips apart, so waiting for them takes at most 1/20 of an
+/// upload
+const PING_SPACING: u32 = 20;
+
+/// Decides when an upload pings, from the round trip time of earlier pings. Fast links ping
+/// about once a second; slow ones, such as BLE, ping less often, but never less than every
+/// [`MAX_PING_INTERVAL`].
+#[derive(Debug)]
+pub(crate) struct PingCadence {
+    interval: Duration,
+    last: Instant,
+}
+
+impl PingCadence {
+    pub(crate) fn start() -> Self {
+        Self {
+            interval: MIN_PING_INTERVAL,
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn is_due(&self) -> bool {
+        self.last.elapsed() >= self.interval
+    }
 
-/// How many chunks in a second?
-pub(crate) const CHUNKS_PER_SECOND: usize = (THROUGHPUT_KIB * 1024) / CHUNK_SIZE;
+    /// Records a ping that was sent at `sent` and answered at `now`
+    pub(crate) fn record(&mut self, sent: Instant, now: Instant) {
+        let round_trip = now.duration_since(sent);
 
-/// How many seconds between pings
-pub(crate) const PING_INTERVAL_SECONDS: usize = 5; // 5 Seconds per ping
+        self.interval = (round_trip * PING_SPACING).clamp(MIN_PING_INTERVAL, MAX_PING_INTERVAL);
+        self.last = now;
+        trace!(?round_trip, interval = ?self.interval, "ping cadence");
+    }
+}
 
-/// How many chunks between pings?
-pub(crate) const CHUNKS_PER_PING: usize = PING_INTERVAL_SECONDS * CHUNKS_PER_SECOND;
+/// Storage errors worth retrying a write for. Both are reported for transient SD card trouble
+/// (busy card, quota bookkeeping), not just for permanent conditions.
+fn is_retryable(error: &Error) -> bool {
+    matches!(
+        error.rpc_status(),
+        Some(CommandStatus::ErrorStorageInternal | CommandStatus::ErrorStorageDenied)
+    )
+}
 
 impl<T> FsWrite for T
 where
     T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
 {
-    fn fs_write(
+    fn fs_write_with(
         &mut self,
         path: impl AsRef<Path>,
         data: impl AsRef<[u8]>,
-        #[cfg(feature = "fs-write-progress-mpsc")] tx: Option<Sender<usize>>,
-    ) -> Result<()> {
+        options: WriteOptions,
+    ) -> Result<TransferSummary> {
         let path = path.as_ref();
 
         let path_str = os_str_to_str(path.as_os_str())?;
-
-        let file = path
-            .file_name()
-            .and_then(std::ffi::OsStr::to_str)
-            .ok_or_else(|| {
-                std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "path must include a UTF-8 file name; use fs_mkdir for directories",
-                )
-            })?;
+        let file = file_name(path)?;
 
         let data = data.as_ref();
 
-        let chunks = chunks_or_empty(data, CHUNK_SIZE);
-        let total_chunks = chunks.len();
+        operation("fs_write", path_str, || {
+            debug!("writing {} bytes to {path:?}", data.len());
 
-        #[cfg(feature = "fs-write-progress-mpsc")]
-        let mut sent = 0;
+            let mut retries = options.retries;
+
+            loop {
+                match write_attempt(self, path_str, file, data, &options) {
+                    Err(e) if retries > 0 && is_retryable(&e) => {
+                        retries -= 1;
+                        warn!(error = %e, retries, "write chunk failed, restarting the chain");
+
+                        resync(self)?;
+                    }
+                    other => return other,
+                }
+            }
+        })
+    }
+
+    fn fs_open_write(self, path: impl AsRef<Path>) -> Result<FileWriter<Self>>
+    where
+        Self: Sized + Send + 'static,
+    {
+        FileWriter::open(self, path, DEFAULT_WRITE_BEHIND)
+    }
+}
+
+/// The name of the file `path` points to
+pub(crate) fn file_name(path: &Path) -> Result<&str> {
+    path.file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "path must include a UTF-8 file name; use fs_mkdir for directories",
+            )
+            .into()
+        })
+}
+
+/// Sends one complete write chain and waits for its answer
+fn write_attempt<T>(
+    transport: &mut T,
+    path: &str,
+    file: &str,
+    data: &[u8],
+    options: &WriteOptions,
+) -> Result<TransferSummary>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let chunk_size = options.chunk_size.unwrap_or(CHUNK_SIZE).max(1);
+    let chain = WriteChain::open(transport, path, file)
+        .chunk_md5(!options.skip_chunk_md5)
+        .chunk_size(chunk_size);
+    let mut meter = RateMeter::start(Some(data.len()));
+    let mut cadence = PingCadence::start();
+
+    #[cfg(feature = "fs-write-progress-mpsc")]
+    let mut sent = 0;
+
+    #[cfg(feature = "fs-write-progress-mpsc")]
+    if let Some(ref tx) = options.progress {
+        tx.send(sent)?;
+    }
+
+    // UPDATE: Files must be sent with occasional PINGS! This tells the flipper to not close
+    // the connection, since we have not read anything for a while. Inserts a ping every
+    // few seconds, see PingCadence.
+
+    for (i, write_req) in chain.messages(data).enumerate() {
+        if transport.take_abort() || is_cancelled(options.cancel.as_ref()) {
+            if i > 0 {
+                warn!(chunk = i, "write aborted, closing the chain");
+                chain.close_early(transport)?;
+            }
+
+            return Err(aborted());
+        }
+
+        if cadence.is_due() {
+            let sent = Instant::now();
+            chain.ping(transport)?;
+            cadence.record(sent, Instant::now());
+        }
+        trace!(
+            chunk = i,
+            has_next = write_req.has_next,
+            "sending write chunk"
+        );
+        let has_next = write_req.has_next;
+        transport.send_raw(write_req)?;
+
+        if options.retries > 0 && has_next {
+            chain.poll(transport)?;
+        }
 
         #[cfg(feature = "fs-write-progress-mpsc")]
-        if let Some(ref tx) = tx {
+        if let Some(ref tx) = options.progress {
+            sent += chunk_size.min(data.len() - sent);
             tx.send(sent)?;
         }
 
-        let command_id = self.command_index();
+        let progress = meter.record(chunk_size.min(data.len() - i * chunk_size));
+        if let Some(ref on_progress) = options.on_progress {
+            on_progress.call(progress);
+        }
+    }
 
-        debug!("writing {} bytes to {path:?}", data.len());
+    chain.finish(transport)?;
 
-        // UPDATE: Files must be sent with occasional PINGS! This tells the flipper to not close
-        // the connection, since we have not read anything for a while. Inserts a ping every
-        // CHUNKS_PER_PING chunks.
+    Ok(meter.finish())
+}
 
-        for (i, chunk) in chunks.enumerate() {
-            if i > CHUNKS_PER_PING && i % CHUNKS_PER_PING == 0 {
-                self.send_and_receive_raw(Request::Ping(vec![0]).into_rpc(command_id + 1))?;
-            }
-            let has_next = i != total_chunks - 1; // If this is not the last chunk, it has another.
-
-            let write_req = Request::StorageWrite(WriteRequest {
-                path: path_str.to_string(),
-                file: Some(File {
-                    r#type: FileType::File.into(),
-                    name: file.to_string(),
-                    data: chunk.to_vec(),
-                    size: chunk.len() as u32,
-                    md5sum: hex::encode(*md5::compute(chunk)),
-                }),
-            })
-            .into_rpc(command_id)
-            .with_has_next(has_next);
+/// Command ids a [`WriteChain`] reserves: one for the chunks, one for keep-alive pings
+pub(crate) const CHAIN_IDS: u32 = 2;
 
-            self.send_raw(write_req)?;
+/// One `StorageWrite` chain on the wire. It owns two command ids, the first for the chunks and
+/// the second for keep-alive pings sent in between, and consumes the device's answer when it
+/// is finished.
+///
+/// Pings must not share the chain's id: the device answers them under the id they were sent
+/// with, and the chain's own answer is told apart from theirs by id alone. The firmware lets
+/// system requests such as pings through while a write is open, but any other storage request
+/// interrupts the chain.
+pub(crate) struct WriteChain<'a> {
+    path: &'a str,
+    file: &'a str,
+    command_id: u32,
+    ping_id: u32,
+    chunk_md5: bool,
+    chunk_size: usize,
+}
 
-            #[cfg(feature = "fs-write-progress-mpsc")]
-            if let Some(ref tx) = tx {
-                sent += chunk.len();
-                tx.send(sent)?;
-            }
+impl<'a> WriteChain<'a> {
+    /// Reserves the chain's command ids on `transport`
+    pub(crate) fn open(transport: &mut impl CommandIndex, path: &'a str, file: &'a str) -> Self {
+        Self::with_ids(path, file, transport.reserve_range(CHAIN_IDS).start)
+    }
+
+    /// A chain over [`CHAIN_IDS`] ids that were already reserved, starting at `command_id`
+    pub(crate) fn with_ids(path: &'a str, file: &'a str, command_id: u32) -> Self {
+        Self {
+            path,
+            file,
+            command_id,
+            ping_id: command_id + 1,
+            chunk_md5: true,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    /// Whether the chunks carry their MD5
+    pub(crate) fn chunk_md5(mut self, chunk_md5: bool) -> Self {
+        self.chunk_md5 = chunk_md5;
+
+        self
+    }
+
+    /// Bytes per chunk, [`CHUNK_SIZE`] by default
+    pub(crate) fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+
+        self
+    }
+
+    /// The chain's messages for `data`, see [`write_chain`]
+    pub(crate) fn messages<'b>(
+        &'b self,
+        data: &'b [u8],
+    ) -> impl ExactSizeIterator<Item = proto::Main> + 'b {
+        write_chain(
+            self.path,
+            self.file,
+            data,
+            self.chunk_size,
+            self.command_id,
+            self.chunk_md5,
+        )
+    }
+
+    /// Pings the device under the chain's second id, so it keeps the connection open during a
+    /// long upload. A chunk that failed before the ping surfaces here as the device's error.
+    pub(crate) fn ping<T>(&self, transport: &mut T) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+    {
+        let answer =
+            transport.send_and_receive_raw(Request::Ping(vec![0]).into_rpc(self.ping_id))?;
+
+        if answer.command_id != self.ping_id {
+            return Err(Error::InvalidRpcPayload(
+                "keep-alive ping answered under another command id",
+            ));
         }
 
-        self.receive_raw()?;
-        self.increment_command_index(2);
+        Ok(())
+    }
+
+    /// Reads whatever the device answered so far without blocking. The device only answers a
+    /// chain early if a chunk failed, which comes back as its error; the chain's own id answered
+    /// with success before the last chunk is a protocol error.
+    pub(crate) fn poll<T>(&self, transport: &mut T) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+    {
+        while let Some(main) = transport.try_receive_raw()? {
+            if self.is_own_answer(&main)? {
+                return Err(Error::InvalidRpcPayload(
+                    "write chain answered before its last chunk",
+                ));
+            }
+        }
 
         Ok(())
     }
+
+    /// Whether `main` answers the chain itself. Late answers to the chain's pings and unsolicited
+    /// messages (command id 0) are skipped, an answer to any other command fails.
+    fn is_own_answer(&self, main: &proto::Main) -> Result<bool> {
+        if main.command_id == self.command_id {
+            return Ok(true);
+        }
+
+        if main.command_id != self.ping_id && main.command_id != 0 {
+            warn!(
+                command_id = main.command_id,
+                "answer to a command outside the write chain"
+            );
+            return Err(Error::InvalidRpcPayload(
+                "answer to a command outside the write chain",
+            ));
+        }
+
+        trace!(
+            command_id = main.command_id,
+            "skipping answer to another command"
+        );
+        Ok(false)
+    }
+
+    /// Ends a chain that was cut short with an empty last chunk, so the device closes the file
+    /// instead of waiting for more data
+    pub(crate) fn close_early<T>(&self, transport: &mut T) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+    {
+        for message in self.messages(&[]) {
+            transport.send_raw(message)?;
+        }
+
+        self.finish(transport)
+    }
+
+    /// Waits for the device's answer to the last chunk, returning its error if the write
+    /// failed. A ping answer nobody read is skipped, see [`WriteChain::is_own_answer`].
+    pub(crate) fn finish<T>(&self, transport: &mut T) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+    {
+        loop {
+            if self.is_own_answer(&transport.receive_raw()?)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Discards answers still queued from a failed chain (e.g. a pending ping) before retrying
+fn resync<T>(transport: &mut T) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+{
+    loop {
+        match transport.try_receive_raw() {
+            Ok(Some(_)) | Err(Error::Rpc(_)) => {}
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Builds the `StorageWrite` chain for `data`: one message per `chunk_size` bytes, all sharing
+/// `command_id`, with `has_next` set on every message but the last. Empty data still produces one
+/// (empty) message so the file gets created. Each chunk carries its MD5 if `md5` is set.
+///
+/// This is the single chunking engine for writes; anything that writes files should go through it
+/// rather than building `WriteRequest`s by hand.
+pub(crate) fn write_chain<'a>(
+    path: &'a str,
+    file: &'a str,
+    data: &'a [u8],
+    chunk_size: usize,
+    command_id: u32,
+    md5: bool,
+) -> impl ExactSizeIterator<Item = proto::Main> + 'a {
+    let chunks = chunks_or_empty(data, chunk_size);
+    let total_chunks = chunks.len();
+
+    chunks.enumerate().map(move |(i, chunk)| {
+        let has_next = i != total_chunks - 1; // If this is not the last chunk, it has another.
+
+        write_message(path, file, chunk, command_id, has_next, md5)
+    })
+}
+
+/// One message of a `StorageWrite` chain, carrying `chunk` and, if `md5` is set, its MD5. The
+/// firmware skips the check for an empty `md5sum`.
+pub(crate) fn write_message(
+    path: &str,
+    file: &str,
+    chunk: &[u8],
+    command_id: u32,
+    has_next: bool,
+    md5: bool,
+) -> proto::Main {
+    Request::StorageWrite(WriteRequest {
+        path: path.to_string(),
+        file: Some(File {
+            r#type: FileType::File.into(),
+            name: file.to_string(),
+            data: Bytes::copy_from_slice(chunk),
+            size: chunk.len() as u32,
+            md5sum: if md5 {
+                hex::encode(*md5::compute(chunk))
+            } else {
+                String::new()
+            },
+        }),
+    })
+    .into_rpc(command_id)
+    .with_has_next(has_next)
 }
 
 #[inline(always)]
@@ -132,3 +588,293 @@ fn chunks_or_empty<'a>(
         Box::new(data.chunks(chunk_size))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::main::Content;
+
+    fn chunk_sizes(data: &[u8]) -> Vec<(usize, bool)> {
+        write_chain("/ext/a.bin", "a.bin", data, CHUNK_SIZE, 7, true)
+            .map(|message| {
+                assert_eq!(message.command_id, 7);
+
+                match message.content {
+                    Some(Content::StorageWriteRequest(WriteRequest {
+                        file: Some(file), ..
+                    })) => {
+                        assert_eq!(file.size as usize, file.data.len());
+                        (file.data.len(), message.has_next)
+                    }
+                    other => panic!("expected a write request, got {other:?}"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_chunk_carries_only_its_own_data() {
+        assert_eq!(
+            chunk_sizes(&[0; 2 * CHUNK_SIZE + 10]),
+            [(CHUNK_SIZE, true), (CHUNK_SIZE, true), (10, false)]
+        );
+        assert_eq!(chunk_sizes(&[0; CHUNK_SIZE]), [(CHUNK_SIZE, false)]);
+    }
+
+    #[test]
+    fn ping_cadence_follows_the_round_trip_time() {
+        let mut cadence = PingCadence::start();
+        let sent = Instant::now();
+
+        cadence.record(sent, sent + Duration::from_millis(2));
+        assert_eq!(cadence.interval, MIN_PING_INTERVAL);
+
+        cadence.record(sent, sent + Duration::from_millis(150));
+        assert_eq!(cadence.interval, Duration::from_secs(3));
+
+        cadence.record(sent, sent + Duration::from_secs(1));
+        assert_eq!(cadence.interval, MAX_PING_INTERVAL);
+    }
+
+    #[test]
+    fn empty_data_still_creates_the_file() {
+        assert_eq!(chunk_sizes(&[]), [(0, false)]);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn retries_restart_the_chain_after_a_failed_chunk() {
+        use crate::proto::CommandStatus;
+        use crate::testing::EmulatedFlipper;
+
+        let data = (0..3 * CHUNK_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fail_next(CommandStatus::ErrorStorageInternal);
+        flipper
+            .fs_write_with("/ext/a.bin", &data, WriteOptions::default().retries(1))
+            .expect("retry should succeed");
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data.as_slice()));
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fail_next(CommandStatus::ErrorStorageInternal);
+        flipper.fail_next(CommandStatus::ErrorStorageDenied);
+        assert!(
+            flipper
+                .fs_write_with("/ext/a.bin", &data, WriteOptions::default().retries(1))
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn finishing_consumes_the_final_answer() {
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        let data = vec![7; 2 * CHUNK_SIZE];
+
+        let chain = WriteChain::open(&mut flipper, "/ext/a.bin", "a.bin");
+        let ping_id = chain.ping_id;
+
+        // An unread ping answer in front of the chain's answer is skipped
+        flipper
+            .send_raw(Request::Ping(vec![0]).into_rpc(ping_id))
+            .unwrap();
+        for message in chain.messages(&data) {
+            flipper.send_raw(message).unwrap();
+        }
+        chain.finish(&mut flipper).unwrap();
+
+        assert!(flipper.try_receive_raw().unwrap().is_none());
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data.as_slice()));
+        assert!(flipper.command_index() > ping_id);
+
+        // An answer to a command outside the chain is not dropped silently
+        let chain = WriteChain::open(&mut flipper, "/ext/b.bin", "b.bin");
+        let other = flipper.reserve_range(1).start;
+        flipper
+            .send_raw(Request::Ping(vec![0]).into_rpc(other))
+            .unwrap();
+        for message in chain.messages(b"b") {
+            flipper.send_raw(message).unwrap();
+        }
+        assert!(matches!(
+            chain.finish(&mut flipper),
+            Err(Error::InvalidRpcPayload(_))
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn keepalive_pings_use_their_own_reserved_id() {
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        let data = vec![9; 3 * CHUNK_SIZE];
+
+        let chain = WriteChain::open(&mut flipper, "/ext/a.bin", "a.bin");
+        assert_ne!(chain.ping_id, chain.command_id);
+        for (i, message) in chain.messages(&data).enumerate() {
+            if i > 0 {
+                chain.ping(&mut flipper).unwrap();
+            }
+            flipper.send_raw(message).unwrap();
+        }
+        chain.finish(&mut flipper).unwrap();
+
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data.as_slice()));
+        assert!(flipper.try_receive_raw().unwrap().is_none());
+        // Both ids were reserved, the next command gets a fresh one
+        assert!(flipper.command_index() > chain.ping_id);
+
+        // Other storage requests, unlike pings, interrupt an open chain
+        let chain = WriteChain::open(&mut flipper, "/ext/b.bin", "b.bin");
+        flipper
+            .send_raw(chain.messages(&data).next().unwrap())
+            .unwrap();
+        let error = flipper.fs_write_str("/ext/c.txt", "c").unwrap_err();
+        assert_eq!(error.kind(), crate::error::ErrorKind::Interrupted);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn finishing_surfaces_a_failed_write() {
+        use crate::proto::CommandStatus;
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fail_next(CommandStatus::ErrorStorageInternal);
+
+        assert!(
+            flipper
+                .fs_write(
+                    "/ext/a.bin",
+                    b"data",
+                    #[cfg(feature = "fs-write-progress-mpsc")]
+                    None
+                )
+                .is_err()
+        );
+        assert!(flipper.try_receive_raw().unwrap().is_none());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn polling_acts_on_early_answers() {
+        use crate::proto::CommandStatus;
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        let chain = WriteChain::open(&mut flipper, "/ext/a.bin", "a.bin");
+        let mut messages = chain.messages(&[5; 2 * CHUNK_SIZE]);
+
+        flipper.send_raw(messages.next().unwrap()).unwrap();
+        chain.poll(&mut flipper).unwrap();
+
+        // A failed chunk surfaces as the device's error
+        flipper.fail_next(CommandStatus::ErrorStorageInternal);
+        flipper.send_raw(messages.next().unwrap()).unwrap();
+        assert!(is_retryable(&chain.poll(&mut flipper).unwrap_err()));
+
+        // A successful answer under the chain's id means the chain ended early
+        let chain = WriteChain::open(&mut flipper, "/ext/b.bin", "b.bin");
+        for message in chain.messages(b"b") {
+            flipper.send_raw(message).unwrap();
+        }
+        assert!(matches!(
+            chain.poll(&mut flipper),
+            Err(Error::InvalidRpcPayload(_))
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn reports_progress_and_a_summary() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::testing::EmulatedFlipper;
+
+        let data = vec![1; 2 * CHUNK_SIZE + 10];
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let mut flipper = EmulatedFlipper::new();
+        let summary = flipper
+            .fs_write_with(
+                "/ext/a.bin",
+                &data,
+                WriteOptions::default().on_progress({
+                    let seen = Arc::clone(&seen);
+                    move |progress| seen.lock().unwrap().push((progress.bytes, progress.total))
+                }),
+            )
+            .unwrap();
+
+        let total = Some(data.len());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (CHUNK_SIZE, total),
+                (2 * CHUNK_SIZE, total),
+                (data.len(), total)
+            ]
+        );
+        assert_eq!(summary.bytes, data.len());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn chunk_md5s_can_be_skipped() {
+        use crate::testing::EmulatedFlipper;
+
+        let data = vec![3; CHUNK_SIZE + 1];
+        let mut flipper = EmulatedFlipper::new();
+        flipper
+            .fs_write_with(
+                "/ext/a.bin",
+                &data,
+                WriteOptions::default().skip_chunk_md5(true),
+            )
+            .unwrap();
+
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data.as_slice()));
+        let md5s = flipper
+            .requests()
+            .iter()
+            .filter_map(|main| match &main.content {
+                Some(Content::StorageWriteRequest(req)) => Some(req.file.as_ref()?.md5sum.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(md5s, ["", ""]);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn writes_strings_and_serialized_values() {
+        use crate::testing::EmulatedFlipper;
+
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fs_write_str("/ext/hello.txt", "héllo").unwrap();
+        assert_eq!(flipper.file("/ext/hello.txt"), Some("héllo".as_bytes()));
+
+        #[cfg(feature = "fs-write-json")]
+        {
+            let value = serde_json::json!({ "name": "flipper", "level": 3 });
+            flipper.fs_write_json("/ext/a.json", &value).unwrap();
+            let written: serde_json::Value =
+                serde_json::from_slice(flipper.file("/ext/a.json").unwrap()).unwrap();
+            assert_eq!(written, value);
+        }
+
+        #[cfg(feature = "fs-write-toml")]
+        {
+            let value = std::collections::BTreeMap::from([("name", "flipper")]);
+            flipper.fs_write_toml("/ext/a.toml", &value).unwrap();
+            assert_eq!(
+                flipper.file("/ext/a.toml"),
+                Some(&b"name = \"flipper\"\n"[..])
+            );
+        }
+    }
+}
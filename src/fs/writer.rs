@@ -0,0 +1,308 @@
+//! Streaming file writes with write-behind buffering
+//!
+//! [`FileWriter`] is the counterpart of [`FileReader`](super::FileReader): it uploads a file
+//! through [`std::io::Write`] without holding all of it in memory. Written data is cut into
+//! chunks and encoded, MD5 included, on the caller's thread, then queued for a background
+//! thread that sends them. Encoding the next chunks overlaps with the device taking the
+//! previous ones.
+//!
+//! The device only answers a write chain at its end, or early if a chunk failed.
+//! [`std::io::Write::flush`] sends the buffered data, waits for the queue to drain and reports
+//! such an early failure; [`FileWriter::finish`] ends the chain and waits for the answer.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::fs::FsWrite;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut writer = SerialRpcTransport::new("/dev/ttyACM0")?.fs_open_write("/ext/log.txt")?;
+//! for line in 0..1000 {
+//!     writeln!(writer, "line {line}")?;
+//! }
+//!
+//! let transport = writer.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, SyncSender, channel, sync_channel};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::fs::CHUNK_SIZE;
+use crate::fs::helpers::os_str_to_str;
+use crate::fs::write::{CHAIN_IDS, PingCadence, WriteChain, file_name, write_message};
+use crate::logging::{debug, trace};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// Chunks [`FsWrite::fs_open_write`](super::FsWrite::fs_open_write) queues ahead of the device
+pub const DEFAULT_WRITE_BEHIND: usize = 4;
+
+/// What the background thread is asked to do
+#[derive(Debug)]
+enum Queued {
+    /// Send one encoded chunk
+    Chunk(proto::Main),
+    /// Report back once everything before it was sent
+    Flush,
+}
+
+/// A file being written to the device, see the [module docs](self)
+///
+/// Dropping the writer without [`FileWriter::finish`] closes the chain in the background,
+/// leaving the data sent so far as a partial file, and loses the transport.
+#[derive(Debug)]
+pub struct FileWriter<T> {
+    path: String,
+    file: String,
+    command_id: u32,
+    buffer: Vec<u8>,
+    queue: SyncSender<Queued>,
+    /// Flush acknowledgements, and the error that stopped the background thread
+    acks: Receiver<Result<()>>,
+    sender: JoinHandle<T>,
+}
+
+impl<T> FileWriter<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>
+        + CommandIndex
+        + std::fmt::Debug
+        + Send
+        + 'static,
+{
+    /// Starts writing `path`, queueing up to `write_behind` encoded chunks for the device
+    pub fn open(mut transport: T, path: impl AsRef<Path>, write_behind: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let file = file_name(path)?.to_string();
+        let path = os_str_to_str(path.as_os_str())?.to_string();
+
+        let command_id = transport.reserve_range(CHAIN_IDS).start;
+        debug!(path, write_behind, "init write-behind chain");
+
+        let (queue, queued) = sync_channel(write_behind);
+        let (ack, acks) = channel();
+        let sender = {
+            let (path, file) = (path.clone(), file.clone());
+
+            std::thread::spawn(move || {
+                let chain = WriteChain::with_ids(&path, &file, command_id);
+                let result = send_chain(&mut transport, &chain, &queued, &ack);
+                // Closed before the error is reported, so the writer cannot queue anything
+                // once it has seen it
+                drop(queued);
+                if let Err(e) = result {
+                    let _ = ack.send(Err(e));
+                }
+
+                transport
+            })
+        };
+
+        Ok(Self {
+            path,
+            file,
+            command_id,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            queue,
+            acks,
+            sender,
+        })
+    }
+
+    /// Sends the rest of the data, ends the chain and waits for the device's answer. Returns
+    /// the transport.
+    ///
+    /// # Errors
+    ///
+    /// Returns the device's error if a chunk failed, or the transport's.
+    pub fn finish(self) -> Result<T> {
+        let last = write_message(
+            &self.path,
+            &self.file,
+            &self.buffer,
+            self.command_id,
+            false,
+            true,
+        );
+        // Fails if the thread already stopped on an error, which may have been reported by
+        // an earlier flush
+        let queued = self.queue.send(Queued::Chunk(last)).is_ok();
+        drop(self.queue);
+
+        let transport = self
+            .sender
+            .join()
+            .map_err(|_| std::io::Error::other("write chain sender panicked"))?;
+
+        match self.acks.try_iter().find_map(Result::err) {
+            Some(e) => Err(e),
+            None if !queued => Err(chain_stopped()),
+            None => Ok(transport),
+        }
+    }
+
+    /// Encodes and queues the buffered data as a chunk that is not the last
+    fn queue_buffer(&mut self, len: usize) -> Result<()> {
+        let message = write_message(
+            &self.path,
+            &self.file,
+            &self.buffer[..len],
+            self.command_id,
+            true,
+            true,
+        );
+        self.buffer.drain(..len);
+
+        self.enqueue(Queued::Chunk(message))
+    }
+
+    fn enqueue(&mut self, queued: Queued) -> Result<()> {
+        self.queue
+            .send(queued)
+            .map_err(|_| self.failure().unwrap_or_else(chain_stopped))
+    }
+
+    /// The error that stopped the background thread, if it stopped
+    fn failure(&mut self) -> Option<Error> {
+        self.acks.try_iter().find_map(Result::err)
+    }
+}
+
+fn chain_stopped() -> Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "write chain stopped").into()
+}
+
+/// Sends queued chunks until the last one and waits for the device's answer. Closes the chain
+/// early if the writer is dropped before its last chunk.
+fn send_chain<T>(
+    transport: &mut T,
+    chain: &WriteChain,
+    queued: &Receiver<Queued>,
+    ack: &Sender<Result<()>>,
+) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+{
+    let mut cadence = PingCadence::start();
+
+    for queued in queued {
+        match queued {
+            Queued::Chunk(message) => {
+                if cadence.is_due() {
+                    let sent = Instant::now();
+                    chain.ping(transport)?;
+                    cadence.record(sent, Instant::now());
+                }
+
+                let has_next = message.has_next;
+                trace!(has_next, "sending queued write chunk");
+                transport.send_raw(message)?;
+
+                if !has_next {
+                    return chain.finish(transport);
+                }
+            }
+            Queued::Flush => {
+                chain.poll(transport)?;
+                let _ = ack.send(Ok(()));
+            }
+        }
+    }
+
+    debug!("writer dropped, closing the chain");
+    chain.close_early(transport)
+}
+
+impl<T> std::io::Write for FileWriter<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>
+        + CommandIndex
+        + std::fmt::Debug
+        + Send
+        + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(e) = self.failure() {
+            return Err(e.into());
+        }
+
+        self.buffer.extend_from_slice(buf);
+
+        // A full buffer is held back until more data arrives, so the last chunk of the chain
+        // always carries data unless the file is empty
+        while self.buffer.len() > CHUNK_SIZE {
+            self.queue_buffer(CHUNK_SIZE)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.queue_buffer(self.buffer.len())?;
+        }
+
+        self.enqueue(Queued::Flush)?;
+
+        match self.acks.recv() {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(chain_stopped().into()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::io::Write;
+
+    use crate::fs::{FsRead, FsWrite};
+    use crate::proto::CommandStatus;
+    use crate::testing::EmulatedFlipper;
+
+    use super::*;
+
+    #[test]
+    fn streams_the_file_and_returns_the_transport() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+
+        let mut writer = EmulatedFlipper::new()
+            .fs_open_write("/ext/big.bin")
+            .unwrap();
+        for part in data.chunks(300) {
+            writer.write_all(part).unwrap();
+        }
+        writer.flush().unwrap();
+        let mut flipper = writer.finish().unwrap();
+
+        assert_eq!(flipper.fs_read("/ext/big.bin").unwrap().as_ref(), data);
+
+        let writer = flipper.fs_open_write("/ext/empty.bin").unwrap();
+        let flipper = writer.finish().unwrap();
+        assert_eq!(flipper.file("/ext/empty.bin"), Some(&[][..]));
+    }
+
+    #[test]
+    fn flush_reports_failed_chunks() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fail_next(CommandStatus::ErrorStorageDenied);
+
+        let mut writer = flipper.fs_open_write("/ext/a.bin").unwrap();
+        writer.write_all(&[1; CHUNK_SIZE * 2]).unwrap();
+
+        let error = writer.flush().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(writer.finish().is_err());
+    }
+}
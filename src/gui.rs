@@ -0,0 +1,291 @@
+//! Experimental input macros
+//!
+//! The RPC protocol does not report physical button presses, only what they cause: a new screen
+//! frame, a desktop lock change, an app starting or closing. [`macro_record`] timestamps these
+//! state changes while someone uses the device. [`Recording::to_macro`] then turns them into a
+//! replayable [`InputMacro`], with a caller-supplied function deciding which key caused each
+//! change, e.g. by matching known screens.
+//!
+//! This API is experimental and may change in any release.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::gui::macro_record;
+//! use flipper_rpc::proto::gui::InputKey;
+//! use flipper_rpc::remote_control::{RemoteControl, RemoteEvent};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut remote = RemoteControl::start(SerialRpcTransport::new("/dev/ttyACM0")?)?;
+//!
+//! // Record for ten seconds while pressing buttons on the device
+//! let recording = macro_record(&mut remote, |observation| {
+//!     observation.at < Duration::from_secs(10)
+//! })?;
+//!
+//! // Assume every screen change was a press of OK
+//! let input_macro = recording.to_macro(|_, observation| match observation.event {
+//!     RemoteEvent::Frame(_) => Some(InputKey::Ok),
+//!     _ => None,
+//! });
+//!
+//! input_macro.replay(&mut remote)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::logging::{debug, warn};
+
+use crate::proto::desktop::{StatusSubscribeRequest, StatusUnsubscribeRequest};
+use crate::proto::gui::{InputKey, ScreenFrame};
+use crate::remote_control::{RemoteControl, RemoteEvent};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// A state change seen while recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    /// Time since the recording started
+    pub at: Duration,
+    /// What changed
+    pub event: RemoteEvent,
+}
+
+/// State changes recorded by [`macro_record`], in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    /// Every observed change. Repeated identical frames are left out.
+    pub observations: Vec<Observation>,
+}
+
+/// One short button press of an [`InputMacro`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacroStep {
+    /// Pause before the press
+    pub delay: Duration,
+    /// Key to press
+    pub key: InputKey,
+}
+
+/// A replayable sequence of button presses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputMacro {
+    /// Presses in order
+    pub steps: Vec<MacroStep>,
+}
+
+/// Records state changes on `remote` until `keep_going` returns `false` for an observation.
+///
+/// Subscribes to desktop status changes for the duration of the recording, and unsubscribes
+/// even if it fails. `keep_going` is only called when something changes, so an idle device keeps
+/// the recording open.
+pub fn macro_record<T>(
+    remote: &mut RemoteControl<T>,
+    keep_going: impl FnMut(&Observation) -> bool,
+) -> Result<Recording>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    // Taken before subscribing: frames that arrive meanwhile are queued as events, but already
+    // update `last_frame`
+    let last_frame = remote.last_frame().cloned();
+    let start = Instant::now();
+
+    remote.request(Request::DesktopStatusSubscribe(StatusSubscribeRequest {}))?;
+
+    let recording = record(remote, last_frame, start, keep_going);
+
+    // Unsubscribed even if the recording failed, so the device stops pushing status updates
+    if let Err(e) = remote.request(Request::DesktopStatusUnsubscribe(
+        StatusUnsubscribeRequest {},
+    )) {
+        if recording.is_ok() {
+            return Err(e);
+        }
+        warn!(error = %e, "could not unsubscribe from desktop status");
+    }
+
+    recording
+}
+
+/// The loop of [`macro_record`], between subscribing and unsubscribing
+fn record<T>(
+    remote: &mut RemoteControl<T>,
+    mut last_frame: Option<ScreenFrame>,
+    start: Instant,
+    mut keep_going: impl FnMut(&Observation) -> bool,
+) -> Result<Recording>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let mut recording = Recording::default();
+
+    loop {
+        let event = remote.next_event()?;
+
+        if let RemoteEvent::Frame(frame) = &event {
+            if last_frame.as_ref() == Some(frame) {
+                continue;
+            }
+            last_frame = Some(frame.clone());
+        }
+
+        let observation = Observation {
+            at: start.elapsed(),
+            event,
+        };
+        let done = !keep_going(&observation);
+        recording.observations.push(observation);
+
+        if done {
+            break;
+        }
+    }
+
+    debug!(
+        observations = recording.observations.len(),
+        "macro recorded"
+    );
+
+    Ok(recording)
+}
+
+impl Recording {
+    /// Builds a macro with one press per observation that `infer` maps to a key. `infer` gets the
+    /// previous observation, if any, and the current one. Each press keeps the delay since the
+    /// previous press, or since the start of the recording for the first one.
+    pub fn to_macro(
+        &self,
+        mut infer: impl FnMut(Option<&Observation>, &Observation) -> Option<InputKey>,
+    ) -> InputMacro {
+        let mut steps = Vec::new();
+        let mut previous_press = Duration::ZERO;
+        let mut previous = None;
+
+        for observation in &self.observations {
+            if let Some(key) = infer(previous, observation) {
+                steps.push(MacroStep {
+                    delay: observation.at.saturating_sub(previous_press),
+                    key,
+                });
+                previous_press = observation.at;
+            }
+
+            previous = Some(observation);
+        }
+
+        InputMacro { steps }
+    }
+}
+
+impl InputMacro {
+    /// Presses every key in order, waiting each step's delay first
+    pub fn replay<T>(&self, remote: &mut RemoteControl<T>) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+    {
+        for step in &self.steps {
+            std::thread::sleep(step.delay);
+            remote.press(step.key)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::proto::app::{AppState, AppStateResponse};
+    use crate::proto::gui::ScreenFrame;
+    use crate::proto::main::Content;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn records_changes_and_replays_them() {
+        let flipper = EmulatedFlipper::new().with_screen(vec![0; 1024], 2);
+        let mut remote = RemoteControl::start(flipper).unwrap();
+
+        let menu = ScreenFrame {
+            data: vec![1; 1024].into(),
+            orientation: 0,
+        };
+        remote
+            .get_mut()
+            .push_event(Content::AppStateResponse(AppStateResponse {
+                state: AppState::AppStarted.into(),
+            }));
+        remote
+            .get_mut()
+            .push_event(Content::GuiScreenFrame(menu.clone()));
+
+        let recording = macro_record(&mut remote, |observation| {
+            !matches!(observation.event, RemoteEvent::Frame(ref frame) if *frame == menu)
+        })
+        .unwrap();
+
+        // The second blank frame repeats the first and is left out
+        let events = recording
+            .observations
+            .iter()
+            .map(|observation| &observation.event)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                &RemoteEvent::Frame(ScreenFrame {
+                    data: vec![0; 1024].into(),
+                    orientation: 0
+                }),
+                &RemoteEvent::AppState(AppState::AppStarted),
+                &RemoteEvent::Frame(menu),
+            ]
+        );
+
+        let input_macro = recording.to_macro(|previous, observation| {
+            (previous.is_some() && matches!(observation.event, RemoteEvent::Frame(_)))
+                .then_some(InputKey::Ok)
+        });
+        assert_eq!(input_macro.steps.len(), 1);
+        assert_eq!(input_macro.steps[0].key, InputKey::Ok);
+
+        input_macro.replay(&mut remote).unwrap();
+        remote.stop().unwrap();
+        let flipper = remote.into_inner();
+
+        let inputs = flipper
+            .requests()
+            .iter()
+            .filter(|main| matches!(main.content, Some(Content::GuiSendInputEventRequest(_))))
+            .count();
+        assert_eq!(inputs, 3);
+    }
+
+    #[test]
+    fn unsubscribes_when_recording_fails() {
+        // No frames and no events, so waiting for the first change fails
+        let flipper = EmulatedFlipper::new().with_screen(vec![0; 1024], 0);
+        let mut remote = RemoteControl::start(flipper).unwrap();
+
+        assert!(macro_record(&mut remote, |_| true).is_err());
+        assert!(matches!(
+            remote
+                .get_ref()
+                .requests()
+                .last()
+                .and_then(|main| main.content.as_ref()),
+            Some(Content::DesktopStatusUnsubscribeRequest(_))
+        ));
+    }
+}
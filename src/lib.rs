@@ -6,6 +6,7 @@
     all(docsrs, feature = "document-features"),
     feature(doc_cfg, doc_auto_cfg)
 )]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![deny(missing_docs)]
 #![deny(unused_must_use)]
 #![deny(clippy::all)]
@@ -20,20 +21,90 @@
 //!
 //! Filesystem helpers live under [`fs`] and are enabled feature-by-feature so downstream crates can
 //! keep compile times and dependency surface small.
+//!
+//! # `no_std`
+//!
+//! Without the default `std` feature the crate builds on `core` and `alloc` only. The protocol
+//! layer stays available: [`proto`], the framing in [`codec`] and [`proto_ext`], the easy-rpc
+//! `Request`/`Response` mapping, and the error types. Transports, filesystem helpers and
+//! everything built on them require `std`.
+
+extern crate alloc;
 
 // I don't have the time to write docs for auto-generated things
 #[cfg(feature = "proto")]
 #[allow(missing_docs)]
 pub mod proto;
 
+#[cfg(feature = "proto")]
+pub mod proto_ext;
+
+#[cfg(feature = "proto")]
+pub mod codec;
+
+mod features;
+
 pub mod error;
+pub mod firmware;
 pub mod logging;
 
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "std")]
+pub use cancel::CancelToken;
+
 #[cfg(feature = "easy-rpc")]
 pub mod rpc;
 
 #[cfg(feature = "fs-any")]
 pub mod fs;
 
+#[cfg(feature = "app")]
+pub mod app;
+
+#[cfg(feature = "desktop")]
+pub mod desktop;
+
+#[cfg(feature = "system")]
+pub mod system;
+
+#[cfg(feature = "session")]
+pub mod session;
+
+#[cfg(feature = "flipper")]
+pub mod flipper;
+#[cfg(feature = "flipper")]
+pub use flipper::FlipperZero;
+
+#[cfg(feature = "remote-control")]
+pub mod remote_control;
+
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+
+#[cfg(feature = "dolphin")]
+pub mod dolphin;
+
+#[cfg(feature = "emulate")]
+pub mod emulate;
+
+#[cfg(feature = "notes")]
+pub mod notes;
+
+#[cfg(feature = "scripting")]
+pub mod scripting;
+
+#[cfg(feature = "settings")]
+pub mod settings;
+
+#[cfg(feature = "update")]
+pub mod update;
+
+#[cfg(feature = "gui-macro")]
+pub mod gui;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
 #[cfg(feature = "transport-any")]
 pub mod transport;
@@ -48,3 +48,77 @@ pub(crate) use {
     event_span, event_span as debug_span, event_span as error_span, event_span as info_span,
     event_span as trace_span, event_span as warn_span,
 };
+
+/// Source of operation ids, see [`operation`]
+#[cfg(feature = "std")]
+static NEXT_OP_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+/// Runs `f` as one logical operation (e.g. a whole `fs_write`, not a single chunk).
+///
+/// Every event emitted inside `f` is recorded in a span carrying a fresh `op_id`, so interleaved
+/// chains from concurrent tools can be told apart. A failure is logged at debug level, leaving
+/// louder reporting to the caller, and returned as [`Error::Operation`] with the same id. Errors
+/// of nested operations keep the innermost id.
+///
+/// [`Error::Operation`]: crate::error::Error::Operation
+#[cfg(feature = "std")]
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn operation<R>(
+    op: &'static str,
+    target: &str,
+    f: impl FnOnce() -> crate::error::Result<R>,
+) -> crate::error::Result<R> {
+    use crate::error::Error;
+
+    let op_id = NEXT_OP_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    #[cfg(feature = "tracing")]
+    let _span = debug_span!("operation", op_id, op, target).entered();
+
+    f().map_err(|error| {
+        debug!(op_id, op, target, %error, "operation failed");
+
+        match error {
+            error @ Error::Operation { .. } => error,
+            error => Error::Operation {
+                op,
+                op_id,
+                error: Box::new(error),
+            },
+        }
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn failures_carry_the_operation_id() {
+        let error = operation("fs_test", "/ext/a", || {
+            Err::<(), _>(Error::InvalidRpcPayload("bad"))
+        })
+        .unwrap_err();
+        let Error::Operation { op, op_id, .. } = &error else {
+            panic!("not tagged: {error:?}");
+        };
+        assert_eq!(*op, "fs_test");
+        assert_eq!(error.op_id(), Some(*op_id));
+        assert!(matches!(error.root(), Error::InvalidRpcPayload("bad")));
+        assert_eq!(error.kind(), crate::error::ErrorKind::Protocol);
+
+        // Nested operations keep the innermost id
+        let mut inner = None;
+        let error = operation("fs_outer", "/ext", || {
+            let error = operation("fs_inner", "/ext/a", || Err::<(), _>(Error::DeviceRebooted))
+                .unwrap_err();
+            inner = error.op_id();
+
+            Err::<(), _>(error)
+        })
+        .unwrap_err();
+        assert!(inner.is_some_and(|inner| inner != *op_id));
+        assert_eq!(error.op_id(), inner);
+    }
+}
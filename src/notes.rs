@@ -0,0 +1,102 @@
+//! Text notes in `/ext/docs`
+//!
+//! The Flipper shows text files in `/ext/docs` in its file browser, which makes the folder a
+//! handy place to drop notes from the host, e.g. the clipboard contents.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::notes;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! notes::push_text(&mut rpc, "wifi.txt", "guest / hunter2")?;
+//! assert_eq!(notes::pull_text(&mut rpc, "wifi.txt")?, "guest / hunter2");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::logging::debug;
+
+use crate::fs::{DB_DOCS, FsCreateDir, FsRead, FsWrite};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// Writes `text` to the note `name`, replacing it if it exists. `/ext/docs` is created if
+/// needed.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error if `name` is empty or contains a `/`,
+/// or the device's error if the write fails.
+pub fn push_text<T>(transport: &mut T, name: &str, text: &str) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let path = note_path(name)?;
+
+    transport.fs_create_dir(DB_DOCS)?;
+    transport.fs_write(
+        &path,
+        text,
+        #[cfg(feature = "fs-write-progress-mpsc")]
+        None,
+    )?;
+    debug!(path, bytes = text.len(), "note pushed");
+
+    Ok(())
+}
+
+/// Reads the note `name`. Invalid UTF-8 is replaced, as notes edited on the device may contain
+/// stray bytes.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error if `name` is empty or contains a `/`,
+/// or the device's error if the note cannot be read, e.g. because it does not exist.
+pub fn pull_text<T>(transport: &mut T, name: &str) -> Result<String>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let path = note_path(name)?;
+
+    Ok(transport.fs_read_to_string_lossy(&path)?.into_owned())
+}
+
+fn note_path(name: &str) -> Result<String> {
+    if name.is_empty() || name.contains('/') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "note name must be a non-empty file name",
+        )
+        .into());
+    }
+
+    Ok(format!("{DB_DOCS}/{name}"))
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn pushes_and_pulls_notes() {
+        let mut flipper = EmulatedFlipper::new();
+
+        push_text(&mut flipper, "todo.txt", "buy milk").unwrap();
+        push_text(&mut flipper, "todo.txt", "buy oat milk").unwrap();
+
+        assert!(flipper.is_dir(DB_DOCS));
+        assert_eq!(pull_text(&mut flipper, "todo.txt").unwrap(), "buy oat milk");
+        assert!(pull_text(&mut flipper, "missing.txt").is_err());
+        assert!(push_text(&mut flipper, "../todo.txt", "").is_err());
+    }
+}
@@ -1,3 +1,28 @@
+//! Generated bindings for the official Flipper protobuf schema
+//!
+//! [`prost`] is re-exported so downstream crates that build or decode raw messages use the same
+//! version these bindings were generated with. A separately pinned prost of another version has
+//! its own `Message` trait, which the types here do not implement.
+//!
+//! The large payload fields, `PB_Storage.File.data` and `PB_Gui.ScreenFrame.data`, are generated
+//! as [`prost::bytes::Bytes`] (`prost_build::Config::bytes`). Decoding from a `Bytes` buffer then
+//! slices the payload out of it instead of copying it.
+//!
+//! ```
+//! use flipper_rpc::proto::{self, prost::Message};
+//!
+//! let bytes = proto::Main::default().encode_length_delimited_to_vec();
+//! assert_eq!(proto::Main::decode_length_delimited(&*bytes).unwrap(), proto::Main::default());
+//! ```
+
+pub use prost;
+
+/// Version of the Flipper protobuf schema these bindings were generated from, as major and minor
+pub const SCHEMA_VERSION: (u32, u32) = (0, 25);
+
+/// Version requirement of prost these bindings were generated with
+pub const PROST_VERSION: &str = "0.14";
+
 pub mod app;
 pub mod desktop;
 pub mod gpio;
@@ -8,3 +33,16 @@ pub mod system;
 
 mod flipper;
 pub use flipper::*;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn prost_version_matches_the_dependency() {
+        let manifest = include_str!("../Cargo.toml");
+
+        assert!(
+            manifest.contains(&format!("prost = {{ version = \"{}.", super::PROST_VERSION)),
+            "update PROST_VERSION along with the prost dependency"
+        );
+    }
+}
@@ -7,8 +7,8 @@ pub struct File {
     pub name: ::prost::alloc::string::String,
     #[prost(uint32, tag = "3")]
     pub size: u32,
-    #[prost(bytes = "vec", tag = "4")]
-    pub data: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "bytes", tag = "4")]
+    pub data: ::prost::bytes::Bytes,
     #[prost(string, tag = "5")]
     pub md5sum: ::prost::alloc::string::String,
 }
@@ -0,0 +1,259 @@
+//! Helpers over the generated protobuf types
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+
+use prost::Message;
+
+use crate::{
+    error::{Error, Result},
+    proto::{self, CommandStatus, main::Content},
+};
+
+impl proto::Main {
+    /// Creates a message with `content`, command id 0, status OK and no `has_next`.
+    ///
+    /// Set the rest with [`proto::Main::with_command_id`] and [`proto::Main::with_has_next`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flipper_rpc::proto::{self, system::PingRequest};
+    ///
+    /// let ping = proto::Main::new(PingRequest { data: vec![1, 2, 3] }).with_command_id(1);
+    /// ```
+    pub fn new(content: impl Into<Content>) -> Self {
+        Self {
+            command_id: 0,
+            command_status: CommandStatus::Ok.into(),
+            has_next: false,
+            content: Some(content.into()),
+        }
+    }
+
+    /// Sets the command id in a proto
+    pub fn with_command_id(mut self, command_id: u32) -> Self {
+        self.command_id = command_id;
+
+        self
+    }
+
+    /// Sets the has_next flag in a proto
+    pub fn with_has_next(mut self, has_next: bool) -> Self {
+        self.has_next = has_next;
+
+        self
+    }
+}
+
+/// Implements `From<payload>` for every [`Content`] variant
+macro_rules! content_from {
+    ($($variant:ident($typ:ty)),* $(,)?) => {
+        $(
+            impl From<$typ> for Content {
+                fn from(value: $typ) -> Self {
+                    Content::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+use proto::{app, desktop, gpio, gui, property, storage, system};
+
+content_from! {
+    Empty(proto::Empty),
+    StopSession(proto::StopSession),
+    SystemPingRequest(system::PingRequest),
+    SystemPingResponse(system::PingResponse),
+    SystemRebootRequest(system::RebootRequest),
+    SystemDeviceInfoRequest(system::DeviceInfoRequest),
+    SystemDeviceInfoResponse(system::DeviceInfoResponse),
+    SystemFactoryResetRequest(system::FactoryResetRequest),
+    SystemGetDatetimeRequest(system::GetDateTimeRequest),
+    SystemGetDatetimeResponse(system::GetDateTimeResponse),
+    SystemSetDatetimeRequest(system::SetDateTimeRequest),
+    SystemPlayAudiovisualAlertRequest(system::PlayAudiovisualAlertRequest),
+    SystemProtobufVersionRequest(system::ProtobufVersionRequest),
+    SystemProtobufVersionResponse(system::ProtobufVersionResponse),
+    SystemUpdateRequest(system::UpdateRequest),
+    SystemUpdateResponse(system::UpdateResponse),
+    SystemPowerInfoRequest(system::PowerInfoRequest),
+    SystemPowerInfoResponse(system::PowerInfoResponse),
+    StorageInfoRequest(storage::InfoRequest),
+    StorageInfoResponse(storage::InfoResponse),
+    StorageTimestampRequest(storage::TimestampRequest),
+    StorageTimestampResponse(storage::TimestampResponse),
+    StorageStatRequest(storage::StatRequest),
+    StorageStatResponse(storage::StatResponse),
+    StorageListRequest(storage::ListRequest),
+    StorageListResponse(storage::ListResponse),
+    StorageReadRequest(storage::ReadRequest),
+    StorageReadResponse(storage::ReadResponse),
+    StorageWriteRequest(storage::WriteRequest),
+    StorageDeleteRequest(storage::DeleteRequest),
+    StorageMkdirRequest(storage::MkdirRequest),
+    StorageRenameRequest(storage::RenameRequest),
+    StorageBackupCreateRequest(storage::BackupCreateRequest),
+    StorageBackupRestoreRequest(storage::BackupRestoreRequest),
+    StorageTarExtractRequest(storage::TarExtractRequest),
+    AppStartRequest(app::StartRequest),
+    AppLockStatusRequest(app::LockStatusRequest),
+    AppLockStatusResponse(app::LockStatusResponse),
+    AppExitRequest(app::AppExitRequest),
+    AppLoadFileRequest(app::AppLoadFileRequest),
+    AppButtonPressRequest(app::AppButtonPressRequest),
+    AppButtonReleaseRequest(app::AppButtonReleaseRequest),
+    AppButtonPressReleaseRequest(app::AppButtonPressReleaseRequest),
+    AppGetErrorRequest(app::GetErrorRequest),
+    AppGetErrorResponse(app::GetErrorResponse),
+    AppDataExchangeRequest(app::DataExchangeRequest),
+    GuiStartScreenStreamRequest(gui::StartScreenStreamRequest),
+    GuiStopScreenStreamRequest(gui::StopScreenStreamRequest),
+    GuiScreenFrame(gui::ScreenFrame),
+    GuiSendInputEventRequest(gui::SendInputEventRequest),
+    GuiStartVirtualDisplayRequest(gui::StartVirtualDisplayRequest),
+    GuiStopVirtualDisplayRequest(gui::StopVirtualDisplayRequest),
+    GpioSetPinMode(gpio::SetPinMode),
+    GpioSetInputPull(gpio::SetInputPull),
+    GpioGetPinMode(gpio::GetPinMode),
+    GpioGetPinModeResponse(gpio::GetPinModeResponse),
+    GpioReadPin(gpio::ReadPin),
+    GpioReadPinResponse(gpio::ReadPinResponse),
+    GpioWritePin(gpio::WritePin),
+    GpioGetOtgMode(gpio::GetOtgMode),
+    GpioGetOtgModeResponse(gpio::GetOtgModeResponse),
+    GpioSetOtgMode(gpio::SetOtgMode),
+    AppStateResponse(app::AppStateResponse),
+    PropertyGetRequest(property::GetRequest),
+    PropertyGetResponse(property::GetResponse),
+    DesktopIsLockedRequest(desktop::IsLockedRequest),
+    DesktopUnlockRequest(desktop::UnlockRequest),
+    DesktopStatusSubscribeRequest(desktop::StatusSubscribeRequest),
+    DesktopStatusUnsubscribeRequest(desktop::StatusUnsubscribeRequest),
+    DesktopStatus(desktop::Status),
+}
+
+impl CommandStatus {
+    /// The numeric code the firmware sends for this status
+    pub fn as_error_code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    /// Formats as the schema name, e.g. `ERROR_BUSY`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str_name())
+    }
+}
+
+/// Error returned when parsing a [`CommandStatus`] from a string fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCommandStatusError(String);
+
+impl fmt::Display for ParseCommandStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown command status: {}", self.0)
+    }
+}
+
+impl core::error::Error for ParseCommandStatusError {}
+
+impl core::str::FromStr for CommandStatus {
+    type Err = ParseCommandStatusError;
+
+    /// Parses a schema name (`ERROR_BUSY`) or a numeric code (`5`)
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let s = s.trim();
+
+        Self::from_str_name(s)
+            .or_else(|| {
+                s.parse::<i32>()
+                    .ok()
+                    .and_then(|code| Self::try_from(code).ok())
+            })
+            .ok_or_else(|| ParseCommandStatusError(s.to_string()))
+    }
+}
+
+/// Encodes `message` length-delimited into `buf`, replacing its contents.
+///
+/// Reusing the same `buf` across messages keeps its allocation, so a busy stream (screen frames,
+/// write chains) does not allocate once per message.
+pub fn encode_into(message: &proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+    buf.clear();
+    message.encode_length_delimited(buf)?;
+
+    Ok(())
+}
+
+/// Splits a length-delimited frame header off the front of `buf`.
+///
+/// Returns `(header_len, body_len)`, or `None` if the varint length is not complete yet.
+pub fn frame_len(buf: &[u8]) -> Result<Option<(usize, usize)>> {
+    // Varints end at the first byte without the continuation bit, and are at most 10 bytes
+    match buf.iter().take(10).position(|byte| byte & 0x80 == 0) {
+        Some(end) => {
+            let body_len = prost::decode_length_delimiter(&buf[..=end])?;
+
+            Ok(Some((end + 1, body_len)))
+        }
+        None if buf.len() < 10 => Ok(None),
+        None => Err(Error::InvalidFrame("length prefix is longer than 10 bytes")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{main::Content, system::PingRequest};
+
+    #[test]
+    fn matches_allocating_encode_and_keeps_capacity() {
+        let message = proto::Main {
+            command_id: 3,
+            content: Some(Content::SystemPingRequest(PingRequest {
+                data: vec![1, 2, 3],
+            })),
+            ..Default::default()
+        };
+
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(b"stale");
+
+        encode_into(&message, &mut buf).unwrap();
+
+        assert_eq!(buf, message.encode_length_delimited_to_vec());
+        assert!(buf.capacity() >= 64);
+    }
+
+    #[test]
+    fn new_wraps_content() {
+        let message = proto::Main::new(PingRequest { data: vec![1] }).with_command_id(4);
+
+        assert_eq!(message.command_id, 4);
+        assert_eq!(
+            message.content,
+            Some(Content::SystemPingRequest(PingRequest { data: vec![1] }))
+        );
+    }
+
+    #[test]
+    fn command_status_round_trips() {
+        for code in 0..64 {
+            let Ok(status) = CommandStatus::try_from(code) else {
+                continue;
+            };
+
+            assert_eq!(status.as_error_code(), code);
+            assert_eq!(status.to_string().parse::<CommandStatus>(), Ok(status));
+            assert_eq!(code.to_string().parse::<CommandStatus>(), Ok(status));
+        }
+
+        assert!("ERROR_NOPE".parse::<CommandStatus>().is_err());
+    }
+}
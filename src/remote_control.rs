@@ -0,0 +1,483 @@
+//! Remote control: the screen stream and input events in one session
+//!
+//! Controlling a Flipper from a PC means mirroring its screen while sending button presses. Both
+//! share one RPC stream: frames are pushed by the device with command id `0`, while every input
+//! event is answered with an acknowledgement under its own id. [`RemoteControl`] keeps the two
+//! apart. Inputs are sent without waiting, their acknowledgements are consumed while frames are
+//! read, and a rejected input is reported by the next call that reads from the device.
+//!
+//! The device streams frames as fast as the screen changes. A consumer that handles frames more
+//! slowly than that, e.g. a UI redrawing at its own pace, falls behind and lets the serial
+//! buffers fill up. [`FrameMode::Latest`] reads everything that is pending on every call and
+//! keeps only the newest frame, counting the rest as dropped in [`FrameStats`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::proto::gui::InputKey;
+//! use flipper_rpc::remote_control::RemoteControl;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! let mut remote = RemoteControl::start(rpc)?;
+//!
+//! remote.press(InputKey::Ok)?;
+//! let frame = remote.next_frame()?;
+//! println!("{} bytes of screen", frame.data.len());
+//!
+//! remote.stop()?;
+//! let _rpc = remote.into_inner();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+
+use crate::cancel::CancelToken;
+use crate::logging::{debug, trace};
+
+use crate::proto::app::{AppState, AppStateResponse};
+use crate::proto::desktop::Status;
+use crate::proto::gui::{
+    InputKey, InputType, ScreenFrame, SendInputEventRequest, StartScreenStreamRequest,
+    StopScreenStreamRequest,
+};
+use crate::transport::{CommandIndex, check_status};
+use crate::{
+    error::{Error, Result},
+    proto::{self, main::Content},
+    rpc::{req::Request, res::Response},
+    transport::TransportRaw,
+};
+
+/// Something the device pushed on its own during a remote control session
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum RemoteEvent {
+    /// A screen frame
+    Frame(ScreenFrame),
+    /// The desktop was locked or unlocked, sent after a `DesktopStatusSubscribe` request
+    DesktopStatus(Status),
+    /// An app was started or closed
+    AppState(AppState),
+}
+
+/// How frames that were not read yet are kept, see [`RemoteControl::with_frame_mode`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameMode {
+    /// Every frame is delivered, in order
+    #[default]
+    Queue,
+    /// Only the newest unread frame is kept. Reads drain everything pending on the transport
+    /// first, so a slow consumer always gets the current screen. Needs a transport that
+    /// implements [`TransportRaw::try_receive_raw`] without blocking.
+    Latest,
+}
+
+/// Frame counters of a [`RemoteControl`] session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Frames received from the device
+    pub received: u64,
+    /// Frames returned to the caller
+    pub delivered: u64,
+    /// Frames replaced by a newer one before they were read
+    pub dropped: u64,
+}
+
+/// A screen stream with input, over the transport `T`
+#[derive(Debug)]
+pub struct RemoteControl<T> {
+    transport: T,
+    /// Command ids that were sent but not answered yet, oldest first
+    pending: VecDeque<u32>,
+    /// Events that arrived while waiting for a response
+    backlog: VecDeque<RemoteEvent>,
+    last_frame: Option<ScreenFrame>,
+    frame_mode: FrameMode,
+    frame_stats: FrameStats,
+    cancel: Option<CancelToken>,
+}
+
+impl<T> RemoteControl<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Starts the screen stream on `transport`.
+    ///
+    /// The stream keeps running until [`RemoteControl::stop`]; dropping the session without
+    /// stopping it leaves frames flowing on the transport.
+    pub fn start(mut transport: T) -> Result<Self> {
+        let id = send(
+            &mut transport,
+            Request::GuiStartScreenStream(StartScreenStreamRequest {}),
+        )?;
+
+        let mut remote = Self {
+            transport,
+            pending: VecDeque::new(),
+            backlog: VecDeque::new(),
+            last_frame: None,
+            frame_mode: FrameMode::default(),
+            frame_stats: FrameStats::default(),
+            cancel: None,
+        };
+        remote.wait_for(id)?;
+        debug!("remote control started");
+
+        Ok(remote)
+    }
+
+    /// Sets how unread frames are kept, [`FrameMode::Queue`] by default
+    pub fn with_frame_mode(mut self, frame_mode: FrameMode) -> Self {
+        self.frame_mode = frame_mode;
+        self
+    }
+
+    /// Makes reading events and frames fail with an [`std::io::ErrorKind::Interrupted`] error
+    /// once `token` is cancelled. The stream keeps running until [`RemoteControl::stop`].
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Frames received, delivered and dropped so far
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    /// Sends an input event without waiting for the device to acknowledge it
+    pub fn send_input(&mut self, key: InputKey, r#type: InputType) -> Result<()> {
+        let id = send(
+            &mut self.transport,
+            Request::GuiSendInputEvent(SendInputEventRequest {
+                key: key.into(),
+                r#type: r#type.into(),
+            }),
+        )?;
+        self.pending.push_back(id);
+
+        Ok(())
+    }
+
+    /// Sends a short button press: press, short, release
+    pub fn press(&mut self, key: InputKey) -> Result<()> {
+        for r#type in [InputType::Press, InputType::Short, InputType::Release] {
+            self.send_input(key, r#type)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a long button press: press, long, release
+    pub fn long_press(&mut self, key: InputKey) -> Result<()> {
+        for r#type in [InputType::Press, InputType::Long, InputType::Release] {
+            self.send_input(key, r#type)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends any other request and waits for its response. Events that arrive meanwhile are
+    /// kept for [`RemoteControl::next_event`].
+    pub fn request(&mut self, req: Request) -> Result<()> {
+        let id = send(&mut self.transport, req)?;
+
+        self.wait_for(id)
+    }
+
+    /// Blocks until the device pushes an event, consuming input acknowledgements on the way
+    pub fn next_event(&mut self) -> Result<RemoteEvent> {
+        loop {
+            self.check_cancelled()?;
+
+            if self.frame_mode == FrameMode::Latest {
+                self.drain_pending()?;
+            }
+
+            if let Some(event) = self.backlog.pop_front() {
+                if let RemoteEvent::Frame(_) = event {
+                    self.frame_stats.delivered += 1;
+                }
+
+                return Ok(event);
+            }
+
+            let main = self.receive()?;
+            if let Some(event) = self.handle(main)? {
+                self.queue(event);
+            }
+        }
+    }
+
+    /// Blocks until the next frame arrives, skipping other events
+    pub fn next_frame(&mut self) -> Result<ScreenFrame> {
+        loop {
+            if let RemoteEvent::Frame(frame) = self.next_event()? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Returns the newest frame that has already arrived, without blocking. Older frames and
+    /// other events that piled up are skipped.
+    pub fn poll_frame(&mut self) -> Result<Option<ScreenFrame>> {
+        self.check_cancelled()?;
+        self.drain_pending()?;
+
+        let mut newest = None;
+        for event in std::mem::take(&mut self.backlog) {
+            if let RemoteEvent::Frame(frame) = event {
+                if newest.replace(frame).is_some() {
+                    self.frame_stats.dropped += 1;
+                }
+            }
+        }
+
+        if newest.is_some() {
+            self.frame_stats.delivered += 1;
+        }
+
+        Ok(newest)
+    }
+
+    /// The most recent frame received
+    pub fn last_frame(&self) -> Option<&ScreenFrame> {
+        self.last_frame.as_ref()
+    }
+
+    /// Number of input events the device has not acknowledged yet
+    pub fn pending_inputs(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the wrapped transport. Reading from it directly can take
+    /// frames and acknowledgements away from the session.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns the wrapped transport. Call [`RemoteControl::stop`] first to end the stream.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Stops the screen stream. The session keeps the transport even if this fails, so it can be
+    /// retried or taken back with [`RemoteControl::into_inner`].
+    pub fn stop(&mut self) -> Result<()> {
+        let id = send(
+            &mut self.transport,
+            Request::GuiStopScreenStream(StopScreenStreamRequest {}),
+        )?;
+        self.wait_for(id)?;
+        debug!("remote control stopped");
+
+        Ok(())
+    }
+
+    /// Reads until the response to `id`, handling frames and acknowledgements meanwhile
+    fn wait_for(&mut self, id: u32) -> Result<()> {
+        self.pending.push_back(id);
+
+        while self.pending.contains(&id) {
+            let main = self.receive()?;
+
+            if let Some(event) = self.handle(main)? {
+                self.queue(event);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel {
+            Some(cancel) => cancel.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues everything that already arrived, without blocking
+    fn drain_pending(&mut self) -> Result<()> {
+        while let Some(main) = self.try_receive()? {
+            if let Some(event) = self.handle(main)? {
+                self.queue(event);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds an event to the backlog. In [`FrameMode::Latest`] a frame replaces the unread ones.
+    fn queue(&mut self, event: RemoteEvent) {
+        if self.frame_mode == FrameMode::Latest && matches!(event, RemoteEvent::Frame(_)) {
+            let before = self.backlog.len();
+            self.backlog
+                .retain(|event| !matches!(event, RemoteEvent::Frame(_)));
+            self.frame_stats.dropped += (before - self.backlog.len()) as u64;
+        }
+
+        self.backlog.push_back(event);
+    }
+
+    /// Receives without checking the status, so a rejected command keeps its id
+    fn receive(&mut self) -> Result<proto::Main> {
+        self.transport.receive_raw_unchecked()
+    }
+
+    fn try_receive(&mut self) -> Result<Option<proto::Main>> {
+        self.transport.try_receive_raw_unchecked()
+    }
+
+    /// Records an event or an acknowledgement, returning the event if it was one. A rejected
+    /// command is no longer pending and fails with its status.
+    fn handle(&mut self, main: proto::Main) -> Result<Option<RemoteEvent>> {
+        if main.command_id == 0 {
+            return Ok(match main.content {
+                Some(Content::GuiScreenFrame(frame)) => {
+                    self.frame_stats.received += 1;
+                    self.last_frame = Some(frame.clone());
+
+                    Some(RemoteEvent::Frame(frame))
+                }
+                Some(Content::DesktopStatus(status)) => Some(RemoteEvent::DesktopStatus(status)),
+                Some(Content::AppStateResponse(AppStateResponse { state })) => {
+                    AppState::try_from(state).ok().map(RemoteEvent::AppState)
+                }
+                _ => {
+                    trace!("skipping unsolicited message");
+                    None
+                }
+            });
+        }
+
+        if let Some(index) = self.pending.iter().position(|id| *id == main.command_id) {
+            self.pending.remove(index);
+        }
+
+        Response::try_from(check_status(main)?)?;
+
+        Ok(None)
+    }
+}
+
+/// Sends `req` under a fresh command id and returns the id
+fn send<T>(transport: &mut T, req: Request) -> Result<u32>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex,
+{
+    let id = transport.command_index();
+    transport.increment_command_index(1);
+
+    transport.send_raw(req.into_rpc(id))?;
+
+    Ok(id)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn frames_and_inputs_share_the_stream() {
+        let flipper = EmulatedFlipper::new().with_screen(vec![0xff; 1024], 3);
+        let mut remote = RemoteControl::start(flipper).unwrap();
+
+        remote.press(InputKey::Back).unwrap();
+        assert_eq!(remote.pending_inputs(), 3);
+
+        let frame = remote.next_frame().unwrap();
+        assert_eq!(frame.data, vec![0xff; 1024]);
+
+        // The rest of the frames are already queued, then the acknowledgements
+        assert!(remote.poll_frame().unwrap().is_some());
+        assert_eq!(remote.pending_inputs(), 0);
+        assert_eq!(remote.last_frame(), Some(&frame));
+
+        remote.stop().unwrap();
+        let flipper = remote.into_inner();
+        assert!(matches!(
+            flipper
+                .requests()
+                .last()
+                .and_then(|main| main.content.as_ref()),
+            Some(Content::GuiStopScreenStreamRequest(_))
+        ));
+    }
+
+    #[test]
+    fn latest_mode_drops_unread_frames() {
+        let flipper = EmulatedFlipper::new().with_screen(vec![0xff; 1024], 5);
+        let mut remote = RemoteControl::start(flipper)
+            .unwrap()
+            .with_frame_mode(FrameMode::Latest);
+
+        remote.next_frame().unwrap();
+        assert_eq!(
+            remote.frame_stats(),
+            FrameStats {
+                received: 5,
+                delivered: 1,
+                dropped: 4,
+            }
+        );
+        assert_eq!(remote.poll_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn cancelled_streams_stop_delivering_frames() {
+        let cancel = CancelToken::new();
+        let flipper = EmulatedFlipper::new().with_screen(vec![0xff; 1024], 3);
+        let mut remote = RemoteControl::start(flipper)
+            .unwrap()
+            .with_cancel_token(cancel.clone());
+
+        remote.next_frame().unwrap();
+        cancel.cancel();
+
+        assert!(matches!(
+            remote.next_frame(),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::Interrupted
+        ));
+        remote.stop().unwrap();
+    }
+
+    #[test]
+    fn failures_are_matched_to_their_command() {
+        let flipper = EmulatedFlipper::new();
+        let mut remote = RemoteControl::start(flipper).unwrap();
+        remote.next_frame().unwrap();
+
+        // A request sent around the session fails before the input is acknowledged
+        let rpc = remote.get_mut();
+        rpc.fail_next(proto::CommandStatus::ErrorBusy);
+        send(rpc, Request::Ping(vec![1])).unwrap();
+        remote.send_input(InputKey::Ok, InputType::Press).unwrap();
+
+        let error = remote.poll_frame().expect_err("the ping was rejected");
+        assert_eq!(error.rpc_status(), Some(proto::CommandStatus::ErrorBusy));
+        assert_eq!(remote.pending_inputs(), 1);
+
+        remote.poll_frame().unwrap();
+        assert_eq!(remote.pending_inputs(), 0);
+    }
+
+    #[test]
+    fn failed_stops_keep_the_transport() {
+        let mut remote = RemoteControl::start(EmulatedFlipper::new()).unwrap();
+
+        remote.get_mut().fail_next(proto::CommandStatus::ErrorBusy);
+        assert!(remote.stop().is_err());
+
+        remote.stop().unwrap();
+        assert!(remote.into_inner().requests().len() >= 3);
+    }
+}
@@ -5,5 +5,7 @@
 //! Enabled through easy-rpc feature
 
 pub mod error;
+#[cfg(feature = "script")]
+pub mod parse;
 pub mod req;
 pub mod res;
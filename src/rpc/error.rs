@@ -2,31 +2,175 @@
 //! Error types are meant to be used with results, and an Ok is either Ok(()) or Ok(some data to
 //! return)
 
+use alloc::{boxed::Box, format, string::String};
+
 use thiserror::Error;
 
-use crate::{error::Result, proto::CommandStatus};
+use crate::{
+    error::Result,
+    proto::{self, CommandStatus, main::Content},
+};
 
 #[derive(Error, Debug)]
 #[non_exhaustive]
 /// Generic error type for all RPC errors
 pub enum Error {
-    #[error("command: {0}")]
+    #[error("command: {0} [code {code}]", code = .0.code())]
     /// Command error
     CommandError(#[from] CommandError),
-    #[error("storage: {0}")]
+    #[error("storage: {0} [code {code}]", code = .0.code())]
     /// Storage error
     StorageError(#[from] StorageError),
-    #[error("application: {0}")]
+    #[error("application: {0} [code {code}]", code = .0.code())]
     /// Application error
     ApplicationError(#[from] ApplicationError),
-    #[error("virtual display: {0}")]
+    #[error("virtual display: {0} [code {code}]", code = .0.code())]
     /// Virtual Display error
     VirtualDisplayError(#[from] VirtualDisplayError),
-    #[error("gpio: {0}")]
+    #[error("gpio: {0} [code {code}]", code = .0.code())]
     /// GPIO error
     GPIOError(#[from] GPIOError),
+    #[error("{error} (remote content: {content})")]
+    /// An error whose failing response carried content, such as the path echoed back by storage
+    /// commands. Use [`Error::root`] to match on the underlying error.
+    WithContent {
+        /// The mapped error
+        #[source]
+        error: Box<Error>,
+        /// Debug snapshot of the response content, truncated to [`CONTENT_SNAPSHOT_LIMIT`] bytes
+        content: String,
+    },
+}
+
+/// Maximum length of the content snapshot in [`Error::WithContent`]
+pub const CONTENT_SNAPSHOT_LIMIT: usize = 256;
+
+impl Error {
+    /// The firmware status this error was mapped from
+    pub fn status(&self) -> CommandStatus {
+        match self {
+            Error::CommandError(e) => e.status(),
+            Error::StorageError(e) => e.status(),
+            Error::ApplicationError(e) => e.status(),
+            Error::VirtualDisplayError(e) => e.status(),
+            Error::GPIOError(e) => e.status(),
+            Error::WithContent { error, .. } => error.status(),
+        }
+    }
+
+    /// The mapped error without any attached content snapshot
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContent { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// The debug snapshot of the failing response's content, if it carried any
+    pub fn content(&self) -> Option<&str> {
+        match self {
+            Error::WithContent { content, .. } => Some(content),
+            _ => None,
+        }
+    }
+
+    /// The raw numeric firmware error code
+    pub fn code(&self) -> i32 {
+        self.status().as_error_code()
+    }
+
+    /// The closest [`std::io::ErrorKind`], used when converting into a [`std::io::Error`]
+    #[cfg(feature = "std")]
+    pub fn io_kind(&self) -> std::io::ErrorKind {
+        use std::io::ErrorKind;
+
+        match self.root() {
+            Error::CommandError(error) => match error {
+                CommandError::Decode => ErrorKind::InvalidData,
+                CommandError::NotImplemented => ErrorKind::Unsupported,
+                CommandError::Busy => ErrorKind::ResourceBusy,
+                CommandError::ContinuousCommandInterrupted => ErrorKind::Interrupted,
+                CommandError::InvalidParameters => ErrorKind::InvalidInput,
+                CommandError::Unknown => ErrorKind::Other,
+            },
+            Error::StorageError(error) => match error {
+                StorageError::AlreadyExists => ErrorKind::AlreadyExists,
+                StorageError::NotFound => ErrorKind::NotFound,
+                StorageError::InvalidParameter | StorageError::InvalidName => {
+                    ErrorKind::InvalidInput
+                }
+                StorageError::PermissionDenied => ErrorKind::PermissionDenied,
+                StorageError::NotImplemented => ErrorKind::Unsupported,
+                StorageError::AlreadyOpen => ErrorKind::ResourceBusy,
+                StorageError::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+                StorageError::NotReady | StorageError::Internal => ErrorKind::Other,
+            },
+            Error::ApplicationError(ApplicationError::SystemLocked) => ErrorKind::PermissionDenied,
+            Error::ApplicationError(_) | Error::VirtualDisplayError(_) => ErrorKind::Other,
+            Error::GPIOError(_) => ErrorKind::InvalidInput,
+            Error::WithContent { .. } => unreachable!("root never returns WithContent"),
+        }
+    }
 }
 
+/// Adds `status()` and `code()` to an error enum, mapping each variant back to its status
+macro_rules! status_codes {
+    ($name:ident { $($variant:ident => $status:ident),* $(,)? }) => {
+        impl $name {
+            /// The firmware status this error was mapped from
+            pub fn status(&self) -> CommandStatus {
+                match self {
+                    $($name::$variant => CommandStatus::$status,)*
+                }
+            }
+
+            /// The raw numeric firmware error code
+            pub fn code(&self) -> i32 {
+                self.status().as_error_code()
+            }
+        }
+    };
+}
+
+status_codes!(CommandError {
+    Unknown => Error,
+    Decode => ErrorDecode,
+    NotImplemented => ErrorNotImplemented,
+    Busy => ErrorBusy,
+    ContinuousCommandInterrupted => ErrorContinuousCommandInterrupted,
+    InvalidParameters => ErrorInvalidParameters,
+});
+
+status_codes!(StorageError {
+    NotReady => ErrorStorageNotReady,
+    AlreadyExists => ErrorStorageExist,
+    NotFound => ErrorStorageNotExist,
+    InvalidParameter => ErrorStorageInvalidParameter,
+    PermissionDenied => ErrorStorageDenied,
+    InvalidName => ErrorStorageInvalidName,
+    Internal => ErrorStorageInternal,
+    NotImplemented => ErrorStorageNotImplemented,
+    AlreadyOpen => ErrorStorageAlreadyOpen,
+    DirectoryNotEmpty => ErrorStorageDirNotEmpty,
+});
+
+status_codes!(ApplicationError {
+    CannotStart => ErrorAppCantStart,
+    SystemLocked => ErrorAppSystemLocked,
+    RpcUnavailable => ErrorAppNotRunning,
+    CommandExecution => ErrorAppCmdError,
+});
+
+status_codes!(VirtualDisplayError {
+    AlreadyStarted => ErrorVirtualDisplayAlreadyStarted,
+    NotStarted => ErrorVirtualDisplayNotStarted,
+});
+
+status_codes!(GPIOError {
+    IncorrectMode => ErrorGpioModeIncorrect,
+    UnknownMode => ErrorGpioUnknownPinMode,
+});
+
 #[derive(Error, Debug)]
 #[non_exhaustive]
 /// Command errors
@@ -133,10 +277,32 @@ pub enum GPIOError {
 }
 
 impl CommandStatus {
+    /// Like [`CommandStatus::into_result`] for a received message. If the status is an error and
+    /// the message carries content, a debug snapshot of it is attached as
+    /// [`Error::WithContent`].
+    pub fn into_result_with_content(self, main: proto::Main) -> Result<proto::Main> {
+        let error = match self.into_result(()) {
+            Ok(()) => return Ok(main),
+            Err(crate::error::Error::Rpc(error)) => error,
+            Err(e) => return Err(e),
+        };
+
+        let content = match main.content {
+            None | Some(Content::Empty(_)) => return Err(error.into()),
+            Some(content) => snapshot(&content),
+        };
+
+        Err(Error::WithContent {
+            error: Box::new(error),
+            content,
+        }
+        .into())
+    }
+
     /// Converts a CommandStatus and a value into a Result<T, Error> using the commandstatus as the
     /// Err value and the value as the Ok value.
     pub fn into_result<T>(self, value: T) -> Result<T> {
-        let result: std::result::Result<T, Error> = match self {
+        let result: core::result::Result<T, Error> = match self {
             CommandStatus::Ok => Ok(value),
             CommandStatus::Error => Err(CommandError::Unknown.into()),
             CommandStatus::ErrorDecode => Err(CommandError::Decode.into()),
@@ -175,3 +341,82 @@ impl CommandStatus {
         result.map_err(Into::into)
     }
 }
+
+/// Debug-formats `content`, cut to [`CONTENT_SNAPSHOT_LIMIT`] bytes on a char boundary
+fn snapshot(content: &Content) -> String {
+    let mut snapshot = format!("{content:?}");
+
+    if snapshot.len() > CONTENT_SNAPSHOT_LIMIT {
+        let mut end = CONTENT_SNAPSHOT_LIMIT;
+        while !snapshot.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        snapshot.truncate(end);
+        snapshot.push_str("...");
+    }
+
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_errors_keep_their_status() {
+        for code in 1..64 {
+            let Ok(status) = CommandStatus::try_from(code) else {
+                continue;
+            };
+
+            let Err(crate::error::Error::Rpc(error)) = status.into_result(()) else {
+                panic!("{status} should map to an rpc error");
+            };
+
+            assert_eq!(error.status(), status);
+            assert_eq!(error.code(), code);
+            assert!(error.to_string().ends_with(&format!("[code {code}]")));
+        }
+    }
+
+    #[test]
+    fn failing_content_is_attached() {
+        let main = proto::Main::new(proto::storage::StatRequest {
+            path: "/ext/missing.txt".to_string(),
+        });
+
+        let Err(crate::error::Error::Rpc(error)) =
+            CommandStatus::ErrorStorageNotExist.into_result_with_content(main.clone())
+        else {
+            panic!("should map to an rpc error");
+        };
+
+        assert!(matches!(
+            error.root(),
+            Error::StorageError(StorageError::NotFound)
+        ));
+        assert_eq!(error.status(), CommandStatus::ErrorStorageNotExist);
+        assert!(error.content().unwrap().contains("/ext/missing.txt"));
+        assert!(error.to_string().contains("/ext/missing.txt"));
+
+        let empty = proto::Main::new(proto::Empty {});
+        let Err(crate::error::Error::Rpc(error)) =
+            CommandStatus::ErrorBusy.into_result_with_content(empty)
+        else {
+            panic!("should map to an rpc error");
+        };
+
+        assert!(error.content().is_none());
+        assert!(CommandStatus::Ok.into_result_with_content(main).is_ok());
+    }
+
+    #[test]
+    fn snapshots_are_truncated() {
+        let content = Content::SystemPingResponse(proto::system::PingResponse {
+            data: vec![0xAA; 1024],
+        });
+
+        assert_eq!(snapshot(&content).len(), CONTENT_SNAPSHOT_LIMIT + 3);
+    }
+}
@@ -0,0 +1,597 @@
+//! Text parser for [`Request`], for REPLs and config-driven tools
+//!
+//! A command is a group, a command name and its arguments, separated by whitespace:
+//!
+//! ```text
+//! storage read /ext/foo.txt
+//! storage rename "/ext/old name.txt" /ext/new.txt
+//! gui input ok short
+//! ```
+//!
+//! Arguments containing whitespace are quoted with `"` or `'`; inside double quotes `\` escapes
+//! the next character. Groups, command names and enum values are case-insensitive, paths and
+//! text are kept as written. [`COMMANDS`] lists the whole grammar, e.g. for a `help` command.
+//!
+//! Requests that can't be expressed as text, or that should not be one typo away, are left out:
+//! factory resets, setting the clock, app data exchange and virtual display frames.
+//!
+//! Enabled through the `script` feature
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use prost::bytes::Bytes;
+use thiserror::Error;
+
+use super::req::Request;
+use crate::proto::{
+    app::{
+        AppButtonPressReleaseRequest, AppButtonPressRequest, AppButtonReleaseRequest,
+        AppExitRequest, AppLoadFileRequest, GetErrorRequest, LockStatusRequest, StartRequest,
+    },
+    desktop::{IsLockedRequest, StatusSubscribeRequest, StatusUnsubscribeRequest, UnlockRequest},
+    gpio::{
+        GetOtgMode, GetPinMode, GpioInputPull, GpioOtgMode, GpioPin, GpioPinMode, ReadPin,
+        SetInputPull, SetOtgMode, SetPinMode, WritePin,
+    },
+    gui::{
+        InputKey, InputType, SendInputEventRequest, StartScreenStreamRequest,
+        StartVirtualDisplayRequest, StopScreenStreamRequest, StopVirtualDisplayRequest,
+    },
+    property::GetRequest,
+    storage::{DeleteRequest, File, InfoRequest, ListRequest, TimestampRequest, WriteRequest},
+    system::{UpdateRequest, reboot_request::RebootMode},
+};
+
+/// Usage of every command [`Request::parse`] understands, one per line
+pub const COMMANDS: &[&str] = &[
+    "ping [data]",
+    "stop-session",
+    "system info",
+    "system datetime",
+    "system protobuf",
+    "system power",
+    "system alert",
+    "system reboot [os|dfu|update]",
+    "system update <manifest>",
+    "storage info <path>",
+    "storage timestamp <path>",
+    "storage stat <path>",
+    "storage list <path> [--md5]",
+    "storage read <path>",
+    "storage write <path> <text>",
+    "storage delete <path> [--recursive]",
+    "storage mkdir <path>",
+    "storage md5 <path>",
+    "storage rename <from> <to>",
+    "storage extract <tar> <out>",
+    "storage backup-create <archive>",
+    "storage backup-restore <archive>",
+    "app start <name> [args]",
+    "app exit",
+    "app load-file <path>",
+    "app press <args> [index]",
+    "app release",
+    "app press-release <args> [index]",
+    "app lock-status",
+    "app error",
+    "gui input <up|down|left|right|ok|back> <press|release|short|long|repeat>",
+    "gui screen-stream <start|stop>",
+    "gui virtual-display <start|stop> [--input]",
+    "gpio mode <pin>",
+    "gpio set-mode <pin> <input|output>",
+    "gpio pull <pin> <no|up|down>",
+    "gpio read <pin>",
+    "gpio write <pin> <0|1>",
+    "gpio otg [on|off]",
+    "property get <key>",
+    "desktop locked",
+    "desktop unlock",
+    "desktop subscribe",
+    "desktop unsubscribe",
+];
+
+/// Error returned by [`Request::parse`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseRequestError {
+    #[error("empty command")]
+    /// The text holds no command
+    Empty,
+
+    #[error("unterminated quote")]
+    /// A quoted argument is not closed
+    UnterminatedQuote,
+
+    #[error("unknown command: {0}")]
+    /// No command of this name, see [`COMMANDS`]
+    UnknownCommand(String),
+
+    #[error("{command}: missing argument <{argument}>")]
+    /// A required argument is missing
+    MissingArgument {
+        /// The command being parsed
+        command: &'static str,
+        /// Name of the missing argument
+        argument: &'static str,
+    },
+
+    #[error("{command}: unexpected argument {value}")]
+    /// More arguments than the command takes
+    UnexpectedArgument {
+        /// The command being parsed
+        command: &'static str,
+        /// The first extra argument
+        value: String,
+    },
+
+    #[error("{command}: invalid <{argument}>: {value}")]
+    /// An argument that is not one of the accepted values or not a number
+    InvalidArgument {
+        /// The command being parsed
+        command: &'static str,
+        /// Name of the argument
+        argument: &'static str,
+        /// The rejected value
+        value: String,
+    },
+}
+
+type Result<T> = core::result::Result<T, ParseRequestError>;
+
+impl Request {
+    /// Parses a request from one line of text, e.g. `storage read /ext/foo.txt`. See the
+    /// [module docs](self) for the grammar.
+    ///
+    /// ```
+    /// use flipper_rpc::rpc::req::Request;
+    ///
+    /// let request = Request::parse("storage read /ext/foo.txt").unwrap();
+    /// assert!(matches!(request, Request::StorageRead(path) if path == "/ext/foo.txt"));
+    /// ```
+    pub fn parse(text: &str) -> Result<Request> {
+        let words = split(text)?;
+        let Some(first) = words.first() else {
+            return Err(ParseRequestError::Empty);
+        };
+        let (command, len) = lookup(&words).ok_or_else(|| {
+            let name = words.iter().take(2).map(String::as_str).collect::<Vec<_>>();
+            ParseRequestError::UnknownCommand(if words.len() > 1 && is_group(first) {
+                name.join(" ")
+            } else {
+                first.clone()
+            })
+        })?;
+        let mut args = Args {
+            command,
+            words: words.into_iter().skip(len).collect(),
+        };
+
+        let request = match command {
+            "ping" => Request::Ping(args.optional().map(String::into_bytes).unwrap_or_default()),
+            "stop-session" => Request::StopSession,
+
+            "system info" => Request::SystemDeviceInfo,
+            "system datetime" => Request::SystemGetDatetime,
+            "system protobuf" => Request::SystemProtobufVersion,
+            "system power" => Request::SystemPowerInfo,
+            "system alert" => Request::PlayAvAlert,
+            "system reboot" => Request::Reboot(match args.optional() {
+                Some(mode) => args.enumeration("mode", &mode, RebootMode::from_str_name)?,
+                None => RebootMode::Os,
+            }),
+            "system update" => Request::SystemUpdate(UpdateRequest {
+                update_manifest: args.required("manifest")?,
+            }),
+
+            "storage info" => Request::StorageInfo(InfoRequest {
+                path: args.required("path")?,
+            }),
+            "storage timestamp" => Request::StorageTimestamp(TimestampRequest {
+                path: args.required("path")?,
+            }),
+            "storage stat" => Request::StorageMetadata(args.required("path")?),
+            "storage list" => {
+                let include_md5 = args.flag("--md5");
+                Request::StorageList(ListRequest {
+                    path: args.required("path")?,
+                    include_md5,
+                    filter_max_size: 0,
+                })
+            }
+            "storage read" => Request::StorageRead(args.required("path")?),
+            "storage write" => {
+                let path = args.required("path")?;
+                let data = Bytes::from(args.required("text")?);
+                Request::StorageWrite(WriteRequest {
+                    path,
+                    file: Some(File {
+                        size: data.len() as u32,
+                        data,
+                        ..Default::default()
+                    }),
+                })
+            }
+            "storage delete" => {
+                let recursive = args.flag("--recursive") | args.flag("-r");
+                Request::StorageDelete(DeleteRequest {
+                    path: args.required("path")?,
+                    recursive,
+                })
+            }
+            "storage mkdir" => Request::StorageMkdir(args.required("path")?),
+            "storage md5" => Request::StorageMd5sum(args.required("path")?),
+            "storage rename" => {
+                Request::StorageRename(args.required("from")?, args.required("to")?)
+            }
+            "storage extract" => {
+                Request::StorageTarExtract(args.required("tar")?, args.required("out")?)
+            }
+            "storage backup-create" => Request::StorageBackupCreate(args.required("archive")?),
+            "storage backup-restore" => Request::StorageBackupRestore(args.required("archive")?),
+
+            "app start" => Request::AppStart(StartRequest {
+                name: args.required("name")?,
+                args: args.optional().unwrap_or_default(),
+            }),
+            "app exit" => Request::AppExit(AppExitRequest {}),
+            "app load-file" => Request::AppLoadFile(AppLoadFileRequest {
+                path: args.required("path")?,
+            }),
+            "app press" => Request::AppButtonPress(AppButtonPressRequest {
+                args: args.required("args")?,
+                index: args.number("index")?,
+            }),
+            "app release" => Request::AppButtonRelease(AppButtonReleaseRequest {}),
+            "app press-release" => Request::AppButtonPressRelease(AppButtonPressReleaseRequest {
+                args: args.required("args")?,
+                index: args.number("index")?,
+            }),
+            "app lock-status" => Request::AppLockStatus(LockStatusRequest {}),
+            "app error" => Request::AppGetError(GetErrorRequest {}),
+
+            "gui input" => {
+                let key = args.required("key")?;
+                let key = args.enumeration("key", &key, InputKey::from_str_name)?;
+                let kind = args.required("type")?;
+                let kind = args.enumeration("type", &kind, InputType::from_str_name)?;
+                Request::GuiSendInputEvent(SendInputEventRequest {
+                    key: key.into(),
+                    r#type: kind.into(),
+                })
+            }
+            "gui screen-stream" => match args.start_stop()? {
+                true => Request::GuiStartScreenStream(StartScreenStreamRequest {}),
+                false => Request::GuiStopScreenStream(StopScreenStreamRequest {}),
+            },
+            "gui virtual-display" => {
+                let send_input = args.flag("--input");
+                match args.start_stop()? {
+                    true => Request::GuiStartVirtualDisplay(StartVirtualDisplayRequest {
+                        first_frame: None,
+                        send_input,
+                    }),
+                    false => Request::GuiStopVirtualDisplay(StopVirtualDisplayRequest {}),
+                }
+            }
+
+            "gpio mode" => Request::GpioGetPinMode(GetPinMode {
+                pin: args.pin()?.into(),
+            }),
+            "gpio set-mode" => {
+                let pin = args.pin()?;
+                let mode = args.required("mode")?;
+                let mode = args.enumeration("mode", &mode, GpioPinMode::from_str_name)?;
+                Request::GpioSetPinMode(SetPinMode {
+                    pin: pin.into(),
+                    mode: mode.into(),
+                })
+            }
+            "gpio pull" => {
+                let pin = args.pin()?;
+                let pull = args.required("pull")?;
+                let pull = args.enumeration("pull", &pull, GpioInputPull::from_str_name)?;
+                Request::GpioSetInputPull(SetInputPull {
+                    pin: pin.into(),
+                    pull_mode: pull.into(),
+                })
+            }
+            "gpio read" => Request::GpioReadPin(ReadPin {
+                pin: args.pin()?.into(),
+            }),
+            "gpio write" => {
+                let pin = args.pin()?;
+                let value = args.required("value")?;
+                let value = match value.as_str() {
+                    "0" => 0,
+                    "1" => 1,
+                    _ => return Err(args.invalid("value", value)),
+                };
+                Request::GpioWritePin(WritePin {
+                    pin: pin.into(),
+                    value,
+                })
+            }
+            "gpio otg" => match args.optional() {
+                Some(mode) => Request::GpioSetOtgMode(SetOtgMode {
+                    mode: args
+                        .enumeration("mode", &mode, GpioOtgMode::from_str_name)?
+                        .into(),
+                }),
+                None => Request::GpioGetOtgMode(GetOtgMode {}),
+            },
+
+            "property get" => Request::PropertyGet(GetRequest {
+                key: args.required("key")?,
+            }),
+
+            "desktop locked" => Request::DesktopIsLocked(IsLockedRequest {}),
+            "desktop unlock" => Request::DesktopUnlock(UnlockRequest {}),
+            "desktop subscribe" => Request::DesktopStatusSubscribe(StatusSubscribeRequest {}),
+            "desktop unsubscribe" => Request::DesktopStatusUnsubscribe(StatusUnsubscribeRequest {}),
+
+            _ => unreachable!("every entry of COMMANDS is handled"),
+        };
+        args.finish()?;
+
+        Ok(request)
+    }
+}
+
+impl core::str::FromStr for Request {
+    type Err = ParseRequestError;
+
+    /// Same as [`Request::parse`]
+    fn from_str(text: &str) -> Result<Self> {
+        Request::parse(text)
+    }
+}
+
+/// The name of the command `words` start with, as written in [`COMMANDS`], and its length in
+/// words
+fn lookup(words: &[String]) -> Option<(&'static str, usize)> {
+    COMMANDS.iter().find_map(|usage| {
+        let len = usage
+            .split(' ')
+            .take_while(|word| !word.starts_with(['<', '[']))
+            .count();
+        let name = usage.splitn(len + 1, ' ').take(len);
+        let matches = words.len() >= len
+            && name
+                .zip(words)
+                .all(|(expected, word)| word.eq_ignore_ascii_case(expected));
+
+        matches.then(|| {
+            let end = usage
+                .match_indices(' ')
+                .nth(len - 1)
+                .map_or(usage.len(), |(i, _)| i);
+            (&usage[..end], len)
+        })
+    })
+}
+
+/// Whether `word` names a group of commands, like `storage`
+fn is_group(word: &str) -> bool {
+    COMMANDS.iter().any(|usage| {
+        usage.split_once(' ').is_some_and(|(group, rest)| {
+            !rest.starts_with(['<', '[']) && word.eq_ignore_ascii_case(group)
+        })
+    })
+}
+
+/// The arguments after the command name
+struct Args {
+    command: &'static str,
+    words: Vec<String>,
+}
+
+impl Args {
+    /// Removes `flag` from anywhere in the arguments, returning whether it was there
+    fn flag(&mut self, flag: &str) -> bool {
+        let len = self.words.len();
+        self.words.retain(|word| word != flag);
+
+        self.words.len() != len
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        (!self.words.is_empty()).then(|| self.words.remove(0))
+    }
+
+    fn required(&mut self, argument: &'static str) -> Result<String> {
+        self.optional().ok_or(ParseRequestError::MissingArgument {
+            command: self.command,
+            argument,
+        })
+    }
+
+    /// An optional number, zero when missing
+    fn number(&mut self, argument: &'static str) -> Result<i32> {
+        match self.optional() {
+            Some(value) => value.parse().map_err(|_| self.invalid(argument, value)),
+            None => Ok(0),
+        }
+    }
+
+    /// A protobuf enum value, by its schema name in any case
+    fn enumeration<E>(
+        &self,
+        argument: &'static str,
+        value: &str,
+        from_str_name: fn(&str) -> Option<E>,
+    ) -> Result<E> {
+        from_str_name(&value.to_ascii_uppercase())
+            .ok_or_else(|| self.invalid(argument, value.to_string()))
+    }
+
+    fn pin(&mut self) -> Result<GpioPin> {
+        let pin = self.required("pin")?;
+
+        self.enumeration("pin", &pin, GpioPin::from_str_name)
+    }
+
+    /// `start` or `stop`, as `true` or `false`
+    fn start_stop(&mut self) -> Result<bool> {
+        let action = self.required("start|stop")?;
+
+        match action.to_ascii_lowercase().as_str() {
+            "start" => Ok(true),
+            "stop" => Ok(false),
+            _ => Err(self.invalid("start|stop", action)),
+        }
+    }
+
+    fn invalid(&self, argument: &'static str, value: String) -> ParseRequestError {
+        ParseRequestError::InvalidArgument {
+            command: self.command,
+            argument,
+            value,
+        }
+    }
+
+    /// Fails if any argument is left over
+    fn finish(mut self) -> Result<()> {
+        match self.optional() {
+            Some(value) => Err(ParseRequestError::UnexpectedArgument {
+                command: self.command,
+                value,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Splits `text` into words, honoring quotes
+fn split(text: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(words);
+        }
+
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '"' => loop {
+                    match chars.next().ok_or(ParseRequestError::UnterminatedQuote)? {
+                        '"' => break,
+                        '\\' => {
+                            word.push(chars.next().ok_or(ParseRequestError::UnterminatedQuote)?)
+                        }
+                        c => word.push(c),
+                    }
+                },
+                '\'' => loop {
+                    match chars.next().ok_or(ParseRequestError::UnterminatedQuote)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                },
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_listed_command() {
+        for usage in COMMANDS {
+            let example = usage
+                .split(' ')
+                .filter(|word| !word.starts_with('['))
+                .map(|word| match word {
+                    "<pin>" => "pa7",
+                    "<0|1>" => "1",
+                    "<start|stop>" => "start",
+                    word if word.starts_with("<") && word.contains('|') => {
+                        word[1..].split('|').next().unwrap()
+                    }
+                    word if word.starts_with('<') => "/ext/x",
+                    word => word,
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            Request::parse(&example).unwrap_or_else(|e| panic!("{example}: {e}"));
+        }
+    }
+
+    #[test]
+    fn keeps_quoted_arguments_together() {
+        let request = Request::parse(r#"Storage RENAME "/ext/old name.txt" '/ext/it''s'"#).unwrap();
+
+        assert!(matches!(
+            request,
+            Request::StorageRename(from, to) if from == "/ext/old name.txt" && to == "/ext/its"
+        ));
+        assert!(matches!(
+            Request::parse(r#"storage write /ext/a "say \"hi\"""#).unwrap(),
+            Request::StorageWrite(WriteRequest { file: Some(file), .. }) if file.data == "say \"hi\""
+        ));
+    }
+
+    #[test]
+    fn parses_flags_and_enums() {
+        assert!(matches!(
+            Request::parse("storage delete -r /ext/dir").unwrap(),
+            Request::StorageDelete(DeleteRequest { recursive: true, path }) if path == "/ext/dir"
+        ));
+        assert!(matches!(
+            Request::parse("system reboot dfu").unwrap(),
+            Request::Reboot(RebootMode::Dfu)
+        ));
+        assert!(matches!(
+            Request::parse("gui input back long").unwrap(),
+            Request::GuiSendInputEvent(SendInputEventRequest { key: 5, r#type: 3 })
+        ));
+        assert!(matches!(
+            Request::parse("gpio otg").unwrap(),
+            Request::GpioGetOtgMode(_)
+        ));
+    }
+
+    #[test]
+    fn reports_what_is_wrong() {
+        assert_eq!(Request::parse("  ").unwrap_err(), ParseRequestError::Empty);
+        assert_eq!(
+            Request::parse("storage frobnicate /ext").unwrap_err(),
+            ParseRequestError::UnknownCommand("storage frobnicate".to_string())
+        );
+        assert_eq!(
+            Request::parse("storage read").unwrap_err(),
+            ParseRequestError::MissingArgument {
+                command: "storage read",
+                argument: "path"
+            }
+        );
+        assert_eq!(
+            Request::parse("storage read /a /b").unwrap_err(),
+            ParseRequestError::UnexpectedArgument {
+                command: "storage read",
+                value: "/b".to_string()
+            }
+        );
+        assert_eq!(
+            Request::parse("gpio read pz9").unwrap_err(),
+            ParseRequestError::InvalidArgument {
+                command: "gpio read",
+                argument: "pin",
+                value: "pz9".to_string()
+            }
+        );
+        assert_eq!(
+            Request::parse("storage read \"/ext").unwrap_err(),
+            ParseRequestError::UnterminatedQuote
+        );
+    }
+}
@@ -1,5 +1,7 @@
 //! Request type. Covers all Content's ending with "Request"
 
+use alloc::{string::String, vec::Vec};
+
 use crate::proto::StopSession;
 use crate::proto::desktop::{
     IsLockedRequest, StatusSubscribeRequest, StatusUnsubscribeRequest, UnlockRequest,
@@ -34,7 +36,7 @@ use crate::proto::{
 
 /// Wrapper around proto::Main tailored for requests. Can be turned into a proto::Main by
 /// RcpRequest::into_rpc(self)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Request {
     /// Stops the current RPC session, returning to a text cli
@@ -45,7 +47,8 @@ pub enum Request {
     Reboot(RebootMode),
     /// Requests detailed device info
     SystemDeviceInfo,
-    /// Factory resets the device
+    /// Factory resets the device. Prefer `system::factory_reset`, which requires an explicit
+    /// confirmation.
     SystemFactoryReset,
     /// Asks for the device's current date time
     SystemGetDatetime,
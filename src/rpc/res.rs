@@ -1,10 +1,12 @@
 //! Response type. Maps all Content's ending with "Response"
 
-use std::borrow::Cow;
+use alloc::{string::String, vec::Vec};
+
+use prost::bytes::Bytes;
 
 use crate::proto::{
     self,
-    app::{AppStateResponse, GetErrorResponse, LockStatusResponse},
+    app::{AppStateResponse, DataExchangeRequest, GetErrorResponse, LockStatusResponse},
     desktop::Status,
     gpio::{GetOtgModeResponse, GetPinModeResponse, ReadPinResponse},
     gui::ScreenFrame,
@@ -18,7 +20,7 @@ use crate::proto::{
 
 macro_rules! define_into_impl {
     ($enum_name:ident $variant:ident $typ:ty) => {
-        impl std::convert::TryFrom<$enum_name> for $typ {
+        impl core::convert::TryFrom<$enum_name> for $typ {
             type Error = crate::error::Error;
 
             fn try_from(value: $enum_name) -> Result<$typ, Self::Error> {
@@ -78,7 +80,8 @@ pub enum Response {
     StorageTimestamp(TimestampResponse),
     StorageStat(Option<u32>),
     StorageList(Vec<ReadDirItem>),
-    StorageRead(Option<Cow<'static, [u8]>>),
+    /// File data, a slice of the received message when it was decoded from [`Bytes`]
+    StorageRead(Option<Bytes>),
     StorageMd5sum(String),
     AppLockStatus(LockStatusResponse),
     AppGetError(GetErrorResponse),
@@ -87,9 +90,25 @@ pub enum Response {
     GpioReadPin(ReadPinResponse),
     GpioGetOtgMode(GetOtgModeResponse),
     AppState(AppStateResponse),
+    /// Data pushed by the running app. The device reuses the request message for this.
+    AppDataExchange(DataExchangeRequest),
     PropertyGet(GetResponse),
     DesktopStatus(Status),
+    /// Content without a mapping, only produced in [`DecodeMode::Lenient`]
+    Unknown(Content),
+}
 }
+
+/// How [`Response::decode`] treats message contents it has no variant for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Fail with [`crate::error::Error::UnsupportedRpcContent`]. Catches schema drift early, so
+    /// tests want this.
+    #[default]
+    Strict,
+    /// Pass the content through as [`Response::Unknown`]. Tools that talk to many firmware
+    /// forks want this.
+    Lenient,
 }
 
 /// Item read using fs_read_dir / Request::StorageList
@@ -124,8 +143,10 @@ impl Response {
             Self::GpioReadPin(_) => "GpioReadPin",
             Self::GpioGetOtgMode(_) => "GpioGetOtgMode",
             Self::AppState(_) => "AppState",
+            Self::AppDataExchange(_) => "AppDataExchange",
             Self::PropertyGet(_) => "PropertyGet",
             Self::DesktopStatus(_) => "DesktopStatus",
+            Self::Unknown(_) => "Unknown",
         }
     }
 }
@@ -138,6 +159,13 @@ impl TryFrom<proto::Main> for Response {
     type Error = crate::error::Error;
 
     fn try_from(value: proto::Main) -> Result<Self, Self::Error> {
+        Self::decode(value, DecodeMode::Strict)
+    }
+}
+
+impl Response {
+    /// Converts a raw message, treating contents without a variant as `mode` says
+    pub fn decode(value: proto::Main, mode: DecodeMode) -> Result<Self, crate::error::Error> {
         use Response::*;
         let content = value.content;
 
@@ -182,7 +210,7 @@ impl TryFrom<proto::Main> for Response {
                     let data = match r.file {
                         None => None,
                         Some(file) => match decode_storage_file_type(file.r#type)? {
-                            FileType::File => Some(file.data.into()),
+                            FileType::File => Some(file.data),
                             FileType::Dir => {
                                 return Err(crate::error::Error::InvalidRpcPayload(
                                     "storage read response contained a directory entry",
@@ -201,10 +229,14 @@ impl TryFrom<proto::Main> for Response {
                 Content::GpioReadPinResponse(r) => Ok(GpioReadPin(r)),
                 Content::GpioGetOtgModeResponse(r) => Ok(GpioGetOtgMode(r)),
                 Content::AppStateResponse(r) => Ok(AppState(r)),
+                Content::AppDataExchangeRequest(r) => Ok(AppDataExchange(r)),
                 Content::PropertyGetResponse(r) => Ok(PropertyGet(r)),
                 Content::DesktopStatus(r) => Ok(DesktopStatus(r)),
 
-                _ => Err(crate::error::Error::UnsupportedRpcContent),
+                other => match mode {
+                    DecodeMode::Strict => Err(crate::error::Error::UnsupportedRpcContent),
+                    DecodeMode::Lenient => Ok(Unknown(other)),
+                },
             },
         }
     }
@@ -263,7 +295,7 @@ mod tests {
                     r#type: 99,
                     name: "bad".to_string(),
                     size: 1,
-                    data: Vec::new(),
+                    data: Bytes::new(),
                     md5sum: String::new(),
                 }],
             })),
@@ -302,7 +334,7 @@ mod tests {
                     r#type: FileType::File.into(),
                     name: "note.txt".to_string(),
                     size: 4,
-                    data: b"test".to_vec(),
+                    data: Bytes::from_static(b"test"),
                     md5sum: String::new(),
                 }),
             })),
@@ -312,7 +344,32 @@ mod tests {
 
         assert_eq!(
             response,
-            Response::StorageRead(Some(Cow::Owned(b"test".to_vec())))
+            Response::StorageRead(Some(Bytes::from_static(b"test")))
         );
     }
+
+    #[test]
+    fn file_payloads_are_sliced_from_the_received_buffer() {
+        use prost::Message;
+
+        let message = proto::Main {
+            content: Some(Content::StorageReadResponse(storage::ReadResponse {
+                file: Some(storage::File {
+                    r#type: FileType::File.into(),
+                    data: Bytes::from(vec![0xAB; 512]),
+                    ..Default::default()
+                }),
+            })),
+            ..Default::default()
+        };
+        let received = Bytes::from(message.encode_to_vec());
+
+        let decoded = proto::Main::decode(received.clone()).unwrap();
+        let Ok(Response::StorageRead(Some(data))) = Response::try_from(decoded) else {
+            panic!("expected file data");
+        };
+
+        assert_eq!(data.len(), 512);
+        assert!(received.as_ptr_range().contains(&data.as_ptr()));
+    }
 }
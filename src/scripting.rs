@@ -0,0 +1,359 @@
+//! Automation scripts in [rhai](https://rhai.rs)
+//!
+//! [`ScriptEngine`] runs rhai scripts against a [`FlipperZero`], so provisioning steps or UI
+//! macros can be written without compiling Rust. Scripts call plain functions:
+//!
+//! | Function | Does |
+//! |---|---|
+//! | `fs_read(path)` / `fs_read_string(path)` | Reads a file as a blob or a string |
+//! | `fs_write(path, data)` | Writes a blob or a string, replacing the file |
+//! | `fs_list(path)` | Lists a directory as maps with `name`, `dir` and `size` |
+//! | `fs_mkdir(path)` / `fs_mkdir_all(path)` | Creates a directory, or it and its parents |
+//! | `fs_remove(path)` / `fs_remove_all(path)` | Removes a file or empty directory, or a tree |
+//! | `fs_size(path)` / `fs_md5(path)` | File size, and the MD5 calculated on the device |
+//! | `ping()` | Pings the device |
+//! | `device_info()` | The device info as a map |
+//! | `protobuf_version()` | The RPC schema version as `"major.minor"` |
+//! | `play_alert()` | Flashes the screen, vibrates and beeps |
+//! | `press(key)` / `send_input(key, type)` | Emulates input, e.g. `press("ok")` |
+//! | `gpio_mode(pin, mode)`, `gpio_read(pin)`, `gpio_write(pin, high)` | GPIO pins, e.g. `"pa7"` |
+//! | `set_otg(enabled)` | 5V on the GPIO header |
+//! | `sleep(ms)` | Waits on the host |
+//!
+//! Key, input type, pin and mode names are the schema names in any case. Factory resets and
+//! reboots are left out on purpose. Failing calls end the script with the crate's [`Error`],
+//! unless the script catches them with `try`/`catch`.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::FlipperZero;
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::scripting::ScriptEngine;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let flipper = FlipperZero::new(SerialRpcTransport::new("/dev/ttyACM0")?);
+//! let mut engine = ScriptEngine::new(flipper);
+//!
+//! engine.run(
+//!     r#"
+//!     fs_mkdir_all("/ext/badusb");
+//!     fs_write("/ext/badusb/hello.txt", "STRING hello");
+//!     for entry in fs_list("/ext/badusb") { print(entry.name); }
+//!     press("back");
+//!     "#,
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{cell::RefCell, path::Path, rc::Rc, time::Duration};
+
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map};
+
+use crate::FlipperZero;
+use crate::proto::gpio::{GpioPin, GpioPinMode};
+use crate::proto::gui::{InputKey, InputType};
+use crate::rpc::res::ReadDirItem;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// Registers a script function that runs `$body` with the device borrowed as `$flipper`. A
+/// failing body raises a rhai runtime error and keeps the crate error for [`ScriptEngine::run`].
+macro_rules! bind {
+    ($script:ident, $name:literal, |$flipper:ident $(, $arg:ident: $ty:ty)*| $body:expr) => {{
+        let flipper = $script.flipper.clone();
+        let last_error = $script.last_error.clone();
+
+        $script
+            .engine
+            .register_fn($name, move |$($arg: $ty),*| -> CallResult<_> {
+                let $flipper = &mut *flipper.borrow_mut();
+                // A closure, so `?` in the body returns from the body only
+                #[allow(clippy::redundant_closure_call)]
+                let result: Result<_> = (|| $body)();
+
+                result.map_err(|error| raise(&last_error, error))
+            });
+    }};
+}
+
+type Shared<T> = Rc<RefCell<FlipperZero<T>>>;
+type CallResult<V> = std::result::Result<V, Box<EvalAltResult>>;
+
+/// A rhai engine with the device bindings registered
+pub struct ScriptEngine<T> {
+    engine: Engine,
+    flipper: Shared<T>,
+    /// The error of the last failing binding, returned in place of rhai's copy of its message
+    last_error: Rc<RefCell<Option<Error>>>,
+}
+
+impl<T> ScriptEngine<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>
+        + CommandIndex
+        + std::fmt::Debug
+        + 'static,
+{
+    /// Creates an engine driving `flipper`
+    pub fn new(flipper: FlipperZero<T>) -> Self {
+        let mut script = Self {
+            engine: Engine::new(),
+            flipper: Rc::new(RefCell::new(flipper)),
+            last_error: Rc::default(),
+        };
+        script.register();
+
+        script
+    }
+
+    /// Runs `script` and returns the value of its last expression
+    ///
+    /// # Errors
+    ///
+    /// Returns the crate error of a failing device call the script did not catch, or
+    /// [`Error::Script`] if the script does not compile or fails otherwise.
+    pub fn run(&mut self, script: &str) -> Result<Dynamic> {
+        self.last_error.borrow_mut().take();
+
+        self.engine
+            .eval::<Dynamic>(script)
+            .map_err(|error| self.script_error(*error))
+    }
+
+    /// Runs the script in the host file `path`, see [`ScriptEngine::run`]
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<Dynamic> {
+        let script = std::fs::read_to_string(path)?;
+
+        self.run(&script)
+    }
+
+    fn script_error(&self, error: EvalAltResult) -> Error {
+        let last_error = self.last_error.borrow_mut().take();
+
+        match (error, last_error) {
+            // The script let a binding's error through instead of throwing one of its own
+            (EvalAltResult::ErrorRuntime(value, _), Some(last_error))
+                if value.to_string() == last_error.to_string() =>
+            {
+                last_error
+            }
+            (error, _) => Error::Script(error.to_string()),
+        }
+    }
+
+    fn register(&mut self) {
+        self.register_fs();
+        self.register_system();
+        self.register_gui();
+        self.register_gpio();
+
+        self.engine.register_fn("sleep", |ms: i64| {
+            std::thread::sleep(Duration::from_millis(ms.max(0) as u64));
+        });
+    }
+
+    fn register_fs(&mut self) {
+        bind!(self, "fs_read", |flipper, path: &str| {
+            Ok(Blob::from(flipper.fs().read(path)?.into_owned()))
+        });
+        bind!(self, "fs_read_string", |flipper, path: &str| {
+            Ok(flipper.fs().read_to_string(path)?.into_owned())
+        });
+        bind!(self, "fs_write", |flipper, path: &str, data: Blob| {
+            flipper.fs().write(path, data)
+        });
+        bind!(self, "fs_write", |flipper, path: &str, text: &str| {
+            flipper.fs().write_str(path, text)
+        });
+        bind!(self, "fs_list", |flipper, path: &str| {
+            Ok(flipper
+                .fs()
+                .read_dir(path, false)?
+                .map(entry_map)
+                .collect::<Array>())
+        });
+        bind!(self, "fs_mkdir", |flipper, path: &str| {
+            flipper.fs().create_dir(path)
+        });
+        bind!(self, "fs_mkdir_all", |flipper, path: &str| {
+            flipper.fs().create_dir_all(path)
+        });
+        bind!(self, "fs_remove", |flipper, path: &str| {
+            flipper.fs().remove(path, false)
+        });
+        bind!(self, "fs_remove_all", |flipper, path: &str| {
+            flipper.fs().remove(path, true)
+        });
+        bind!(self, "fs_size", |flipper, path: &str| {
+            Ok(i64::from(flipper.fs().metadata(path)?))
+        });
+        bind!(self, "fs_md5", |flipper, path: &str| flipper.fs().md5(path));
+    }
+
+    fn register_system(&mut self) {
+        bind!(self, "ping", |flipper| {
+            flipper.system().ping(Vec::new()).map(drop)
+        });
+        bind!(self, "device_info", |flipper| {
+            Ok(flipper
+                .system()
+                .device_info()?
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect::<Map>())
+        });
+        bind!(self, "protobuf_version", |flipper| {
+            let (major, minor) = flipper.system().protobuf_version()?;
+            Ok(format!("{major}.{minor}"))
+        });
+        bind!(self, "play_alert", |flipper| flipper.system().play_alert());
+    }
+
+    fn register_gui(&mut self) {
+        bind!(self, "press", |flipper, key: &str| {
+            flipper
+                .gui()
+                .press(schema_name(key, InputKey::from_str_name)?)
+        });
+        bind!(self, "send_input", |flipper, key: &str, r#type: &str| {
+            flipper.gui().send_input(
+                schema_name(key, InputKey::from_str_name)?,
+                schema_name(r#type, InputType::from_str_name)?,
+            )
+        });
+    }
+
+    fn register_gpio(&mut self) {
+        bind!(self, "gpio_mode", |flipper, pin: &str, mode: &str| {
+            flipper.gpio().set_pin_mode(
+                schema_name(pin, GpioPin::from_str_name)?,
+                schema_name(mode, GpioPinMode::from_str_name)?,
+            )
+        });
+        bind!(self, "gpio_read", |flipper, pin: &str| {
+            flipper
+                .gpio()
+                .read(schema_name(pin, GpioPin::from_str_name)?)
+        });
+        bind!(self, "gpio_write", |flipper, pin: &str, high: bool| {
+            flipper
+                .gpio()
+                .write(schema_name(pin, GpioPin::from_str_name)?, high)
+        });
+        bind!(self, "set_otg", |flipper, enabled: bool| {
+            flipper.gpio().set_otg(enabled)
+        });
+    }
+}
+
+impl<T> ScriptEngine<T> {
+    /// The rhai engine, e.g. to register more functions or redirect `print`
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    /// Returns the wrapped device. Functions registered on the engine may still hold on to it,
+    /// so this drops the engine first.
+    pub fn into_inner(self) -> FlipperZero<T> {
+        drop(self.engine);
+
+        match Rc::try_unwrap(self.flipper) {
+            Ok(flipper) => flipper.into_inner(),
+            Err(_) => unreachable!("only the dropped engine's functions share the device"),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for ScriptEngine<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptEngine")
+            .field("flipper", &self.flipper)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keeps `error` for [`ScriptEngine::run`] and hands rhai its message
+fn raise(last_error: &RefCell<Option<Error>>, error: Error) -> Box<EvalAltResult> {
+    let message = error.to_string();
+    *last_error.borrow_mut() = Some(error);
+
+    message.into()
+}
+
+/// Looks up a protobuf enum value by its schema name in any case
+fn schema_name<E>(name: &str, from_str_name: fn(&str) -> Option<E>) -> Result<E> {
+    from_str_name(&name.to_ascii_uppercase())
+        .ok_or_else(|| Error::Script(format!("unknown name: {name}")))
+}
+
+fn entry_map(entry: ReadDirItem) -> Dynamic {
+    let (name, dir, size) = match entry {
+        ReadDirItem::Dir(name) => (name, true, 0),
+        ReadDirItem::File(name, size, _) => (name, false, size),
+    };
+
+    let mut map = Map::new();
+    map.insert("name".into(), name.into());
+    map.insert("dir".into(), dir.into());
+    map.insert("size".into(), i64::from(size).into());
+
+    map.into()
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn scripts_reach_the_device() {
+        let mut engine = ScriptEngine::new(FlipperZero::new(EmulatedFlipper::new()));
+
+        let count = engine
+            .run(
+                r#"
+                fs_mkdir_all("/ext/scripts/a");
+                fs_write("/ext/scripts/hello.txt", "hello");
+                fs_write("/ext/scripts/raw.bin", blob(3, 7));
+                let names = [];
+                for entry in fs_list("/ext/scripts") { names.push(entry.name); }
+                if fs_read_string("/ext/scripts/hello.txt") != "hello" { throw "bad read"; }
+                press("OK");
+                names.len
+                "#,
+            )
+            .unwrap();
+        assert_eq!(count.as_int().unwrap(), 3);
+
+        let device = engine.into_inner().into_inner();
+        assert_eq!(device.file("/ext/scripts/raw.bin"), Some(&[7, 7, 7][..]));
+    }
+
+    #[test]
+    fn uncaught_device_errors_keep_their_kind() {
+        let mut engine = ScriptEngine::new(FlipperZero::new(EmulatedFlipper::new()));
+
+        let error = engine.run(r#"fs_read("/ext/missing")"#).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let caught = engine
+            .run(r#"let caught = false; try { fs_read("/ext/missing"); } catch { caught = true; } caught"#)
+            .unwrap();
+        assert!(caught.as_bool().unwrap());
+
+        let error = engine.run(r#"throw "stop""#).unwrap_err();
+        assert!(matches!(error, Error::Script(_)));
+        assert!(matches!(engine.run("let x = ;"), Err(Error::Script(_))));
+    }
+}
@@ -0,0 +1,539 @@
+//! Long lived RPC sessions
+//!
+//! [`RpcSession`] wraps a raw transport and keeps per-session state that would otherwise be
+//! requested again and again, such as the [`DeviceIdentity`]. It implements the same traits as
+//! the transport it wraps, so every helper that accepts a transport also accepts a session.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::session::RpcSession;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let session = RpcSession::new(SerialRpcTransport::new("/dev/ttyACM0")?)?;
+//!
+//! println!("connected to {}", session.identity().name);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+};
+use std::time::{Duration, Instant};
+
+pub mod capabilities;
+pub mod events;
+mod keepalive;
+pub mod pending;
+pub use capabilities::Capabilities;
+pub use events::{DisconnectReason, SessionEvent};
+pub use keepalive::Keepalive;
+pub use pending::PendingResponse;
+
+use crate::cancel::{CancelToken, is_cancelled};
+use crate::firmware::FirmwareFlavor;
+use crate::logging::debug;
+use events::Events;
+
+use crate::proto::system::ProtobufVersionResponse;
+use crate::transport::Transport;
+use crate::transport::{CommandIndex, check_status};
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::{req::Request, res::DecodeMode},
+    system::device_info,
+    transport::TransportRaw,
+};
+
+/// Who the device is, read once when the session starts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    /// User-visible device name
+    pub name: String,
+    /// Hardware revision
+    pub hardware_version: String,
+    /// Firmware version string, e.g. `1.0.1`
+    pub firmware_version: String,
+    /// Git commit the firmware was built from
+    pub firmware_commit: String,
+    /// Git branch the firmware was built from
+    pub firmware_branch: String,
+    /// RPC protobuf schema version as `(major, minor)`
+    pub protobuf_version: (u32, u32),
+    /// Unique hardware id, used as the serial number
+    pub serial: String,
+    /// Every key reported by the device info chain
+    pub info: BTreeMap<String, String>,
+}
+
+impl DeviceIdentity {
+    /// Builds an identity from a device info map.
+    ///
+    /// Understands both the dotted keys of current firmware (`hardware.name`) and the underscored
+    /// keys of old firmware (`hardware_name`). Missing keys are left empty.
+    pub fn from_info(info: BTreeMap<String, String>, protobuf_version: (u32, u32)) -> Self {
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| info.get(*key))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        Self {
+            name: get(&["hardware.name", "hardware_name"]),
+            hardware_version: get(&["hardware.ver", "hardware_ver"]),
+            firmware_version: get(&["firmware.version", "firmware_version"]),
+            firmware_commit: get(&["firmware.commit.hash", "firmware_commit"]),
+            firmware_branch: get(&["firmware.branch.name", "firmware_branch"]),
+            protobuf_version,
+            serial: get(&["hardware.uid", "hardware_uid"]),
+            info,
+        }
+    }
+
+    /// The firmware fork the device runs, from the `firmware.origin.fork` key where the
+    /// firmware reports it, otherwise from the version. See [`FirmwareFlavor`].
+    pub fn flavor(&self) -> FirmwareFlavor {
+        let fork = ["firmware.origin.fork", "firmware_origin_fork"]
+            .iter()
+            .find_map(|key| self.info.get(*key));
+
+        FirmwareFlavor::detect(fork.map(String::as_str), &self.firmware_version)
+    }
+}
+
+/// Requests an abort of whatever chained operation the session is running.
+///
+/// Cheap to clone and safe to trigger from another thread, e.g. a Ctrl-C handler. A write chain
+/// is closed with an empty last chunk and a read chain is drained, so the device and the stream
+/// are left consistent; the aborted operation returns an [`std::io::ErrorKind::Interrupted`]
+/// error.
+#[derive(Debug, Clone, Default)]
+pub struct AbortHandle(Arc<AtomicBool>);
+
+impl AbortHandle {
+    /// Requests an abort. Applies to the operation in progress, or to the next one if the
+    /// session is idle.
+    pub fn abort(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether an abort is pending
+    pub fn is_aborting(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// An RPC session over a raw transport, with cached device state
+#[derive(Debug)]
+pub struct RpcSession<T> {
+    transport: T,
+    identity: DeviceIdentity,
+    abort: AbortHandle,
+    cancel: Option<CancelToken>,
+    events: Events,
+    last_activity: Instant,
+    decode_mode: DecodeMode,
+    /// Messages read so far for each submitted request, by command id
+    outstanding: BTreeMap<u32, Vec<proto::Main>>,
+    /// Messages read while waiting for a submitted request that belong to nobody submitted,
+    /// with their status not checked yet
+    unclaimed: VecDeque<proto::Main>,
+    /// Probed on demand by RpcSession::capabilities
+    capabilities: Option<Capabilities>,
+}
+
+impl<T> RpcSession<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Starts a session over `transport`, reading the device identity.
+    pub fn new(transport: T) -> Result<Self> {
+        Self::start(transport, Events::default())
+    }
+
+    /// Starts a session like [`RpcSession::new`], reporting its connection state to `events`.
+    /// See [`events`].
+    pub fn new_with_events(transport: T, events: Sender<SessionEvent>) -> Result<Self> {
+        Self::start(transport, Events::new(Some(events)))
+    }
+
+    fn start(mut transport: T, mut events: Events) -> Result<Self> {
+        events.emit(SessionEvent::Connected);
+        let identity = handshake(&mut transport, &mut events)?;
+        debug!(name = identity.name, "session started");
+
+        Ok(Self {
+            transport,
+            identity,
+            abort: AbortHandle::default(),
+            cancel: None,
+            events,
+            last_activity: Instant::now(),
+            decode_mode: DecodeMode::Strict,
+            outstanding: BTreeMap::new(),
+            unclaimed: VecDeque::new(),
+            capabilities: None,
+        })
+    }
+
+    /// Continues the session over a new transport, e.g. after the device rebooted or was
+    /// plugged back in, and reads the device identity again. Returns the old transport.
+    pub fn reconnect(&mut self, mut transport: T) -> Result<T> {
+        self.events.emit(SessionEvent::Reconnecting);
+        self.events.emit(SessionEvent::Connected);
+        self.identity = handshake(&mut transport, &mut self.events)?;
+        self.last_activity = Instant::now();
+        // Answers still in flight were lost with the old connection
+        self.outstanding.clear();
+        self.unclaimed.clear();
+        // The device may run another firmware now
+        self.capabilities = None;
+        debug!(name = self.identity.name, "session reconnected");
+
+        Ok(std::mem::replace(&mut self.transport, transport))
+    }
+
+    /// The identity read when the session started
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
+
+    /// Reads the identity again, e.g. after the device was renamed
+    pub fn refresh_identity(&mut self) -> Result<&DeviceIdentity> {
+        // Through the session, so answers to submitted requests read meanwhile are kept
+        self.identity = read_identity(self)?;
+
+        Ok(&self.identity)
+    }
+}
+
+impl<T> RpcSession<T> {
+    /// Time since the last message was sent or received
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Sets how responses without a known variant are handled, see [`DecodeMode`]. Sessions
+    /// start out strict.
+    pub fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    /// Returns a handle that aborts the session's current chained operation
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+
+    /// Stops every chained operation on the session, and its keepalive thread, once `token` is
+    /// cancelled. See [`CancelToken`].
+    ///
+    /// ```no_run
+    /// use flipper_rpc::CancelToken;
+    /// use flipper_rpc::error::Result;
+    /// use flipper_rpc::session::RpcSession;
+    /// use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+    ///
+    /// # fn main() -> Result<()> {
+    /// let cancel = CancelToken::new();
+    /// let session =
+    ///     RpcSession::new(SerialRpcTransport::new("/dev/ttyACM0")?)?.with_cancel_token(cancel.clone());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// The token set with [`RpcSession::with_cancel_token`]
+    pub fn cancel_token(&self) -> Option<&CancelToken> {
+        self.cancel.as_ref()
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the wrapped transport
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Ends the session, returning the wrapped transport
+    pub fn into_inner(mut self) -> T {
+        self.events.emit(SessionEvent::Disconnected {
+            reason: DisconnectReason::Closed,
+        });
+
+        self.transport
+    }
+}
+
+/// Reads the identity, reporting the outcome to `events`
+fn handshake<T>(transport: &mut T, events: &mut Events) -> Result<DeviceIdentity>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    match read_identity(transport) {
+        Ok(identity) => {
+            events.emit(SessionEvent::HandshakeComplete(identity.clone()));
+            Ok(identity)
+        }
+        Err(error) => {
+            let reason = DisconnectReason::from_error(&error)
+                .unwrap_or(DisconnectReason::HandshakeFailed(error.kind()));
+            events.emit(SessionEvent::Disconnected { reason });
+            Err(error)
+        }
+    }
+}
+
+fn read_identity<T>(transport: &mut T) -> Result<DeviceIdentity>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let info = device_info(transport)?;
+
+    let ProtobufVersionResponse { major, minor } = transport
+        .send_and_receive(Request::SystemProtobufVersion)?
+        .try_into()?;
+
+    Ok(DeviceIdentity::from_info(info, (major, minor)))
+}
+
+impl<T> TransportRaw<proto::Main> for RpcSession<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+{
+    type Err = Error;
+
+    fn send_raw(&mut self, value: proto::Main) -> Result<()> {
+        self.last_activity = Instant::now();
+        self.events.observe(self.transport.send_raw(value))
+    }
+
+    fn send_raw_buf(&mut self, value: proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+        self.last_activity = Instant::now();
+        self.events.observe(self.transport.send_raw_buf(value, buf))
+    }
+
+    fn receive_raw(&mut self) -> Result<proto::Main> {
+        check_status(self.receive_raw_unchecked()?)
+    }
+
+    fn receive_raw_unchecked(&mut self) -> Result<proto::Main> {
+        if let Some(main) = self.unclaimed.pop_front() {
+            return Ok(main);
+        }
+
+        loop {
+            // Unchecked, so failed answers to submitted requests are set aside like the others
+            let main = self
+                .events
+                .observe(self.transport.receive_raw_unchecked())?;
+            self.last_activity = Instant::now();
+
+            // Answers to submitted requests are kept for RpcSession::wait
+            match self.outstanding.get_mut(&main.command_id) {
+                Some(parts) => parts.push(main),
+                None => return Ok(main),
+            }
+        }
+    }
+
+    fn try_receive_raw(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw_unchecked()?
+            .map(check_status)
+            .transpose()
+    }
+
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<proto::Main>> {
+        if let Some(main) = self.unclaimed.pop_front() {
+            return Ok(Some(main));
+        }
+
+        // Unchecked, so a failed answer is only raised by the wait for its request
+        while let Some(main) = self
+            .events
+            .observe(self.transport.try_receive_raw_unchecked())?
+        {
+            self.last_activity = Instant::now();
+
+            match self.outstanding.get_mut(&main.command_id) {
+                Some(parts) => parts.push(main),
+                None => return Ok(Some(main)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn take_abort(&mut self) -> bool {
+        self.abort.0.swap(false, Ordering::SeqCst)
+            || is_cancelled(self.cancel.as_ref())
+            || self.transport.take_abort()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+        self.transport.set_timeout(timeout)
+    }
+
+    fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+}
+
+impl<T> CommandIndex for RpcSession<T>
+where
+    T: CommandIndex,
+{
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        self.transport.increment_command_index(by)
+    }
+
+    fn command_index(&mut self) -> u32 {
+        self.transport.command_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_reads_current_and_legacy_keys() {
+        let current = BTreeMap::from([
+            ("hardware.name".to_string(), "Vexer".to_string()),
+            ("firmware.version".to_string(), "1.0.1".to_string()),
+        ]);
+        let legacy = BTreeMap::from([
+            ("hardware_name".to_string(), "Vexer".to_string()),
+            ("firmware_version".to_string(), "1.0.1".to_string()),
+        ]);
+
+        let current = DeviceIdentity::from_info(current, (0, 25));
+        let legacy = DeviceIdentity::from_info(legacy, (0, 25));
+
+        assert_eq!(current.name, "Vexer");
+        assert_eq!(current.firmware_version, legacy.firmware_version);
+        assert_eq!(legacy.name, "Vexer");
+        assert!(current.serial.is_empty());
+    }
+
+    #[test]
+    fn flavor_prefers_the_reported_fork() {
+        let momentum = BTreeMap::from([
+            ("firmware.version".to_string(), "mntm-008".to_string()),
+            ("firmware.origin.fork".to_string(), "Momentum".to_string()),
+        ]);
+        let unleashed =
+            BTreeMap::from([("firmware_version".to_string(), "unlshd-080".to_string())]);
+
+        assert_eq!(
+            DeviceIdentity::from_info(momentum, (0, 25)).flavor(),
+            FirmwareFlavor::Momentum
+        );
+        assert_eq!(
+            DeviceIdentity::from_info(unleashed, (0, 25)).flavor(),
+            FirmwareFlavor::Unleashed
+        );
+    }
+
+    #[cfg(all(feature = "testing", feature = "fs-write"))]
+    #[test]
+    fn abort_closes_a_write_chain() {
+        use crate::fs::{CHUNK_SIZE, FsWrite};
+        use crate::testing::EmulatedFlipper;
+
+        use crate::transport::faults::{FaultPlan, FaultyTransport};
+
+        let mut session = RpcSession::new(FaultyTransport::new(
+            EmulatedFlipper::new(),
+            FaultPlan::default(),
+        ))
+        .unwrap();
+        // Triggers the abort handle once the first chunk is sent
+        let handle = session.abort_handle();
+        session.get_mut().on_send(move |_| handle.abort());
+
+        let error = session
+            .fs_write(
+                "/ext/big.bin",
+                vec![7; CHUNK_SIZE * 4],
+                #[cfg(feature = "fs-write-progress-mpsc")]
+                None,
+            )
+            .expect_err("write should be aborted");
+
+        assert!(
+            matches!(error.root(), Error::Io(e) if e.kind() == std::io::ErrorKind::Interrupted)
+        );
+        assert_eq!(
+            session
+                .get_ref()
+                .get_ref()
+                .file("/ext/big.bin")
+                .map(<[u8]>::len),
+            Some(CHUNK_SIZE)
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn lenient_sessions_pass_unknown_contents_through() {
+        use crate::proto::{main::Content, system::PingRequest};
+        use crate::rpc::res::Response;
+        use crate::testing::EmulatedFlipper;
+
+        let mut session = RpcSession::new(EmulatedFlipper::new()).unwrap();
+        let unknown = Content::SystemPingRequest(PingRequest { data: vec![1] });
+
+        session.get_mut().push_event(unknown.clone());
+        assert!(matches!(
+            session.receive(),
+            Err(Error::UnsupportedRpcContent)
+        ));
+
+        session.set_decode_mode(DecodeMode::Lenient);
+        session.get_mut().push_event(unknown.clone());
+        assert_eq!(session.receive().unwrap(), Response::Unknown(unknown));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn lifecycle_events_are_reported() {
+        use crate::testing::EmulatedFlipper;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut session = RpcSession::new_with_events(EmulatedFlipper::new(), tx).unwrap();
+        let identity = session.identity().clone();
+
+        session.reconnect(EmulatedFlipper::new()).unwrap();
+        assert!(session.is_connected());
+        session.into_inner();
+
+        let handshake = SessionEvent::HandshakeComplete(identity);
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [
+                SessionEvent::Connected,
+                handshake.clone(),
+                SessionEvent::Reconnecting,
+                SessionEvent::Connected,
+                handshake,
+                SessionEvent::Disconnected {
+                    reason: DisconnectReason::Closed
+                },
+            ]
+        );
+    }
+}
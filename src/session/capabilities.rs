@@ -0,0 +1,292 @@
+//! Which optional RPC commands the firmware implements
+//!
+//! Firmwares differ in the requests they answer: old releases lack power info and GPIO, and
+//! forks drop or add commands independently of the protobuf version they report.
+//! [`probe_capabilities`] sends one side-effect free request per optional command and records
+//! which ones come back with anything other than `ERROR_NOT_IMPLEMENTED`, so UIs can grey out
+//! what the device cannot do. [`RpcSession::capabilities`] probes once per connection.
+//!
+//! The desktop status subscription has no such request, since subscribing or unsubscribing
+//! changes what the device pushes to other users of the connection. The firmware implements it
+//! next to `DesktopIsLocked`, so [`Capabilities::DESKTOP_STATUS`] is assumed along with
+//! [`Capabilities::DESKTOP_LOCK`].
+//!
+//! Reads at an offset and PWM output are not part of the schema this crate is generated from
+//! (see [`proto::SCHEMA_VERSION`]), so there are no flags for them.
+
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::logging::debug;
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    proto::{
+        self,
+        app::{GetErrorRequest, LockStatusRequest},
+        desktop::IsLockedRequest,
+        gpio::{GetOtgMode, GetPinMode, GpioPin},
+        property::GetRequest,
+        storage::TimestampRequest,
+    },
+    rpc::req::Request,
+    transport::{CommandIndex, Transport, TransportRaw},
+};
+
+use super::RpcSession;
+
+/// A set of optional commands the firmware implements
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// `SystemPowerInfo`, battery and charger state over RPC
+    pub const POWER_INFO: Self = Self(1 << 0);
+    /// `StorageTimestamp`, file modification times
+    pub const STORAGE_TIMESTAMP: Self = Self(1 << 1);
+    /// `StorageTarExtract`, unpacking archives on the device
+    pub const TAR_EXTRACT: Self = Self(1 << 2);
+    /// `PropertyGet`, the property tree
+    pub const PROPERTIES: Self = Self(1 << 3);
+    /// `DesktopIsLocked` and `DesktopUnlock`
+    pub const DESKTOP_LOCK: Self = Self(1 << 4);
+    /// `DesktopStatusSubscribe`, lock state changes pushed by the device
+    pub const DESKTOP_STATUS: Self = Self(1 << 5);
+    /// GPIO pin modes, reads and writes
+    pub const GPIO: Self = Self(1 << 6);
+    /// `GpioGetOtgMode` and `GpioSetOtgMode`, 5V on the GPIO header
+    pub const GPIO_OTG: Self = Self(1 << 7);
+    /// `AppGetError`, the error text of the running app
+    pub const APP_ERROR: Self = Self(1 << 8);
+    /// `AppLockStatus`
+    pub const APP_LOCK_STATUS: Self = Self(1 << 9);
+
+    const NAMES: [(Self, &'static str); 10] = [
+        (Self::POWER_INFO, "POWER_INFO"),
+        (Self::STORAGE_TIMESTAMP, "STORAGE_TIMESTAMP"),
+        (Self::TAR_EXTRACT, "TAR_EXTRACT"),
+        (Self::PROPERTIES, "PROPERTIES"),
+        (Self::DESKTOP_LOCK, "DESKTOP_LOCK"),
+        (Self::DESKTOP_STATUS, "DESKTOP_STATUS"),
+        (Self::GPIO, "GPIO"),
+        (Self::GPIO_OTG, "GPIO_OTG"),
+        (Self::APP_ERROR, "APP_ERROR"),
+        (Self::APP_LOCK_STATUS, "APP_LOCK_STATUS"),
+    ];
+
+    /// No capabilities
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Every capability this crate knows
+    pub const fn all() -> Self {
+        Self((1 << Self::NAMES.len()) - 1)
+    }
+
+    /// The raw bits
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether every capability of `other` is in `self`
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds the capabilities of `other`
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// Whether the set is empty
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The names of the capabilities in the set, e.g. `GPIO_OTG`
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .into_iter()
+            .filter(move |(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.insert(rhs);
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// Probes every capability, see the [module docs](self).
+///
+/// # Errors
+///
+/// Returns transport errors. Rejections other than `ERROR_NOT_IMPLEMENTED`, e.g. a missing
+/// file, mean the firmware knows the command and count as supported.
+pub fn probe_capabilities<T>(transport: &mut T) -> Result<Capabilities>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let probes = [
+        (Capabilities::POWER_INFO, Request::SystemPowerInfo),
+        (
+            Capabilities::STORAGE_TIMESTAMP,
+            Request::StorageTimestamp(TimestampRequest {
+                path: "/int".to_string(),
+            }),
+        ),
+        (
+            Capabilities::TAR_EXTRACT,
+            // The firmware rejects the `?` in the output name before opening anything
+            Request::StorageTarExtract(
+                "/int/.capability-probe.tar".to_string(),
+                "/int/.capability-probe?".to_string(),
+            ),
+        ),
+        (
+            Capabilities::PROPERTIES,
+            Request::PropertyGet(GetRequest {
+                key: "devinfo.hardware.name".to_string(),
+            }),
+        ),
+        (
+            Capabilities::DESKTOP_LOCK | Capabilities::DESKTOP_STATUS,
+            Request::DesktopIsLocked(IsLockedRequest {}),
+        ),
+        (
+            Capabilities::GPIO,
+            Request::GpioGetPinMode(GetPinMode {
+                pin: GpioPin::Pc0.into(),
+            }),
+        ),
+        (
+            Capabilities::GPIO_OTG,
+            Request::GpioGetOtgMode(GetOtgMode {}),
+        ),
+        (
+            Capabilities::APP_ERROR,
+            Request::AppGetError(GetErrorRequest {}),
+        ),
+        (
+            Capabilities::APP_LOCK_STATUS,
+            Request::AppLockStatus(LockStatusRequest {}),
+        ),
+    ];
+
+    let mut capabilities = Capabilities::empty();
+    for (flag, request) in probes {
+        if is_implemented(transport, request)? {
+            capabilities.insert(flag);
+        }
+    }
+    debug!(?capabilities, "capabilities probed");
+
+    Ok(capabilities)
+}
+
+/// Sends `request` and reads its whole answer
+fn is_implemented<T>(transport: &mut T, request: Request) -> Result<bool>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    transport.send(request)?;
+
+    loop {
+        match transport.receive_raw() {
+            Ok(main) if main.has_next => {}
+            Ok(_) => return Ok(true),
+            Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(false),
+            Err(Error::Rpc(_)) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl<T> RpcSession<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// The optional commands the firmware implements, probed on the first call and cached until
+    /// the session reconnects. See [`probe_capabilities`].
+    pub fn capabilities(&mut self) -> Result<Capabilities> {
+        if let Some(capabilities) = self.capabilities {
+            return Ok(capabilities);
+        }
+
+        let capabilities = probe_capabilities(self)?;
+        self.capabilities = Some(capabilities);
+
+        Ok(capabilities)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn probes_what_the_emulator_implements() {
+        let mut session = RpcSession::new(EmulatedFlipper::new()).unwrap();
+
+        let capabilities = session.capabilities().unwrap();
+        assert_eq!(
+            capabilities,
+            Capabilities::TAR_EXTRACT
+                | Capabilities::DESKTOP_LOCK
+                | Capabilities::DESKTOP_STATUS
+                | Capabilities::GPIO
+                | Capabilities::GPIO_OTG
+        );
+        assert!(!capabilities.contains(Capabilities::POWER_INFO));
+        assert_eq!(
+            format!("{capabilities:?}"),
+            r#"{"TAR_EXTRACT", "DESKTOP_LOCK", "DESKTOP_STATUS", "GPIO", "GPIO_OTG"}"#
+        );
+
+        // Cached, no further requests
+        let sent = session.get_ref().requests().len();
+        assert_eq!(session.capabilities().unwrap(), capabilities);
+        assert_eq!(session.get_ref().requests().len(), sent);
+
+        // Neither desktop subscriptions nor the filesystem were touched
+        assert!(session.get_ref().requests().iter().all(|request| !matches!(
+            request.content,
+            Some(proto::main::Content::DesktopStatusSubscribeRequest(_))
+                | Some(proto::main::Content::DesktopStatusUnsubscribeRequest(_))
+        )));
+        assert!(session.get_ref().file("/int/.capability-probe?").is_none());
+
+        // The session still answers in order
+        assert_eq!(
+            session.send_and_receive(Request::Ping(vec![1])).unwrap(),
+            crate::rpc::res::Response::Ping(vec![1])
+        );
+    }
+
+    #[test]
+    fn all_has_every_flag() {
+        assert_eq!(
+            Capabilities::all().names().count(),
+            Capabilities::NAMES.len()
+        );
+        assert!(Capabilities::all().contains(Capabilities::APP_LOCK_STATUS));
+        assert!(Capabilities::empty().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Connection lifecycle events of a session
+//!
+//! A session started with [`RpcSession::new_with_events`] reports its connection state through
+//! an [`mpsc`](std::sync::mpsc) channel, so a GUI can show it as it changes instead of inferring
+//! it from failed calls:
+//!
+//! - [`SessionEvent::Connected`] when the session takes over a transport
+//! - [`SessionEvent::HandshakeComplete`] once the device identity was read
+//! - [`SessionEvent::Reconnecting`] when [`RpcSession::reconnect`] swaps the transport
+//! - [`SessionEvent::Disconnected`] once, when the device or the port goes away or the session
+//!   ends
+//!
+//! Events are dropped if the receiver is gone.
+
+use std::sync::mpsc::Sender;
+
+use crate::error::{Error, ErrorKind};
+
+use super::{DeviceIdentity, RpcSession};
+
+/// A change of a session's connection state, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    /// The session took over a transport and is about to read the device identity
+    Connected,
+    /// The device identity was read, the session is ready
+    HandshakeComplete(DeviceIdentity),
+    /// The session is switching to a new transport
+    Reconnecting,
+    /// The session lost its device, or was ended
+    Disconnected {
+        /// Why the connection ended
+        reason: DisconnectReason,
+    },
+}
+
+/// Why a session disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// The session was ended with [`RpcSession::into_inner`]
+    Closed,
+    /// The device rebooted and dropped the RPC session, see [`Error::DeviceRebooted`]
+    Rebooted,
+    /// The port was closed, unplugged or reset
+    PortLost,
+    /// Reading the device identity failed, with an error of this kind
+    HandshakeFailed(ErrorKind),
+}
+
+impl DisconnectReason {
+    /// The reason an error ends the connection, or `None` if the session can go on after it,
+    /// e.g. after a timeout or a device error
+    pub fn from_error(error: &Error) -> Option<Self> {
+        match error {
+            Error::DeviceRebooted => Some(Self::Rebooted),
+            Error::Io(error) => matches!(
+                error.kind(),
+                std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::UnexpectedEof
+            )
+            .then_some(Self::PortLost),
+            #[cfg(feature = "transport-serial")]
+            Error::Serialport(error) => {
+                (error.kind() == serialport::ErrorKind::NoDevice).then_some(Self::PortLost)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Where a session sends its events, and whether it still counts as connected
+#[derive(Debug, Default)]
+pub(super) struct Events {
+    tx: Option<Sender<SessionEvent>>,
+    connected: bool,
+}
+
+impl Events {
+    pub(super) fn new(tx: Option<Sender<SessionEvent>>) -> Self {
+        Self {
+            tx,
+            connected: false,
+        }
+    }
+
+    pub(super) fn emit(&mut self, event: SessionEvent) {
+        match event {
+            SessionEvent::Connected => self.connected = true,
+            SessionEvent::Disconnected { .. } if !self.connected => return,
+            SessionEvent::Disconnected { .. } => self.connected = false,
+            _ => {}
+        }
+
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(event);
+        }
+    }
+
+    /// Reports a disconnect if `result` failed with an error that ends the connection
+    pub(super) fn observe<R>(
+        &mut self,
+        result: crate::error::Result<R>,
+    ) -> crate::error::Result<R> {
+        if let Err(error) = &result {
+            if let Some(reason) = DisconnectReason::from_error(error) {
+                self.emit(SessionEvent::Disconnected { reason });
+            }
+        }
+
+        result
+    }
+}
+
+impl<T> RpcSession<T> {
+    /// Whether the session's transport is still connected, as far as it can tell from the
+    /// errors seen so far. Only tracked for sessions started with
+    /// [`RpcSession::new_with_events`]; others always report `true`.
+    pub fn is_connected(&self) -> bool {
+        self.events.tx.is_none() || self.events.connected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_fatal_errors_disconnect() {
+        let eof = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        let timeout = std::io::Error::from(std::io::ErrorKind::TimedOut);
+
+        assert_eq!(
+            DisconnectReason::from_error(&Error::DeviceRebooted),
+            Some(DisconnectReason::Rebooted)
+        );
+        assert_eq!(
+            DisconnectReason::from_error(&eof.into()),
+            Some(DisconnectReason::PortLost)
+        );
+        assert_eq!(DisconnectReason::from_error(&timeout.into()), None);
+    }
+
+    #[test]
+    fn disconnects_are_reported_once() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut events = Events::new(Some(tx));
+
+        events.emit(SessionEvent::Connected);
+        let _ = events.observe::<()>(Err(Error::DeviceRebooted));
+        let _ = events.observe::<()>(Err(Error::DeviceRebooted));
+
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [
+                SessionEvent::Connected,
+                SessionEvent::Disconnected {
+                    reason: DisconnectReason::Rebooted
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Background keepalive pings for idle sessions
+
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::cancel::is_cancelled;
+use crate::logging::{debug, warn};
+
+use crate::{
+    error::Error,
+    proto,
+    rpc::req::Request,
+    transport::{CommandIndex, Transport, TransportRaw},
+};
+
+use super::RpcSession;
+
+/// Longest the keepalive thread sleeps before checking whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A running keepalive thread, see [`RpcSession::spawn_keepalive`]
+///
+/// Dropping it stops the thread.
+#[derive(Debug)]
+pub struct Keepalive {
+    stop: Arc<AtomicBool>,
+    healthy: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Keepalive {
+    /// Whether the last keepalive ping got an answer. Stays `true` until a ping fails.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    /// Stops the thread and waits for it to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<T> RpcSession<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>
+        + CommandIndex
+        + std::fmt::Debug
+        + Send
+        + 'static,
+{
+    /// Spawns a thread that pings the device whenever `session` has been idle for `interval`,
+    /// so slow interactive tools are not dropped back to the CLI by the firmware.
+    ///
+    /// The thread locks `session` for each ping. Hold the lock for the whole of an operation
+    /// (e.g. an entire `fs_write`) so a ping can never land in the middle of a chain.
+    ///
+    /// The thread also stops once the session's [`CancelToken`](crate::CancelToken) is
+    /// cancelled.
+    pub fn spawn_keepalive(session: &Arc<Mutex<Self>>, interval: Duration) -> Keepalive {
+        let stop = Arc::new(AtomicBool::new(false));
+        let healthy = Arc::new(AtomicBool::new(true));
+        let cancel = session
+            .lock()
+            .ok()
+            .and_then(|session| session.cancel_token().cloned());
+
+        let thread = {
+            let session = Arc::clone(session);
+            let stop = Arc::clone(&stop);
+            let healthy = Arc::clone(&healthy);
+
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) && !is_cancelled(cancel.as_ref()) {
+                    std::thread::sleep(POLL_INTERVAL.min(interval));
+
+                    let Ok(mut session) = session.lock() else {
+                        warn!("session mutex poisoned, stopping keepalive");
+                        break;
+                    };
+
+                    if session.idle_for() < interval {
+                        continue;
+                    }
+
+                    debug!("session idle, sending keepalive ping");
+                    match session.send_and_receive(Request::Ping(vec![0])) {
+                        Ok(_) => healthy.store(true, Ordering::SeqCst),
+                        Err(_e) => {
+                            warn!(error = %_e, "keepalive ping failed");
+                            healthy.store(false, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+        };
+
+        Keepalive {
+            stop,
+            healthy,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::proto::main::Content;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn pings_while_idle() {
+        let session = Arc::new(Mutex::new(RpcSession::new(EmulatedFlipper::new()).unwrap()));
+
+        let keepalive = RpcSession::spawn_keepalive(&session, Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(keepalive.is_healthy());
+        keepalive.stop();
+
+        let session = session.lock().unwrap();
+        let pings = session
+            .get_ref()
+            .requests()
+            .iter()
+            .filter(|m| matches!(m.content, Some(Content::SystemPingRequest(_))))
+            .count();
+
+        assert!(pings > 0);
+    }
+}
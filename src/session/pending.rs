@@ -0,0 +1,187 @@
+//! Requests whose answers are collected later
+//!
+//! [`RpcSession::submit`] sends a request without waiting for its answer. Until the answer is
+//! collected with [`RpcSession::wait`], every read on the session sets messages carrying the
+//! request's command id aside, so other requests can run in between, e.g. a periodic power
+//! reading while a download is in progress.
+//!
+//! The firmware answers requests in the order it gets them and some operations do not tolerate
+//! others in between: a write chain is interrupted by any other storage request. Submitting
+//! only helps where the firmware handles requests independently.
+//!
+//! Answers are read with [`TransportRaw::receive_raw_unchecked`], so a failed answer is set
+//! aside by its command id like any other and only turns into an error in its own `wait`.
+//! Transports that do not override it lose the id of failed answers, which then fail whichever
+//! call reads them.
+
+use std::time::Instant;
+
+use crate::logging::trace;
+
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::{req::Request, res::Response},
+    transport::{CommandIndex, Transport, TransportRaw, check_status},
+};
+
+use super::RpcSession;
+
+/// A request sent with [`RpcSession::submit`] whose answer has not been collected yet
+///
+/// Dropping it without waiting leaves the answer set aside in the session.
+#[derive(Debug, PartialEq, Eq, Hash)]
+#[must_use = "the answer is set aside until it is collected with RpcSession::wait"]
+pub struct PendingResponse {
+    command_id: u32,
+}
+
+impl PendingResponse {
+    /// The command id the request was sent with
+    pub fn command_id(&self) -> u32 {
+        self.command_id
+    }
+}
+
+impl<T> RpcSession<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    /// Sends `req` without waiting for the answer. See the [module docs](self).
+    pub fn submit(&mut self, req: Request) -> Result<PendingResponse> {
+        let command_id = self.command_index();
+        self.send(req)?;
+        self.outstanding.insert(command_id, Vec::new());
+        trace!(command_id, "request submitted");
+
+        Ok(PendingResponse { command_id })
+    }
+
+    /// Whether the whole answer to `pending` has already been read
+    pub fn is_ready(&self, pending: &PendingResponse) -> bool {
+        self.outstanding
+            .get(&pending.command_id)
+            .and_then(|parts| parts.last())
+            .is_some_and(|main| !main.has_next)
+    }
+
+    /// Blocks until the answer to `pending` arrived and returns its first message. Use
+    /// [`RpcSession::wait_chain`] for requests answered with a `has_next` chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the answer, or any error reading from the device.
+    pub fn wait(&mut self, pending: PendingResponse) -> Result<Response> {
+        self.wait_chain(pending)?
+            .into_iter()
+            .next()
+            .ok_or(Error::InvalidRpcPayload("empty answer"))
+    }
+
+    /// Blocks until the whole answer to `pending` arrived and returns every message of it.
+    /// Messages for other requests read meanwhile are kept for them.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`RpcSession::wait`].
+    pub fn wait_chain(&mut self, pending: PendingResponse) -> Result<Vec<Response>> {
+        while !self.is_ready(&pending) {
+            // Cleared by a reconnect, the answer will never come
+            if !self.outstanding.contains_key(&pending.command_id) {
+                return Err(Error::InvalidRpcPayload("answer lost to a reconnect"));
+            }
+
+            let main = match self.events.observe(self.transport.receive_raw_unchecked()) {
+                Ok(main) => main,
+                Err(error) => {
+                    self.outstanding.remove(&pending.command_id);
+                    return Err(error);
+                }
+            };
+            self.last_activity = Instant::now();
+
+            match self.outstanding.get_mut(&main.command_id) {
+                Some(parts) => parts.push(main),
+                None => self.unclaimed.push_back(main),
+            }
+        }
+
+        let parts = self
+            .outstanding
+            .remove(&pending.command_id)
+            .unwrap_or_default();
+
+        parts
+            .into_iter()
+            .map(|main| Response::decode(check_status(main)?, self.decode_mode))
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn answers_are_kept_for_their_handles() {
+        let mut session = RpcSession::new(EmulatedFlipper::new()).unwrap();
+
+        let ping = session.submit(Request::Ping(vec![1])).unwrap();
+        let version = session.submit(Request::SystemProtobufVersion).unwrap();
+        assert_ne!(ping.command_id(), version.command_id());
+
+        // A request in between gets its own answer, not the submitted ones
+        assert_eq!(
+            session.send_and_receive(Request::Ping(vec![2])).unwrap(),
+            Response::Ping(vec![2])
+        );
+        assert!(session.is_ready(&ping));
+
+        assert!(matches!(
+            session.wait(version).unwrap(),
+            Response::SystemProtobufVersion(_)
+        ));
+        assert_eq!(session.wait(ping).unwrap(), Response::Ping(vec![1]));
+    }
+
+    #[test]
+    fn failed_answers_go_to_their_own_wait() {
+        let mut session = RpcSession::new(EmulatedFlipper::new()).unwrap();
+
+        let missing = session
+            .submit(Request::StorageMetadata("/ext/missing".to_string()))
+            .unwrap();
+        let ping = session.submit(Request::Ping(vec![1])).unwrap();
+
+        assert_eq!(session.wait(ping).unwrap(), Response::Ping(vec![1]));
+        assert!(matches!(
+            session.wait(missing),
+            Err(Error::Rpc(e)) if matches!(
+                e.root(),
+                crate::rpc::error::Error::StorageError(crate::rpc::error::StorageError::NotFound)
+            )
+        ));
+    }
+
+    #[test]
+    fn polling_keeps_failed_answers_for_their_wait() {
+        let mut session = RpcSession::new(EmulatedFlipper::new()).unwrap();
+
+        let missing = session
+            .submit(Request::StorageMetadata("/ext/missing".to_string()))
+            .unwrap();
+        assert_eq!(session.try_receive_raw().unwrap(), None);
+        assert!(session.is_ready(&missing));
+
+        // Reading the identity in between leaves other answers where they are
+        let ping = session.submit(Request::Ping(vec![1])).unwrap();
+        session.refresh_identity().unwrap();
+        assert_eq!(session.wait(ping).unwrap(), Response::Ping(vec![1]));
+
+        assert_eq!(
+            session.wait(missing).unwrap_err().rpc_status(),
+            Some(crate::proto::CommandStatus::ErrorStorageNotExist)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Device settings files
+//!
+//! Typed views of the settings files the firmware keeps in the [Flipper File Format](fff), such
+//! as the custom device name in [`NameSettings::PATH`]. [`read`] parses one into its typed
+//! struct, and [`write()`] validates the struct before updating the file on the device. Keys this
+//! crate does not know are kept as they are.
+//!
+//! The desktop, notification and power settings on `/int` are binary `saved_struct` files whose
+//! layout changes between firmware versions, so they are not covered here.
+//!
+//! Settings are loaded at boot, so changes take effect after a reboot.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::settings::{self, NameSettings};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let name = NameSettings {
+//!     name: "Dolph1n".to_string(),
+//! };
+//! settings::write(&mut rpc, &name)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod fff;
+pub use fff::FlipperFormat;
+
+use crate::logging::debug;
+
+use crate::fs::{EXTERNAL_STORAGE, FsRead, FsWrite, INTERNAL_FLASH};
+use crate::proto::CommandStatus;
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// A typed settings file
+pub trait SettingsFile: Sized {
+    /// Path of the file on the device
+    const PATH: &'static str;
+    /// Value of the `Filetype` header
+    const FILETYPE: &'static str;
+    /// Value of the `Version` header
+    const VERSION: u32;
+
+    /// Reads the settings from a parsed file
+    fn load(document: &FlipperFormat) -> Result<Self>;
+
+    /// Stores the settings into `document`, leaving other keys alone
+    fn store(&self, document: &mut FlipperFormat);
+
+    /// Checks that the device would accept the settings
+    fn validate(&self) -> Result<()>;
+}
+
+/// Longest device name the firmware accepts, see [`NameSettings`]
+pub const MAX_NAME_LEN: usize = 8;
+
+/// Custom device name, `/ext/dolphin/name.settings`
+///
+/// Firmwares that support renaming read the file at boot and advertise the name over USB and
+/// Bluetooth instead of the one set at the factory. Names are 1 to [`MAX_NAME_LEN`] ASCII
+/// letters and digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameSettings {
+    /// The name, without the `Flipper ` prefix the firmware adds
+    pub name: String,
+}
+
+impl SettingsFile for NameSettings {
+    const PATH: &'static str = "/ext/dolphin/name.settings";
+    const FILETYPE: &'static str = "Flipper Name File";
+    const VERSION: u32 = 1;
+
+    fn load(document: &FlipperFormat) -> Result<Self> {
+        Ok(Self {
+            name: document.parse_value("Name")?,
+        })
+    }
+
+    fn store(&self, document: &mut FlipperFormat) {
+        document.set("Name", &self.name);
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+            return Err(invalid("name must be 1 to 8 characters long"));
+        }
+        if !self.name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid("name may only contain ASCII letters and digits"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the settings file `S` from the device.
+///
+/// # Errors
+///
+/// Returns the device's error if the file cannot be read, or an
+/// [`std::io::ErrorKind::InvalidData`] error if it is not an FFF file of the expected type.
+pub fn read<S, T>(transport: &mut T) -> Result<S>
+where
+    S: SettingsFile,
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let document = read_document::<S, T>(transport)?;
+
+    S::load(&document)
+}
+
+/// Validates `settings` and writes them to the device, keeping keys this crate does not know.
+/// The file is created if it does not exist.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error if validation fails, an
+/// [`std::io::ErrorKind::InvalidData`] error if the existing file is not an FFF file of the
+/// expected type, or the device's error if the transfer fails.
+pub fn write<S, T>(transport: &mut T, settings: &S) -> Result<()>
+where
+    S: SettingsFile,
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    settings.validate()?;
+
+    let mut document = match read_document::<S, T>(transport) {
+        Ok(document) => document,
+        Err(e) if e.rpc_status() == Some(CommandStatus::ErrorStorageNotExist) => {
+            FlipperFormat::new(S::FILETYPE, S::VERSION)
+        }
+        Err(e) => return Err(e),
+    };
+    settings.store(&mut document);
+
+    let text = document.to_string();
+    transport.fs_write(
+        S::PATH,
+        &text,
+        #[cfg(feature = "fs-write-progress-mpsc")]
+        None,
+    )?;
+    debug!(path = S::PATH, "settings written");
+
+    Ok(())
+}
+
+fn read_document<S, T>(transport: &mut T) -> Result<FlipperFormat>
+where
+    S: SettingsFile,
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    debug_assert!(S::PATH.starts_with(INTERNAL_FLASH) || S::PATH.starts_with(EXTERNAL_STORAGE));
+
+    let document: FlipperFormat = transport.fs_read_to_string_lossy(S::PATH)?.parse()?;
+    if document.filetype() != Some(S::FILETYPE) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: expected filetype {}", S::PATH, S::FILETYPE),
+        )
+        .into());
+    }
+
+    Ok(document)
+}
+
+fn invalid(message: &'static str) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn writes_validated_settings_and_keeps_unknown_keys() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file(
+            NameSettings::PATH,
+            b"Filetype: Flipper Name File\nVersion: 1\n# set by the user\nName: Old\nExtra: 1\n"
+                .to_vec(),
+        );
+
+        let mut settings: NameSettings = read(&mut flipper).unwrap();
+        assert_eq!(settings.name, "Old");
+
+        settings.name = "Dolph1n".to_string();
+        write(&mut flipper, &settings).unwrap();
+        assert_eq!(
+            flipper.file(NameSettings::PATH),
+            Some(&b"Filetype: Flipper Name File\nVersion: 1\n# set by the user\nName: Dolph1n\nExtra: 1\n"[..])
+        );
+        assert_eq!(read::<NameSettings, _>(&mut flipper).unwrap(), settings);
+    }
+
+    #[test]
+    fn validates_names() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/dolphin/state", Vec::new());
+
+        for name in ["", "TooLongName", "Näme", "a b"] {
+            let settings = NameSettings {
+                name: name.to_string(),
+            };
+            assert!(write(&mut flipper, &settings).is_err(), "{name:?}");
+        }
+        assert_eq!(flipper.file(NameSettings::PATH), None);
+
+        let settings = NameSettings {
+            name: "Dolph1n".to_string(),
+        };
+        write(&mut flipper, &settings).unwrap();
+        assert_eq!(
+            flipper.file(NameSettings::PATH),
+            Some(&b"Filetype: Flipper Name File\nVersion: 1\nName: Dolph1n\n"[..])
+        );
+    }
+
+    #[test]
+    fn refuses_to_overwrite_other_files() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file(NameSettings::PATH, vec![0x9e, 0x02, 0x17, 0x00]);
+
+        let settings = NameSettings {
+            name: "Dolph1n".to_string(),
+        };
+
+        assert!(write(&mut flipper, &settings).is_err());
+        assert_eq!(
+            flipper.file(NameSettings::PATH),
+            Some(&[0x9e, 0x02, 0x17, 0x00][..])
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Flipper File Format codec
+//!
+//! FFF is the line based `Key: value` text format the firmware uses for key files and settings.
+//! The first two entries are the `Filetype` and `Version` headers; `#` starts a comment line.
+//! [`FlipperFormat`] keeps entries, comments and blank lines in file order and leaves unknown
+//! keys alone, so a file can be read, changed and written back without losing what this crate
+//! does not understand. Only line endings are normalized to `\n`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// Key of the file type header
+pub const FILETYPE_KEY: &str = "Filetype";
+/// Key of the format version header
+pub const VERSION_KEY: &str = "Version";
+
+/// An FFF document, as ordered key/value entries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlipperFormat {
+    lines: Vec<Line>,
+}
+
+/// One line of a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Entry(String, String),
+    /// A comment or blank line, kept verbatim
+    Other(String),
+}
+
+impl FlipperFormat {
+    /// An empty document with the `Filetype` and `Version` headers set
+    pub fn new(filetype: &str, version: u32) -> Self {
+        let mut document = Self::default();
+        document.set(FILETYPE_KEY, filetype);
+        document.set(VERSION_KEY, version);
+
+        document
+    }
+
+    /// The value of the first entry named `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the value of `key`, failing if it is missing or malformed
+    pub fn parse_value<V: FromStr>(&self, key: &str) -> Result<V> {
+        let value = self
+            .get(key)
+            .ok_or_else(|| invalid(format!("missing key {key}")))?;
+
+        value
+            .parse()
+            .map_err(|_| invalid(format!("invalid value for {key}: {value}")))
+    }
+
+    /// Sets `key` to `value`, replacing the first entry of that name or appending a new one
+    pub fn set(&mut self, key: &str, value: impl fmt::Display) {
+        let value = value.to_string();
+
+        let entry = self.lines.iter_mut().find_map(|line| match line {
+            Line::Entry(k, old) if k == key => Some(old),
+            _ => None,
+        });
+
+        match entry {
+            Some(old) => *old = value,
+            None => self.lines.push(Line::Entry(key.to_string(), value)),
+        }
+    }
+
+    /// The `Filetype` header
+    pub fn filetype(&self) -> Option<&str> {
+        self.get(FILETYPE_KEY)
+    }
+
+    /// The `Version` header
+    pub fn version(&self) -> Option<u32> {
+        self.get(VERSION_KEY)?.parse().ok()
+    }
+
+    /// All entries in file order, without comments
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry(key, value) => Some((key.as_str(), value.as_str())),
+            Line::Other(_) => None,
+        })
+    }
+}
+
+impl FromStr for FlipperFormat {
+    type Err = Error;
+
+    /// Parses FFF text. Comments and blank lines are kept for [`fmt::Display`].
+    fn from_str(text: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                lines.push(Line::Other(line.to_string()));
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(invalid(format!("fff: line without a key: {line}")));
+            };
+            lines.push(Line::Entry(
+                key.trim().to_string(),
+                value.trim().to_string(),
+            ));
+        }
+
+        let document = Self { lines };
+        if document
+            .entries()
+            .next()
+            .is_none_or(|(key, _)| key != FILETYPE_KEY)
+        {
+            return Err(invalid("fff: missing Filetype header".to_string()));
+        }
+
+        Ok(document)
+    }
+}
+
+impl fmt::Display for FlipperFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Entry(key, value) => writeln!(f, "{key}: {value}")?,
+                Line::Other(text) => writeln!(f, "{text}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_keeps_unknown_keys() {
+        let text = "Filetype: Flipper Power Settings\r\nVersion: 1\n# idle shutdown\n\nShutdown_idle_delay_ms: 0\nFuture: a: b\n";
+        let mut document: FlipperFormat = text.parse().unwrap();
+
+        assert_eq!(document.filetype(), Some("Flipper Power Settings"));
+        assert_eq!(document.version(), Some(1));
+        assert_eq!(document.get("Future"), Some("a: b"));
+        assert_eq!(
+            document
+                .parse_value::<u32>("Shutdown_idle_delay_ms")
+                .unwrap(),
+            0
+        );
+        assert!(document.parse_value::<u32>("Future").is_err());
+        assert!(document.parse_value::<u32>("Missing").is_err());
+
+        document.set("Shutdown_idle_delay_ms", 60_000);
+        assert_eq!(
+            document.to_string(),
+            "Filetype: Flipper Power Settings\nVersion: 1\n# idle shutdown\n\nShutdown_idle_delay_ms: 60000\nFuture: a: b\n"
+        );
+
+        document.set("Added", 1);
+        let text = document.to_string();
+        assert_eq!(text.parse::<FlipperFormat>().unwrap().to_string(), text);
+
+        assert!("Version: 1\n".parse::<FlipperFormat>().is_err());
+        assert!("\u{1}\u{2}binary".parse::<FlipperFormat>().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! System helpers
+//!
+//! Wrappers around `System*` requests that need more care than a bare [`Request`].
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "system-log")]
+pub mod log;
+#[cfg(feature = "system-log")]
+pub use log::{LogLevel, LogRecord, LogStream, log_stream};
+
+#[cfg(feature = "system-name")]
+pub mod name;
+#[cfg(feature = "system-name")]
+pub use name::set_name;
+
+use crate::logging::{trace, warn};
+
+use crate::rpc::res::Response;
+use crate::transport::CommandIndex;
+use crate::transport::Transport;
+use crate::{
+    error::{Error, Result},
+    proto::{self, system::DeviceInfoResponse},
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// Explicit acknowledgement required by destructive system calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Confirmation {
+    /// The caller understands that all user data on the device will be erased
+    IUnderstandDataLoss,
+}
+
+/// Factory resets the device, erasing internal storage and settings.
+///
+/// The device reboots immediately and does not answer, so the session is unusable afterwards.
+/// Requiring a [`Confirmation`] keeps a stray call in copied example code from wiping a device.
+pub fn factory_reset<T>(transport: &mut T, confirmation: Confirmation) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    match confirmation {
+        Confirmation::IUnderstandDataLoss => {
+            warn!("factory resetting device, all user data will be erased");
+
+            transport.send(Request::SystemFactoryReset)
+        }
+    }
+}
+
+/// Reads the full device info chain into a key/value map.
+///
+/// The device answers `SystemDeviceInfo` with one `has_next` message per key.
+pub fn device_info<T>(transport: &mut T) -> Result<BTreeMap<String, String>>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let mut info = BTreeMap::new();
+
+    transport.send(Request::SystemDeviceInfo)?;
+
+    loop {
+        let response = transport.receive_raw()?;
+        let has_next = response.has_next;
+
+        let DeviceInfoResponse { key, value } = Response::try_from(response)?.try_into()?;
+        trace!(key, value, "device info");
+        info.insert(key, value);
+
+        if !has_next {
+            break;
+        }
+    }
+
+    Ok(info)
+}
@@ -0,0 +1,226 @@
+//! Device log streaming
+//!
+//! The RPC schema has no log messages, so logs are read through the text CLI `log` command.
+//! On an RPC connection, run [`log_stream`] inside `SerialRpcTransport::with_cli` to get logs and
+//! RPC over a single port.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::system::{LogLevel, log_stream};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! rpc.with_cli(|cli| {
+//!     for record in log_stream(cli, LogLevel::Debug)?.take(10) {
+//!         let record = record?;
+//!         println!("[{}] {}", record.tag, record.message);
+//!     }
+//!     Ok(())
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use crate::error::Result;
+use crate::logging::{debug, trace};
+use crate::transport::{Transport, serial::cli::SerialCliTransport};
+
+/// Severity of a device log record, most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// `[E]`
+    Error,
+    /// `[W]`
+    Warn,
+    /// `[I]`
+    Info,
+    /// `[D]`
+    Debug,
+    /// `[T]`
+    Trace,
+}
+
+impl LogLevel {
+    /// The argument the `log` command takes for this level
+    fn as_arg(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    fn from_letter(letter: &str) -> Option<Self> {
+        Some(match letter {
+            "E" => Self::Error,
+            "W" => Self::Warn,
+            "I" => Self::Info,
+            "D" => Self::Debug,
+            "T" => Self::Trace,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_arg())
+    }
+}
+
+/// A single device log line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Milliseconds since boot
+    pub timestamp_ms: u32,
+    /// Severity
+    pub level: LogLevel,
+    /// Subsystem tag, e.g. `Storage`
+    pub tag: String,
+    /// The log message
+    pub message: String,
+}
+
+impl LogRecord {
+    /// Parses a line printed by the `log` command, such as
+    /// `1234 [I][Storage] SD card mounted`. Color escape codes are ignored.
+    ///
+    /// Returns `None` for lines that are not log records.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = strip_ansi(line);
+
+        let (timestamp, rest) = line.trim_start().split_once(' ')?;
+        let timestamp_ms = timestamp.parse().ok()?;
+
+        let rest = rest.trim_start().strip_prefix('[')?;
+        let (level, rest) = rest.split_once("][")?;
+        let (tag, message) = rest.split_once(']')?;
+
+        Some(Self {
+            timestamp_ms,
+            level: LogLevel::from_letter(level)?,
+            tag: tag.to_string(),
+            message: message.trim().to_string(),
+        })
+    }
+}
+
+/// A running `log` command, yielding records until dropped
+///
+/// Dropping the stream sends Ctrl-C and waits for the prompt, leaving the CLI usable. Use
+/// [`LogStream::stop`] to see errors from that.
+#[derive(Debug)]
+pub struct LogStream<'a> {
+    cli: &'a mut SerialCliTransport,
+    stopped: bool,
+}
+
+/// Starts streaming device logs at `level` and above.
+///
+/// Lines that are not log records (the command banner, multi-line continuations) are skipped.
+/// Iterating blocks until the next record arrives.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn log_stream(cli: &mut SerialCliTransport, level: LogLevel) -> Result<LogStream<'_>> {
+    debug!(%level, "starting log stream");
+    cli.send(format!("log {}", level.as_arg()))?;
+
+    Ok(LogStream {
+        cli,
+        stopped: false,
+    })
+}
+
+impl LogStream<'_> {
+    /// Stops the `log` command and returns to the prompt
+    pub fn stop(mut self) -> Result<()> {
+        self.stopped = true;
+
+        self.cli.interrupt()
+    }
+}
+
+impl Iterator for LogStream<'_> {
+    type Item = Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.cli.read_line() {
+                Ok(line) => {
+                    trace!(line, "log line");
+
+                    if let Some(record) = LogRecord::parse(&line) {
+                        return Some(Ok(record));
+                    }
+                }
+                // A quiet device is not an error, keep waiting
+                Err(crate::error::Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Drop for LogStream<'_> {
+    fn drop(&mut self) {
+        if !self.stopped {
+            let _ = self.cli.interrupt();
+        }
+    }
+}
+
+/// Removes `ESC [ ... <letter>` color sequences
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip until the final byte of the sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colored_records() {
+        let record = LogRecord::parse("\x1b[32m1234 [I][Storage] \x1b[0mSD card mounted\r")
+            .expect("should parse");
+
+        assert_eq!(
+            record,
+            LogRecord {
+                timestamp_ms: 1234,
+                level: LogLevel::Info,
+                tag: "Storage".to_string(),
+                message: "SD card mounted".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn skips_non_records() {
+        assert_eq!(LogRecord::parse("Press CTRL+C to stop..."), None);
+        assert_eq!(LogRecord::parse(""), None);
+        assert_eq!(LogRecord::parse("12 [X][Tag] unknown level"), None);
+    }
+}
@@ -0,0 +1,79 @@
+//! Renaming the device
+//!
+//! The name lives in a settings file, [`NameSettings`], which the firmware reads at boot. Stock
+//! firmwares before custom names were supported ignore it and keep the factory name.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::proto::system::reboot_request::RebootMode;
+//! use flipper_rpc::rpc::req::Request;
+//! use flipper_rpc::system::set_name;
+//! use flipper_rpc::transport::{Transport, serial::rpc::SerialRpcTransport};
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! set_name(&mut rpc, "Dolphin")?;
+//! // Takes effect after a reboot, the device does not answer this request
+//! rpc.send(Request::Reboot(RebootMode::Os))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::logging::debug;
+
+use crate::settings::{self, NameSettings};
+use crate::transport::CommandIndex;
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::TransportRaw,
+};
+
+/// Sets the device name to `name`, effective after the next reboot. The name is 1 to
+/// [`MAX_NAME_LEN`](crate::settings::MAX_NAME_LEN) ASCII letters and digits, without the
+/// `Flipper ` prefix.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error if the name is not allowed, or the
+/// device's error if the file cannot be written, e.g. without an SD card.
+pub fn set_name<T>(transport: &mut T, name: &str) -> Result<()>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    settings::write(
+        transport,
+        &NameSettings {
+            name: name.to_string(),
+        },
+    )?;
+    debug!(name, "device renamed, reboot to apply");
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsFile;
+    use crate::testing::EmulatedFlipper;
+
+    #[test]
+    fn keeps_other_keys_of_the_name_file() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file(
+            NameSettings::PATH,
+            b"Filetype: Flipper Name File\nVersion: 1\nName: Old\nExtra: 1\n".to_vec(),
+        );
+
+        set_name(&mut flipper, "New").unwrap();
+        assert_eq!(
+            flipper.file(NameSettings::PATH),
+            Some(&b"Filetype: Flipper Name File\nVersion: 1\nName: New\nExtra: 1\n"[..])
+        );
+        assert!(set_name(&mut flipper, "Flipper Zero").is_err());
+    }
+}
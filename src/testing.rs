@@ -0,0 +1,942 @@
+//! Test helpers
+//!
+//! [`EmulatedFlipper`] is a pure-software stand-in for a Flipper Zero. It speaks the device side
+//! of the RPC protocol over an in-memory byte pipe, with an in-memory filesystem, so sessions,
+//! chained reads and writes, and error paths can be tested end-to-end without hardware.
+//!
+//! # Examples
+//!
+//! ```
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::rpc::{req::Request, res::Response};
+//! use flipper_rpc::testing::EmulatedFlipper;
+//! use flipper_rpc::transport::Transport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut flipper = EmulatedFlipper::new();
+//!
+//! let response = flipper.send_and_receive(Request::Ping(vec![1, 2, 3]))?;
+//! assert_eq!(response, Response::Ping(vec![1, 2, 3]));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+
+use prost::bytes::{Bytes, BytesMut};
+
+use crate::codec::FrameCodec;
+
+use crate::logging::trace;
+
+use crate::{
+    error::{Error, Result},
+    proto::{
+        self, CommandStatus, Empty,
+        gpio::{GetOtgModeResponse, GetPinModeResponse, GpioPin, GpioPinMode, ReadPinResponse},
+        gui::{InputKey, InputType, ScreenFrame},
+        main::Content,
+        storage::{
+            File, InfoResponse, ListResponse, Md5sumResponse, ReadResponse, StatResponse,
+            file::FileType,
+        },
+        system::{DeviceInfoResponse, PingResponse, ProtobufVersionResponse},
+    },
+    transport::{CommandIndex, FIRST_COMMAND_ID, TransportRaw, check_status, next_command_id},
+};
+
+/// Protobuf schema version reported by the emulator
+pub const PROTOBUF_VERSION: (u32, u32) = proto::SCHEMA_VERSION;
+
+/// Bytes per `ReadResponse` chunk, matching the firmware
+const READ_CHUNK_SIZE: usize = 512;
+
+/// Entries per `ListResponse` chunk, matching the firmware
+const LIST_CHUNK_SIZE: usize = 8;
+
+/// Reported size of the emulated SD card
+const STORAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Bytes in a 128x64 monochrome screen frame
+const SCREEN_FRAME_SIZE: usize = 128 * 64 / 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// An emulated Flipper Zero, see the [module docs](self)
+///
+/// Implements [`TransportRaw`] and [`CommandIndex`], so every helper that accepts a transport
+/// also accepts the emulator.
+#[derive(Debug)]
+pub struct EmulatedFlipper {
+    command_index: u32,
+    /// Bytes written by the host that the device has not consumed yet
+    to_device: BytesMut,
+    /// Bytes written by the device that the host has not read yet
+    from_device: BytesMut,
+    codec: FrameCodec,
+    fs: BTreeMap<String, Entry>,
+    device_info: Vec<(String, String)>,
+    /// Write chains in progress, keyed by command id
+    writes: HashMap<u32, (String, Vec<u8>)>,
+    /// Statuses to answer the next requests with instead of handling them
+    failures: VecDeque<CommandStatus>,
+    requests: Vec<proto::Main>,
+    /// Frame sent while the screen is streamed
+    screen: Bytes,
+    /// Frames sent after each `StartScreenStream`
+    frames_per_stream: usize,
+    /// Mode and level of each GPIO pin, by pin number
+    pins: BTreeMap<i32, (GpioPinMode, u32)>,
+    otg: i32,
+    /// Name and arguments of the running app
+    app: Option<(String, String)>,
+    /// The PIN of a locked desktop, empty for the plain lock screen
+    lock: Option<Vec<InputKey>>,
+    /// Arrow keys typed on the PIN screen since the last OK
+    typed_pin: Vec<InputKey>,
+    /// Every timeout set by the host, in order
+    timeouts: Vec<Duration>,
+}
+
+impl Default for EmulatedFlipper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmulatedFlipper {
+    /// Creates an emulator with empty `/ext` and `/int` storages and a minimal device info
+    pub fn new() -> Self {
+        let device_info = [
+            ("hardware.name", "Emulated"),
+            ("hardware.ver", "12"),
+            ("hardware.uid", "0000000000000000"),
+            ("firmware.version", "0.0.0"),
+            ("firmware.commit.hash", "0000000"),
+            ("firmware.branch.name", "emulated"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        Self {
+            command_index: FIRST_COMMAND_ID,
+            to_device: BytesMut::new(),
+            from_device: BytesMut::new(),
+            codec: FrameCodec::new(),
+            fs: BTreeMap::from([
+                ("/ext".to_string(), Entry::Dir),
+                ("/int".to_string(), Entry::Dir),
+            ]),
+            device_info,
+            writes: HashMap::new(),
+            failures: VecDeque::new(),
+            requests: Vec::new(),
+            screen: Bytes::from(vec![0; SCREEN_FRAME_SIZE]),
+            frames_per_stream: 1,
+            pins: BTreeMap::new(),
+            otg: 0,
+            app: None,
+            lock: None,
+            typed_pin: Vec::new(),
+            timeouts: Vec::new(),
+        }
+    }
+
+    /// Sets the screen contents and how many frames of it each `StartScreenStream` sends. The
+    /// default is one blank frame.
+    pub fn with_screen(mut self, frame: impl Into<Vec<u8>>, frames_per_stream: usize) -> Self {
+        self.screen = Bytes::from(frame.into());
+        self.frames_per_stream = frames_per_stream;
+
+        self
+    }
+
+    /// Whether `pin` is driven high. Pins start as low inputs.
+    pub fn pin_level(&self, pin: GpioPin) -> bool {
+        self.pins
+            .get(&(pin as i32))
+            .is_some_and(|&(_, level)| level != 0)
+    }
+
+    /// Name and arguments of the app started with `AppStart`, if it has not exited
+    pub fn running_app(&self) -> Option<(&str, &str)> {
+        self.app
+            .as_ref()
+            .map(|(name, args)| (name.as_str(), args.as_str()))
+    }
+
+    /// Locks the desktop. Short presses of the arrow keys in `pin` followed by OK unlock it; with
+    /// an empty `pin`, `DesktopUnlock` does.
+    pub fn lock(&mut self, pin: &[InputKey]) {
+        self.lock = Some(pin.to_vec());
+        self.typed_pin.clear();
+    }
+
+    /// Whether the desktop is locked
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Sets a device info key, replacing any previous value
+    pub fn with_device_info(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        let value = value.into();
+
+        match self.device_info.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.device_info.push((key, value)),
+        }
+
+        self
+    }
+
+    /// Creates a file, along with any missing parent directories
+    pub fn insert_file(&mut self, path: &str, data: impl Into<Vec<u8>>) {
+        let path = normalize(path);
+
+        let mut parent = path.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if dir.is_empty() {
+                break;
+            }
+            self.fs.entry(dir.to_string()).or_insert(Entry::Dir);
+            parent = dir;
+        }
+
+        self.fs.insert(path, Entry::File(data.into()));
+    }
+
+    /// Returns the contents of a file, if it exists
+    pub fn file(&self, path: &str) -> Option<&[u8]> {
+        match self.fs.get(&normalize(path)) {
+            Some(Entry::File(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Whether a directory exists
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.fs.get(&normalize(path)) == Some(&Entry::Dir)
+    }
+
+    /// Answers the next request with `status` instead of handling it. Queued failures are used
+    /// in order, one per request.
+    pub fn fail_next(&mut self, status: CommandStatus) {
+        self.failures.push_back(status);
+    }
+
+    /// Queues a message the device sends on its own, such as a screen frame or a desktop status
+    pub fn push_event(&mut self, content: Content) {
+        self.respond(0, CommandStatus::Ok, false, Some(content));
+    }
+
+    /// Every message the device has received, in order
+    pub fn requests(&self) -> &[proto::Main] {
+        &self.requests
+    }
+
+    /// Every timeout the host has set, in order. The emulator itself never times out.
+    pub fn timeouts(&self) -> &[Duration] {
+        &self.timeouts
+    }
+
+    /// Decodes and handles every complete message the host has written
+    fn process(&mut self) -> Result<()> {
+        while let Some(message) = self.codec.decode(&mut self.to_device)? {
+            trace!(command_id = message.command_id, "emulator received");
+            self.requests.push(message.clone());
+            self.handle(message);
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, message: proto::Main) {
+        let id = message.command_id;
+
+        if let Some(status) = self.failures.pop_front() {
+            self.writes.remove(&id);
+            return self.respond(id, status, false, None);
+        }
+
+        let Some(content) = message.content else {
+            return self.respond(id, CommandStatus::ErrorDecode, false, None);
+        };
+
+        // Like the firmware, any other storage request interrupts an open write chain, while
+        // system requests such as pings pass
+        if is_storage_request(&content) {
+            let interrupted = self
+                .writes
+                .keys()
+                .copied()
+                .filter(|&open| open != id)
+                .collect::<Vec<_>>();
+
+            for open in interrupted {
+                self.writes.remove(&open);
+                self.respond(
+                    open,
+                    CommandStatus::ErrorContinuousCommandInterrupted,
+                    false,
+                    None,
+                );
+            }
+        }
+
+        match content {
+            Content::StopSession(_) => self.ok(id, Content::Empty(Empty {})),
+            Content::SystemPingRequest(req) => self.ok(
+                id,
+                Content::SystemPingResponse(PingResponse { data: req.data }),
+            ),
+            Content::SystemDeviceInfoRequest(_) => {
+                let info = self.device_info.clone();
+                let last = info.len().saturating_sub(1);
+
+                for (i, (key, value)) in info.into_iter().enumerate() {
+                    self.respond(
+                        id,
+                        CommandStatus::Ok,
+                        i != last,
+                        Some(Content::SystemDeviceInfoResponse(DeviceInfoResponse {
+                            key,
+                            value,
+                        })),
+                    );
+                }
+            }
+            Content::SystemProtobufVersionRequest(_) => self.ok(
+                id,
+                Content::SystemProtobufVersionResponse(ProtobufVersionResponse {
+                    major: PROTOBUF_VERSION.0,
+                    minor: PROTOBUF_VERSION.1,
+                }),
+            ),
+            Content::StorageInfoRequest(_) => {
+                let used: u64 = self
+                    .fs
+                    .values()
+                    .map(|entry| match entry {
+                        Entry::File(data) => data.len() as u64,
+                        Entry::Dir => 0,
+                    })
+                    .sum();
+
+                self.ok(
+                    id,
+                    Content::StorageInfoResponse(InfoResponse {
+                        total_space: STORAGE_SIZE,
+                        free_space: STORAGE_SIZE.saturating_sub(used),
+                    }),
+                )
+            }
+            Content::StorageStatRequest(req) => match self.fs.get(&normalize(&req.path)) {
+                Some(entry) => {
+                    let file = describe(&req.path, entry, false);
+                    self.ok(
+                        id,
+                        Content::StorageStatResponse(StatResponse { file: Some(file) }),
+                    )
+                }
+                None => self.error(id, CommandStatus::ErrorStorageNotExist),
+            },
+            Content::StorageListRequest(req) => self.list(id, &req.path, req.include_md5),
+            Content::StorageReadRequest(req) => self.read(id, &req.path),
+            Content::StorageWriteRequest(req) => {
+                let data = req.file.map(|file| file.data).unwrap_or_default();
+                let (_, buf) = self
+                    .writes
+                    .entry(id)
+                    .or_insert_with(|| (req.path, Vec::new()));
+                buf.extend_from_slice(&data);
+
+                if !message.has_next {
+                    let (path, data) = self.writes.remove(&id).unwrap_or_default();
+                    self.write(id, &path, data);
+                }
+            }
+            Content::StorageDeleteRequest(req) => self.delete(id, &req.path, req.recursive),
+            Content::StorageMkdirRequest(req) => {
+                let path = normalize(&req.path);
+
+                let failure = if self.fs.contains_key(&path) {
+                    Some(CommandStatus::ErrorStorageExist)
+                } else if !self.parent_exists(&path) {
+                    Some(CommandStatus::ErrorStorageNotExist)
+                } else {
+                    None
+                };
+
+                match failure {
+                    Some(status) => self.error(id, status),
+                    None => {
+                        self.fs.insert(path, Entry::Dir);
+                        self.ok(id, Content::Empty(Empty {}))
+                    }
+                }
+            }
+            Content::StorageMd5sumRequest(req) => match self.fs.get(&normalize(&req.path)) {
+                Some(Entry::File(data)) => {
+                    let md5sum = format!("{:x}", md5::compute(data));
+                    self.ok(
+                        id,
+                        Content::StorageMd5sumResponse(Md5sumResponse { md5sum }),
+                    )
+                }
+                Some(Entry::Dir) => self.error(id, CommandStatus::ErrorStorageInvalidName),
+                None => self.error(id, CommandStatus::ErrorStorageNotExist),
+            },
+            Content::StorageRenameRequest(req) => {
+                let from = normalize(&req.old_path);
+                let to = normalize(&req.new_path);
+
+                if !self.fs.contains_key(&from) {
+                    return self.error(id, CommandStatus::ErrorStorageNotExist);
+                }
+                if self.fs.contains_key(&to) {
+                    return self.error(id, CommandStatus::ErrorStorageExist);
+                }
+
+                let moved = self
+                    .fs
+                    .keys()
+                    .filter(|key| is_within(key, &from))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                for key in moved {
+                    let entry = self.fs.remove(&key).expect("key was just listed");
+                    self.fs.insert(format!("{to}{}", &key[from.len()..]), entry);
+                }
+
+                self.ok(id, Content::Empty(Empty {}))
+            }
+            Content::StorageTarExtractRequest(req) => {
+                self.extract_tar(id, &req.tar_path, &req.out_path)
+            }
+            Content::AppStartRequest(req) => {
+                if self.app.is_some() {
+                    self.error(id, CommandStatus::ErrorAppSystemLocked)
+                } else {
+                    self.app = Some((req.name, req.args));
+                    self.ok(id, Content::Empty(Empty {}))
+                }
+            }
+            Content::AppExitRequest(_) => match self.app.take() {
+                Some(_) => self.ok(id, Content::Empty(Empty {})),
+                None => self.error(id, CommandStatus::ErrorAppNotRunning),
+            },
+            Content::GuiStartScreenStreamRequest(_) => {
+                self.ok(id, Content::Empty(Empty {}));
+
+                // Frames are pushed by the device, outside of any command
+                for _ in 0..self.frames_per_stream {
+                    let frame = ScreenFrame {
+                        data: self.screen.clone(),
+                        orientation: 0,
+                    };
+                    self.respond(
+                        0,
+                        CommandStatus::Ok,
+                        false,
+                        Some(Content::GuiScreenFrame(frame)),
+                    );
+                }
+            }
+            Content::GuiSendInputEventRequest(req) => {
+                if req.r#type() == InputType::Short {
+                    self.type_pin(req.key());
+                }
+                self.ok(id, Content::Empty(Empty {}))
+            }
+            // Like the firmware, a generic error when unlocked
+            Content::DesktopIsLockedRequest(_) => match self.lock {
+                Some(_) => self.ok(id, Content::Empty(Empty {})),
+                None => self.error(id, CommandStatus::Error),
+            },
+            // Only dismisses the plain lock screen
+            Content::DesktopUnlockRequest(_) => {
+                if self.lock.as_ref().is_some_and(Vec::is_empty) {
+                    self.lock = None;
+                }
+                self.ok(id, Content::Empty(Empty {}))
+            }
+            Content::GuiStopScreenStreamRequest(_)
+            | Content::DesktopStatusSubscribeRequest(_)
+            | Content::DesktopStatusUnsubscribeRequest(_) => self.ok(id, Content::Empty(Empty {})),
+            Content::GpioSetPinMode(req) => {
+                let mode = GpioPinMode::try_from(req.mode).unwrap_or(GpioPinMode::Input);
+                self.pins.insert(req.pin, (mode, 0));
+                self.ok(id, Content::Empty(Empty {}))
+            }
+            Content::GpioGetPinMode(req) => {
+                let mode = self.pin_mode(req.pin);
+                self.ok(
+                    id,
+                    Content::GpioGetPinModeResponse(GetPinModeResponse { mode: mode.into() }),
+                )
+            }
+            Content::GpioSetInputPull(req) => match self.pin_mode(req.pin) {
+                GpioPinMode::Input => self.ok(id, Content::Empty(Empty {})),
+                GpioPinMode::Output => self.error(id, CommandStatus::ErrorGpioModeIncorrect),
+            },
+            Content::GpioReadPin(req) => match self.pins.get(&req.pin) {
+                Some((GpioPinMode::Output, _)) => {
+                    self.error(id, CommandStatus::ErrorGpioModeIncorrect)
+                }
+                pin => {
+                    let value = pin.map_or(0, |&(_, level)| level);
+                    self.ok(id, Content::GpioReadPinResponse(ReadPinResponse { value }))
+                }
+            },
+            Content::GpioWritePin(req) => match self.pins.get_mut(&req.pin) {
+                Some((GpioPinMode::Output, level)) => {
+                    *level = req.value;
+                    self.ok(id, Content::Empty(Empty {}))
+                }
+                _ => self.error(id, CommandStatus::ErrorGpioModeIncorrect),
+            },
+            Content::GpioGetOtgMode(_) => self.ok(
+                id,
+                Content::GpioGetOtgModeResponse(GetOtgModeResponse { mode: self.otg }),
+            ),
+            Content::GpioSetOtgMode(req) => {
+                self.otg = req.mode;
+                self.ok(id, Content::Empty(Empty {}))
+            }
+            _ => self.error(id, CommandStatus::ErrorNotImplemented),
+        }
+    }
+
+    /// Feeds a short press to the PIN screen, if the desktop is locked with a PIN
+    fn type_pin(&mut self, key: InputKey) {
+        let Some(pin) = self.lock.as_ref().filter(|pin| !pin.is_empty()) else {
+            return;
+        };
+
+        match key {
+            InputKey::Ok => {
+                if self.typed_pin == *pin {
+                    self.lock = None;
+                }
+                self.typed_pin.clear();
+            }
+            InputKey::Up | InputKey::Down | InputKey::Left | InputKey::Right => {
+                self.typed_pin.push(key)
+            }
+            InputKey::Back => self.typed_pin.clear(),
+        }
+    }
+
+    fn pin_mode(&self, pin: i32) -> GpioPinMode {
+        self.pins
+            .get(&pin)
+            .map_or(GpioPinMode::Input, |&(mode, _)| mode)
+    }
+
+    fn list(&mut self, id: u32, path: &str, include_md5: bool) {
+        let path = normalize(path);
+
+        match self.fs.get(&path) {
+            Some(Entry::Dir) => {}
+            Some(Entry::File(_)) => return self.error(id, CommandStatus::ErrorStorageInvalidName),
+            None => return self.error(id, CommandStatus::ErrorStorageNotExist),
+        }
+
+        let files = self
+            .fs
+            .iter()
+            .filter(|(key, _)| parent(key) == Some(path.as_str()))
+            .map(|(key, entry)| describe(key, entry, include_md5))
+            .collect::<Vec<_>>();
+
+        if files.is_empty() {
+            return self.ok(id, Content::StorageListResponse(ListResponse::default()));
+        }
+
+        let chunks = files.chunks(LIST_CHUNK_SIZE).count();
+        for (i, chunk) in files.chunks(LIST_CHUNK_SIZE).enumerate() {
+            self.respond(
+                id,
+                CommandStatus::Ok,
+                i != chunks - 1,
+                Some(Content::StorageListResponse(ListResponse {
+                    file: chunk.to_vec(),
+                })),
+            );
+        }
+    }
+
+    fn read(&mut self, id: u32, path: &str) {
+        let data = match self.fs.get(&normalize(path)) {
+            Some(Entry::File(data)) => data.clone(),
+            Some(Entry::Dir) => return self.error(id, CommandStatus::ErrorStorageInvalidName),
+            None => return self.error(id, CommandStatus::ErrorStorageNotExist),
+        };
+
+        let chunks = if data.is_empty() {
+            vec![&[][..]]
+        } else {
+            data.chunks(READ_CHUNK_SIZE).collect()
+        };
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            self.respond(
+                id,
+                CommandStatus::Ok,
+                i != chunks.len() - 1,
+                Some(Content::StorageReadResponse(ReadResponse {
+                    file: Some(File {
+                        r#type: FileType::File.into(),
+                        size: chunk.len() as u32,
+                        data: Bytes::copy_from_slice(chunk),
+                        ..Default::default()
+                    }),
+                })),
+            );
+        }
+    }
+
+    fn write(&mut self, id: u32, path: &str, data: Vec<u8>) {
+        let path = normalize(path);
+
+        match self.fs.get(&path) {
+            Some(Entry::Dir) => self.error(id, CommandStatus::ErrorStorageInvalidName),
+            _ if !self.parent_exists(&path) => self.error(id, CommandStatus::ErrorStorageNotExist),
+            _ => {
+                self.fs.insert(path, Entry::File(data));
+                self.ok(id, Content::Empty(Empty {}))
+            }
+        }
+    }
+
+    fn delete(&mut self, id: u32, path: &str, recursive: bool) {
+        let path = normalize(path);
+
+        if !self.fs.contains_key(&path) {
+            return self.error(id, CommandStatus::ErrorStorageNotExist);
+        }
+
+        let has_children = self.fs.keys().any(|key| parent(key) == Some(path.as_str()));
+        if has_children && !recursive {
+            return self.error(id, CommandStatus::ErrorStorageDirNotEmpty);
+        }
+
+        self.fs.retain(|key, _| !is_within(key, &path));
+        self.ok(id, Content::Empty(Empty {}))
+    }
+
+    /// Unpacks a plain ustar archive, like the firmware's extractor: directory entries are
+    /// created, file entries written, and parents must come before their children
+    fn extract_tar(&mut self, id: u32, tar_path: &str, out_path: &str) {
+        // Like the firmware, refuse names it cannot store before opening the archive
+        let out_name = out_path.rsplit('/').next().unwrap_or_default();
+        if !out_name
+            .chars()
+            .all(|c| (' '..='~').contains(&c) && !"\\<>*|\":?".contains(c))
+        {
+            return self.error(id, CommandStatus::ErrorStorageInvalidName);
+        }
+
+        let archive = match self.fs.get(&normalize(tar_path)) {
+            Some(Entry::File(data)) => data.clone(),
+            Some(Entry::Dir) => return self.error(id, CommandStatus::ErrorStorageInvalidName),
+            None => return self.error(id, CommandStatus::ErrorStorageNotExist),
+        };
+        let out = normalize(out_path);
+        if self.fs.get(&out) != Some(&Entry::Dir) {
+            return self.error(id, CommandStatus::ErrorStorageNotExist);
+        }
+
+        let mut offset = 0;
+        while let Some(header) = archive.get(offset..offset + 512) {
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+
+            let field = |range: std::ops::Range<usize>| {
+                let field = &header[range];
+                let end = field
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .unwrap_or(field.len());
+                String::from_utf8_lossy(&field[..end]).trim().to_string()
+            };
+            let name = field(0..100);
+            let Ok(size) = usize::from_str_radix(&field(124..136), 8) else {
+                return self.error(id, CommandStatus::ErrorStorageInternal);
+            };
+            let Some(data) = archive.get(offset + 512..offset + 512 + size) else {
+                return self.error(id, CommandStatus::ErrorStorageInternal);
+            };
+
+            let path = normalize(&format!("{out}/{name}"));
+            if !self.parent_exists(&path) {
+                return self.error(id, CommandStatus::ErrorStorageNotExist);
+            }
+            let entry = match header[156] {
+                b'5' => Entry::Dir,
+                _ => Entry::File(data.to_vec()),
+            };
+            self.fs.insert(path, entry);
+
+            offset += 512 + size.next_multiple_of(512);
+        }
+
+        self.ok(id, Content::Empty(Empty {}))
+    }
+
+    fn parent_exists(&self, path: &str) -> bool {
+        parent(path).is_some_and(|parent| self.fs.get(parent) == Some(&Entry::Dir))
+    }
+
+    fn ok(&mut self, id: u32, content: Content) {
+        self.respond(id, CommandStatus::Ok, false, Some(content));
+    }
+
+    fn error(&mut self, id: u32, status: CommandStatus) {
+        self.respond(id, status, false, None);
+    }
+
+    fn respond(
+        &mut self,
+        id: u32,
+        status: CommandStatus,
+        has_next: bool,
+        content: Option<Content>,
+    ) {
+        let response = proto::Main {
+            command_id: id,
+            command_status: status.into(),
+            has_next,
+            content: Some(content.unwrap_or(Content::Empty(Empty {}))),
+        };
+
+        self.codec
+            .encode(&response, &mut self.from_device)
+            .expect("BytesMut grows to fit the response");
+    }
+}
+
+impl TransportRaw<proto::Main> for EmulatedFlipper {
+    type Err = Error;
+
+    fn send_raw(&mut self, value: proto::Main) -> Result<()> {
+        self.codec.encode(&value, &mut self.to_device)?;
+
+        self.process()
+    }
+
+    fn receive_raw(&mut self) -> Result<proto::Main> {
+        check_status(self.receive_raw_unchecked()?)
+    }
+
+    fn receive_raw_unchecked(&mut self) -> Result<proto::Main> {
+        if self.from_device.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "emulator has nothing to send",
+            )
+            .into());
+        }
+
+        let main = self.codec.decode(&mut self.from_device)?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "emulator holds a partial message",
+            )
+        })?;
+
+        Ok(main)
+    }
+
+    fn try_receive_raw(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw_unchecked()?
+            .map(check_status)
+            .transpose()
+    }
+
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<proto::Main>> {
+        if self.from_device.is_empty() {
+            return Ok(None);
+        }
+
+        self.receive_raw_unchecked().map(Some)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+        let previous = self.timeouts.last().copied();
+        self.timeouts.push(timeout);
+
+        Ok(previous)
+    }
+}
+
+impl CommandIndex for EmulatedFlipper {
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        self.command_index = next_command_id(self.command_index, by);
+
+        self.command_index
+    }
+
+    fn command_index(&mut self) -> u32 {
+        self.command_index
+    }
+}
+
+/// Whether `content` is handled by the firmware's storage service
+fn is_storage_request(content: &Content) -> bool {
+    matches!(
+        content,
+        Content::StorageInfoRequest(_)
+            | Content::StorageTimestampRequest(_)
+            | Content::StorageStatRequest(_)
+            | Content::StorageListRequest(_)
+            | Content::StorageReadRequest(_)
+            | Content::StorageWriteRequest(_)
+            | Content::StorageDeleteRequest(_)
+            | Content::StorageMkdirRequest(_)
+            | Content::StorageMd5sumRequest(_)
+            | Content::StorageRenameRequest(_)
+            | Content::StorageBackupCreateRequest(_)
+            | Content::StorageBackupRestoreRequest(_)
+            | Content::StorageTarExtractRequest(_)
+    )
+}
+
+/// Strips trailing slashes, so `/ext/` and `/ext` are the same entry
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn parent(path: &str) -> Option<&str> {
+    match path.rsplit_once('/')? {
+        ("", "") => None,
+        ("", _) => Some("/"),
+        (parent, _) => Some(parent),
+    }
+}
+
+/// Whether `path` is `root` or lies below it
+fn is_within(path: &str, root: &str) -> bool {
+    path == root
+        || path
+            .strip_prefix(root)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn describe(path: &str, entry: &Entry, include_md5: bool) -> File {
+    let name = path.rsplit('/').next().unwrap_or_default().to_string();
+
+    match entry {
+        Entry::Dir => File {
+            r#type: FileType::Dir.into(),
+            name,
+            ..Default::default()
+        },
+        Entry::File(data) => File {
+            r#type: FileType::File.into(),
+            name,
+            size: data.len() as u32,
+            md5sum: if include_md5 {
+                format!("{:x}", md5::compute(data))
+            } else {
+                String::new()
+            },
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{req::Request, res::Response};
+    use crate::transport::Transport;
+
+    #[test]
+    fn ping_roundtrips_through_the_pipe() {
+        let mut flipper = EmulatedFlipper::new();
+
+        let response = flipper
+            .send_and_receive(Request::Ping(vec![0xDE, 0xAD]))
+            .unwrap();
+
+        assert_eq!(response, Response::Ping(vec![0xDE, 0xAD]));
+        assert_eq!(flipper.requests()[0].command_id, FIRST_COMMAND_ID);
+    }
+
+    #[test]
+    fn injected_failures_surface_as_errors() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.fail_next(CommandStatus::ErrorBusy);
+
+        let error = flipper
+            .send_and_receive(Request::Ping(vec![1]))
+            .expect_err("busy should be reported");
+
+        assert!(matches!(
+            error,
+            Error::Rpc(crate::rpc::error::Error::CommandError(
+                crate::rpc::error::CommandError::Busy
+            ))
+        ));
+        assert!(flipper.send_and_receive(Request::Ping(vec![1])).is_ok());
+    }
+
+    #[test]
+    fn try_receive_does_not_block() {
+        let mut flipper = EmulatedFlipper::new();
+        assert_eq!(flipper.try_receive_raw().unwrap(), None);
+
+        flipper.send(Request::Ping(vec![1])).unwrap();
+        assert!(flipper.try_receive_raw().unwrap().is_some());
+        assert_eq!(flipper.try_receive_raw().unwrap(), None);
+    }
+
+    #[cfg(all(feature = "fs-read", feature = "fs-write"))]
+    #[test]
+    fn chained_write_then_read() {
+        use crate::fs::{FsRead, FsWrite};
+
+        let mut flipper = EmulatedFlipper::new();
+        let data = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+
+        flipper
+            .fs_write(
+                "/ext/test.bin",
+                &data,
+                #[cfg(feature = "fs-write-progress-mpsc")]
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(flipper.file("/ext/test.bin"), Some(data.as_slice()));
+        assert_eq!(flipper.fs_read("/ext/test.bin").unwrap().as_ref(), data);
+        assert!(flipper.fs_read("/ext/missing").is_err());
+    }
+
+    #[cfg(feature = "session")]
+    #[test]
+    fn session_reads_identity() {
+        let flipper = EmulatedFlipper::new().with_device_info("hardware.name", "Vexer");
+
+        let session = crate::session::RpcSession::new(flipper).unwrap();
+
+        assert_eq!(session.identity().name, "Vexer");
+        assert_eq!(session.identity().protobuf_version, PROTOBUF_VERSION);
+    }
+}
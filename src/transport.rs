@@ -1,15 +1,86 @@
 //! Generic transport traits
 
+use std::ops::Range;
+use std::time::Duration;
+
 #[cfg(feature = "easy-rpc")]
-use crate::{error::Error, transport::serial::rpc::CommandIndex};
 use crate::{
+    error::Error,
     proto,
-    rpc::{req::Request, res::Response},
+    rpc::{
+        req::Request,
+        res::{DecodeMode, Response},
+    },
 };
 
 #[cfg(feature = "transport-serial")]
 pub mod serial;
 
+pub mod throttle;
+
+#[cfg(feature = "easy-rpc")]
+pub mod busy;
+
+#[cfg(feature = "easy-rpc")]
+pub mod dynamic;
+
+#[cfg(feature = "easy-rpc")]
+pub mod faults;
+
+#[cfg(feature = "easy-rpc")]
+pub mod options;
+
+/// The first command id of a session. `0` is reserved for messages the device sends on its own
+/// (screen frames, app data), so requests never use it.
+pub const FIRST_COMMAND_ID: u32 = 1;
+
+/// Adds a command_index getter/setter. Useful since Transports dont automatically track command
+/// index, and these functions can directly interop with the Transport's governing RPC channel.
+///
+/// Command ids live in `1..=u32::MAX`. Incrementing past `u32::MAX` wraps around to
+/// [`FIRST_COMMAND_ID`], never to the reserved `0`; implementors should use
+/// [`next_command_id`] to get this behaviour.
+pub trait CommandIndex {
+    /// Changes the command index and returns the new value
+    fn increment_command_index(&mut self, by: u32) -> u32;
+
+    /// Gets the current command index
+    fn command_index(&mut self) -> u32;
+
+    /// Reserves `n` consecutive command ids for a multi-message chain and returns them.
+    ///
+    /// The returned range never contains `0` and never wraps: if it would run past `u32::MAX`,
+    /// the reservation restarts at [`FIRST_COMMAND_ID`] instead.
+    fn reserve_range(&mut self, n: u32) -> Range<u32> {
+        let current = self.command_index();
+
+        if current == 0 {
+            self.increment_command_index(1);
+        } else if current.checked_add(n).is_none() {
+            // Advance to the wrap-around point, landing on FIRST_COMMAND_ID
+            self.increment_command_index(u32::MAX - current + 1);
+        }
+
+        let start = self.command_index();
+        self.increment_command_index(n);
+
+        start..start + n
+    }
+}
+
+/// Advances a command id by `by`, wrapping from `u32::MAX` to [`FIRST_COMMAND_ID`] and skipping
+/// the reserved `0`.
+pub fn next_command_id(current: u32, by: u32) -> u32 {
+    if by == 0 {
+        return current;
+    }
+
+    // Ids form a cycle of u32::MAX values, 1..=u32::MAX
+    let offset = (u64::from(current) + u64::from(by) - 1) % u64::from(u32::MAX);
+
+    offset as u32 + FIRST_COMMAND_ID
+}
+
 /// Encodes, Decodes, Transports, and Receives data types
 pub trait Transport<Send, Recv = Send> {
     /// Error type
@@ -48,6 +119,76 @@ pub trait TransportRaw<Send, Recv = Send> {
     /// For a reader based transport, this function must consume stream data.
     fn receive_raw(&mut self) -> Result<Recv, Self::Err>;
 
+    /// Like [`TransportRaw::receive_raw`], but hands back a message whose `command_status` is an
+    /// error as it is instead of as an [`Error`], so its `command_id` is not lost. Used where
+    /// answers are routed by command id, like `session::RpcSession::wait`.
+    ///
+    /// By default this calls receive_raw, whose errors carry no command id. Transports that check
+    /// the status themselves should override it, and decorators should forward it.
+    fn receive_raw_unchecked(&mut self) -> Result<Recv, Self::Err> {
+        self.receive_raw()
+    }
+
+    /// Receives a value if a complete one is already available, without blocking.
+    ///
+    /// Returns `Ok(None)` when nothing (or only part of a message) has arrived yet, which makes
+    /// the transport usable from event loops that poll. Implementations must never block here;
+    /// callers rely on it to peek for early answers between sends.
+    ///
+    /// By default this always returns `Ok(None)`, as if nothing had arrived: the message is left
+    /// for the next [`TransportRaw::receive_raw`]. Transports that can tell whether data is
+    /// pending should override it, and decorators should forward it.
+    fn try_receive_raw(&mut self) -> Result<Option<Recv>, Self::Err> {
+        Ok(None)
+    }
+
+    /// Like [`TransportRaw::try_receive_raw`], but hands back failed answers as messages, see
+    /// [`TransportRaw::receive_raw_unchecked`].
+    ///
+    /// By default this calls try_receive_raw, whose errors carry no command id. Transports that
+    /// override both of the above should override it too, and decorators should forward it.
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<Recv>, Self::Err> {
+        self.try_receive_raw()
+    }
+
+    /// Like [`TransportRaw::send_raw`], but encodes into the caller-provided `buf` instead of a
+    /// fresh allocation. Useful when sending many messages in a row.
+    ///
+    /// By default `buf` is ignored and this just calls send_raw. Encoding transports should
+    /// override it.
+    fn send_raw_buf(&mut self, value: Send, buf: &mut Vec<u8>) -> Result<(), Self::Err> {
+        let _ = buf;
+
+        self.send_raw(value)
+    }
+
+    /// Returns `true` once if an abort of the current operation was requested, clearing the
+    /// request.
+    ///
+    /// Chained operations (e.g. `fs_write`) poll this between messages and wind the chain down
+    /// cleanly when it returns `true`. By default aborting is not supported and this always
+    /// returns `false`; see `session::AbortHandle`.
+    fn take_abort(&mut self) -> bool {
+        false
+    }
+
+    /// Changes how long a receive may block before failing with
+    /// [`std::io::ErrorKind::TimedOut`], returning the previous timeout.
+    ///
+    /// Returns `Ok(None)` when the transport has no timeout to change; this is the default.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>, Self::Err> {
+        let _ = timeout;
+
+        Ok(None)
+    }
+
+    /// How the easy API turns received messages into [`Response`]s. Defaults to
+    /// [`DecodeMode::Strict`]; see `session::RpcSession::set_decode_mode`.
+    #[cfg(feature = "easy-rpc")]
+    fn decode_mode(&self) -> DecodeMode {
+        DecodeMode::Strict
+    }
+
     /// Send a value, then immediately wait for and return a response.
     /// For a reader based transport, this function must consume the sent and received data,
     /// returning the latter.
@@ -94,8 +235,83 @@ where
     fn receive(&mut self) -> Result<Response, Self::Err> {
         let response = self.receive_raw()?;
 
-        let rpc = Response::try_from(response)?;
+        let rpc = Response::decode(response, self.decode_mode())?;
 
         Ok(rpc)
     }
 }
+
+/// Parses a raw `command_status`, rejecting values outside the schema
+#[cfg(feature = "easy-rpc")]
+pub(crate) fn decode_command_status(raw: i32) -> crate::error::Result<proto::CommandStatus> {
+    proto::CommandStatus::try_from(raw).map_err(|_| Error::InvalidCommandStatus(raw))
+}
+
+/// Turns a received message with a failed `command_status` into its error, the check
+/// [`TransportRaw::receive_raw_unchecked`] skips
+#[cfg(feature = "easy-rpc")]
+pub(crate) fn check_status(main: proto::Main) -> crate::error::Result<proto::Main> {
+    decode_command_status(main.command_status)?.into_result_with_content(main)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Counter(u32);
+
+    impl CommandIndex for Counter {
+        fn increment_command_index(&mut self, by: u32) -> u32 {
+            self.0 = next_command_id(self.0, by);
+            self.0
+        }
+
+        fn command_index(&mut self) -> u32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn wraps_around_without_zero() {
+        assert_eq!(next_command_id(1, 1), 2);
+        assert_eq!(next_command_id(u32::MAX, 1), FIRST_COMMAND_ID);
+        assert_eq!(next_command_id(u32::MAX - 1, 3), 2);
+        assert_eq!(next_command_id(0, 1), 1);
+        assert_eq!(next_command_id(5, 0), 5);
+    }
+
+    #[test]
+    fn reserved_ranges_are_contiguous() {
+        let mut counter = Counter(FIRST_COMMAND_ID);
+        assert_eq!(counter.reserve_range(2), 1..3);
+        assert_eq!(counter.command_index(), 3);
+
+        let mut counter = Counter(u32::MAX - 1);
+        assert_eq!(counter.reserve_range(4), 1..5);
+        assert_eq!(counter.command_index(), 5);
+
+        let mut counter = Counter(0);
+        assert_eq!(counter.reserve_range(1), 1..2);
+    }
+
+    #[test]
+    fn default_try_receive_does_not_block() {
+        #[derive(Debug)]
+        struct Blocking;
+
+        impl TransportRaw<u8> for Blocking {
+            type Err = std::io::Error;
+
+            fn send_raw(&mut self, _: u8) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn receive_raw(&mut self) -> std::io::Result<u8> {
+                panic!("receive_raw blocks")
+            }
+        }
+
+        assert!(Blocking.try_receive_raw().unwrap().is_none());
+    }
+}
@@ -0,0 +1,172 @@
+//! Retry policies for requests rejected while the device is busy
+//!
+//! While an app is open most commands fail with `ERROR_BUSY` or `ERROR_APP_SYSTEM_LOCKED`.
+//! [`BusyPolicy`] decides, per request, whether to give up, close the app, or wait and retry.
+
+use std::time::Duration;
+
+use crate::logging::{debug, warn};
+
+use crate::{
+    error::{Error, Result},
+    proto::{CommandStatus, app::AppExitRequest},
+    rpc::{req::Request, res::Response},
+    transport::Transport,
+};
+
+/// What to do when a request is rejected because the device is busy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BusyPolicy {
+    /// Return the busy error to the caller
+    #[default]
+    Fail,
+    /// Close the running app with `AppExit`, then retry once
+    ExitApp,
+    /// Retry up to `retries` times, doubling the delay after each attempt
+    Backoff {
+        /// Maximum number of retries
+        retries: u32,
+        /// Delay before the first retry
+        initial_delay: Duration,
+    },
+}
+
+/// Whether an error means the device is busy and the request may succeed later
+pub fn is_busy(error: &Error) -> bool {
+    matches!(
+        error.rpc_status(),
+        Some(CommandStatus::ErrorBusy | CommandStatus::ErrorAppSystemLocked)
+    )
+}
+
+/// Adds busy-aware sending to easy-rpc transports
+pub trait TransportBusy {
+    /// Like [`Transport::send_and_receive`], but handles busy errors according to `policy`
+    fn send_and_receive_busy(&mut self, req: Request, policy: BusyPolicy) -> Result<Response>;
+}
+
+impl<T> TransportBusy for T
+where
+    T: Transport<Request, Response, Err = Error>,
+{
+    fn send_and_receive_busy(&mut self, req: Request, policy: BusyPolicy) -> Result<Response> {
+        match policy {
+            BusyPolicy::Fail => self.send_and_receive(req),
+            BusyPolicy::ExitApp => match self.send_and_receive(req.clone()) {
+                Err(e) if is_busy(&e) => {
+                    warn!("device busy, exiting the running app");
+
+                    match self.send_and_receive(Request::AppExit(AppExitRequest {})) {
+                        // Busy without a running app, nothing to exit
+                        Ok(_) | Err(Error::Rpc(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+
+                    self.send_and_receive(req)
+                }
+                other => other,
+            },
+            BusyPolicy::Backoff {
+                retries,
+                initial_delay,
+            } => {
+                let mut delay = initial_delay;
+
+                for _ in 0..retries {
+                    match self.send_and_receive(req.clone()) {
+                        Err(e) if is_busy(&e) => {
+                            debug!(?delay, "device busy, backing off");
+                            std::thread::sleep(delay);
+                            delay = delay.saturating_mul(2);
+                        }
+                        other => return other,
+                    }
+                }
+
+                self.send_and_receive(req)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::error::CommandError;
+
+    /// Answers busy a set number of times, then pings back
+    #[derive(Debug)]
+    struct BusyDevice {
+        busy_for: usize,
+        sent: Vec<Request>,
+    }
+
+    impl Transport<Request, Response> for BusyDevice {
+        type Err = Error;
+
+        fn send(&mut self, value: Request) -> Result<()> {
+            self.sent.push(value);
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Response> {
+            if self.busy_for > 0 {
+                self.busy_for -= 1;
+                return Err(crate::rpc::error::Error::from(CommandError::Busy).into());
+            }
+
+            Ok(Response::Ping(vec![1]))
+        }
+    }
+
+    #[test]
+    fn backoff_retries_until_not_busy() {
+        let mut device = BusyDevice {
+            busy_for: 2,
+            sent: Vec::new(),
+        };
+
+        let response = device
+            .send_and_receive_busy(
+                Request::Ping(vec![1]),
+                BusyPolicy::Backoff {
+                    retries: 3,
+                    initial_delay: Duration::from_millis(1),
+                },
+            )
+            .expect("third attempt should succeed");
+
+        assert_eq!(response, Response::Ping(vec![1]));
+        assert_eq!(device.sent.len(), 3);
+    }
+
+    #[test]
+    fn exit_app_closes_the_app_before_retrying() {
+        let mut device = BusyDevice {
+            busy_for: 1,
+            sent: Vec::new(),
+        };
+
+        device
+            .send_and_receive_busy(Request::Ping(vec![1]), BusyPolicy::ExitApp)
+            .expect("retry after exit should succeed");
+
+        assert!(matches!(device.sent[1], Request::AppExit(_)));
+        assert_eq!(device.sent.len(), 3);
+    }
+
+    #[test]
+    fn fail_returns_the_busy_error() {
+        let mut device = BusyDevice {
+            busy_for: 1,
+            sent: Vec::new(),
+        };
+
+        let error = device
+            .send_and_receive_busy(Request::Ping(vec![1]), BusyPolicy::Fail)
+            .expect_err("busy should be reported");
+
+        assert!(is_busy(&error));
+    }
+}
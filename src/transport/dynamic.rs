@@ -0,0 +1,212 @@
+//! Object-safe transports
+//!
+//! [`TransportRaw`] is generic over its message types and the easy-rpc helpers are bounded on
+//! `CommandIndex`, so a transport can't be stored as a trait object directly. [`DynRpcTransport`]
+//! is the object-safe subset of both traits, implemented for every RPC transport. A
+//! `Box<dyn DynRpcTransport>` implements [`TransportRaw`] and `CommandIndex` again, so it works
+//! with every helper that accepts a transport.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::rpc::req::Request;
+//! use flipper_rpc::transport::Transport;
+//! use flipper_rpc::transport::dynamic::DynRpcTransport;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! struct App {
+//!     flipper: Box<dyn DynRpcTransport + Send>,
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let mut app = App {
+//!     flipper: Box::new(SerialRpcTransport::new("/dev/ttyACM0")?),
+//! };
+//!
+//! app.flipper.send_and_receive(Request::Ping(vec![1, 2, 3]))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::res::DecodeMode,
+    transport::{CommandIndex, TransportRaw},
+};
+
+/// Object-safe RPC transport, implemented for every [`TransportRaw`] + `CommandIndex` transport
+pub trait DynRpcTransport: std::fmt::Debug {
+    /// Sends a raw message, see [`TransportRaw::send_raw`]
+    fn send_main(&mut self, main: proto::Main) -> Result<()>;
+
+    /// Sends a raw message through a reusable buffer, see [`TransportRaw::send_raw_buf`]
+    fn send_main_buf(&mut self, main: proto::Main, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Receives a raw message, see [`TransportRaw::receive_raw`]
+    fn recv_main(&mut self) -> Result<proto::Main>;
+
+    /// Receives a raw message without checking its status, see
+    /// [`TransportRaw::receive_raw_unchecked`]
+    fn recv_main_unchecked(&mut self) -> Result<proto::Main>;
+
+    /// Receives a raw message without blocking, see [`TransportRaw::try_receive_raw`]
+    fn try_recv_main(&mut self) -> Result<Option<proto::Main>>;
+
+    /// Receives a raw message without blocking or checking its status, see
+    /// [`TransportRaw::try_receive_raw_unchecked`]
+    fn try_recv_main_unchecked(&mut self) -> Result<Option<proto::Main>>;
+
+    /// See [`TransportRaw::take_abort`]
+    fn take_abort(&mut self) -> bool;
+
+    /// See [`TransportRaw::set_timeout`]
+    fn set_recv_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>>;
+
+    /// See [`TransportRaw::decode_mode`]
+    fn response_decode_mode(&self) -> DecodeMode;
+
+    /// See `CommandIndex::increment_command_index`
+    fn increment_command_index(&mut self, by: u32) -> u32;
+
+    /// See `CommandIndex::command_index`
+    fn command_index(&mut self) -> u32;
+}
+
+impl<T> DynRpcTransport for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn send_main(&mut self, main: proto::Main) -> Result<()> {
+        self.send_raw(main)
+    }
+
+    fn send_main_buf(&mut self, main: proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+        self.send_raw_buf(main, buf)
+    }
+
+    fn recv_main(&mut self) -> Result<proto::Main> {
+        self.receive_raw()
+    }
+
+    fn recv_main_unchecked(&mut self) -> Result<proto::Main> {
+        self.receive_raw_unchecked()
+    }
+
+    fn try_recv_main(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw()
+    }
+
+    fn try_recv_main_unchecked(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw_unchecked()
+    }
+
+    fn take_abort(&mut self) -> bool {
+        TransportRaw::take_abort(self)
+    }
+
+    fn set_recv_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+        self.set_timeout(timeout)
+    }
+
+    fn response_decode_mode(&self) -> DecodeMode {
+        self.decode_mode()
+    }
+
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        CommandIndex::increment_command_index(self, by)
+    }
+
+    fn command_index(&mut self) -> u32 {
+        CommandIndex::command_index(self)
+    }
+}
+
+/// Implements the generic traits for a boxed trait object, forwarding to [`DynRpcTransport`]
+macro_rules! boxed_adapter {
+    ($($ty:ty),*) => {
+        $(
+            impl TransportRaw<proto::Main> for Box<$ty> {
+                type Err = Error;
+
+                fn send_raw(&mut self, value: proto::Main) -> Result<()> {
+                    (**self).send_main(value)
+                }
+
+                fn send_raw_buf(&mut self, value: proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+                    (**self).send_main_buf(value, buf)
+                }
+
+                fn receive_raw(&mut self) -> Result<proto::Main> {
+                    (**self).recv_main()
+                }
+
+                fn receive_raw_unchecked(&mut self) -> Result<proto::Main> {
+                    (**self).recv_main_unchecked()
+                }
+
+                fn try_receive_raw(&mut self) -> Result<Option<proto::Main>> {
+                    (**self).try_recv_main()
+                }
+
+                fn try_receive_raw_unchecked(&mut self) -> Result<Option<proto::Main>> {
+                    (**self).try_recv_main_unchecked()
+                }
+
+                fn take_abort(&mut self) -> bool {
+                    DynRpcTransport::take_abort(&mut **self)
+                }
+
+                fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+                    (**self).set_recv_timeout(timeout)
+                }
+
+                fn decode_mode(&self) -> DecodeMode {
+                    (**self).response_decode_mode()
+                }
+            }
+
+            impl CommandIndex for Box<$ty> {
+                fn increment_command_index(&mut self, by: u32) -> u32 {
+                    DynRpcTransport::increment_command_index(&mut **self, by)
+                }
+
+                fn command_index(&mut self) -> u32 {
+                    DynRpcTransport::command_index(&mut **self)
+                }
+            }
+        )*
+    };
+}
+
+boxed_adapter!(dyn DynRpcTransport, dyn DynRpcTransport + Send);
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::rpc::{req::Request, res::Response};
+    use crate::testing::EmulatedFlipper;
+    use crate::transport::Transport;
+
+    #[test]
+    fn boxed_transports_use_the_easy_api() {
+        let mut transports: Vec<Box<dyn DynRpcTransport + Send>> = vec![
+            Box::new(EmulatedFlipper::new()),
+            Box::new(crate::transport::throttle::Throttled::new(
+                EmulatedFlipper::new(),
+                Default::default(),
+            )),
+        ];
+
+        for transport in &mut transports {
+            let response = transport
+                .send_and_receive(Request::Ping(vec![1, 2, 3]))
+                .unwrap();
+
+            assert_eq!(response, Response::Ping(vec![1, 2, 3]));
+        }
+    }
+}
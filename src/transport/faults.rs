@@ -0,0 +1,507 @@
+//! Fault injection for testing error handling
+//!
+//! [`FaultyTransport`] wraps a raw transport and damages the messages it receives according to a
+//! [`FaultPlan`]: it delays them, truncates or bit-flips their encoding, or drops them so the
+//! caller sees a timeout. Random faults come from a seeded generator, so a run that exposed a bug
+//! replays exactly with the same seed; faults can also be scheduled for specific messages, e.g.
+//! to reproduce a field report of the third answer of a download going missing.
+//!
+//! Damaged messages are re-decoded on their own. A real link that truncates a frame also
+//! desynchronizes the frames after it, which is not modelled.
+//!
+//! For faults no plan describes, [`FaultyTransport::on_send`] and
+//! [`FaultyTransport::on_receive`] hook into every sent and received message.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::faults::{Fault, FaultPlan, FaultyTransport};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! let mut rpc = FaultyTransport::new(
+//!     rpc,
+//!     FaultPlan::default()
+//!         .seed(7)
+//!         .delay(0.1, Duration::from_millis(5)..Duration::from_millis(50))
+//!         .bit_flip(0.01)
+//!         .at(2, Fault::Drop),
+//! );
+//! // ... run the code under test against `rpc`, then look at what was injected
+//! println!("{:?}", rpc.injected());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::time::Duration;
+
+use prost::Message;
+
+use crate::logging::debug;
+
+use crate::{
+    error::{Error, Result},
+    proto,
+    transport::{CommandIndex, TransportRaw, check_status},
+};
+
+/// A fault applied to one received message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Deliver the message after a pause
+    Delay(Duration),
+    /// Keep only the first bytes of the encoded message
+    Truncate(usize),
+    /// Flip one bit of the encoded message, counted from the first bit of the first byte
+    BitFlip(usize),
+    /// Swallow the message and fail the receive with [`std::io::ErrorKind::TimedOut`]
+    Drop,
+    /// Fail the receive with [`std::io::ErrorKind::TimedOut`], and hand the message out by the
+    /// next receive instead, like an answer that arrived just after its timeout
+    Late,
+}
+
+/// Which faults [`FaultyTransport`] injects. The default injects none.
+///
+/// Probabilities are per received message and range from `0.0` to `1.0`. A delay can come on
+/// top of any other fault; of the others at most one applies, checked in the order drop,
+/// truncate, bit flip.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultPlan {
+    /// Seed of the generator behind the random faults
+    pub seed: u64,
+    /// Chance of delaying a message, and the range the delay is drawn from
+    pub delay: Option<(f64, Range<Duration>)>,
+    /// Chance of dropping a message
+    pub drop_probability: f64,
+    /// Chance of truncating a message
+    pub truncate_probability: f64,
+    /// Chance of flipping a bit in a message
+    pub bit_flip_probability: f64,
+    /// Faults for specific messages, by index of the received message starting at `0`. They
+    /// replace the random faults for that message.
+    pub scheduled: BTreeMap<u64, Fault>,
+}
+
+impl FaultPlan {
+    /// Sets the seed of the random faults
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+
+        self
+    }
+
+    /// Delays messages with the chance `probability` by a duration drawn from `range`
+    pub fn delay(mut self, probability: f64, range: Range<Duration>) -> Self {
+        self.delay = Some((probability, range));
+
+        self
+    }
+
+    /// Drops messages with the chance `probability`
+    pub fn drop_response(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+
+        self
+    }
+
+    /// Truncates messages with the chance `probability`
+    pub fn truncate(mut self, probability: f64) -> Self {
+        self.truncate_probability = probability;
+
+        self
+    }
+
+    /// Flips a bit in messages with the chance `probability`
+    pub fn bit_flip(mut self, probability: f64) -> Self {
+        self.bit_flip_probability = probability;
+
+        self
+    }
+
+    /// Applies `fault` to the received message with index `index`
+    pub fn at(mut self, index: u64, fault: Fault) -> Self {
+        self.scheduled.insert(index, fault);
+
+        self
+    }
+}
+
+/// Called with every message before it is sent
+type SendHook = Box<dyn FnMut(&proto::Main) + Send>;
+
+/// Called with every message before the plan's faults are applied to it
+type ReceiveHook = Box<dyn FnMut(&mut proto::Main) + Send>;
+
+/// A transport decorator that injects the faults of a [`FaultPlan`] into received messages
+pub struct FaultyTransport<T> {
+    inner: T,
+    plan: FaultPlan,
+    rng: u64,
+    received: u64,
+    injected: Vec<(u64, Fault)>,
+    /// Messages held back by [`Fault::Late`]
+    late: VecDeque<proto::Main>,
+    on_send: Option<SendHook>,
+    on_receive: Option<ReceiveHook>,
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for FaultyTransport<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FaultyTransport")
+            .field("inner", &self.inner)
+            .field("plan", &self.plan)
+            .field("received", &self.received)
+            .field("injected", &self.injected)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> FaultyTransport<T> {
+    /// Wraps `inner` with the given plan
+    pub fn new(inner: T, plan: FaultPlan) -> Self {
+        Self {
+            inner,
+            rng: plan.seed,
+            plan,
+            received: 0,
+            injected: Vec::new(),
+            late: VecDeque::new(),
+            on_send: None,
+            on_receive: None,
+        }
+    }
+
+    /// Calls `hook` with every message before it is sent, e.g. to block until a test is ready
+    /// for it. Replaces any earlier hook.
+    pub fn on_send(&mut self, hook: impl FnMut(&proto::Main) + Send + 'static) {
+        self.on_send = Some(Box::new(hook));
+    }
+
+    /// Calls `hook` with every received message, before the plan's faults, e.g. to corrupt a
+    /// payload in a way the plan cannot describe. Replaces any earlier hook.
+    pub fn on_receive(&mut self, hook: impl FnMut(&mut proto::Main) + Send + 'static) {
+        self.on_receive = Some(Box::new(hook));
+    }
+
+    /// Returns the active plan
+    pub fn plan(&self) -> &FaultPlan {
+        &self.plan
+    }
+
+    /// Every fault injected so far, with the index of the message it hit
+    pub fn injected(&self) -> &[(u64, Fault)] {
+        &self.injected
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the inner transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 <= probability
+    }
+
+    /// A number in `0..n`, `n` must not be `0`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// The faults for the next message, given its encoded length
+    fn faults_for(&mut self, index: u64, len: usize) -> Vec<Fault> {
+        if let Some(&fault) = self.plan.scheduled.get(&index) {
+            return vec![fault];
+        }
+
+        let mut faults = Vec::new();
+
+        if let Some((probability, range)) = self.plan.delay.clone() {
+            if self.chance(probability) {
+                let span = range.end.saturating_sub(range.start).as_nanos() as u64;
+                let extra = if span == 0 { 0 } else { self.below(span) };
+                faults.push(Fault::Delay(range.start + Duration::from_nanos(extra)));
+            }
+        }
+
+        if self.chance(self.plan.drop_probability) {
+            faults.push(Fault::Drop);
+        } else if len > 0 && self.chance(self.plan.truncate_probability) {
+            faults.push(Fault::Truncate(self.below(len as u64) as usize));
+        } else if len > 0 && self.chance(self.plan.bit_flip_probability) {
+            faults.push(Fault::BitFlip(self.below(len as u64 * 8) as usize));
+        }
+
+        faults
+    }
+
+    /// Calls the send hook
+    fn sending(&mut self, main: &proto::Main) {
+        if let Some(hook) = &mut self.on_send {
+            hook(main);
+        }
+    }
+
+    /// Applies the receive hook and the plan to a received message
+    fn damage(&mut self, mut main: proto::Main) -> Result<proto::Main> {
+        if let Some(hook) = &mut self.on_receive {
+            hook(&mut main);
+        }
+
+        let index = self.received;
+        self.received += 1;
+
+        let mut encoded = main.encode_to_vec();
+        let faults = self.faults_for(index, encoded.len());
+        if faults.is_empty() {
+            return Ok(main);
+        }
+
+        let mut damaged = false;
+        for &fault in &faults {
+            debug!(index, ?fault, "injecting fault");
+            self.injected.push((index, fault));
+
+            match fault {
+                Fault::Delay(delay) => std::thread::sleep(delay),
+                Fault::Truncate(len) => {
+                    encoded.truncate(len);
+                    damaged = true;
+                }
+                Fault::BitFlip(bit) => {
+                    if let Some(byte) = encoded.get_mut(bit / 8) {
+                        *byte ^= 1 << (bit % 8);
+                        damaged = true;
+                    }
+                }
+                Fault::Drop => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "response dropped by fault injection",
+                    )
+                    .into());
+                }
+                Fault::Late => {
+                    self.late.push_back(main);
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "response held back by fault injection",
+                    )
+                    .into());
+                }
+            }
+        }
+
+        if !damaged {
+            return Ok(main);
+        }
+
+        Ok(proto::Main::decode(encoded.as_slice())?)
+    }
+}
+
+impl<T> TransportRaw<proto::Main, proto::Main> for FaultyTransport<T>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error>,
+{
+    type Err = Error;
+
+    fn send_raw(&mut self, value: proto::Main) -> Result<()> {
+        self.sending(&value);
+        self.inner.send_raw(value)
+    }
+
+    fn send_raw_buf(&mut self, value: proto::Main, buf: &mut Vec<u8>) -> Result<()> {
+        self.sending(&value);
+        self.inner.send_raw_buf(value, buf)
+    }
+
+    fn receive_raw(&mut self) -> Result<proto::Main> {
+        if let Some(main) = self.late.pop_front() {
+            return check_status(main);
+        }
+
+        let main = self.inner.receive_raw()?;
+
+        self.damage(main)
+    }
+
+    fn receive_raw_unchecked(&mut self) -> Result<proto::Main> {
+        if let Some(main) = self.late.pop_front() {
+            return Ok(main);
+        }
+
+        let main = self.inner.receive_raw_unchecked()?;
+
+        self.damage(main)
+    }
+
+    fn try_receive_raw(&mut self) -> Result<Option<proto::Main>> {
+        self.try_receive_raw_unchecked()?
+            .map(check_status)
+            .transpose()
+    }
+
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<proto::Main>> {
+        if let Some(main) = self.late.pop_front() {
+            return Ok(Some(main));
+        }
+
+        match self.inner.try_receive_raw_unchecked()? {
+            Some(main) => self.damage(main).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn take_abort(&mut self) -> bool {
+        self.inner.take_abort()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn decode_mode(&self) -> crate::rpc::res::DecodeMode {
+        self.inner.decode_mode()
+    }
+}
+
+impl<T> CommandIndex for FaultyTransport<T>
+where
+    T: CommandIndex,
+{
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        self.inner.increment_command_index(by)
+    }
+
+    fn command_index(&mut self) -> u32 {
+        self.inner.command_index()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::rpc::{req::Request, res::Response};
+    use crate::testing::EmulatedFlipper;
+    use crate::transport::Transport;
+
+    fn pings(plan: FaultPlan) -> (Vec<Result<Response>>, Vec<(u64, Fault)>) {
+        let mut flipper = FaultyTransport::new(EmulatedFlipper::new(), plan);
+        let results = (0..50u8)
+            .map(|i| flipper.send_and_receive(Request::Ping(vec![i; 16])))
+            .collect();
+
+        (results, flipper.injected().to_vec())
+    }
+
+    #[test]
+    fn scheduled_faults_hit_their_message() {
+        let (results, injected) = pings(FaultPlan::default().at(1, Fault::Drop));
+
+        assert_eq!(injected, [(1, Fault::Drop)]);
+        assert!(
+            matches!(&results[1], Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut)
+        );
+        assert_eq!(results[2].as_ref().unwrap(), &Response::Ping(vec![2; 16]));
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let plan = FaultPlan::default()
+            .seed(42)
+            .delay(0.1, Duration::ZERO..Duration::from_micros(10))
+            .drop_response(0.1)
+            .truncate(0.1)
+            .bit_flip(0.1);
+
+        let (first, injected) = pings(plan.clone());
+        let (second, replayed) = pings(plan);
+        assert!(!injected.is_empty());
+        assert_eq!(injected, replayed);
+
+        let faulty = injected
+            .iter()
+            .filter(|(_, fault)| !matches!(fault, Fault::Delay(_)))
+            .count();
+        let ok = first
+            .iter()
+            .zip(0..)
+            .filter(|(result, i)| result.as_ref().ok() == Some(&Response::Ping(vec![*i; 16])))
+            .count();
+        assert!(ok >= first.len() - faulty);
+        assert_eq!(first.len(), second.len());
+
+        let (_, other) = pings(FaultPlan::default().seed(43).drop_response(0.1));
+        assert_ne!(injected, other);
+    }
+
+    #[test]
+    fn late_responses_arrive_on_the_next_receive() {
+        let mut flipper = FaultyTransport::new(
+            EmulatedFlipper::new(),
+            FaultPlan::default().at(0, Fault::Late),
+        );
+
+        let command_id = flipper.command_index();
+        flipper
+            .send_raw(Request::Ping(vec![1]).into_rpc(command_id))
+            .unwrap();
+        assert!(
+            matches!(flipper.receive_raw(), Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut)
+        );
+        assert_eq!(flipper.receive_raw().unwrap().command_id, command_id);
+        assert!(flipper.try_receive_raw().unwrap().is_none());
+    }
+
+    #[test]
+    fn hooks_see_every_message() {
+        use std::sync::{Arc, Mutex};
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut flipper = FaultyTransport::new(EmulatedFlipper::new(), FaultPlan::default());
+        flipper.on_send({
+            let sent = Arc::clone(&sent);
+            move |main| sent.lock().unwrap().push(main.command_id)
+        });
+        flipper.on_receive(|main| {
+            if let Some(proto::main::Content::SystemPingResponse(response)) = &mut main.content {
+                response.data = vec![9];
+            }
+        });
+
+        let command_id = flipper.command_index();
+        assert_eq!(
+            flipper.send_and_receive(Request::Ping(vec![1])).unwrap(),
+            Response::Ping(vec![9])
+        );
+        assert_eq!(*sent.lock().unwrap(), [command_id]);
+    }
+}
@@ -0,0 +1,221 @@
+//! Per-request timeouts and retries
+//!
+//! Every request normally shares the transport's timeout, `Timeouts::operation` for serial.
+//! That is too short for an MD5 of a large file or a tar extract, and too long to notice a dead
+//! link on a ping. [`TransportOptions::send_and_receive_with`] overrides the timeout for a single
+//! request and can retry it when it times out.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::rpc::req::Request;
+//! use flipper_rpc::transport::options::{RequestOptions, TransportOptions};
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let options = RequestOptions::default().timeout(Duration::from_secs(300));
+//! rpc.send_and_receive_with(Request::StorageMd5sum("/ext/big.bin".to_string()), options)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::logging::{debug, trace};
+
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::{req::Request, res::Response},
+    transport::{CommandIndex, TransportRaw, check_status},
+};
+
+/// Overrides for a single request. The default changes nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    /// Receive timeout for this request, instead of the transport's
+    pub timeout: Option<Duration>,
+    /// How many times to send the request again after it timed out
+    pub retries: u32,
+}
+
+impl RequestOptions {
+    /// Sets the receive timeout for the request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Sets how many times a timed out request is sent again
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+
+        self
+    }
+}
+
+/// Whether an error is a receive timeout, also when wrapped in an operation's context
+pub fn is_timeout(error: &Error) -> bool {
+    matches!(error.root(), Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut)
+}
+
+/// Adds per-request options to easy-rpc transports
+pub trait TransportOptions {
+    /// Like `Transport::send_and_receive`, but applies `options` to this request only.
+    ///
+    /// The transport's timeout is restored afterwards, even if the request failed. A retried
+    /// request is sent with a new command id, and responses carrying another id (such as a late
+    /// answer or error to the timed out attempt) are skipped.
+    fn send_and_receive_with(&mut self, req: Request, options: RequestOptions) -> Result<Response>;
+}
+
+impl<T> TransportOptions for T
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    fn send_and_receive_with(&mut self, req: Request, options: RequestOptions) -> Result<Response> {
+        let previous = match options.timeout {
+            Some(timeout) => self.set_timeout(timeout)?,
+            None => None,
+        };
+
+        let mut result = send_and_receive_once(self, req.clone());
+
+        for _attempt in 1..=options.retries {
+            match &result {
+                Err(e) if is_timeout(e) => {
+                    debug!(attempt = _attempt, "request timed out, retrying");
+                    result = send_and_receive_once(self, req.clone());
+                }
+                _ => break,
+            }
+        }
+
+        if let Some(previous) = previous {
+            self.set_timeout(previous)?;
+        }
+
+        result
+    }
+}
+
+/// Sends `req` and waits for the response with the same command id
+fn send_and_receive_once<T>(transport: &mut T, req: Request) -> Result<Response>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex,
+{
+    let command_id = transport.command_index();
+    transport.increment_command_index(1);
+
+    transport.send_raw(req.into_rpc(command_id))?;
+
+    loop {
+        // Unchecked, so a late failure of an earlier attempt is skipped rather than returned
+        let main = transport.receive_raw_unchecked()?;
+
+        if main.command_id == command_id {
+            return Response::try_from(check_status(main)?);
+        }
+
+        trace!(
+            command_id = main.command_id,
+            "skipping response to another request"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::EmulatedFlipper;
+    use crate::transport::faults::{Fault, FaultPlan, FaultyTransport};
+
+    /// An emulator that answers the first `late` requests just after they time out
+    fn slow_device(late: u64) -> FaultyTransport<EmulatedFlipper> {
+        let plan = (0..late).fold(FaultPlan::default(), |plan, index| {
+            plan.at(index, Fault::Late)
+        });
+        let mut device = FaultyTransport::new(EmulatedFlipper::new(), plan);
+        device.set_timeout(Duration::from_secs(10)).unwrap();
+
+        device
+    }
+
+    #[test]
+    fn retries_skip_late_responses() {
+        let mut device = slow_device(2);
+        let first = device.command_index();
+
+        let response = device
+            .send_and_receive_with(
+                Request::Ping(vec![1]),
+                RequestOptions::default()
+                    .timeout(Duration::from_millis(50))
+                    .retries(2),
+            )
+            .expect("third attempt should succeed");
+
+        assert_eq!(response, Response::Ping(vec![1]));
+        assert_eq!(device.command_index(), first + 3);
+        // The answers to the first two attempts were skipped
+        assert!(device.try_receive_raw().unwrap().is_none());
+        assert_eq!(
+            device.get_ref().timeouts(),
+            [
+                Duration::from_secs(10),
+                Duration::from_millis(50),
+                Duration::from_secs(10)
+            ]
+        );
+    }
+
+    #[test]
+    fn timeout_is_restored_after_failure() {
+        let mut device = slow_device(5);
+
+        let error = device
+            .send_and_receive_with(
+                Request::Ping(vec![1]),
+                RequestOptions::default()
+                    .timeout(Duration::from_millis(50))
+                    .retries(1),
+            )
+            .expect_err("every attempt times out");
+
+        assert!(is_timeout(&error));
+        assert_eq!(
+            device.get_ref().timeouts().last(),
+            Some(&Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn retries_skip_late_failures() {
+        let mut device = slow_device(1);
+        device.get_mut().fail_next(proto::CommandStatus::ErrorBusy);
+
+        let response = device
+            .send_and_receive_with(Request::Ping(vec![1]), RequestOptions::default().retries(1))
+            .expect("the failure answered the timed out attempt");
+
+        assert_eq!(response, Response::Ping(vec![1]));
+        assert!(device.try_receive_raw().unwrap().is_none());
+    }
+
+    #[test]
+    fn wrapped_timeouts_are_timeouts() {
+        let error = Error::Operation {
+            op: "fs_md5",
+            op_id: 1,
+            error: Box::new(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+        };
+
+        assert!(is_timeout(&error));
+    }
+}
@@ -1,19 +1,74 @@
 //! Implementation for serial communication protocols
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use serialport::SerialPort;
+
+use crate::error::Error;
 use crate::logging::debug;
 
+pub mod banner;
+pub mod builder;
 pub mod cli;
 pub mod helpers;
+pub mod lock;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod probe;
 pub mod rpc;
+pub mod stats;
+
+pub use probe::{PortProbe, probe_port};
 
 /// Baud rate for the flipper
 pub(crate) const FLIPPER_BAUD: u32 = 115_200;
 
-/// Global timeout for serial operations. Kinda large as large files may take a LONG time to
-/// process
-pub(crate) const TIMEOUT: Duration = Duration::from_secs(10);
+/// Default [`Timeouts::handshake`]
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default [`Timeouts::read`]
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default [`Timeouts::operation`]. Kinda large as large files may take a LONG time to process
+pub const DEFAULT_OPERATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the serial transports wait for the device, set with
+/// [`builder::SerialBuilder::timeouts`]
+///
+/// A single timeout does not fit both cases: waiting for the next bytes of a message that is
+/// already arriving should fail fast, while the device may think for a long time before it
+/// starts to answer, e.g. when hashing a large file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Waiting for the CLI prompt and for RPC sessions to start or stop
+    pub handshake: Duration,
+    /// Waiting for more bytes once a message or CLI output started arriving. Also how often a
+    /// wait for an answer checks [`Timeouts::operation`].
+    pub read: Duration,
+    /// Waiting for the device to start answering a request. Changed per request by
+    /// [`crate::transport::TransportRaw::set_timeout`].
+    pub operation: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake: DEFAULT_HANDSHAKE_TIMEOUT,
+            read: DEFAULT_READ_TIMEOUT,
+            operation: DEFAULT_OPERATION_TIMEOUT,
+        }
+    }
+}
+
+/// The prompt the stock firmware's CLI prints when it is ready for a command
+pub const DEFAULT_PROMPT: &str = ">: ";
+
+/// How often [`wait_for_port_free`] retries opening the port
+const PORT_FREE_POLL: Duration = Duration::from_millis(250);
+
+/// Hint attached to [`Error::PortInUse`]
+const PORT_IN_USE_HINT: &str =
+    "close qFlipper, lab.flipper.net or any other program connected to the flipper";
 
 /// A flipper device. Contains port and device name;
 #[derive(Debug)]
@@ -24,11 +79,94 @@ pub struct FlipperDevice {
     pub device_name: String,
 }
 
+/// USB manufacturer string reported by genuine Flipper Zeros
+pub const FLIPPER_MANUFACTURER: &str = "Flipper Devices Inc.";
+
+/// USB vendor id of the Flipper Zero's CDC interface (STMicroelectronics)
+pub const FLIPPER_VID: u16 = 0x0483;
+
+/// USB product id of the Flipper Zero's CDC interface (STM32 virtual COM port)
+pub const FLIPPER_PID: u16 = 0x5740;
+
+/// Decides which USB serial ports are flippers, see [`list_ports_matching`]
+#[non_exhaustive]
+pub enum PortMatcher {
+    /// Matches the USB manufacturer string exactly
+    Manufacturer(String),
+    /// Matches a USB vendor and product id
+    UsbId {
+        /// Vendor id
+        vid: u16,
+        /// Product id
+        pid: u16,
+    },
+    /// Matches if any of the inner matchers match
+    Any(Vec<PortMatcher>),
+    /// Matches with a user supplied predicate
+    Custom(Box<dyn Fn(&serialport::UsbPortInfo) -> bool + Send + Sync>),
+}
+
+impl PortMatcher {
+    /// Matches ports by manufacturer or by the Flipper VID/PID (`0483:5740`), which also finds
+    /// clones and custom firmwares that change the manufacturer string.
+    ///
+    /// The VID/PID is the generic STM32 virtual COM port, so other STM32 based devices will
+    /// match too.
+    pub fn flipper() -> Self {
+        Self::Any(vec![
+            Self::Manufacturer(FLIPPER_MANUFACTURER.to_string()),
+            Self::UsbId {
+                vid: FLIPPER_VID,
+                pid: FLIPPER_PID,
+            },
+        ])
+    }
+
+    /// Matches with a user supplied predicate
+    pub fn custom(f: impl Fn(&serialport::UsbPortInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self::Custom(Box::new(f))
+    }
+
+    /// Whether the port described by `info` matches
+    pub fn matches(&self, info: &serialport::UsbPortInfo) -> bool {
+        match self {
+            Self::Manufacturer(manufacturer) => {
+                info.manufacturer.as_deref() == Some(manufacturer.as_str())
+            }
+            Self::UsbId { vid, pid } => info.vid == *vid && info.pid == *pid,
+            Self::Any(matchers) => matchers.iter().any(|matcher| matcher.matches(info)),
+            Self::Custom(f) => f(info),
+        }
+    }
+}
+
+impl std::fmt::Debug for PortMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Manufacturer(manufacturer) => {
+                f.debug_tuple("Manufacturer").field(manufacturer).finish()
+            }
+            Self::UsbId { vid, pid } => write!(f, "UsbId({vid:04x}:{pid:04x})"),
+            Self::Any(matchers) => f.debug_tuple("Any").field(matchers).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
 /// Lists all flippers connected to the current system
 ///
-/// Scans ports and filters by manufacturer name == "Flipper Devices Inc."
+/// Scans ports and filters by manufacturer name == "Flipper Devices Inc.". Use
+/// [`list_ports_matching`] with [`PortMatcher::flipper`] to also find devices by VID/PID.
 #[cfg_attr(feature = "tracing", tracing::instrument)]
 pub fn list_flipper_ports() -> Result<Vec<FlipperDevice>, serialport::Error> {
+    list_ports_matching(&PortMatcher::Manufacturer(FLIPPER_MANUFACTURER.to_string()))
+}
+
+/// Lists all USB serial ports accepted by `matcher`
+///
+/// Ports without a product string are named after their port.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn list_ports_matching(matcher: &PortMatcher) -> Result<Vec<FlipperDevice>, serialport::Error> {
     debug!("scanning ports");
 
     let ports = serialport::available_ports()?;
@@ -38,14 +176,12 @@ pub fn list_flipper_ports() -> Result<Vec<FlipperDevice>, serialport::Error> {
         .filter_map(|port| {
             debug!("{}", port.port_name);
             if let serialport::SerialPortType::UsbPort(usb_info) = port.port_type {
-                if usb_info.manufacturer.as_deref() == Some("Flipper Devices Inc.") {
-                    if let Some(product) = usb_info.product {
-                        debug!("└── is flipper");
-                        return Some(FlipperDevice {
-                            port_name: port.port_name,
-                            device_name: product,
-                        });
-                    }
+                if matcher.matches(&usb_info) {
+                    debug!("└── is flipper");
+                    return Some(FlipperDevice {
+                        device_name: usb_info.product.unwrap_or_else(|| port.port_name.clone()),
+                        port_name: port.port_name,
+                    });
                 }
             }
             debug!("└── is not flipper");
@@ -55,3 +191,127 @@ pub fn list_flipper_ports() -> Result<Vec<FlipperDevice>, serialport::Error> {
 
     Ok(ports)
 }
+
+/// Opens `port` at the flipper's baud rate with `read_timeout` per read
+///
+/// # Errors
+///
+/// Returns [`Error::PortInUse`] if another program holds the port, or the serialport error.
+pub(crate) fn open_port(
+    port: &str,
+    read_timeout: Duration,
+) -> crate::error::Result<Box<dyn SerialPort>> {
+    serialport::new(port, FLIPPER_BAUD)
+        .timeout(read_timeout)
+        .open()
+        .map_err(|e| {
+            if is_port_in_use(&e) {
+                Error::PortInUse {
+                    port: port.to_string(),
+                    hint: PORT_IN_USE_HINT,
+                }
+            } else {
+                e.into()
+            }
+        })
+}
+
+/// Waits until `port` can be opened, e.g. after asking the user to close qFlipper. The port is
+/// closed again before returning.
+///
+/// # Errors
+///
+/// Returns [`Error::PortInUse`] if the port is still held after `timeout`, or any other error
+/// opening the port right away.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn wait_for_port_free(port: &str, timeout: Duration) -> crate::error::Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match open_port(port, DEFAULT_READ_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(Error::PortInUse { .. }) if Instant::now() < deadline => {
+                debug!(port, "port in use, waiting");
+                std::thread::sleep(PORT_FREE_POLL);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether an open error means another program holds the port.
+///
+/// serialport reports a locked port (`EBUSY` or a taken `flock` on unix, access denied on
+/// Windows) as [`serialport::ErrorKind::NoDevice`], which it also uses for missing ports, so the
+/// description has to be checked too.
+fn is_port_in_use(error: &serialport::Error) -> bool {
+    let description = error.description.to_lowercase();
+
+    match error.kind {
+        serialport::ErrorKind::Io(std::io::ErrorKind::ResourceBusy) => true,
+        serialport::ErrorKind::NoDevice => ["busy", "lock", "denied"]
+            .iter()
+            .any(|needle| description.contains(needle)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb(vid: u16, pid: u16, manufacturer: Option<&str>) -> serialport::UsbPortInfo {
+        serialport::UsbPortInfo {
+            vid,
+            pid,
+            serial_number: None,
+            manufacturer: manufacturer.map(str::to_string),
+            product: None,
+        }
+    }
+
+    #[test]
+    fn flipper_matcher_accepts_manufacturer_or_usb_id() {
+        let matcher = PortMatcher::flipper();
+
+        assert!(matcher.matches(&usb(0x1234, 0x5678, Some(FLIPPER_MANUFACTURER))));
+        assert!(matcher.matches(&usb(FLIPPER_VID, FLIPPER_PID, Some("Clone Co."))));
+        assert!(!matcher.matches(&usb(0x1234, 0x5678, None)));
+    }
+
+    #[test]
+    fn detects_ports_held_by_other_programs() {
+        use serialport::ErrorKind;
+
+        for (kind, description) in [
+            (ErrorKind::NoDevice, "Device or resource busy"),
+            (
+                ErrorKind::NoDevice,
+                "Unable to acquire exclusive lock on serial port",
+            ),
+            (ErrorKind::NoDevice, "Access is denied."),
+        ] {
+            assert!(is_port_in_use(&serialport::Error::new(kind, description)));
+        }
+
+        for (kind, description) in [
+            (
+                ErrorKind::NoDevice,
+                "The system cannot find the file specified.",
+            ),
+            (
+                ErrorKind::Io(std::io::ErrorKind::PermissionDenied),
+                "Permission denied",
+            ),
+        ] {
+            assert!(!is_port_in_use(&serialport::Error::new(kind, description)));
+        }
+    }
+
+    #[test]
+    fn custom_matcher_is_called() {
+        let matcher = PortMatcher::custom(|info| info.serial_number.is_none());
+
+        assert!(matcher.matches(&usb(0, 0, None)));
+    }
+}
@@ -0,0 +1,148 @@
+//! The banner the CLI prints when a connection opens
+//!
+//! Before the first prompt, the firmware prints a welcome line and the version it was built
+//! from:
+//!
+//! ```text
+//! Welcome to Flipper Zero Command Line Interface!
+//! Firmware version: 1.0.1 1.0.1 (e1dd5bd8 built on 23-09-2024)
+//! ```
+//!
+//! The banner is only printed once per connection, so a port that was already sitting at the
+//! prompt has none.
+
+use crate::firmware::FirmwareFlavor;
+
+/// Firmware details read from the CLI banner
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CliBanner {
+    /// The welcome line, e.g. `Welcome to Flipper Zero Command Line Interface!`
+    pub welcome: Option<String>,
+    /// Git branch the firmware was built from, e.g. `dev`. Release builds print their version.
+    pub branch: Option<String>,
+    /// Firmware version, e.g. `1.0.1` or `mntm-008`
+    pub version: Option<String>,
+    /// Git commit hash, with a `-dirty` suffix for builds of a modified tree
+    pub commit: Option<String>,
+    /// Build date, as printed by the firmware
+    pub build_date: Option<String>,
+}
+
+impl CliBanner {
+    /// Parses the output printed before the first prompt. Returns `None` if it holds no banner.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut banner = Self::default();
+
+        for line in text.lines().map(str::trim) {
+            if line.starts_with("Welcome to ") {
+                banner.welcome = Some(line.to_string());
+            } else if let Some(version) = line.strip_prefix("Firmware version:") {
+                banner.parse_version(version.trim());
+            }
+        }
+
+        (banner != Self::default()).then_some(banner)
+    }
+
+    /// `<branch> <version> (<commit> built on <date>)`
+    fn parse_version(&mut self, text: &str) {
+        let (names, details) = match text.split_once('(') {
+            Some((names, details)) => (names, Some(details.trim_end_matches(')'))),
+            None => (text, None),
+        };
+
+        let mut names = names.split_whitespace();
+        self.branch = names.next().map(str::to_string);
+        self.version = names.next().map(str::to_string);
+
+        if let Some(details) = details {
+            let (commit, date) = match details.split_once(" built on ") {
+                Some((commit, date)) => (commit, Some(date)),
+                None => (details, None),
+            };
+            self.commit = Some(commit.trim().to_string()).filter(|commit| !commit.is_empty());
+            self.build_date = date.map(|date| date.trim().to_string());
+        }
+    }
+
+    /// Whether the firmware was built from a modified tree, i.e. the commit ends in `-dirty`
+    pub fn is_dirty(&self) -> bool {
+        self.commit
+            .as_deref()
+            .is_some_and(|commit| commit.ends_with("-dirty"))
+    }
+
+    /// The build date as `(year, month, day)`. The firmware prints it as `DD-MM-YYYY`, `None`
+    /// if it is missing or in another format.
+    pub fn build_ymd(&self) -> Option<(u16, u8, u8)> {
+        let mut parts = self.build_date.as_deref()?.split('-');
+        let day = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let year = parts.next()?.parse().ok()?;
+
+        (parts.next().is_none() && (1..=12).contains(&month) && (1..=31).contains(&day))
+            .then_some((year, month, day))
+    }
+
+    /// The firmware fork, guessed from the version. See [`FirmwareFlavor::from_version`].
+    pub fn flavor(&self) -> FirmwareFlavor {
+        self.version
+            .as_deref()
+            .map_or(FirmwareFlavor::Unknown, FirmwareFlavor::from_version)
+    }
+
+    /// Name of the firmware family, guessed from the version: `Official` for plain version
+    /// numbers, or the custom firmware whose version prefix matches. `None` if unknown.
+    pub fn firmware_name(&self) -> Option<&'static str> {
+        match self.flavor() {
+            FirmwareFlavor::Official => Some("Official"),
+            FirmwareFlavor::Unleashed => Some("Unleashed"),
+            FirmwareFlavor::Momentum => Some("Momentum"),
+            FirmwareFlavor::RogueMaster => Some("RogueMaster"),
+            FirmwareFlavor::Xtreme => Some("Xtreme"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_official_and_custom_banners() {
+        let banner = CliBanner::parse(
+            "\r\n   _.-------.._\r\nWelcome to Flipper Zero Command Line Interface!\r\n\
+             Read the manual: https://docs.flipper.net/development/cli\r\n\r\n\
+             Firmware version: 1.0.1 1.0.1 (e1dd5bd8 built on 23-09-2024)\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            banner.welcome.as_deref(),
+            Some("Welcome to Flipper Zero Command Line Interface!")
+        );
+        assert_eq!(banner.branch.as_deref(), Some("1.0.1"));
+        assert_eq!(banner.version.as_deref(), Some("1.0.1"));
+        assert_eq!(banner.commit.as_deref(), Some("e1dd5bd8"));
+        assert_eq!(banner.build_date.as_deref(), Some("23-09-2024"));
+        assert_eq!(banner.firmware_name(), Some("Official"));
+        assert_eq!(banner.build_ymd(), Some((2024, 9, 23)));
+        assert!(!banner.is_dirty());
+
+        let banner = CliBanner::parse(
+            "Firmware version: mntm-dev mntm-008 (7a1c2f3-dirty built on 01-01-2025)",
+        )
+        .unwrap();
+        assert_eq!(banner.welcome, None);
+        assert_eq!(banner.commit.as_deref(), Some("7a1c2f3-dirty"));
+        assert_eq!(banner.firmware_name(), Some("Momentum"));
+        assert!(banner.is_dirty());
+
+        let banner =
+            CliBanner::parse("Firmware version: dev 1.2.0 (1234abcd built on today)").unwrap();
+        assert_eq!(banner.build_ymd(), None);
+
+        assert_eq!(CliBanner::parse("\r\n"), None);
+    }
+}
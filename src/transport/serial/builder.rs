@@ -0,0 +1,172 @@
+//! Configurable connection setup for the serial transports
+//!
+//! [`SerialCliTransport::new`] and [`SerialRpcTransport::new`] connect with the defaults.
+//! [`SerialBuilder`] is for firmwares that differ from them, e.g. custom firmwares that change
+//! the CLI prompt, or to tune the [`Timeouts`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::{error::Result, transport::serial::builder::SerialBuilder};
+//!
+//! # fn main() -> Result<()> {
+//! let rpc = SerialBuilder::new("/dev/ttyACM0").prompt("> ").open_rpc()?;
+//!
+//! if let Some(banner) = rpc.banner() {
+//!     println!("{:?} {:?}", banner.firmware_name(), banner.version);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::logging::debug;
+use crate::transport::serial::{
+    DEFAULT_PROMPT, Timeouts,
+    banner::CliBanner,
+    cli::{SerialCliTransport, TextMode},
+    helpers::read_until_str,
+    lock::PortLock,
+    open_port,
+    rpc::{DEFAULT_MAX_MESSAGE_LEN, SerialRpcTransport},
+};
+
+/// Opens serial transports with non-default settings
+#[derive(Debug, Clone)]
+pub struct SerialBuilder {
+    port: String,
+    prompt: String,
+    max_message_len: usize,
+    timeouts: Timeouts,
+    text_mode: TextMode,
+    strip_echo: bool,
+    lock_port: bool,
+}
+
+impl SerialBuilder {
+    /// Starts a builder for the port at `port`, e.g. `/dev/ttyACM0` or `COM3`
+    pub fn new(port: impl Into<String>) -> Self {
+        Self {
+            port: port.into(),
+            prompt: DEFAULT_PROMPT.to_string(),
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            timeouts: Timeouts::default(),
+            text_mode: TextMode::default(),
+            strip_echo: false,
+            lock_port: false,
+        }
+    }
+
+    /// Sets the CLI prompt to wait for, [`DEFAULT_PROMPT`] by default
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    /// Sets the longest message body the RPC transport accepts, see
+    /// [`SerialRpcTransport::with_max_message_len`]
+    pub fn max_message_len(mut self, max_message_len: usize) -> Self {
+        self.max_message_len = max_message_len;
+        self
+    }
+
+    /// Sets all timeouts at once, [`Timeouts::default`] by default
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sets how long to wait for the CLI prompt and for RPC sessions to start, see
+    /// [`Timeouts::handshake`]
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.handshake = timeout;
+        self
+    }
+
+    /// Sets how long to wait for more bytes of a message that started arriving, see
+    /// [`Timeouts::read`]
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = timeout;
+        self
+    }
+
+    /// Sets how long to wait for the device to start answering a request, see
+    /// [`Timeouts::operation`]
+    pub fn operation_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.operation = timeout;
+        self
+    }
+
+    /// Sets how the CLI transport decodes output, see [`SerialCliTransport::with_text_mode`]
+    pub fn text_mode(mut self, text_mode: TextMode) -> Self {
+        self.text_mode = text_mode;
+        self
+    }
+
+    /// Makes the CLI transport drop command echoes and prompts from its output, see
+    /// [`SerialCliTransport::with_echo_stripping`]
+    pub fn strip_echo(mut self, strip_echo: bool) -> Self {
+        self.strip_echo = strip_echo;
+        self
+    }
+
+    /// Takes a host-side lock on the port before opening it, so another process using this crate
+    /// fails with [`crate::error::Error::PortLockedByPid`] instead of sharing the session. Off
+    /// by default, see [`crate::transport::serial::lock`].
+    pub fn lock_port(mut self, lock_port: bool) -> Self {
+        self.lock_port = lock_port;
+        self
+    }
+
+    /// Opens the port and waits for the CLI prompt. The banner printed before it, if any, is
+    /// available from [`SerialCliTransport::banner`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`std::io::ErrorKind::InvalidInput`] error if the prompt is empty, an error if
+    /// the port is locked or cannot be opened, or a timeout if the prompt does not appear.
+    #[cfg_attr(feature = "tracing", tracing::instrument)]
+    pub fn open_cli(self) -> Result<SerialCliTransport> {
+        if self.prompt.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the cli prompt must not be empty",
+            )
+            .into());
+        }
+
+        let lock = self
+            .lock_port
+            .then(|| PortLock::acquire(&self.port))
+            .transpose()?;
+        let mut port = open_port(&self.port, self.timeouts.read)?;
+
+        debug!("Reading port until prompt");
+        let output = read_until_str(&mut port, &self.prompt, self.timeouts.handshake)?;
+        let banner = CliBanner::parse(&String::from_utf8_lossy(&output));
+        debug!(?banner, "cli ready");
+
+        Ok(
+            SerialCliTransport::from_parts(port, self.prompt, banner, self.timeouts)
+                .with_text_mode(self.text_mode)
+                .with_echo_stripping(self.strip_echo)
+                .with_lock(lock),
+        )
+    }
+
+    /// Opens the port, waits for the CLI prompt and starts an RPC session
+    ///
+    /// # Errors
+    ///
+    /// Same as [`SerialBuilder::open_cli`], or an error if the session cannot be started.
+    pub fn open_rpc(self) -> Result<SerialRpcTransport> {
+        let max_message_len = self.max_message_len;
+
+        Ok(self
+            .open_cli()?
+            .into_rpc()?
+            .with_max_message_len(max_message_len))
+    }
+}
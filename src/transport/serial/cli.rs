@@ -21,19 +21,48 @@
 //! ```
 
 use crate::error::Error;
-use crate::transport::serial::{TIMEOUT, helpers::drain_until_str};
-use crate::{error::Result, logging::debug};
+use crate::error::Result;
+#[cfg(feature = "system-log")]
+use crate::transport::serial::helpers::drain_until_str;
+use crate::transport::serial::{
+    Timeouts, banner::CliBanner, builder::SerialBuilder, lock::PortLock,
+};
 
 use crate::logging::trace;
 use serialport::SerialPort;
 
-use crate::transport::{Transport, serial::FLIPPER_BAUD};
+use crate::transport::Transport;
 
 use super::{
-    helpers::{drain_until, read_to_string_no_eof},
+    helpers::{drain_until, read_to_end_no_eof, read_until_str},
     rpc::SerialRpcTransport,
 };
 
+#[cfg(feature = "cli-fallback")]
+pub mod storage;
+
+#[cfg(feature = "subghz")]
+pub mod subghz;
+
+#[cfg(feature = "infrared")]
+pub mod infrared;
+
+#[cfg(feature = "apps")]
+pub mod apps;
+
+#[cfg(feature = "cli-info")]
+pub mod info;
+
+/// How [`SerialCliTransport::receive`] turns the bytes it read into text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextMode {
+    /// Fails with [`std::io::ErrorKind::InvalidData`] on invalid UTF-8
+    #[default]
+    Strict,
+    /// Replaces invalid UTF-8 with `U+FFFD`, e.g. for commands that dump binary data
+    Lossy,
+}
+
 /// # Flipper Text CLI
 ///
 /// A `Transport` for communicating with Flipper Zero devices over a serial port using the text-based cli.
@@ -59,6 +88,20 @@ use super::{
 #[derive(Debug)]
 pub struct SerialCliTransport {
     port: Box<dyn SerialPort>,
+    /// The prompt that ends the output of every command
+    prompt: String,
+    /// The banner printed before the first prompt, if it was read
+    banner: Option<CliBanner>,
+    /// How long to wait for the device
+    timeouts: Timeouts,
+    /// How received bytes are decoded
+    text_mode: TextMode,
+    /// Whether receive drops the echo of the last command and the prompt
+    strip_echo: bool,
+    /// The last command sent, to recognize its echo
+    last_command: Option<String>,
+    /// Host-side lock on the port, handed on to the RPC session
+    lock: Option<PortLock>,
 }
 
 impl SerialCliTransport {
@@ -69,17 +112,192 @@ impl SerialCliTransport {
     /// Will error if serialport cannot connect to the port or if the flipper shell prompt does not
     /// appear
     ///
-    /// The above errors occur after a 2 second timeout
+    /// The above errors occur once [`Timeouts::handshake`] has passed
+    ///
+    /// Use [`SerialBuilder`] for firmwares with a different prompt.
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     pub fn new<S: AsRef<str> + std::fmt::Debug>(port: S) -> Result<Self> {
-        let mut port = serialport::new(port.as_ref(), FLIPPER_BAUD)
-            .timeout(TIMEOUT)
-            .open()?;
+        SerialBuilder::new(port.as_ref()).open_cli()
+    }
+
+    /// Wraps a SerialPort that is already sitting at `prompt`. Does not drain or reconfigure the
+    /// port.
+    pub(crate) fn from_parts(
+        port: Box<dyn SerialPort>,
+        prompt: String,
+        banner: Option<CliBanner>,
+        timeouts: Timeouts,
+    ) -> Self {
+        Self {
+            port,
+            prompt,
+            banner,
+            timeouts,
+            text_mode: TextMode::default(),
+            strip_echo: false,
+            last_command: None,
+            lock: None,
+        }
+    }
+
+    /// Holds `lock` for as long as the port is used, including by the RPC session started from
+    /// this transport
+    pub(crate) fn with_lock(mut self, lock: Option<PortLock>) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Sets how [`SerialCliTransport::receive`] decodes the output, [`TextMode::Strict`] by
+    /// default
+    pub fn with_text_mode(mut self, text_mode: TextMode) -> Self {
+        self.text_mode = text_mode;
+        self
+    }
+
+    /// Makes [`SerialCliTransport::receive`] return only the output of the command. The device
+    /// echoes every command it gets and prints the prompt after the output, both are dropped.
+    /// Off by default.
+    pub fn with_echo_stripping(mut self, strip_echo: bool) -> Self {
+        self.strip_echo = strip_echo;
+        self
+    }
+
+    /// Reads whatever the device printed until the port times out, without decoding it. Echo
+    /// stripping applies as for [`SerialCliTransport::receive`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the port fails.
+    pub fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        let bytes = read_to_end_no_eof(&mut self.port)?;
+
+        if !self.strip_echo {
+            return Ok(bytes);
+        }
+
+        Ok(strip_echo(&bytes, self.last_command.as_deref(), &self.prompt).to_vec())
+    }
 
-        debug!("Draining port until prompt");
-        drain_until_str(&mut port, ">: ", TIMEOUT)?;
+    /// Runs `cmd` and returns its output once the prompt is back, without the echo of the
+    /// command or the prompt. The output is decoded as set by
+    /// [`SerialCliTransport::with_text_mode`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`std::io::ErrorKind::TimedOut`] error if the prompt does not come back within
+    /// [`Timeouts::operation`], or an error if the output is not valid UTF-8 in strict mode.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use flipper_rpc::{error::Result, transport::serial::cli::SerialCliTransport};
+    ///
+    /// # fn main() -> Result<()> {
+    /// let mut cli = SerialCliTransport::new("/dev/ttyACM0")?;
+    ///
+    /// println!("{}", cli.exec("uptime")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument)]
+    pub fn exec(&mut self, cmd: impl Into<String> + std::fmt::Debug) -> Result<String> {
+        let cmd = cmd.into();
+        self.send(cmd.clone())?;
+
+        let output = read_until_str(&mut self.port, &self.prompt, self.timeouts.operation)?;
+        let output = strip_echo(&output, Some(&cmd), "");
+
+        self.decode(output.to_vec())
+    }
+
+    /// Decodes output as set by [`SerialCliTransport::with_text_mode`]
+    fn decode(&self, bytes: Vec<u8>) -> Result<String> {
+        match self.text_mode {
+            TextMode::Strict => String::from_utf8(bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()),
+            TextMode::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
 
-        Ok(Self { port })
+    /// The prompt that ends the output of every command
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// Firmware details from the banner printed when the CLI opened. `None` if the port was
+    /// already past the banner, e.g. when another program connected first.
+    pub fn banner(&self) -> Option<&CliBanner> {
+        self.banner.as_ref()
+    }
+
+    /// How long the transport waits for the device
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Reads a single `\n` terminated line, byte by byte, so that nothing after the line is
+    /// consumed. The trailing `\r\n` is stripped and invalid UTF-8 is replaced.
+    #[cfg(any(feature = "cli-fallback", feature = "system-log"))]
+    pub(crate) fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+
+        loop {
+            self.port.read_exact(&mut byte)?;
+
+            if byte[0] == b'\n' {
+                break;
+            }
+
+            line.push(byte[0]);
+        }
+
+        let line = String::from_utf8_lossy(&line);
+
+        Ok(line.trim_end_matches('\r').to_string())
+    }
+
+    /// Reads the output of a command up to the next prompt, returning it line by line. The echo
+    /// of the command itself is included.
+    #[cfg(any(
+        feature = "subghz",
+        feature = "infrared",
+        feature = "apps",
+        feature = "cli-info"
+    ))]
+    pub(crate) fn read_until_prompt(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+
+        // The prompt is not followed by a newline, so this can't use read_line
+        loop {
+            self.port.read_exact(&mut byte)?;
+
+            match byte[0] {
+                b'\n' => {
+                    let text = String::from_utf8_lossy(&line);
+                    lines.push(text.trim_end_matches('\r').to_string());
+                    line.clear();
+                }
+                b => line.push(b),
+            }
+
+            if line.ends_with(self.prompt.as_bytes()) {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Stops the running command with Ctrl-C and waits for the prompt to come back
+    #[cfg(feature = "system-log")]
+    pub(crate) fn interrupt(&mut self) -> Result<()> {
+        self.port.write_all(&[0x03])?;
+        self.port.flush()?;
+
+        drain_until_str(&mut self.port, &self.prompt, self.timeouts.handshake)?;
+
+        Ok(())
     }
 
     /// Converts a SerialCliTransport into a SerialRpcTransport
@@ -93,9 +311,14 @@ impl SerialCliTransport {
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     pub fn into_rpc(mut self) -> Result<SerialRpcTransport> {
         self.send("start_rpc_session".to_string())?;
-        drain_until(&mut self.port, b'\n', TIMEOUT)?;
 
-        SerialRpcTransport::from_port(self.port)
+        trace!("draining(start_rpc_session, \\n)");
+        drain_until(&mut self.port, b'\n', self.timeouts.handshake)?;
+
+        Ok(
+            SerialRpcTransport::from_parts(self.port, self.prompt, self.banner, self.timeouts)
+                .with_lock(self.lock),
+        )
     }
 }
 
@@ -109,13 +332,57 @@ impl Transport<String> for SerialCliTransport {
         self.port.write_all(b"\r")?;
         self.port.flush()?;
 
+        self.last_command = Some(cmd);
+
         Ok(())
     }
 
+    /// Reads whatever the device printed until the port times out, decoded as set by
+    /// [`SerialCliTransport::with_text_mode`]
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     fn receive(&mut self) -> std::result::Result<String, Self::Err> {
-        let string = read_to_string_no_eof(&mut self.port)?;
+        let bytes = self.receive_bytes()?;
+
+        self.decode(bytes)
+    }
+}
+
+/// Drops the echo of `command` from the start of `output` and the prompt from its end, along
+/// with the line breaks around them
+fn strip_echo<'a>(output: &'a [u8], command: Option<&str>, prompt: &str) -> &'a [u8] {
+    let mut output = output;
+
+    if let Some(rest) = command.and_then(|command| output.strip_prefix(command.as_bytes())) {
+        output = rest.strip_prefix(b"\r\n").unwrap_or(rest);
+    }
+
+    if let Some(rest) = output.strip_suffix(prompt.as_bytes()) {
+        output = rest;
+        while let Some(rest) = output.strip_suffix(b"\r\n") {
+            output = rest;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_the_echo_and_prompt() {
+        let output = b"led g 255\r\nok\r\n\r\n>: ";
+        assert_eq!(strip_echo(output, Some("led g 255"), ">: "), b"ok");
+
+        // Output that does not start with the command is kept
+        assert_eq!(strip_echo(b"other\r\n>: ", Some("led"), ">: "), b"other");
+        assert_eq!(strip_echo(b"partial", None, ">: "), b"partial");
 
-        Ok(string)
+        // What exec sees, the prompt was already cut off
+        assert_eq!(
+            strip_echo(b"uptime\r\nUptime: 0h0m5s\r\n\r\n", Some("uptime"), ""),
+            b"Uptime: 0h0m5s"
+        );
     }
 }
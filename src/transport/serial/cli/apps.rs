@@ -0,0 +1,146 @@
+//! Text CLI `loader` commands
+//!
+//! RPC can start an app by name with `AppStart`, but has no way to ask which names exist. The CLI
+//! `loader list` command prints the built-in apps grouped by menu, which [`list_installed`] parses
+//! into [`InstalledApp`]s. Their names are exactly what `AppStart` expects.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::Transport;
+//! use flipper_rpc::transport::serial::cli::apps;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//!
+//! let installed = rpc.with_cli(apps::list_installed)?;
+//! if let Some(app) = installed.iter().find(|app| app.name == "Sub-GHz") {
+//!     rpc.send_and_receive(app.start_request(""))?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::Result;
+use crate::logging::{debug, trace};
+use crate::proto::app::StartRequest;
+use crate::rpc::req::Request;
+use crate::transport::Transport;
+
+use super::SerialCliTransport;
+
+/// An app listed by `loader list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledApp {
+    /// Name to start the app by
+    pub name: String,
+    /// Menu the app is listed under, e.g. `Apps` or `Settings`
+    pub category: String,
+}
+
+impl InstalledApp {
+    /// Builds the `AppStart` request that launches this app with `args`
+    pub fn start_request(&self, args: impl Into<String>) -> Request {
+        Request::AppStart(StartRequest {
+            name: self.name.clone(),
+            args: args.into(),
+        })
+    }
+}
+
+/// Lists the apps the loader can start, in the order the device prints them.
+///
+/// # Errors
+///
+/// Returns an IO error if the device does not know the command or the output cannot be parsed.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn list_installed(cli: &mut SerialCliTransport) -> Result<Vec<InstalledApp>> {
+    cli.send("loader list".to_string())?;
+
+    let apps = parse_list_output(&cli.read_until_prompt()?)?;
+    debug!(apps = apps.len(), "loader listed");
+
+    Ok(apps)
+}
+
+/// `loader list` prints a `Category:` header followed by one indented name per app
+fn parse_list_output(lines: &[String]) -> Result<Vec<InstalledApp>> {
+    let mut apps = Vec::new();
+    let mut category: Option<String> = None;
+
+    // The first line is the echo of the command
+    for line in lines.iter().skip(1) {
+        trace!(line, "loader list");
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            let Some(category) = &category else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("loader cli: app before any category: {line}"),
+                )
+                .into());
+            };
+
+            apps.push(InstalledApp {
+                name: line.trim().to_string(),
+                category: category.clone(),
+            });
+        } else if let Some(header) = line.trim_end().strip_suffix(':') {
+            category = Some(header.to_string());
+        } else {
+            return Err(std::io::Error::other(format!("loader cli: {line}")).into());
+        }
+    }
+
+    Ok(apps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_list_output() {
+        let apps = parse_list_output(&lines(&[
+            "loader list",
+            "Apps:",
+            "\tSub-GHz",
+            "\t125 kHz RFID",
+            "Settings:",
+            "\tBluetooth",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            apps,
+            [
+                InstalledApp {
+                    name: "Sub-GHz".to_string(),
+                    category: "Apps".to_string()
+                },
+                InstalledApp {
+                    name: "125 kHz RFID".to_string(),
+                    category: "Apps".to_string()
+                },
+                InstalledApp {
+                    name: "Bluetooth".to_string(),
+                    category: "Settings".to_string()
+                },
+            ]
+        );
+
+        let error = parse_list_output(&lines(&["loader lsit", "Command not found"]))
+            .expect_err("unknown command should fail");
+        assert!(error.to_string().contains("not found"));
+    }
+}
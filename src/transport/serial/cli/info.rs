@@ -0,0 +1,287 @@
+//! Text CLI `info`, `power info`, `free` and `uptime` commands
+//!
+//! Old firmwares answer few of the RPC system requests, but every firmware has these commands.
+//! Their output is one `key: value` pair per line, which [`info`] returns as is and the other
+//! commands parse into typed structs. Every struct keeps the raw pairs as well, for keys this
+//! crate does not know.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::cli::{SerialCliTransport, info};
+//!
+//! # fn main() -> Result<()> {
+//! let mut cli = SerialCliTransport::new("/dev/ttyACM0")?;
+//!
+//! let power = info::power_info(&mut cli)?;
+//! println!("{:?}% at {:?} mV", power.charge_percent, power.voltage_mv);
+//! println!("up for {:?}", info::uptime(&mut cli)?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::logging::{debug, trace};
+use crate::transport::Transport;
+
+use super::SerialCliTransport;
+
+/// Battery state printed by `power info`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PowerInfo {
+    /// Charge level in percent
+    pub charge_percent: Option<u8>,
+    /// Charger state, e.g. `charging` or `discharging`
+    pub charge_state: Option<String>,
+    /// Battery voltage in millivolts
+    pub voltage_mv: Option<u32>,
+    /// Battery current in milliamps, negative while discharging
+    pub current_ma: Option<i32>,
+    /// Battery temperature in degrees Celsius
+    pub temperature_c: Option<i32>,
+    /// Battery health in percent
+    pub health_percent: Option<u8>,
+    /// Every pair as printed, with keys normalized to `.` separators
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Heap usage printed by `free`, all sizes in bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MemoryInfo {
+    /// Currently free heap
+    pub free_heap: Option<u64>,
+    /// Size of the whole heap
+    pub total_heap: Option<u64>,
+    /// Lowest free heap since boot
+    pub min_free_heap: Option<u64>,
+    /// Largest block that can be allocated right now
+    pub max_heap_block: Option<u64>,
+    /// Every pair as printed
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Reads the device info through `info`, keyed like [`crate::system::device_info`] reads it
+/// over RPC.
+///
+/// # Errors
+///
+/// Returns an IO error if the device does not know the command or prints no pairs.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn info(cli: &mut SerialCliTransport) -> Result<BTreeMap<String, String>> {
+    run(cli, "info")
+}
+
+/// Reads the battery state through `power info`
+///
+/// # Errors
+///
+/// Same as [`info`].
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn power_info(cli: &mut SerialCliTransport) -> Result<PowerInfo> {
+    let fields = run(cli, "power info")?;
+
+    Ok(parse_power_info(fields))
+}
+
+/// Reads the heap usage through `free`
+///
+/// # Errors
+///
+/// Same as [`info`].
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn free(cli: &mut SerialCliTransport) -> Result<MemoryInfo> {
+    let fields = run(cli, "free")?;
+
+    Ok(parse_free(fields))
+}
+
+/// Reads the time since boot through `uptime`
+///
+/// # Errors
+///
+/// Same as [`info`], or an [`std::io::ErrorKind::InvalidData`] error if the uptime cannot be
+/// parsed.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn uptime(cli: &mut SerialCliTransport) -> Result<Duration> {
+    let fields = run(cli, "uptime")?;
+
+    let uptime = fields
+        .get("Uptime")
+        .and_then(|uptime| parse_uptime(uptime))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("uptime cli: unexpected output {fields:?}"),
+            )
+        })?;
+
+    Ok(uptime)
+}
+
+/// Runs `command` and collects the pairs it prints
+fn run(cli: &mut SerialCliTransport, command: &str) -> Result<BTreeMap<String, String>> {
+    cli.send(command.to_string())?;
+
+    let fields = parse_fields(command, &cli.read_until_prompt()?)?;
+    debug!(command, fields = fields.len(), "cli info read");
+
+    Ok(fields)
+}
+
+/// Splits `key: value` lines, skipping the echo of the command and lines without a colon
+fn parse_fields(command: &str, lines: &[String]) -> Result<BTreeMap<String, String>> {
+    let mut fields = BTreeMap::new();
+
+    // The first line is the echo of the command
+    for line in lines.iter().skip(1) {
+        trace!(line, command);
+
+        if line.to_ascii_lowercase().contains("command not found") || line.starts_with("Usage:") {
+            return Err(std::io::Error::other(format!("{command} cli: {line}")).into());
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    if fields.is_empty() {
+        return Err(std::io::Error::other(format!("{command} cli: no output")).into());
+    }
+
+    Ok(fields)
+}
+
+/// Older firmwares separate the parts of a key with `_` instead of `.`
+fn parse_power_info(fields: BTreeMap<String, String>) -> PowerInfo {
+    let fields: BTreeMap<_, _> = fields
+        .into_iter()
+        .map(|(key, value)| (key.replace('_', "."), value))
+        .collect();
+
+    PowerInfo {
+        charge_percent: number(&fields, "charge.level"),
+        charge_state: fields.get("charge.state").cloned(),
+        voltage_mv: number(&fields, "battery.voltage"),
+        current_ma: number(&fields, "battery.current"),
+        temperature_c: number(&fields, "battery.temp"),
+        health_percent: number(&fields, "battery.health"),
+        fields,
+    }
+}
+
+fn parse_free(fields: BTreeMap<String, String>) -> MemoryInfo {
+    MemoryInfo {
+        free_heap: number(&fields, "Free heap size"),
+        total_heap: number(&fields, "Total heap size"),
+        min_free_heap: number(&fields, "Minimum heap size"),
+        max_heap_block: number(&fields, "Maximum heap block"),
+        fields,
+    }
+}
+
+/// The value of `key` if it is a number
+fn number<N: FromStr>(fields: &BTreeMap<String, String>, key: &str) -> Option<N> {
+    fields.get(key).and_then(|value| value.parse().ok())
+}
+
+/// `1h2m3s`, optionally with days and spaces between the parts
+fn parse_uptime(text: &str) -> Option<Duration> {
+    let mut seconds = 0u64;
+    let mut digits = String::new();
+
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'd' => 86_400,
+            'h' => 3_600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        seconds += digits.parse::<u64>().ok()? * unit;
+        digits.clear();
+    }
+
+    digits.is_empty().then(|| Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_power_info_with_either_separator() {
+        let output = lines(&[
+            "power info",
+            "format.major        : 2",
+            "charge.level        : 87",
+            "charge.state        : discharging",
+            "battery.voltage     : 4012",
+            "battery.current     : -25",
+            "battery.temp        : 27",
+        ]);
+        let power = parse_power_info(parse_fields("power info", &output).unwrap());
+        assert_eq!(power.charge_percent, Some(87));
+        assert_eq!(power.charge_state.as_deref(), Some("discharging"));
+        assert_eq!(power.voltage_mv, Some(4012));
+        assert_eq!(power.current_ma, Some(-25));
+        assert_eq!(power.temperature_c, Some(27));
+        assert_eq!(power.health_percent, None);
+
+        let output = lines(&["power info", "charge_level: 50", "battery_health: 98"]);
+        let power = parse_power_info(parse_fields("power info", &output).unwrap());
+        assert_eq!(power.charge_percent, Some(50));
+        assert_eq!(power.health_percent, Some(98));
+    }
+
+    #[test]
+    fn parses_free_and_uptime() {
+        let output = lines(&[
+            "free",
+            "Free heap size: 112424",
+            "Total heap size: 196608",
+            "Minimum heap size: 101832",
+            "Maximum heap block: 98304",
+            "Aux pool total free: 0",
+        ]);
+        let memory = parse_free(parse_fields("free", &output).unwrap());
+        assert_eq!(memory.free_heap, Some(112_424));
+        assert_eq!(memory.total_heap, Some(196_608));
+        assert_eq!(memory.min_free_heap, Some(101_832));
+        assert_eq!(memory.max_heap_block, Some(98_304));
+        assert_eq!(memory.fields["Aux pool total free"], "0");
+
+        assert_eq!(parse_uptime("1h2m3s"), Some(Duration::from_secs(3_723)));
+        assert_eq!(
+            parse_uptime("2d 0h 0m 5s"),
+            Some(Duration::from_secs(172_805))
+        );
+        assert_eq!(parse_uptime("12"), None);
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let error = parse_fields("free", &lines(&["free", "`free` command not found"]));
+        assert!(error.is_err());
+
+        let error = parse_fields("uptime", &lines(&["uptime", "Command not found"]))
+            .expect_err("unknown command should fail");
+        assert!(error.to_string().contains("not found"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Text CLI `ir` commands
+//!
+//! RPC can only replay infrared signals stored in `.ir` files through the Infrared app. The CLI
+//! `ir tx` command transmits a decoded message or raw timings directly, which [`tx`] and
+//! [`tx_raw`] wrap.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::cli::{SerialCliTransport, infrared};
+//!
+//! # fn main() -> Result<()> {
+//! let mut cli = SerialCliTransport::new("/dev/ttyACM0")?;
+//!
+//! // Power toggle of many NEC remotes
+//! infrared::tx(&mut cli, infrared::Protocol::Nec, 0x04, 0x08)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::error::Result;
+use crate::logging::{debug, trace};
+use crate::transport::Transport;
+
+use super::SerialCliTransport;
+
+/// Carrier frequencies `ir tx RAW` accepts, in Hz
+pub const FREQUENCY_RANGE: RangeInclusive<u32> = 10_000..=56_000;
+
+/// Most timings `ir tx RAW` accepts in one signal
+pub const MAX_SAMPLES: usize = 512;
+
+/// Infrared protocols the firmware can encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Protocol {
+    /// NEC, 8 bit address and command
+    Nec,
+    /// Extended NEC, 16 bit address
+    NecExt,
+    /// NEC with 13 bit address
+    Nec42,
+    /// Extended NEC42
+    Nec42Ext,
+    /// Samsung, 32 bit
+    Samsung32,
+    /// Philips RC5
+    Rc5,
+    /// Philips RC5 with extended commands
+    Rc5X,
+    /// Philips RC6
+    Rc6,
+    /// Sony SIRC, 12 bit
+    Sirc,
+    /// Sony SIRC, 15 bit
+    Sirc15,
+    /// Sony SIRC, 20 bit
+    Sirc20,
+    /// Panasonic Kaseikyo
+    Kaseikyo,
+    /// RCA
+    Rca,
+    /// Pioneer
+    Pioneer,
+}
+
+impl Protocol {
+    /// The name the firmware uses for the protocol
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Nec => "NEC",
+            Self::NecExt => "NECext",
+            Self::Nec42 => "NEC42",
+            Self::Nec42Ext => "NEC42ext",
+            Self::Samsung32 => "Samsung32",
+            Self::Rc5 => "RC5",
+            Self::Rc5X => "RC5X",
+            Self::Rc6 => "RC6",
+            Self::Sirc => "SIRC",
+            Self::Sirc15 => "SIRC15",
+            Self::Sirc20 => "SIRC20",
+            Self::Kaseikyo => "Kaseikyo",
+            Self::Rca => "RCA",
+            Self::Pioneer => "Pioneer",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Transmits a decoded message. The firmware checks `address` and `command` against the
+/// protocol's width.
+///
+/// # Errors
+///
+/// Returns an IO error with the device's message if it rejects the message.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn tx(
+    cli: &mut SerialCliTransport,
+    protocol: Protocol,
+    address: u32,
+    command: u32,
+) -> Result<()> {
+    cli.send(format!("ir tx {protocol} {address:X} {command:X}"))?;
+
+    parse_tx_output(&cli.read_until_prompt()?)?;
+    debug!(%protocol, address, command, "ir transmitted");
+
+    Ok(())
+}
+
+/// Transmits raw timings: alternating mark and space durations in microseconds, starting with a
+/// mark, on a `frequency` Hz carrier with a `duty_cycle` percent duty cycle.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error without contacting the device if the
+/// frequency is outside [`FREQUENCY_RANGE`], the duty cycle is above 100, or there are no
+/// samples or more than [`MAX_SAMPLES`]. Returns an IO error with the device's message if it
+/// rejects the signal.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(samples)))]
+pub fn tx_raw(
+    cli: &mut SerialCliTransport,
+    frequency: u32,
+    duty_cycle: u8,
+    samples: &[u32],
+) -> Result<()> {
+    check_raw_args(frequency, duty_cycle, samples.len())?;
+
+    let mut command = format!("ir tx RAW F:{frequency} DC:{duty_cycle}");
+    for sample in samples {
+        command.push_str(&format!(" {sample}"));
+    }
+
+    cli.send(command)?;
+
+    parse_tx_output(&cli.read_until_prompt()?)?;
+    debug!(frequency, samples = samples.len(), "ir raw transmitted");
+
+    Ok(())
+}
+
+fn check_raw_args(frequency: u32, duty_cycle: u8, samples: usize) -> Result<()> {
+    let problem = if !FREQUENCY_RANGE.contains(&frequency) {
+        format!("frequency {frequency} Hz is outside {FREQUENCY_RANGE:?}")
+    } else if duty_cycle > 100 {
+        format!("duty cycle {duty_cycle}% is above 100%")
+    } else if samples == 0 || samples > MAX_SAMPLES {
+        format!("{samples} samples, expected 1 to {MAX_SAMPLES}")
+    } else {
+        return Ok(());
+    };
+
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, problem).into())
+}
+
+/// `ir tx` prints nothing on success, and an explanation followed by the usage on failure
+fn parse_tx_output(lines: &[String]) -> Result<()> {
+    // The first line is the echo of the command
+    for line in lines.iter().skip(1) {
+        trace!(line, "ir tx");
+
+        let line = line.trim();
+        if !line.is_empty() {
+            return Err(std::io::Error::other(format!("ir cli: {line}")).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn rejects_invalid_raw_signals() {
+        for (frequency, duty_cycle, samples) in [
+            (9_000, 33, 2),
+            (38_000, 101, 2),
+            (38_000, 33, 0),
+            (38_000, 33, 513),
+        ] {
+            let error =
+                check_raw_args(frequency, duty_cycle, samples).expect_err("should be rejected");
+
+            assert!(matches!(error, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+        }
+
+        assert!(check_raw_args(38_000, 33, MAX_SAMPLES).is_ok());
+    }
+
+    #[test]
+    fn parses_tx_output() {
+        assert!(parse_tx_output(&lines(&["ir tx NEC 4 8", ""])).is_ok());
+
+        let error = parse_tx_output(&lines(&["ir tx FOO 4 8", "Wrong arguments.", "Usage:"]))
+            .expect_err("rejected signal should fail");
+        assert!(error.to_string().contains("Wrong arguments."));
+    }
+}
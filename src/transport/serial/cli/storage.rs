@@ -0,0 +1,226 @@
+//! Text CLI `storage` commands
+//!
+//! Very old firmwares lack some RPC storage commands. These wrappers drive the binary-safe
+//! `storage read_chunks` and `storage write_chunk` variants of `storage read` / `storage write`
+//! instead, so files can still be moved when RPC replies with `ERROR_NOT_IMPLEMENTED`.
+
+use crate::error::{Error, Result};
+use crate::fs::CHUNK_SIZE;
+use crate::logging::{debug, trace};
+use crate::rpc::error::StorageError;
+use crate::transport::Transport;
+use crate::transport::serial::helpers::drain_until_str;
+
+use super::SerialCliTransport;
+
+/// Prefix the firmware prints in front of every storage failure
+const STORAGE_ERROR_PREFIX: &str = "Storage error: ";
+
+impl SerialCliTransport {
+    /// Reads a file through `storage read_chunks`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the matching [`StorageError`] if the CLI reports a storage failure, or an IO error
+    /// if the output cannot be parsed.
+    #[cfg_attr(feature = "tracing", tracing::instrument)]
+    pub fn storage_read(&mut self, path: &str) -> Result<Vec<u8>> {
+        self.send(format!("storage read_chunks {path} {CHUNK_SIZE}"))?;
+
+        let size = loop {
+            let line = self.read_line()?;
+            trace!(line, "read_chunks");
+
+            check_storage_error(&line)?;
+
+            if let Some(size) = line.strip_prefix("Size: ") {
+                break size.trim().parse::<usize>().map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid size line")
+                })?;
+            }
+        };
+
+        debug!(size, "reading file over cli");
+
+        let mut data = vec![0u8; size];
+        let mut read = 0;
+
+        while read < size {
+            // Every chunk is announced with "Ready?" and only sent after the host answers
+            while self.read_line()? != "Ready?" {}
+
+            self.port.write_all(b"y")?;
+            self.port.flush()?;
+
+            let end = (read + CHUNK_SIZE).min(size);
+            self.port.read_exact(&mut data[read..end])?;
+            read = end;
+        }
+
+        drain_until_str(&mut self.port, &self.prompt, self.timeouts.handshake)?;
+
+        Ok(data)
+    }
+
+    /// Writes a file through `storage write_chunk`, replacing any existing file at `path`.
+    ///
+    /// `write_chunk` always appends, so the file is removed first. It also creates the file, so
+    /// empty `data` is written as one empty chunk and leaves an empty file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the matching [`StorageError`] if the CLI reports a storage failure, or an IO error
+    /// if the output cannot be parsed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(data)))]
+    pub fn storage_write(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        match self.storage_remove(path) {
+            Ok(())
+            | Err(Error::Rpc(crate::rpc::error::Error::StorageError(StorageError::NotFound))) => {}
+            Err(e) => return Err(e),
+        }
+
+        debug!("writing {} bytes over cli", data.len());
+
+        if data.is_empty() {
+            return self.write_chunk(path, data);
+        }
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            self.write_chunk(path, chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends `chunk` to `path` with one `storage write_chunk`, creating the file if needed
+    fn write_chunk(&mut self, path: &str, chunk: &[u8]) -> Result<()> {
+        self.send(format!("storage write_chunk {path} {}", chunk.len()))?;
+
+        loop {
+            let line = self.read_line()?;
+            trace!(line, "write_chunk");
+
+            check_storage_error(&line)?;
+
+            if line == "Ready" {
+                break;
+            }
+        }
+
+        self.port.write_all(chunk)?;
+        self.port.flush()?;
+
+        self.expect_prompt()
+    }
+
+    /// Removes a file or an empty directory through `storage remove`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the matching [`StorageError`] if the CLI reports a storage failure.
+    #[cfg_attr(feature = "tracing", tracing::instrument)]
+    pub fn storage_remove(&mut self, path: &str) -> Result<()> {
+        self.send(format!("storage remove {path}"))?;
+
+        self.expect_prompt()
+    }
+
+    /// Reads lines until the prompt comes back, failing on the first storage error line.
+    fn expect_prompt(&mut self) -> Result<()> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+
+        // The prompt is not followed by a newline, so this can't use read_line
+        loop {
+            self.port.read_exact(&mut byte)?;
+
+            match byte[0] {
+                b'\n' => {
+                    check_storage_error(String::from_utf8_lossy(&line).trim_end())?;
+                    line.clear();
+                }
+                b => line.push(b),
+            }
+
+            if line.ends_with(self.prompt.as_bytes()) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Maps a `Storage error: ...` line to the same error RPC would have returned.
+fn check_storage_error(line: &str) -> Result<()> {
+    let Some(description) = line.strip_prefix(STORAGE_ERROR_PREFIX) else {
+        return Ok(());
+    };
+
+    // Descriptions come from the firmware's storage_error_get_desc
+    let error = match description {
+        "filesystem not ready" => StorageError::NotReady,
+        "file/dir already exist" => StorageError::AlreadyExists,
+        "file/dir not exist" => StorageError::NotFound,
+        "invalid parameter" => StorageError::InvalidParameter,
+        "access denied" => StorageError::PermissionDenied,
+        "invalid name/path" => StorageError::InvalidName,
+        "internal error" => StorageError::Internal,
+        "function not implemented" => StorageError::NotImplemented,
+        "file/dir already opened" => StorageError::AlreadyOpen,
+        other => return Err(std::io::Error::other(format!("storage cli: {other}")).into()),
+    };
+
+    Err(crate::rpc::error::Error::from(error).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_cli_storage_errors() {
+        let error = check_storage_error("Storage error: file/dir not exist")
+            .expect_err("storage errors should be reported");
+
+        assert!(matches!(
+            error,
+            Error::Rpc(crate::rpc::error::Error::StorageError(
+                StorageError::NotFound
+            ))
+        ));
+    }
+
+    #[test]
+    fn ignores_regular_output() {
+        assert!(check_storage_error("Size: 12").is_ok());
+        assert!(check_storage_error("storage read_chunks /ext/a 1024").is_ok());
+    }
+
+    #[test]
+    fn empty_writes_create_an_empty_file() {
+        use crate::transport::serial::{DEFAULT_PROMPT, Timeouts, mock::MockPort};
+
+        // Prompt after the remove, then Ready and the prompt for the one empty chunk
+        let port = MockPort::new(b">: Ready\r\n>: ");
+        let mut cli = SerialCliTransport::from_parts(
+            port.boxed(),
+            DEFAULT_PROMPT.to_string(),
+            None,
+            Timeouts::default(),
+        );
+
+        cli.storage_write("/ext/empty.txt", &[]).unwrap();
+
+        assert_eq!(
+            port.written(),
+            b"storage remove /ext/empty.txt\rstorage write_chunk /ext/empty.txt 0\r"
+        );
+    }
+
+    #[test]
+    fn unknown_descriptions_become_io_errors() {
+        let error = check_storage_error("Storage error: something new")
+            .expect_err("storage errors should be reported");
+
+        assert!(matches!(error, Error::Io(_)));
+    }
+}
@@ -0,0 +1,167 @@
+//! Text CLI `subghz` commands
+//!
+//! RPC has no way to transmit on Sub-GHz, but the CLI has `subghz tx`, which sends a 24 bit key
+//! with the Princeton protocol on the internal CC1101 radio. [`tx`] checks the arguments, runs the
+//! command and parses its output.
+//!
+//! Which frequencies may be transmitted on depends on the region the device was provisioned for.
+//! That table lives on the device: [`tx`] only rejects frequencies the radio can't tune to, and
+//! reports the device's refusal for frequencies outside its region as an error.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::cli::{SerialCliTransport, subghz};
+//!
+//! # fn main() -> Result<()> {
+//! let mut cli = SerialCliTransport::new("/dev/ttyACM0")?;
+//!
+//! // 433.92 MHz, key 0x123456, 400us symbol time, 10 repeats
+//! subghz::tx(&mut cli, 433_920_000, 0x12_3456, 400, 10)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ops::RangeInclusive;
+
+use crate::error::Result;
+use crate::logging::{debug, trace};
+use crate::transport::Transport;
+
+use super::SerialCliTransport;
+
+/// Frequency bands the CC1101 can tune to, in Hz
+pub const SUPPORTED_BANDS: [RangeInclusive<u32>; 3] = [
+    300_000_000..=348_000_000,
+    387_000_000..=464_000_000,
+    779_000_000..=928_000_000,
+];
+
+/// Largest key `subghz tx` accepts, 3 bytes
+pub const MAX_KEY: u32 = 0xff_ffff;
+
+/// Whether the radio can tune to `frequency`, in Hz. The device's region may still forbid
+/// transmitting on it.
+pub fn is_frequency_supported(frequency: u32) -> bool {
+    SUPPORTED_BANDS.iter().any(|band| band.contains(&frequency))
+}
+
+/// Transmits `key` on `frequency` Hz with a symbol time of `te` microseconds, `count` times.
+///
+/// Blocks until the transmission is done, so a high `count` may need a longer port timeout.
+///
+/// # Errors
+///
+/// Returns an [`std::io::ErrorKind::InvalidInput`] error without contacting the device if the
+/// frequency is outside [`SUPPORTED_BANDS`], the key is larger than [`MAX_KEY`], or `te` or
+/// `count` is zero. Returns an IO error with the device's message if it refuses to transmit,
+/// e.g. because the frequency is not allowed in its region.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn tx(
+    cli: &mut SerialCliTransport,
+    frequency: u32,
+    key: u32,
+    te: u32,
+    count: u32,
+) -> Result<()> {
+    check_args(frequency, key, te, count)?;
+
+    // The last argument selects the internal radio
+    cli.send(format!("subghz tx {key:06X} {frequency} {te} {count} 0"))?;
+
+    let output = cli.read_until_prompt()?;
+    parse_tx_output(&output)?;
+
+    debug!(frequency, count, "subghz transmitted");
+
+    Ok(())
+}
+
+fn check_args(frequency: u32, key: u32, te: u32, count: u32) -> Result<()> {
+    let problem = if !is_frequency_supported(frequency) {
+        format!("frequency {frequency} Hz is outside the supported bands")
+    } else if key > MAX_KEY {
+        format!("key {key:#x} is longer than 3 bytes")
+    } else if te == 0 {
+        "te must not be zero".to_string()
+    } else if count == 0 {
+        "count must not be zero".to_string()
+    } else {
+        return Ok(());
+    };
+
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, problem).into())
+}
+
+/// Checks the output of `subghz tx` for the transmit confirmation
+fn parse_tx_output(lines: &[String]) -> Result<()> {
+    let mut transmitted = false;
+
+    for line in lines {
+        trace!(line, "subghz tx");
+
+        if line.starts_with("Transmitting at") {
+            transmitted = true;
+        } else if line.starts_with("In your settings/region")
+            || line.starts_with("Frequency must be in")
+            || line.starts_with("Usage:")
+            || line.contains("not found")
+        {
+            return Err(std::io::Error::other(format!("subghz cli: {line}")).into());
+        }
+    }
+
+    if !transmitted {
+        return Err(std::io::Error::other("subghz cli: no transmit confirmation").into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn rejects_unsupported_arguments() {
+        for (frequency, key, te, count) in [
+            (350_000_000, 1, 400, 1),
+            (433_920_000, 0x100_0000, 400, 1),
+            (433_920_000, 1, 0, 1),
+            (433_920_000, 1, 400, 0),
+        ] {
+            let error = check_args(frequency, key, te, count).expect_err("should be rejected");
+
+            assert!(matches!(error, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+        }
+
+        assert!(check_args(868_350_000, MAX_KEY, 400, 3).is_ok());
+    }
+
+    #[test]
+    fn parses_tx_output() {
+        assert!(
+            parse_tx_output(&lines(&[
+                "subghz tx 123456 433920000 400 10 0",
+                "Transmitting at 433920000, key 123456, te 400, repeat 10, device 0. Press CTRL+C to stop",
+            ]))
+            .is_ok()
+        );
+
+        let error = parse_tx_output(&lines(&[
+            "subghz tx 123456 315000000 400 10 0",
+            "In your settings/region, only reception on this frequency (315000000) is allowed,",
+            "the actual operation of the application is not possible",
+        ]))
+        .expect_err("region refusal should fail");
+        assert!(error.to_string().contains("only reception"));
+
+        assert!(parse_tx_output(&lines(&["subghz tx"])).is_err());
+    }
+}
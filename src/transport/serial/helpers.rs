@@ -1,62 +1,90 @@
 //! Helper functions for serial communication
 
-use core::str;
 use std::{
     io::{ErrorKind, Read, Result},
     time::{Duration, Instant},
 };
 
-/// Drain a stream until a str + padding chunk
+/// Finds a pattern in a stream that arrives in chunks, including matches split across chunks.
 ///
-/// Returns `Ok(())` if the str is found, or an error if timed out or another I/O issue occurs.
-pub(crate) fn drain_until_str<R: Read>(
-    reader: &mut R,
-    until_str: &str,
-    timeout: Duration,
-) -> Result<()> {
-    assert!(!until_str.is_empty(), "until_str must not be empty");
-
-    const CHUNK_SIZE: usize = 256;
-
-    // INFO: In the worst case scenario, where the string starts @ the last byte in a chunk, it
-    // must fit within the second chunk, otherwise it will not be found.
-    assert!(until_str.len() <= CHUNK_SIZE + 1);
-
-    let until_bytes = until_str.as_bytes();
-
-    const BUF_LEN: usize = CHUNK_SIZE * 2;
-
-    let mut buf = [0u8; BUF_LEN]; // Two chunk juggle
+/// Only the newly fed bytes and the last `pattern.len() - 1` bytes before them are searched, so
+/// each byte is looked at a bounded number of times however long the stream runs.
+///
+/// # Examples
+///
+/// ```
+/// use flipper_rpc::transport::serial::helpers::StreamMatcher;
+///
+/// let mut matcher = StreamMatcher::new(b">: ");
+///
+/// assert_eq!(matcher.feed(b"banner\r\n>"), None);
+/// assert_eq!(matcher.feed(b": rest"), Some(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamMatcher<'p> {
+    finder: memchr::memmem::Finder<'p>,
+    /// The end of the bytes fed so far, short of a full match
+    tail: Vec<u8>,
+}
 
-    let deadline = Instant::now() + timeout;
+impl<'p> StreamMatcher<'p> {
+    /// Starts looking for `pattern`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` is empty.
+    pub fn new(pattern: &'p [u8]) -> Self {
+        assert!(!pattern.is_empty(), "pattern must not be empty");
+
+        Self {
+            finder: memchr::memmem::Finder::new(pattern),
+            tail: Vec::new(),
+        }
+    }
 
-    let finder = memchr::memmem::Finder::new(until_bytes);
+    /// Feeds the next chunk of the stream. Returns the offset in `chunk` just past the end of
+    /// the first match, which may have started in an earlier chunk.
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<usize> {
+        let len = self.finder.needle().len();
+        let carried = self.tail.len();
 
-    let mut filled = 0;
+        // The carried tail is shorter than the pattern, so any match ends in `chunk`
+        self.tail.extend_from_slice(chunk);
+        if let Some(at) = self.finder.find(&self.tail) {
+            self.tail.clear();
 
-    loop {
-        if filled > CHUNK_SIZE {
-            buf.copy_within(CHUNK_SIZE.., 0);
-            filled -= CHUNK_SIZE;
+            return Some(at + len - carried);
         }
 
-        let now = Instant::now();
-        if now >= deadline {
-            break;
-        }
+        let keep = self.tail.len().min(len - 1);
+        self.tail.drain(..self.tail.len() - keep);
+
+        None
+    }
+}
 
-        match reader.read(&mut buf[filled..filled + CHUNK_SIZE.min(BUF_LEN - filled)]) {
-            Ok(0) => {
-                std::thread::sleep(Duration::from_millis(10)); // Cooldown
-                continue;
-            }
-            Ok(n) => {
-                filled += n;
+/// Reads `reader` until `until` has been read, passing every byte up to the end of the match to
+/// `consume`. Bytes read past the match are dropped.
+fn scan_until<R: Read>(
+    reader: &mut R,
+    until: &[u8],
+    timeout: Duration,
+    mut consume: impl FnMut(&[u8]),
+) -> Result<()> {
+    let mut matcher = StreamMatcher::new(until);
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 256];
 
-                if finder.find(&buf).is_some() {
+    while Instant::now() < deadline {
+        match reader.read(&mut buf) {
+            Ok(0) => std::thread::sleep(Duration::from_millis(10)), // Cooldown
+            Ok(n) => match matcher.feed(&buf[..n]) {
+                Some(end) => {
+                    consume(&buf[..end]);
                     return Ok(());
                 }
-            }
+                None => consume(&buf[..n]),
+            },
             Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
             Err(e) => return Err(e),
         }
@@ -64,15 +92,56 @@ pub(crate) fn drain_until_str<R: Read>(
 
     Err(std::io::Error::new(
         ErrorKind::TimedOut,
-        format!("Timeout searching for '{}'", until_str),
+        format!("Timeout searching for '{}'", until.escape_ascii()),
     ))
 }
 
+/// Drains a stream until `until_str` has been read. Will read over by at most 256 bytes.
+///
+/// Returns `Ok(())` if the str is found, or an error if timed out or another I/O issue occurs.
+///
+/// # Panics
+///
+/// Panics if `until_str` is empty.
+pub fn drain_until_str<R: Read>(reader: &mut R, until_str: &str, timeout: Duration) -> Result<()> {
+    scan_until(reader, until_str.as_bytes(), timeout, |_| {})
+}
+
+/// Reads a stream until `until_str` shows up, returning everything read before it.
+///
+/// Unlike [`drain_until_str`] the output is kept, e.g. to parse the CLI banner printed before
+/// the first prompt. Bytes read past `until_str` are dropped.
+///
+/// # Panics
+///
+/// Panics if `until_str` is empty.
+pub fn read_until_str<R: Read>(
+    reader: &mut R,
+    until_str: &str,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    scan_until(reader, until_str.as_bytes(), timeout, |bytes| {
+        output.extend_from_slice(bytes)
+    })?;
+
+    output.truncate(output.len() - until_str.len());
+
+    Ok(output)
+}
+
+/// Whether `bytes` contain text the CLI prints when it starts: the welcome line or `prompt`
+pub(crate) fn contains_cli_banner(bytes: &[u8], prompt: &str) -> bool {
+    [&b"Command Line Interface"[..], prompt.as_bytes()]
+        .iter()
+        .any(|marker| memchr::memmem::find(bytes, marker).is_some())
+}
+
 /// Reads to the end of a stream without checking for EOF.
 ///
 /// Loops over 1024 byte chunks (OK; since reading over the won't happen) until the reader reads
 /// 0 bytes or an error occurs.
-pub(crate) fn read_to_string_no_eof<R: Read>(reader: &mut R) -> Result<String> {
+pub(crate) fn read_to_end_no_eof<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
     let mut buffer = Vec::new();
     let mut temp = [0; 1024];
 
@@ -85,33 +154,72 @@ pub(crate) fn read_to_string_no_eof<R: Read>(reader: &mut R) -> Result<String> {
         }
     }
 
-    String::from_utf8(buffer).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+    Ok(buffer)
 }
 
 /// Drains a stream until a specific byte is found. Will read over by at most 256 bytes.
 ///
 /// Returns `Ok(()` if the byte is found, or an error if an I/O issue occurs.
 pub(crate) fn drain_until<R: Read>(reader: &mut R, delim: u8, timeout: Duration) -> Result<()> {
-    const CHUNK_SIZE: usize = 256;
+    scan_until(reader, &[delim], timeout, |_| {})
+}
 
-    let mut buf = [0u8; CHUNK_SIZE];
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_cli_banner() {
+        assert!(contains_cli_banner(
+            b"\r\nWelcome to Flipper Zero Command Line Interface!\r\n",
+            ">: "
+        ));
+        assert!(contains_cli_banner(b"\x08\x01\r\n>: ", ">: "));
+        assert!(contains_cli_banner(b"\x08\x01\r\nmntm> ", "mntm> "));
+        assert!(!contains_cli_banner(b"\x08\x01\x12\x00", ">: "));
+    }
 
-    let deadline = Instant::now() + timeout;
+    #[test]
+    fn reads_until_a_prompt_split_across_reads() {
+        // Chained readers hand out the pieces one read at a time
+        let mut reader = (&b"Firmware version: 1.0.1\r\n\r\n>"[..]).chain(&b": ignored"[..]);
 
-    while Instant::now() < deadline {
-        match reader.read(&mut buf) {
-            Ok(read) => {
-                if memchr::memchr(delim, &buf[..read]).is_some() {
-                    return Ok(());
-                }
-            }
-            Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
-            Err(e) => return Err(e),
-        }
+        let output = read_until_str(&mut reader, ">: ", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(output, b"Firmware version: 1.0.1\r\n\r\n");
     }
 
-    Err(std::io::Error::new(
-        ErrorKind::TimedOut,
-        format!("Timeout searching for byte 0x{:02x}", delim),
-    ))
+    #[test]
+    fn matches_across_chunks_without_stale_bytes() {
+        let stream = b"xx>:x>: tail";
+
+        // Fed one byte at a time the match ends in the eighth chunk
+        let mut matcher = StreamMatcher::new(b">: ");
+        let found = stream
+            .chunks(1)
+            .position(|chunk| matcher.feed(chunk).is_some());
+        assert_eq!(found, Some(7));
+
+        // A pattern longer than the chunks it arrives in
+        let mut matcher = StreamMatcher::new(b"Command Line Interface");
+        let found = b"Welcome to Flipper Zero Command Line Interface!"
+            .chunks(4)
+            .find_map(|chunk| matcher.feed(chunk));
+        assert_eq!(found, Some(2));
+
+        // A partial match followed by other bytes must not be completed by a later chunk
+        let mut matcher = StreamMatcher::new(b">: ");
+        assert_eq!(matcher.feed(b">"), None);
+        assert_eq!(matcher.feed(b"x"), None);
+        assert_eq!(matcher.feed(b": "), None);
+    }
+
+    #[test]
+    fn drains_until_timeout() {
+        let mut reader = &b"no prompt here"[..];
+
+        let error = drain_until_str(&mut reader, ">: ", Duration::from_millis(50)).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
 }
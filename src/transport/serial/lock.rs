@@ -0,0 +1,267 @@
+//! Host-side lockfiles that keep two processes off the same port
+//!
+//! Serial ports are not exclusive on every platform: a second process using this crate can open
+//! a port that is already in an RPC session, and the two then read each other's answers. A
+//! [`PortLock`] is a file in the OS temp dir named after the port and holding the pid of its
+//! owner, created with [`SerialBuilder::lock_port`](super::builder::SerialBuilder::lock_port)
+//! and removed when the transport is dropped.
+//!
+//! The pid is written to a private file first and hard-linked into place, so a lockfile is never
+//! seen without its owner.
+//!
+//! Locks left behind by a process that crashed are taken over where the host can tell the
+//! process is gone, which is on Linux. Elsewhere they have to be removed by hand, see
+//! [`lock_path`]. One process at a time takes a lock over, holding a `.takeover` file next to it,
+//! so two processes that find the same stale lock cannot both end up holding it.
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::logging::{debug, warn};
+
+/// A held lock on a port, released on drop
+#[derive(Debug)]
+pub struct PortLock {
+    path: PathBuf,
+}
+
+impl PortLock {
+    /// Locks `port` for this process
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PortLockedByPid`] if another live process holds the lock, or an IO
+    /// error if the lockfile cannot be written.
+    pub fn acquire(port: &str) -> Result<Self> {
+        let path = lock_path(port);
+
+        // Linking fails if the lock exists, and the link appears with the pid already in it
+        let staged = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&staged, std::process::id().to_string())?;
+        let result = Self::link(port, &staged, path);
+        let _ = std::fs::remove_file(&staged);
+
+        result
+    }
+
+    /// Links the `staged` pid file to `path`, taking over a lock whose owner is gone
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn link(port: &str, staged: &Path, path: PathBuf) -> Result<Self> {
+        match std::fs::hard_link(staged, &path) {
+            Ok(()) => {
+                debug!(port, path = %path.display(), "port locked");
+
+                Ok(Self { path })
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => match read_owner(&path) {
+                Some(pid) if is_alive(pid) => Err(Error::PortLockedByPid(pid)),
+                stale => Self::take_over(port, staged, path, stale),
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the lock at `path`, found held by the dead `stale` owner, while holding the
+    /// takeover file
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn take_over(port: &str, staged: &Path, path: PathBuf, stale: Option<u32>) -> Result<Self> {
+        let takeover = path.with_extension("lock.takeover");
+        if let Err(e) = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&takeover)
+        {
+            return Err(match e.kind() {
+                ErrorKind::AlreadyExists => std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "the lock at {} is being taken over, remove {} if no process is",
+                        path.display(),
+                        takeover.display()
+                    ),
+                )
+                .into(),
+                _ => e.into(),
+            });
+        }
+
+        let result = (|| {
+            // Another process may have taken the lock over before we got the takeover file
+            if read_owner(&path) != stale {
+                return Err(locked(&path));
+            }
+
+            warn!(port, path = %path.display(), "removing stale port lock");
+            remove_stale(&path)?;
+
+            match std::fs::hard_link(staged, &path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(locked(&path)),
+                Err(e) => return Err(e.into()),
+            }
+            if read_owner(&path) != Some(std::process::id()) {
+                return Err(locked(&path));
+            }
+            debug!(port, path = %path.display(), "port lock taken over");
+
+            Ok(Self { path: path.clone() })
+        })();
+
+        let _ = std::fs::remove_file(&takeover);
+
+        result
+    }
+
+    /// The lockfile
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        // Someone else's lock if ours was taken over as stale
+        if read_owner(&self.path) != Some(std::process::id()) {
+            warn!(path = %self.path.display(), "port lock was taken over, leaving it");
+            return;
+        }
+
+        if let Err(_e) = std::fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %_e, "failed to remove port lock");
+        }
+    }
+}
+
+/// Where the lock of `port` lives: `flipper-rpc-<port>.lock` in the temp dir, with every
+/// character that is not alphanumeric replaced, e.g. `flipper-rpc-_dev_ttyACM0.lock`
+pub fn lock_path(port: &str) -> PathBuf {
+    let name: String = port
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    std::env::temp_dir().join(format!("flipper-rpc-{name}.lock"))
+}
+
+/// The pid written to a lockfile, `None` if it cannot be read or holds no pid
+fn read_owner(path: &Path) -> Option<u32> {
+    let mut contents = String::new();
+    File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+
+    contents.trim().parse().ok()
+}
+
+/// Whether `pid` is a running process. Only Linux can tell, elsewhere every owner counts as
+/// alive.
+fn is_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+
+    let proc = Path::new("/proc");
+    !proc.is_dir() || proc.join(pid.to_string()).exists()
+}
+
+/// The error for a lock at `path` that another process got first
+fn locked(path: &Path) -> Error {
+    match read_owner(path) {
+        Some(pid) => Error::PortLockedByPid(pid),
+        None => std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("could not replace the lock at {}", path.display()),
+        )
+        .into(),
+    }
+}
+
+/// Removes a lock another process may be removing at the same time
+fn remove_stale(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_on_a_port_fails() {
+        let port = format!("/dev/test-lock-{}", std::process::id());
+
+        let lock = PortLock::acquire(&port).unwrap();
+        assert_eq!(lock.path(), lock_path(&port));
+        assert!(matches!(
+            PortLock::acquire(&port),
+            Err(Error::PortLockedByPid(pid)) if pid == std::process::id()
+        ));
+
+        drop(lock);
+        assert!(!lock_path(&port).exists());
+        drop(PortLock::acquire(&port).unwrap());
+    }
+
+    #[test]
+    fn leaves_no_staging_files_behind() {
+        let port = format!("/dev/test-staging-{}", std::process::id());
+        let lock = PortLock::acquire(&port).unwrap();
+        let _ = PortLock::acquire(&port);
+        drop(lock);
+
+        let prefix = lock_path(&port);
+        let prefix = prefix.file_name().unwrap().to_string_lossy();
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(prefix.trim_end_matches(".lock"))
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn takes_over_unreadable_locks() {
+        let port = format!("/dev/test-stale-{}", std::process::id());
+        std::fs::write(lock_path(&port), "not a pid").unwrap();
+
+        let lock = PortLock::acquire(&port).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(lock.path()).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+
+    #[test]
+    fn one_process_at_a_time_takes_a_lock_over() {
+        let port = format!("/dev/test-takeover-{}", std::process::id());
+        let path = lock_path(&port);
+        std::fs::write(&path, "not a pid").unwrap();
+
+        let takeover = path.with_extension("lock.takeover");
+        std::fs::write(&takeover, "").unwrap();
+        assert!(PortLock::acquire(&port).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a pid");
+
+        std::fs::remove_file(&takeover).unwrap();
+        let lock = PortLock::acquire(&port).unwrap();
+        assert!(!takeover.exists());
+
+        // A process that found the stale lock too must not replace the new one
+        let staged = lock.path().with_extension("0.tmp");
+        std::fs::write(&staged, "0").unwrap();
+        assert!(matches!(
+            PortLock::take_over(&port, &staged, lock_path(&port), None),
+            Err(Error::PortLockedByPid(pid)) if pid == std::process::id()
+        ));
+        std::fs::remove_file(staged).unwrap();
+        assert_eq!(read_owner(lock.path()), Some(std::process::id()));
+    }
+}
@@ -0,0 +1,353 @@
+//! Port classification for device pickers
+//!
+//! [`probe_port`] opens a port for a moment and tells what is on the other end, without starting
+//! or stopping a session: a Flipper at its CLI prompt, a Flipper with an RPC session left open
+//! (e.g. by a program that crashed), a silent device, or something that is not a Flipper.
+//!
+//! Only harmless bytes are sent. An RPC session gets a ping and nothing else; the CLI gets the
+//! same ping as line noise and echoes its few printable bytes. Only after that echo, or a prompt,
+//! is a Ctrl+C sent to discard the line and get a fresh prompt. A silent port, or one answering
+//! with frames the probe cannot make sense of, never sees the Ctrl+C, which an RPC session
+//! would read as a length prefix.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::transport::serial::{PortProbe, list_flipper_ports, probe_port};
+//!
+//! # fn main() -> flipper_rpc::error::Result<()> {
+//! for device in list_flipper_ports()? {
+//!     match probe_port(&device.port_name)? {
+//!         PortProbe::CliPrompt { .. } => println!("{}: ready", device.device_name),
+//!         PortProbe::RpcActive => println!("{}: session left open", device.device_name),
+//!         other => println!("{}: {other:?}", device.device_name),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use prost::bytes::BytesMut;
+
+use crate::codec::FrameCodec;
+use crate::error::Result;
+use crate::logging::debug;
+use crate::proto::{self, main::Content, system::PingRequest};
+use crate::transport::serial::{
+    DEFAULT_PROMPT, banner::CliBanner, helpers::contains_cli_banner, open_port,
+};
+
+/// How long [`probe_port`] listens after opening the port and after each probe it sends
+pub const PROBE_LISTEN: Duration = Duration::from_millis(300);
+
+/// Read timeout while listening, so the listen windows end on time
+const POLL: Duration = Duration::from_millis(25);
+
+/// Command id of the probe's ping. Its frame holds no byte the CLI acts on (CR, LF or Ctrl+C),
+/// and the id is a printable byte for the CLI to echo.
+const PING_ID: u32 = b'p' as u32;
+
+/// What the CLI echoes of the ping frame: its printable bytes, the command id and the tag of the
+/// ping request
+const PING_ECHO: &[u8] = b"p*";
+
+/// Ctrl+C. The CLI drops the line typed so far and prints a new prompt.
+const ETX: u8 = 0x03;
+
+/// What [`probe_port`] found on a port
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PortProbe {
+    /// A Flipper CLI answered with its prompt. The banner is only there if the connection was
+    /// new.
+    CliPrompt {
+        /// The firmware details the CLI printed, if it printed them
+        banner: Option<CliBanner>,
+    },
+    /// A Flipper answered the ping in an open RPC session
+    RpcActive,
+    /// Nothing came back
+    Silent,
+    /// Something answered, but neither like the CLI nor like an RPC session
+    NotFlipper,
+}
+
+impl PortProbe {
+    /// Whether the port leads to a Flipper, in either mode
+    pub fn is_flipper(&self) -> bool {
+        matches!(self, Self::CliPrompt { .. } | Self::RpcActive)
+    }
+}
+
+/// Opens `port` briefly and classifies the device on it. The port is closed again before
+/// returning, with the device left in the mode it was found in.
+///
+/// Takes up to three [`PROBE_LISTEN`] windows: one for a banner printed on open, one for the
+/// answer to a ping and one for the prompt.
+///
+/// # Errors
+///
+/// Returns [`crate::error::Error::PortInUse`] if another program holds the port, or the error
+/// opening or writing to it.
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+pub fn probe_port(port: &str) -> Result<PortProbe> {
+    let probe = probe(&mut open_port(port, POLL)?)?;
+    debug!(port, ?probe, "probed port");
+
+    Ok(probe)
+}
+
+/// [`probe_port`] over an open connection
+fn probe<P: Read + Write>(serial: &mut P) -> Result<PortProbe> {
+    let mut seen = Vec::new();
+
+    // Freshly opened connections print the banner by themselves
+    listen(serial, &mut seen, |seen| {
+        contains_cli_banner(seen, DEFAULT_PROMPT)
+    })?;
+    if contains_cli_banner(&seen, DEFAULT_PROMPT) {
+        return Ok(cli_prompt(&seen));
+    }
+
+    serial.write_all(&ping_frame()?)?;
+    serial.flush()?;
+    let mut reply = Vec::new();
+    listen(serial, &mut reply, |reply| {
+        answers_ping(reply) || echoes_ping(reply)
+    })?;
+    if answers_ping(&reply) {
+        return Ok(PortProbe::RpcActive);
+    }
+    seen.extend_from_slice(&reply);
+
+    if !echoes_ping(&seen) && !contains_cli_banner(&seen, DEFAULT_PROMPT) {
+        return Ok(if seen.is_empty() {
+            PortProbe::Silent
+        } else {
+            PortProbe::NotFlipper
+        });
+    }
+
+    serial.write_all(&[ETX])?;
+    serial.flush()?;
+    let mut prompt = Vec::new();
+    listen(serial, &mut prompt, |prompt| {
+        contains_cli_banner(prompt, DEFAULT_PROMPT)
+    })?;
+    seen.extend_from_slice(&prompt);
+
+    Ok(if contains_cli_banner(&seen, DEFAULT_PROMPT) {
+        cli_prompt(&seen)
+    } else {
+        PortProbe::NotFlipper
+    })
+}
+
+/// Reads into `buf` for [`PROBE_LISTEN`], or until `done` accepts what was read
+fn listen<R: Read>(reader: &mut R, buf: &mut Vec<u8>, done: impl Fn(&[u8]) -> bool) -> Result<()> {
+    let deadline = Instant::now() + PROBE_LISTEN;
+    let mut chunk = [0; 256];
+
+    while Instant::now() < deadline && !done(buf) {
+        match reader.read(&mut chunk) {
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn cli_prompt(seen: &[u8]) -> PortProbe {
+    PortProbe::CliPrompt {
+        banner: CliBanner::parse(&String::from_utf8_lossy(seen)),
+    }
+}
+
+fn ping_frame() -> Result<BytesMut> {
+    let ping = proto::Main::new(PingRequest::default()).with_command_id(PING_ID);
+    let mut frame = BytesMut::new();
+    FrameCodec::new().encode(&ping, &mut frame)?;
+
+    Ok(frame)
+}
+
+/// Whether the CLI echoed the ping frame
+fn echoes_ping(reply: &[u8]) -> bool {
+    memchr::memmem::find(reply, PING_ECHO).is_some()
+}
+
+/// Whether `reply` is a stream of RPC frames holding the answer to the probe's ping. Frames the
+/// device sent on its own, like screen frames, may come first.
+fn answers_ping(reply: &[u8]) -> bool {
+    let codec = FrameCodec::new();
+    let mut reply = BytesMut::from(reply);
+
+    while let Ok(Some(main)) = codec.decode(&mut reply) {
+        if main.command_id == PING_ID
+            && matches!(main.content, Some(Content::SystemPingResponse(_)))
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{gui::ScreenFrame, system::PingResponse};
+    use prost::Message;
+
+    fn frame(command_id: u32, content: Content) -> Vec<u8> {
+        proto::Main {
+            command_id,
+            content: Some(content),
+            ..Default::default()
+        }
+        .encode_length_delimited_to_vec()
+    }
+
+    #[test]
+    fn ping_is_line_noise_to_the_cli() {
+        let frame = ping_frame().unwrap();
+
+        assert!(frame.iter().all(|byte| ![b'\r', b'\n', ETX].contains(byte)));
+        assert_eq!(
+            frame
+                .iter()
+                .copied()
+                .filter(|byte| (0x20..0x7f).contains(byte))
+                .collect::<Vec<_>>(),
+            PING_ECHO
+        );
+    }
+
+    #[test]
+    fn recognizes_the_ping_answer_behind_other_frames() {
+        let pong = frame(
+            PING_ID,
+            Content::SystemPingResponse(PingResponse::default()),
+        );
+        let screen = frame(
+            0,
+            Content::GuiScreenFrame(ScreenFrame {
+                data: vec![0; 16].into(),
+                ..Default::default()
+            }),
+        );
+
+        assert!(answers_ping(&pong));
+        assert!(answers_ping(&[screen.clone(), pong].concat()));
+        assert!(!answers_ping(&screen));
+        assert!(!answers_ping(b"ping\r\n>: "));
+        assert!(!answers_ping(&frame(
+            PING_ID + 1,
+            Content::SystemPingResponse(PingResponse::default())
+        )));
+    }
+
+    #[test]
+    fn keeps_the_banner_of_new_connections() {
+        let seen = b"\r\nWelcome to Flipper Zero Command Line Interface!\r\n\
+            Firmware version: 1.0.1 1.0.1 (e1dd5bd8 built on 23-09-2024)\r\n\r\n>: ";
+
+        let PortProbe::CliPrompt {
+            banner: Some(banner),
+        } = cli_prompt(seen)
+        else {
+            panic!("no banner");
+        };
+        assert_eq!(banner.version.as_deref(), Some("1.0.1"));
+        assert_eq!(cli_prompt(b">: "), PortProbe::CliPrompt { banner: None });
+    }
+
+    /// How the scripted device reacts to what the probe writes
+    enum Device {
+        /// A CLI at its prompt: echoes printable bytes, answers Ctrl+C with a new prompt
+        Cli,
+        /// Never answers
+        Silent,
+        /// Answers anything with the start of a frame it never finishes
+        PartialFrame,
+    }
+
+    /// A port with a [`Device`] behind it, recording what was written
+    struct Scripted {
+        device: Device,
+        output: Vec<u8>,
+        written: Vec<u8>,
+    }
+
+    impl Scripted {
+        fn new(device: Device) -> Self {
+            Self {
+                device,
+                output: Vec::new(),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.output.is_empty() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+
+            let n = buf.len().min(self.output.len());
+            buf[..n].copy_from_slice(&self.output[..n]);
+            self.output.drain(..n);
+
+            Ok(n)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            match self.device {
+                Device::Cli => {
+                    for &byte in buf {
+                        match byte {
+                            ETX => self.output.extend_from_slice(b"^C\r\n>: "),
+                            0x20..0x7f => self.output.push(byte),
+                            _ => {}
+                        }
+                    }
+                }
+                Device::Silent => {}
+                Device::PartialFrame => self.output.extend_from_slice(&[0x10, 0x08]),
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sends_ctrl_c_only_after_cli_output() {
+        let mut cli = Scripted::new(Device::Cli);
+        assert_eq!(
+            probe(&mut cli).unwrap(),
+            PortProbe::CliPrompt { banner: None }
+        );
+        assert_eq!(cli.written.last(), Some(&ETX));
+
+        let mut silent = Scripted::new(Device::Silent);
+        assert_eq!(probe(&mut silent).unwrap(), PortProbe::Silent);
+        assert!(!silent.written.contains(&ETX));
+
+        let mut partial = Scripted::new(Device::PartialFrame);
+        assert_eq!(probe(&mut partial).unwrap(), PortProbe::NotFlipper);
+        assert!(!partial.written.contains(&ETX));
+    }
+}
@@ -15,23 +15,30 @@
 //! # Ok(())
 //! # }
 //! ```
+use crate::codec::{DEFAULT_MAX_FRAME_LEN, FrameCodec};
 use crate::error::{Error, Result};
-use crate::logging::trace;
-use crate::transport::serial::TIMEOUT;
+use crate::logging::{trace, warn};
+use crate::proto_ext::encode_into;
+use crate::transport::serial::{
+    DEFAULT_PROMPT, Timeouts,
+    banner::CliBanner,
+    builder::SerialBuilder,
+    lock::PortLock,
+    stats::{DecodePath, ReceiveStats},
+};
+pub use crate::transport::{CommandIndex, FIRST_COMMAND_ID, next_command_id};
 use crate::{
     proto,
-    transport::{
-        TransportRaw,
-        serial::{
-            FLIPPER_BAUD,
-            helpers::{drain_until, drain_until_str},
-        },
-    },
+    transport::{TransportRaw, check_status, serial::helpers::contains_cli_banner},
 };
 
-use crate::proto::CommandStatus;
 use prost::Message;
+use prost::bytes::{Bytes, BytesMut};
 use serialport::SerialPort;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the rest of a CLI banner after a message failed to decode
+const BANNER_TIMEOUT: Duration = Duration::from_millis(500);
 
 /// A transport that sends RPC messages on a port
 ///
@@ -52,25 +59,49 @@ use serialport::SerialPort;
 /// # Ok(())
 /// # }
 /// ```
+///
+/// # Stack limit
+///
+/// `STACK_LIMIT` is the size in bytes of the stack buffers `receive_raw` decodes into, including
+/// up to 10 bytes of length prefix. Messages that do not fit fall back to a heap buffer. Lower it
+/// on constrained hosts, raise it for screen streaming, see
+/// [`SerialRpcTransport::with_stack_limit`].
 #[derive(Debug)]
-pub struct SerialRpcTransport {
+pub struct SerialRpcTransport<const STACK_LIMIT: usize = DEFAULT_STACK_LIMIT> {
     command_index: u32,
     port: Box<dyn SerialPort>,
+    /// Reused encode buffer for send_raw
+    scratch: Vec<u8>,
+    /// Bytes read ahead of the next message, by try_receive_raw or past the end of a message
+    rx: BytesMut,
+    /// Set once the CLI banner showed up in place of a message. The RPC session is gone, so every
+    /// call fails from then on.
+    rebooted: bool,
+    /// The CLI prompt, used when leaving the session and to spot a reboot
+    prompt: String,
+    /// The banner printed before the CLI prompt, if it was read
+    banner: Option<CliBanner>,
+    /// Receive loop counters
+    stats: ReceiveStats,
+    /// Frames the buffered receive path and enforces the longest message body accepted
+    codec: FrameCodec,
+    /// How long to wait for the device. The port timeout is `timeouts.read`.
+    timeouts: Timeouts,
+    /// Host-side lock on the port, released on drop
+    lock: Option<PortLock>,
 }
 
-/// Adds a command_index getter/setter. Useful since Transports dont automatically track command
-/// index, and these functions can directly interop with the Transport's governing RPC channel.
-pub trait CommandIndex {
-    /// Changes the command index and returns the new value
-    fn increment_command_index(&mut self, by: u32) -> u32;
+/// Default receive stack buffer size: a 10 byte length prefix plus 128 bytes of message. Use
+/// [`SerialRpcTransport::with_stack_limit`] for larger messages; the deprecated
+/// `transport-serial-optimized-large-stack-limit` feature no longer changes it.
+pub const DEFAULT_STACK_LIMIT: usize = 10 + 128;
 
-    /// Gets the current command index
-    fn command_index(&mut self) -> u32;
-}
+/// Default longest message body accepted, the same as [`DEFAULT_MAX_FRAME_LEN`]
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = DEFAULT_MAX_FRAME_LEN;
 
-impl CommandIndex for SerialRpcTransport {
+impl<const STACK_LIMIT: usize> CommandIndex for SerialRpcTransport<STACK_LIMIT> {
     fn increment_command_index(&mut self, by: u32) -> u32 {
-        self.command_index += by;
+        self.command_index = next_command_id(self.command_index, by);
 
         self.command_index
     }
@@ -90,6 +121,8 @@ impl SerialRpcTransport {
     /// Returns an error if the port cannot be opened, initialization commands fail, or
     /// the RPC banner prompt is not received.
     ///
+    /// Use [`SerialBuilder`] for firmwares with a different prompt.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -104,56 +137,195 @@ impl SerialRpcTransport {
     /// ```
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     pub fn new<S: AsRef<str> + std::fmt::Debug>(port: S) -> Result<Self> {
-        let mut port = serialport::new(port.as_ref(), FLIPPER_BAUD)
-            .timeout(TIMEOUT)
-            .open()?;
-
-        trace!("draining(prompt)");
-        drain_until_str(&mut port, ">: ", TIMEOUT)?;
-
-        trace!("start_rpc_session");
-        port.write_all("start_rpc_session\r".as_bytes())?;
-        port.flush()?;
-
-        trace!("draining(start_rpc_session, \\n)");
-        drain_until(&mut port, b'\n', TIMEOUT)?;
-
-        Ok(Self {
-            command_index: 0,
-            port,
-        })
+        SerialBuilder::new(port.as_ref()).open_rpc()
     }
 
     /// Wraps a SerialPort with a SerialRpcTransport
     /// WARN: Does not reconfigure the port, just passes it into the internal holder, you must make
     /// sure that the port is in an RPC session. To convert a SerialCliTransport into
     /// a SerialRpcTransport, use SerialCliTransport::into_rpc(self) instead.
+    ///
+    /// The port keeps its own timeout as [`Timeouts::read`], the other timeouts are the
+    /// defaults.
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     pub fn from_port(port: Box<dyn SerialPort>) -> Result<Self> {
-        Ok(Self {
-            command_index: 0,
+        let timeouts = Timeouts {
+            read: port.timeout(),
+            ..Timeouts::default()
+        };
+
+        Ok(Self::from_parts(
+            port,
+            DEFAULT_PROMPT.to_string(),
+            None,
+            timeouts,
+        ))
+    }
+
+    /// Wraps a SerialPort in an RPC session, remembering the CLI it was started from
+    pub(crate) fn from_parts(
+        port: Box<dyn SerialPort>,
+        prompt: String,
+        banner: Option<CliBanner>,
+        timeouts: Timeouts,
+    ) -> Self {
+        trace!("rpc session started");
+
+        Self {
+            command_index: FIRST_COMMAND_ID,
             port,
-        })
+            scratch: Vec::new(),
+            rx: BytesMut::new(),
+            rebooted: false,
+            prompt,
+            banner,
+            stats: ReceiveStats::default(),
+            codec: FrameCodec::with_max_len(DEFAULT_MAX_MESSAGE_LEN),
+            timeouts,
+            lock: None,
+        }
     }
 }
 
-impl proto::Main {
-    /// Sets the command id in a proto
-    pub fn with_command_id(mut self, command_id: u32) -> Self {
-        self.command_id = command_id;
+impl<const STACK_LIMIT: usize> SerialRpcTransport<STACK_LIMIT> {
+    /// The CLI prompt this session returns to when it leaves RPC
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
 
+    /// Firmware details from the banner printed when the CLI opened. `None` if the port was
+    /// already past the banner or the transport was made with [`SerialRpcTransport::from_port`].
+    pub fn banner(&self) -> Option<&CliBanner> {
+        self.banner.as_ref()
+    }
+
+    /// Counters of the receive loop, for tuning `STACK_LIMIT`, see [`ReceiveStats`]
+    pub fn stats(&self) -> ReceiveStats {
+        self.stats
+    }
+
+    /// Zeroes the counters returned by [`SerialRpcTransport::stats`]
+    pub fn reset_stats(&mut self) {
+        self.stats = ReceiveStats::default();
+    }
+
+    /// Sets the longest message body `receive_raw` accepts, [`DEFAULT_MAX_MESSAGE_LEN`] by
+    /// default. Longer length prefixes fail with [`Error::FrameTooLarge`] before anything is
+    /// allocated for them.
+    pub fn with_max_message_len(mut self, max_message_len: usize) -> Self {
+        self.codec = FrameCodec::with_max_len(max_message_len);
         self
     }
 
-    /// Sets the has_next flag in a proto
-    pub fn with_has_next(mut self, has_next: bool) -> Self {
-        self.has_next = has_next;
+    /// The longest message body `receive_raw` accepts
+    pub fn max_message_len(&self) -> usize {
+        self.codec.max_len()
+    }
 
+    /// Holds `lock` until the transport is dropped
+    pub(crate) fn with_lock(mut self, lock: Option<PortLock>) -> Self {
+        self.lock = lock;
         self
     }
+
+    /// The host-side lock on the port, if [`SerialBuilder::lock_port`] took one
+    pub fn port_lock(&self) -> Option<&PortLock> {
+        self.lock.as_ref()
+    }
+
+    /// How long the transport waits for the device. [`Timeouts::operation`] reflects the last
+    /// [`TransportRaw::set_timeout`].
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Changes the receive stack buffer size, see [Stack limit](SerialRpcTransport#stack-limit).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use flipper_rpc::{error::Result, transport::serial::rpc::SerialRpcTransport};
+    ///
+    /// # fn main() -> Result<()> {
+    /// // Room for a full 1 KiB screen frame on the stack
+    /// let rpc = SerialRpcTransport::new("/dev/ttyACM0")?.with_stack_limit::<{ 10 + 1100 }>();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_stack_limit<const NEW_LIMIT: usize>(self) -> SerialRpcTransport<NEW_LIMIT> {
+        SerialRpcTransport {
+            command_index: self.command_index,
+            port: self.port,
+            scratch: self.scratch,
+            rx: self.rx,
+            rebooted: self.rebooted,
+            prompt: self.prompt,
+            banner: self.banner,
+            stats: self.stats,
+            codec: self.codec,
+            timeouts: self.timeouts,
+            lock: self.lock,
+        }
+    }
+
+    /// Temporarily leaves the RPC session and runs `f` against the text CLI on the same port,
+    /// then starts a new RPC session.
+    ///
+    /// Used by the `cli-fallback` compat layer for commands that old firmwares only expose
+    /// through the CLI, by `system::log_stream` to read logs on the RPC connection, and to reach
+    /// CLI-only commands such as `cli::subghz::tx`, `cli::infrared::tx` and `cli::apps::list_installed`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the session cannot be stopped or restarted, or whatever `f` returns.
+    #[cfg(any(
+        feature = "cli-fallback",
+        feature = "system-log",
+        feature = "subghz",
+        feature = "infrared",
+        feature = "apps",
+        feature = "cli-info"
+    ))]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(f)))]
+    pub fn with_cli<R>(
+        &mut self,
+        f: impl FnOnce(&mut super::cli::SerialCliTransport) -> Result<R>,
+    ) -> Result<R> {
+        use crate::rpc::req::Request;
+        use crate::transport::Transport;
+        use crate::transport::serial::helpers::{drain_until, drain_until_str};
+
+        trace!("stop_session");
+        self.send(Request::StopSession)?;
+
+        // Ask for a fresh prompt, this also skips the StopSession response
+        self.port.write_all(b"\r")?;
+        self.port.flush()?;
+        drain_until_str(&mut self.port, &self.prompt, self.timeouts.handshake)?;
+
+        let mut cli = super::cli::SerialCliTransport::from_parts(
+            self.port.try_clone()?,
+            self.prompt.clone(),
+            self.banner.clone(),
+            self.timeouts,
+        );
+        let result = f(&mut cli);
+
+        if result.is_err() {
+            // The failed command may still be printing, wait for it to hand back the prompt
+            let _ = drain_until_str(&mut self.port, &self.prompt, self.timeouts.handshake);
+        }
+
+        trace!("start_rpc_session");
+        self.port.write_all(b"start_rpc_session\r")?;
+        self.port.flush()?;
+        drain_until(&mut self.port, b'\n', self.timeouts.handshake)?;
+
+        result
+    }
 }
 
-impl TransportRaw<proto::Main> for SerialRpcTransport {
+impl<const STACK_LIMIT: usize> TransportRaw<proto::Main> for SerialRpcTransport<STACK_LIMIT> {
     type Err = Error;
 
     /// Sends a length-delimited Protobuf RPC message to the Flipper.
@@ -203,21 +375,71 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
     /// ```
     #[cfg_attr(feature = "tracing", tracing::instrument)]
     fn send_raw(&mut self, value: proto::Main) -> std::result::Result<(), Self::Err> {
-        let encoded = value.encode_length_delimited_to_vec();
-        self.port.write_all(&encoded)?;
+        self.ensure_session()?;
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let result = self.send_raw_buf(value, &mut scratch);
+        self.scratch = scratch;
+
+        result
+    }
+
+    /// Encodes into `buf` with [`crate::proto_ext::encode_into`] and writes it to the port
+    fn send_raw_buf(
+        &mut self,
+        value: proto::Main,
+        buf: &mut Vec<u8>,
+    ) -> std::result::Result<(), Self::Err> {
+        encode_into(&value, buf)?;
+        self.port.write_all(buf)?;
 
         self.port.flush()?;
 
         Ok(())
     }
 
+    /// Changes how long to wait for the device to start answering, [`Timeouts::operation`].
+    /// The per-read timeout of the port stays as it is.
+    fn set_timeout(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> std::result::Result<Option<std::time::Duration>, Self::Err> {
+        let previous = std::mem::replace(&mut self.timeouts.operation, timeout);
+
+        Ok(Some(previous))
+    }
+
+    /// Reads whatever bytes are pending on the port without waiting, and decodes a message if
+    /// one is complete. Partial messages are kept and finished by later calls, including calls
+    /// to [`TransportRaw::receive_raw`].
+    fn try_receive_raw(&mut self) -> std::result::Result<Option<proto::Main>, Self::Err> {
+        self.try_receive_raw_unchecked()?
+            .map(check_status)
+            .transpose()
+    }
+
+    /// Like [`TransportRaw::try_receive_raw`], but keeps failed answers as messages
+    fn try_receive_raw_unchecked(&mut self) -> std::result::Result<Option<proto::Main>, Self::Err> {
+        self.ensure_session()?;
+
+        let available = self.port.bytes_to_read()? as usize;
+
+        if available > 0 {
+            let start = self.rx.len();
+            self.rx.resize(start + available, 0);
+
+            let read = self.port.read(&mut self.rx[start..])?;
+            self.rx.truncate(start + read);
+        }
+
+        let frame = self.codec.decode(&mut self.rx);
+        self.check_rebooted(frame)
+    }
+
     /// Reads a length-delimited Protobuf RPC message from the flipper. This must be called
     /// directly after data is sent, and cannot be called after a message is sent before (will
     /// panic)
     ///
-    /// Uses a two-shot method of reading: first to get varint length + partial data, then to
-    /// fetch remaining bytes if the message exceeds the initial buffer.
-    ///
     /// # Errors
     ///
     /// Returns an error if no data is received, decoding fails, or IO operations fail.
@@ -225,6 +447,9 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
     /// Additionally, this command returns an error if the underlying RPC command fails with
     /// a non-CommandStatus::Ok status code. It will be auto-converted into an Error
     ///
+    /// Returns [`Error::DeviceRebooted`] if the CLI banner shows up instead of a message, and on
+    /// every call after that.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -239,10 +464,31 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
     /// # }
     /// ```
     #[cfg_attr(feature = "tracing", tracing::instrument)]
-    #[cfg(feature = "transport-serial-optimized")]
     fn receive_raw(&mut self) -> std::result::Result<proto::Main, Self::Err> {
+        check_status(self.receive_raw_unchecked()?)
+    }
+
+    /// Like [`TransportRaw::receive_raw`], but keeps failed answers as messages
+    fn receive_raw_unchecked(&mut self) -> std::result::Result<proto::Main, Self::Err> {
+        self.ensure_session()?;
+
+        let main = self.read_frame();
+        self.check_rebooted(main)
+    }
+}
+
+impl<const STACK_LIMIT: usize> SerialRpcTransport<STACK_LIMIT> {
+    /// Reads one message, without checking its status. Uses a two-shot method of reading: first
+    /// to get varint length + partial data, then to fetch remaining bytes if the message exceeds
+    /// the initial buffer.
+    #[cfg(feature = "transport-serial-optimized")]
+    fn read_frame(&mut self) -> Result<proto::Main> {
         use prost::bytes::Buf;
 
+        if !self.rx.is_empty() {
+            return self.receive_buffered();
+        }
+
         self.port.flush()?;
 
         // INFO: Super-overcomplicated but fast and efficent way of reading any length varint + data in exactly two
@@ -250,12 +496,13 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
         // Tries to use a stack-based approach when possible and does it efficently
 
         // Hard limit for all stack-based buffers
-        // NOTE: Adding 10 as Varint max length is 10
-
-        #[cfg(feature = "transport-serial-optimized-large-stack-limit")]
-        const STACK_LIMIT: usize = 10 + 512;
-        #[cfg(not(feature = "transport-serial-optimized-large-stack-limit"))]
-        const STACK_LIMIT: usize = 10 + 128;
+        // NOTE: Must fit the 10 byte max varint length
+        const {
+            assert!(
+                STACK_LIMIT >= 10,
+                "STACK_LIMIT must fit a 10 byte length prefix"
+            )
+        };
 
         let mut buf = [0u8; STACK_LIMIT];
 
@@ -275,14 +522,27 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
 
         let mut available_bytes = buf.len();
 
+        let mut reads = 0;
+
+        // The device may take a while to start answering, the port timeout only bounds a
+        // single read
+        let deadline = Instant::now() + self.timeouts.operation;
+
         trace!("reading varint");
         while read < available_bytes {
+            reads += 1;
             match self.port.read(&mut buf[read..]) {
                 Ok(0) => break, // No more data
                 Ok(n) => {
                     available_bytes = self.port.bytes_to_read()? as usize;
                     read += n
                 }
+                // Still waiting for the answer to start
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut && read == 0 => {
+                    if Instant::now() >= deadline {
+                        return Err(no_answer(self.timeouts.operation));
+                    }
+                }
                 Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => break,
                 Err(e) => return Err(e.into()),
             }
@@ -296,15 +556,12 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
             .into());
         }
 
-        let total_data_length = prost::decode_length_delimiter(&buf[..read])?;
-        trace!(total_data_length, "decoded response length");
-
         // We have the length of the data, however some or all of the actual data is inside of buf,
         // after the varint, it just continues to RPC data.
-
-        // How many bytes does the varint take up?
-        let varint_length = prost::length_delimiter_len(total_data_length);
-        trace!(varint_length, "varint length");
+        let Some((varint_length, total_data_length)) = self.codec.frame_len(&buf[..read])? else {
+            return Err(Error::InvalidFrame("length prefix cut short"));
+        };
+        trace!(total_data_length, varint_length, "decoded response length");
 
         // PERF: All the data that is not varint data, this is another main optimization,
         // we skip another read, as we have already read the data.
@@ -326,7 +583,15 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
             // trailing zeros if we read less than the buf's size
 
             trace!("L3 decode");
-            proto::Main::decode(partial_data)?
+            let main = proto::Main::decode(&partial_data[..total_data_length])?;
+
+            // The start of the next message, received from `rx` next time
+            let over_read = &partial_data[total_data_length..];
+            self.rx.extend_from_slice(over_read);
+            self.stats
+                .record(DecodePath::L3, reads, total_data_length, over_read.len());
+
+            main
         } else {
             // WARN: Data did NOT fit inside of the buffer, this means that some of the data is
             // missing from the buffer
@@ -355,34 +620,39 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
                 self.port.read_exact(&mut stack_buf[..remaining_length])?;
 
                 let chained = partial_data.chain(&stack_buf[..remaining_length]);
+                let main = proto::Main::decode(chained)?;
 
-                proto::Main::decode(chained)?
-            } else {
-                use crate::logging::warn;
+                self.stats
+                    .record(DecodePath::L2, reads + 1, total_data_length, 0);
 
+                main
+            } else {
                 trace!(
                     "L1 decode - WARN: Increase STACK_LIMIT, current: {STACK_LIMIT}, need: {remaining_length}"
                 );
-                #[cfg(feature = "transport-serial-optimized-large-stack-limit")]
-                warn!(remaining_length, "extremely large response");
-                #[cfg(not(feature = "transport-serial-optimized-large-stack-limit"))]
                 warn!(
                     remaining_length,
-                    "large response; consider enabling the 'transport-serial-optimized-large-stack-limit' feature"
+                    "large response; consider raising the limit with SerialRpcTransport::with_stack_limit"
                 );
 
-                // Uses a slower heap (vec) based decoding for larger messages.
-                let mut remaining_data = vec![0u8; remaining_length];
-                self.port.read_exact(&mut remaining_data)?;
+                // Uses a slower heap (vec) based decoding for larger messages. The whole message
+                // goes into one buffer, so decoding slices the payload (file data, screen frames)
+                // out of it instead of copying it.
+                let mut message = Vec::with_capacity(total_data_length);
+                message.extend_from_slice(partial_data);
+                message.resize(total_data_length, 0);
+                self.port.read_exact(&mut message[partial_data.len()..])?;
+
+                let main = proto::Main::decode(Bytes::from(message))?;
 
-                let chained = partial_data.chain(remaining_data.as_slice());
+                self.stats
+                    .record(DecodePath::L1, reads + 1, total_data_length, 0);
 
-                proto::Main::decode(chained)?
+                main
             }
         };
 
-        // Should be a valid command status
-        decode_command_status(main.command_status)?.into_result(main)
+        Ok(main)
     }
 
     /// Reads a length-delimited Protobuf RPC message from the flipper. This must be called
@@ -408,22 +678,34 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
     ///
     /// Included for compatablity in case the improved function breaks, the user can fallback to
     /// this while they wait for their issue to be resolved through gh
+    ///
+    /// Deprecated since 0.4.0: enable `transport-serial-optimized` instead. Only use this when the
+    /// optimized method is broken, and please open an issue if it is.
     #[cfg(not(feature = "transport-serial-optimized"))]
-    #[cfg_attr(feature = "tracing", tracing::instrument)]
-    #[deprecated(
-        note = "Use the serial-optimized-varint-reading instead. This function is very slow. Only use when optimized method is broken. Please submit a PR/Issue to GH if it is broken.",
-        since = "0.4.0"
-    )]
-    fn receive_raw(&mut self) -> std::result::Result<proto::Main, Self::Err> {
-        use crate::proto::CommandStatus;
+    fn read_frame(&mut self) -> Result<proto::Main> {
+        if !self.rx.is_empty() {
+            return self.receive_buffered();
+        }
 
         self.port.flush()?;
 
         let mut buf = [0u8; 10];
         let mut index = 0;
 
+        let deadline = Instant::now() + self.timeouts.operation;
+
         while index < 10 {
-            self.port.read_exact(&mut buf[index..=index])?;
+            match self.port.read_exact(&mut buf[index..=index]) {
+                Ok(()) => {}
+                // Still waiting for the answer to start
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut && index == 0 => {
+                    if Instant::now() >= deadline {
+                        return Err(no_answer(self.timeouts.operation));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
 
             if buf[index] & 0x80 == 0 {
                 break;
@@ -432,17 +714,165 @@ impl TransportRaw<proto::Main> for SerialRpcTransport {
             index += 1;
         }
 
-        let len = prost::decode_length_delimiter(buf.as_slice())?;
+        let Some((_, len)) = self.codec.frame_len(&buf[..=index.min(9)])? else {
+            return Err(Error::InvalidFrame("length prefix cut short"));
+        };
         let mut msg_buf = vec![0u8; len];
         self.port.read_exact(&mut msg_buf)?;
 
-        let main = proto::Main::decode(msg_buf.as_slice())?;
+        let main = proto::Main::decode(Bytes::from(msg_buf))?;
+
+        // One read per varint byte, then the body on the heap
+        self.stats.record(DecodePath::L1, index as u32 + 2, len, 0);
 
-        // Should be a valid command status
-        decode_command_status(main.command_status)?.into_result(main)
+        Ok(main)
+    }
+
+    /// Fails with [`Error::DeviceRebooted`] once the session is gone
+    fn ensure_session(&self) -> Result<()> {
+        if self.rebooted {
+            return Err(Error::DeviceRebooted);
+        }
+
+        Ok(())
+    }
+
+    /// Turns a decode error into [`Error::DeviceRebooted`] if it was caused by the CLI banner.
+    ///
+    /// After a reboot, e.g. from a firmware assert, the device greets with the CLI banner again
+    /// and the text fails to decode as a message. The rest of the banner is still arriving, so
+    /// it is read and searched for the banner text.
+    fn check_rebooted<M>(&mut self, result: Result<M>) -> Result<M> {
+        if !matches!(
+            result,
+            Err(Error::ProtoDecode(_) | Error::InvalidFrame(_) | Error::FrameTooLarge(_))
+        ) {
+            return result;
+        }
+
+        let mut seen = std::mem::take(&mut self.rx);
+        let previous = self.port.timeout();
+        let _ = self.port.set_timeout(BANNER_TIMEOUT);
+
+        let deadline = Instant::now() + BANNER_TIMEOUT;
+        let mut buf = [0u8; 256];
+        while !contains_cli_banner(&seen, &self.prompt) && Instant::now() < deadline {
+            match self.port.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => seen.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        let _ = self.port.set_timeout(previous);
+
+        if contains_cli_banner(&seen, &self.prompt) {
+            warn!("cli banner in the rpc stream, the device rebooted");
+            self.rebooted = true;
+
+            return Err(Error::DeviceRebooted);
+        }
+
+        result
+    }
+
+    /// Finishes a message that try_receive_raw started buffering, blocking for the rest
+    fn receive_buffered(&mut self) -> Result<proto::Main> {
+        let mut reads = 0;
+
+        loop {
+            let len = self
+                .codec
+                .frame_len(&self.rx)?
+                .map(|(_, body_len)| body_len);
+            if let Some(main) = self.codec.decode(&mut self.rx)? {
+                self.stats
+                    .record(DecodePath::Buffered, reads, len.unwrap_or_default(), 0);
+
+                return Ok(main);
+            }
+
+            let needed = match self.codec.frame_len(&self.rx)? {
+                Some((header_len, body_len)) => header_len + body_len,
+                None => self.rx.len() + 1,
+            };
+
+            let start = self.rx.len();
+            self.rx.resize(needed, 0);
+            self.port.read_exact(&mut self.rx[start..])?;
+            reads += 1;
+        }
     }
 }
 
-fn decode_command_status(raw: i32) -> Result<CommandStatus> {
-    CommandStatus::try_from(raw).map_err(|_| Error::InvalidCommandStatus(raw))
+/// The error for a device that did not start answering within `timeout`
+fn no_answer(timeout: Duration) -> Error {
+    std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!("no answer within the {timeout:?} operation timeout"),
+    )
+    .into()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::io::Write;
+
+    #[test]
+    fn waits_for_the_operation_timeout_not_a_single_read() {
+        let (mut device, mut host) = TTYPort::pair().unwrap();
+        host.set_timeout(Duration::from_millis(20)).unwrap();
+
+        let mut rpc = SerialRpcTransport::from_port(Box::new(host)).unwrap();
+        assert_eq!(rpc.timeouts().read, Duration::from_millis(20));
+        rpc.set_timeout(Duration::from_millis(100)).unwrap();
+
+        let started = Instant::now();
+        let error = rpc.receive_raw().unwrap_err();
+        assert!(
+            matches!(&error, Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut),
+            "{error}"
+        );
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // An answer that starts after many read timeouts still arrives
+        rpc.set_timeout(Duration::from_secs(5)).unwrap();
+        let ping = proto::Main {
+            command_id: 1,
+            ..Default::default()
+        };
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            device
+                .write_all(&ping.encode_length_delimited_to_vec())
+                .unwrap();
+            device
+        });
+
+        assert_eq!(rpc.receive_raw().unwrap().command_id, 1);
+        drop(writer.join().unwrap());
+    }
+
+    #[test]
+    fn keeps_messages_read_past_the_first_one() {
+        let (mut device, host) = TTYPort::pair().unwrap();
+        let mut rpc = SerialRpcTransport::from_port(Box::new(host)).unwrap();
+
+        let answers = [1, 2].map(|command_id| proto::Main {
+            command_id,
+            ..Default::default()
+        });
+        device
+            .write_all(
+                &answers
+                    .map(|main| main.encode_length_delimited_to_vec())
+                    .concat(),
+            )
+            .unwrap();
+
+        assert_eq!(rpc.receive_raw().unwrap().command_id, 1);
+        assert_eq!(rpc.receive_raw().unwrap().command_id, 2);
+        assert_eq!(rpc.stats().l3_decodes, 1);
+    }
 }
@@ -0,0 +1,119 @@
+//! Receive loop counters of [`SerialRpcTransport`](super::rpc::SerialRpcTransport)
+//!
+//! The optimized receive path decodes a message in one of three ways, from the cheapest to the
+//! most expensive:
+//!
+//! - **L3**: the whole message arrived with the first read, into the `STACK_LIMIT` buffer
+//! - **L2**: the rest fit into a second `STACK_LIMIT` stack buffer
+//! - **L1**: the rest did not fit and was read into the heap
+//!
+//! Without `transport-serial-optimized` every message is read into the heap and counts as L1.
+//!
+//! Many L1 decodes mean `STACK_LIMIT` is too small for the traffic, see
+//! [`ReceiveStats::max_message_len`]. Over-read bytes were read past the end of a message in
+//! the first read and are kept as the start of the next one, a sign that messages arrive back to
+//! back faster than they are received.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::{error::Result, rpc::req::Request, transport::serial::rpc::SerialRpcTransport};
+//! use flipper_rpc::transport::Transport;
+//!
+//! # fn main() -> Result<()> {
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! rpc.send_and_receive(Request::Ping(vec![0; 256]))?;
+//!
+//! let stats = rpc.stats();
+//! println!("{:.1} reads per message, {} heap decodes", stats.reads_per_message(), stats.l1_decodes);
+//! # Ok(())
+//! # }
+//! ```
+
+/// How a message was decoded, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DecodePath {
+    L1,
+    // Only the optimized receive path uses stack buffers
+    #[cfg_attr(not(feature = "transport-serial-optimized"), allow(dead_code))]
+    L2,
+    #[cfg_attr(not(feature = "transport-serial-optimized"), allow(dead_code))]
+    L3,
+    /// Finished from bytes `try_receive_raw` had already buffered
+    Buffered,
+}
+
+/// Counters of messages read with `receive_raw`, since the transport was opened or the last
+/// [`SerialRpcTransport::reset_stats`](super::rpc::SerialRpcTransport::reset_stats)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiveStats {
+    /// Messages received
+    pub messages: u64,
+    /// Reads from the port, across all messages
+    pub reads: u64,
+    /// Most reads a single message took
+    pub max_reads: u32,
+    /// Bytes read past the end of a message by the first read, kept for the next message
+    pub over_read: u64,
+    /// Messages decoded from the first read
+    pub l3_decodes: u64,
+    /// Messages finished with a second stack buffer
+    pub l2_decodes: u64,
+    /// Messages finished on the heap because they were larger than `STACK_LIMIT`
+    pub l1_decodes: u64,
+    /// Messages finished from bytes `try_receive_raw` had buffered
+    pub buffered_decodes: u64,
+    /// Length of the largest message, without its length prefix
+    pub max_message_len: usize,
+}
+
+impl ReceiveStats {
+    /// Average number of reads per message, 0 before the first message
+    pub fn reads_per_message(&self) -> f64 {
+        if self.messages == 0 {
+            return 0.0;
+        }
+
+        self.reads as f64 / self.messages as f64
+    }
+
+    /// Counts one received message
+    pub(crate) fn record(&mut self, path: DecodePath, reads: u32, len: usize, over_read: usize) {
+        self.messages += 1;
+        self.reads += u64::from(reads);
+        self.max_reads = self.max_reads.max(reads);
+        self.over_read += over_read as u64;
+        self.max_message_len = self.max_message_len.max(len);
+
+        match path {
+            DecodePath::L1 => self.l1_decodes += 1,
+            DecodePath::L2 => self.l2_decodes += 1,
+            DecodePath::L3 => self.l3_decodes += 1,
+            DecodePath::Buffered => self.buffered_decodes += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_messages() {
+        let mut stats = ReceiveStats::default();
+        assert_eq!(stats.reads_per_message(), 0.0);
+
+        stats.record(DecodePath::L3, 1, 20, 4);
+        stats.record(DecodePath::L1, 3, 2048, 0);
+
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.reads_per_message(), 2.0);
+        assert_eq!(stats.max_reads, 3);
+        assert_eq!(stats.over_read, 4);
+        assert_eq!(
+            (stats.l1_decodes, stats.l2_decodes, stats.l3_decodes),
+            (1, 0, 1)
+        );
+        assert_eq!(stats.max_message_len, 2048);
+    }
+}
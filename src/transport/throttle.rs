@@ -0,0 +1,236 @@
+//! Outgoing message throttling
+//!
+//! Some links (flaky USB hubs, BLE bridges) overrun the firmware's receive buffer when chunks are
+//! sent back-to-back. [`Throttled`] wraps any raw transport and spaces out sends according to
+//! a [`Throttle`] policy. Sends are spaced start to start: the time a send itself takes counts
+//! towards the interval before the next one.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//! use flipper_rpc::transport::throttle::{Throttle, Throttled};
+//!
+//! # fn main() -> Result<()> {
+//! let rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! let mut rpc = Throttled::new(
+//!     rpc,
+//!     Throttle::default()
+//!         .max_messages_per_second(200)
+//!         .inter_message_delay(Duration::from_millis(2)),
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::logging::trace;
+use crate::transport::TransportRaw;
+
+/// Throttling policy. The default does not throttle at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throttle {
+    /// Upper bound on messages sent per second, if any
+    pub max_messages_per_second: Option<u32>,
+    /// Minimum interval between the starts of two sent messages
+    pub inter_message_delay: Duration,
+}
+
+impl Throttle {
+    /// Limits the number of messages sent per second. Zero disables the limit.
+    pub fn max_messages_per_second(mut self, rate: u32) -> Self {
+        self.max_messages_per_second = (rate != 0).then_some(rate);
+
+        self
+    }
+
+    /// Sets the minimum interval between the starts of two sent messages
+    pub fn inter_message_delay(mut self, delay: Duration) -> Self {
+        self.inter_message_delay = delay;
+
+        self
+    }
+
+    /// The minimum time between the start of two sends under this policy
+    pub fn min_interval(&self) -> Duration {
+        let rate_interval = self
+            .max_messages_per_second
+            .map_or(Duration::ZERO, |rate| Duration::from_secs(1) / rate);
+
+        rate_interval.max(self.inter_message_delay)
+    }
+}
+
+/// A transport decorator that applies a [`Throttle`] to every sent message
+#[derive(Debug)]
+pub struct Throttled<T> {
+    inner: T,
+    throttle: Throttle,
+    last_send: Option<Instant>,
+}
+
+impl<T> Throttled<T> {
+    /// Wraps `inner` with the given throttle
+    pub fn new(inner: T, throttle: Throttle) -> Self {
+        Self {
+            inner,
+            throttle,
+            last_send: None,
+        }
+    }
+
+    /// Returns the active throttle
+    pub fn throttle(&self) -> Throttle {
+        self.throttle
+    }
+
+    /// Replaces the active throttle
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    /// Returns a reference to the wrapped transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped transport
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the inner transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Blocks until the next send is allowed
+    fn wait(&mut self) {
+        let interval = self.throttle.min_interval();
+
+        if let Some(last_send) = self.last_send {
+            let elapsed = last_send.elapsed();
+
+            if elapsed < interval {
+                trace!(?interval, "throttling send");
+                std::thread::sleep(interval - elapsed);
+            }
+        }
+
+        self.last_send = Some(Instant::now());
+    }
+}
+
+impl<T, S, R> TransportRaw<S, R> for Throttled<T>
+where
+    T: TransportRaw<S, R>,
+{
+    type Err = T::Err;
+
+    fn send_raw(&mut self, value: S) -> Result<(), Self::Err> {
+        self.wait();
+
+        self.inner.send_raw(value)
+    }
+
+    fn send_raw_buf(&mut self, value: S, buf: &mut Vec<u8>) -> Result<(), Self::Err> {
+        self.wait();
+
+        self.inner.send_raw_buf(value, buf)
+    }
+
+    fn receive_raw(&mut self) -> Result<R, Self::Err> {
+        self.inner.receive_raw()
+    }
+
+    fn receive_raw_unchecked(&mut self) -> Result<R, Self::Err> {
+        self.inner.receive_raw_unchecked()
+    }
+
+    fn try_receive_raw(&mut self) -> Result<Option<R>, Self::Err> {
+        self.inner.try_receive_raw()
+    }
+
+    fn try_receive_raw_unchecked(&mut self) -> Result<Option<R>, Self::Err> {
+        self.inner.try_receive_raw_unchecked()
+    }
+
+    fn take_abort(&mut self) -> bool {
+        self.inner.take_abort()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<Option<Duration>, Self::Err> {
+        self.inner.set_timeout(timeout)
+    }
+
+    #[cfg(feature = "easy-rpc")]
+    fn decode_mode(&self) -> crate::rpc::res::DecodeMode {
+        self.inner.decode_mode()
+    }
+}
+
+impl<T> crate::transport::CommandIndex for Throttled<T>
+where
+    T: crate::transport::CommandIndex,
+{
+    fn increment_command_index(&mut self, by: u32) -> u32 {
+        self.inner.increment_command_index(by)
+    }
+
+    fn command_index(&mut self) -> u32 {
+        self.inner.command_index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Sink(Vec<u8>);
+
+    impl TransportRaw<u8> for Sink {
+        type Err = std::io::Error;
+
+        fn send_raw(&mut self, value: u8) -> Result<(), Self::Err> {
+            self.0.push(value);
+            Ok(())
+        }
+
+        fn receive_raw(&mut self) -> Result<u8, Self::Err> {
+            self.0
+                .pop()
+                .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    #[test]
+    fn min_interval_uses_the_stricter_limit() {
+        let throttle = Throttle::default()
+            .max_messages_per_second(100)
+            .inter_message_delay(Duration::from_millis(2));
+
+        assert_eq!(throttle.min_interval(), Duration::from_millis(10));
+        assert_eq!(Throttle::default().min_interval(), Duration::ZERO);
+    }
+
+    #[test]
+    fn spaces_out_sends() {
+        let mut sink = Throttled::new(
+            Sink::default(),
+            Throttle::default().inter_message_delay(Duration::from_millis(20)),
+        );
+
+        let start = Instant::now();
+        for i in 0..3 {
+            sink.send_raw(i).unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(sink.get_ref().0, [0, 1, 2]);
+    }
+}
@@ -0,0 +1,598 @@
+//! Firmware update packages
+//!
+//! An update package is a directory holding an `update.fuf` manifest and the files it names:
+//! the updater stage that flashes the device (`Loader`), the firmware DFU image (`Firmware`),
+//! and optionally the resources archive, the radio stack and a splash screen. The device only
+//! checks it after the upload, reporting problems as a bare [`UpdateResultCode`].
+//!
+//! [`Bundle`] runs the same checks on the host first, so a broken package fails with a
+//! [`BundleError`] that names the problem before anything is uploaded.
+//!
+//! [`install_resources`] covers the other half of a manual update: it installs a resources
+//! archive, the assets the firmware loads from the SD card, without going through the updater.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use flipper_rpc::error::Result;
+//! use flipper_rpc::transport::serial::rpc::SerialRpcTransport;
+//! use flipper_rpc::update::Bundle;
+//!
+//! # fn main() -> Result<()> {
+//! let bundle = Bundle::from_directory("dist/f7-update-1.0.1")?;
+//!
+//! let mut rpc = SerialRpcTransport::new("/dev/ttyACM0")?;
+//! bundle.install(&mut rpc)?;
+//! // Reboot into the updater with `Request::SystemReboot(RebootMode::Update)`
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::fs::{
+    FsCreateDir, FsRemove, FsTarExtract, FsWrite, TransferSummary, UPDATE_DIR, WriteOptions,
+};
+use crate::logging::debug;
+use crate::proto::system::{UpdateRequest, UpdateResponse, update_response::UpdateResultCode};
+use crate::settings::FlipperFormat;
+use crate::transport::{CommandIndex, Transport};
+use crate::{
+    error::{Error, Result},
+    proto,
+    rpc::req::Request,
+    transport::TransportRaw,
+};
+
+/// File name of the manifest in a package directory
+pub const MANIFEST_NAME: &str = "update.fuf";
+
+/// `Filetype` header of the manifest
+pub const MANIFEST_FILETYPE: &str = "Flipper firmware upgrade configuration";
+
+/// Oldest manifest version the updater accepts
+pub const MIN_MANIFEST_VERSION: u32 = 2;
+
+/// Hardware target of the Flipper Zero
+pub const TARGET_F7: u32 = 7;
+
+/// Where the resources archive is extracted, its paths are relative to the SD card root
+pub const RESOURCES_ROOT: &str = "/ext";
+
+/// Magic bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A package that is not fit for the device
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum BundleError {
+    #[error("reading {path}: {source}")]
+    /// A file of the package could not be read
+    Read {
+        /// The file
+        path: PathBuf,
+        /// Why
+        source: std::io::Error,
+    },
+
+    #[error("no {MANIFEST_NAME} in {0}")]
+    /// The directory holds no manifest
+    MissingManifest(PathBuf),
+
+    #[error("invalid manifest: {0}")]
+    /// The manifest is not a readable update manifest
+    InvalidManifest(String),
+
+    #[error("manifest version {0} is older than {MIN_MANIFEST_VERSION}")]
+    /// The manifest is too old for the updater
+    OutdatedManifestVersion(u32),
+
+    #[error("package targets hardware {0}, not the Flipper Zero ({TARGET_F7})")]
+    /// The package was built for other hardware
+    TargetMismatch(u32),
+
+    #[error("{key} file {} is missing", path.display())]
+    /// A file named by the manifest does not exist
+    MissingFile {
+        /// The manifest key naming the file
+        key: &'static str,
+        /// Where the file should be
+        path: PathBuf,
+    },
+
+    #[error("updater stage CRC is {actual:08x}, the manifest expects {expected:08x}")]
+    /// The updater stage does not match the CRC in the manifest
+    StageIntegrity {
+        /// CRC32 from the manifest
+        expected: u32,
+        /// CRC32 of the stage file
+        actual: u32,
+    },
+
+    #[error("{} is not a DfuSe image: {reason}", path.display())]
+    /// The firmware image is not a valid DfuSe file
+    InvalidDfu {
+        /// The image
+        path: PathBuf,
+        /// What is wrong with it
+        reason: &'static str,
+    },
+
+    #[error("{} is not the firmware named by its package's manifest", .0.display())]
+    /// [`Bundle::from_dfu`] was given a DFU file its package does not use
+    DfuNotInManifest(PathBuf),
+
+    #[error("{} is not a valid gzip stream: {source}", path.display())]
+    /// The resources archive starts like a `.tgz` but does not decompress
+    InvalidGzip {
+        /// The archive
+        path: PathBuf,
+        /// What went wrong while decompressing it
+        source: std::io::Error,
+    },
+
+    #[error("the device rejected the update: {}", .0.as_str_name())]
+    /// The package was uploaded, but the device's own checks failed
+    Rejected(UpdateResultCode),
+}
+
+/// A validated update package, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    dir: PathBuf,
+    manifest: FlipperFormat,
+    files: Vec<String>,
+}
+
+impl Bundle {
+    /// Validates the package in `dir`
+    pub fn from_directory(dir: impl AsRef<Path>) -> std::result::Result<Self, BundleError> {
+        let dir = dir.as_ref().to_path_buf();
+
+        let manifest_path = dir.join(MANIFEST_NAME);
+        if !manifest_path.is_file() {
+            return Err(BundleError::MissingManifest(dir));
+        }
+
+        let text = std::fs::read_to_string(&manifest_path).map_err(|source| BundleError::Read {
+            path: manifest_path,
+            source,
+        })?;
+        let manifest: FlipperFormat = text
+            .parse()
+            .map_err(|e: Error| BundleError::InvalidManifest(e.to_string()))?;
+
+        if manifest.filetype() != Some(MANIFEST_FILETYPE) {
+            return Err(BundleError::InvalidManifest(format!(
+                "unexpected Filetype {:?}",
+                manifest.filetype().unwrap_or_default()
+            )));
+        }
+
+        let version = manifest
+            .version()
+            .ok_or_else(|| BundleError::InvalidManifest("missing Version".to_string()))?;
+        if version < MIN_MANIFEST_VERSION {
+            return Err(BundleError::OutdatedManifestVersion(version));
+        }
+
+        let target = manifest
+            .get("Target")
+            .and_then(|target| target.parse().ok())
+            .ok_or_else(|| BundleError::InvalidManifest("missing or invalid Target".to_string()))?;
+        if target != TARGET_F7 {
+            return Err(BundleError::TargetMismatch(target));
+        }
+
+        let mut bundle = Self {
+            dir,
+            manifest,
+            files: vec![MANIFEST_NAME.to_string()],
+        };
+
+        let stage = bundle.require("Loader")?;
+        bundle.check_stage(&stage)?;
+
+        if let Some(firmware) = bundle.optional("Firmware")? {
+            check_dfu(&firmware)?;
+        }
+        for key in ["Radio", "Resources", "Splashscreen"] {
+            bundle.optional(key)?;
+        }
+
+        debug!(dir = ?bundle.dir, files = bundle.files.len(), "update package valid");
+
+        Ok(bundle)
+    }
+
+    /// Validates the package a firmware DFU image belongs to: the image itself, and the
+    /// package in its directory, whose manifest must name it
+    pub fn from_dfu(path: impl AsRef<Path>) -> std::result::Result<Self, BundleError> {
+        let path = path.as_ref();
+        check_dfu(path)?;
+
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let bundle = Self::from_directory(dir)?;
+
+        let name = path.file_name().and_then(std::ffi::OsStr::to_str);
+        if bundle.manifest.get("Firmware") != name {
+            return Err(BundleError::DfuNotInManifest(path.to_path_buf()));
+        }
+
+        Ok(bundle)
+    }
+
+    /// The package directory on the host
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The parsed manifest
+    pub fn manifest(&self) -> &FlipperFormat {
+        &self.manifest
+    }
+
+    /// Names of the files the package consists of, the manifest first
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// The directory the package is uploaded to, under [`UPDATE_DIR`]
+    pub fn device_dir(&self) -> String {
+        let name = self
+            .dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "package".to_string());
+
+        format!("{UPDATE_DIR}/{name}")
+    }
+
+    /// Uploads the package's files to [`Bundle::device_dir`]. Returns the manifest's path on the
+    /// device.
+    pub fn upload<T>(&self, transport: &mut T) -> Result<String>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+    {
+        let device_dir = self.device_dir();
+        transport.fs_create_dir(UPDATE_DIR)?;
+        transport.fs_create_dir(&device_dir)?;
+
+        for name in &self.files {
+            let path = self.dir.join(name);
+            let data = std::fs::read(&path)
+                .map_err(|source| Error::from(BundleError::Read { path, source }))?;
+
+            debug!(name, len = data.len(), "uploading update file");
+            transport.fs_write(
+                format!("{device_dir}/{name}"),
+                data,
+                #[cfg(feature = "fs-write-progress-mpsc")]
+                None,
+            )?;
+        }
+
+        Ok(format!("{device_dir}/{MANIFEST_NAME}"))
+    }
+
+    /// Uploads the package and asks the device to prepare the update. Rebooting into update
+    /// mode afterwards starts it.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`BundleError::Rejected`] if the device's checks fail anyway.
+    pub fn install<T>(&self, transport: &mut T) -> Result<()>
+    where
+        T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+    {
+        let update_manifest = self.upload(transport)?;
+
+        let UpdateResponse { code } = transport
+            .send_and_receive(Request::SystemUpdate(UpdateRequest { update_manifest }))?
+            .try_into()?;
+
+        match UpdateResultCode::try_from(code) {
+            Ok(UpdateResultCode::Ok) => Ok(()),
+            Ok(code) => Err(BundleError::Rejected(code).into()),
+            Err(_) => Err(BundleError::Rejected(UpdateResultCode::UnspecifiedError).into()),
+        }
+    }
+
+    /// Resolves the file named by `key`, which must be set
+    fn require(&mut self, key: &'static str) -> std::result::Result<PathBuf, BundleError> {
+        self.optional(key)?
+            .ok_or_else(|| BundleError::InvalidManifest(format!("missing {key}")))
+    }
+
+    /// Resolves the file named by `key`, if it is set, and records it as part of the package
+    fn optional(&mut self, key: &'static str) -> std::result::Result<Option<PathBuf>, BundleError> {
+        let Some(name) = self.manifest.get(key).filter(|name| !name.is_empty()) else {
+            return Ok(None);
+        };
+
+        let path = self.dir.join(name);
+        if !path.is_file() {
+            return Err(BundleError::MissingFile { key, path });
+        }
+        self.files.push(name.to_string());
+
+        Ok(Some(path))
+    }
+
+    fn check_stage(&self, stage: &Path) -> std::result::Result<(), BundleError> {
+        let expected = self
+            .manifest
+            .get("Loader CRC")
+            .and_then(parse_crc)
+            .ok_or_else(|| {
+                BundleError::InvalidManifest("missing or invalid Loader CRC".to_string())
+            })?;
+
+        let data = read(stage)?;
+        // CRC-32 (IEEE), as `zlib.crc32`
+        let actual = crc32fast::hash(&data);
+        if actual != expected {
+            return Err(BundleError::StageIntegrity { expected, actual });
+        }
+
+        Ok(())
+    }
+}
+
+/// Installs the resources archive at `path`: uploads it to [`UPDATE_DIR`], extracts it into
+/// [`RESOURCES_ROOT`] and removes it again. Progress of the upload is reported through
+/// `options`; the extraction runs on the device and reports none.
+///
+/// The device's `TarExtract` only takes plain tars. A gzip compressed archive, such as the
+/// `resources.tgz` of some release downloads, is decompressed on the host and uploaded as the
+/// plain tar; progress then counts the decompressed bytes.
+///
+/// # Errors
+///
+/// Fails with [`BundleError::InvalidGzip`] before anything is sent if a compressed archive does
+/// not decompress, and otherwise if the upload or the extraction does. The uploaded archive is
+/// removed either way.
+pub fn install_resources<T>(
+    transport: &mut T,
+    path: impl AsRef<Path>,
+    options: WriteOptions,
+) -> Result<TransferSummary>
+where
+    T: TransportRaw<proto::Main, proto::Main, Err = Error> + CommandIndex + std::fmt::Debug,
+{
+    let path = path.as_ref();
+    let mut data = read(path)?;
+    if data.starts_with(&GZIP_MAGIC) {
+        data = gunzip(path, &data)?;
+    }
+
+    let archive = format!("{UPDATE_DIR}/resources.tar");
+    transport.fs_create_dir(UPDATE_DIR)?;
+
+    debug!(len = data.len(), "uploading resources archive");
+    let summary = transport.fs_write_with(&archive, data, options)?;
+
+    debug!("extracting resources into {RESOURCES_ROOT}");
+    let extracted = transport.fs_extract_tar(&archive, RESOURCES_ROOT);
+    let removed = transport.fs_remove(&archive, false);
+
+    extracted.and(removed).map(|()| summary)
+}
+
+/// Decompresses the gzip stream `data`, read from `path`
+fn gunzip(path: &Path, data: &[u8]) -> std::result::Result<Vec<u8>, BundleError> {
+    use std::io::Read;
+
+    let mut tar = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut tar)
+        .map_err(|source| BundleError::InvalidGzip {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    debug!(
+        compressed = data.len(),
+        len = tar.len(),
+        "decompressed resources archive"
+    );
+
+    Ok(tar)
+}
+
+fn read(path: &Path) -> std::result::Result<Vec<u8>, BundleError> {
+    std::fs::read(path).map_err(|source| BundleError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Checks the DfuSe prefix and the DFU suffix of a firmware image
+fn check_dfu(path: &Path) -> std::result::Result<(), BundleError> {
+    const PREFIX_LEN: usize = 11;
+    const SUFFIX_LEN: usize = 16;
+
+    let invalid = |reason| BundleError::InvalidDfu {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let data = read(path)?;
+    if data.len() < PREFIX_LEN + SUFFIX_LEN {
+        return Err(invalid("file too short"));
+    }
+    if !data.starts_with(b"DfuSe") {
+        return Err(invalid("missing DfuSe prefix"));
+    }
+
+    // bcdDevice, idProduct, idVendor, bcdDFU, then the signature and the suffix length
+    let suffix = &data[data.len() - SUFFIX_LEN..];
+    if &suffix[8..11] != b"UFD" || usize::from(suffix[11]) != SUFFIX_LEN {
+        return Err(invalid("missing DFU suffix"));
+    }
+
+    Ok(())
+}
+
+/// Parses a CRC as written by the firmware's packaging scripts, little endian hex bytes such
+/// as `26 39 F4 CB`, or as a `0x` prefixed number
+fn parse_crc(value: &str) -> Option<u32> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+
+    let bytes = value
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .ok()?;
+
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dfu() -> Vec<u8> {
+        let mut data = b"DfuSe\x01".to_vec();
+        data.resize(64, 0);
+        data.extend_from_slice(&[0, 0, 0xff, 0xff, 0x83, 0x04, 0x1a, 0x01]);
+        data.extend_from_slice(b"UFD\x10");
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    fn package(name: &str, stage_crc: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flipper-rpc-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("updater.bin"), b"123456789").unwrap();
+        std::fs::write(dir.join("firmware.dfu"), dfu()).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_NAME),
+            format!(
+                "Filetype: {MANIFEST_FILETYPE}\nVersion: 2\nInfo: f7-1.0.1\nTarget: 7\n\
+                 Loader: updater.bin\nLoader CRC: {stage_crc}\nFirmware: firmware.dfu\n\
+                 Radio: \nResources: resources.tar\n"
+            ),
+        )
+        .unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn parses_manifest_crcs() {
+        assert_eq!(parse_crc("26 39 F4 CB"), Some(0xCBF4_3926));
+        assert_eq!(parse_crc("0xCBF43926"), Some(0xCBF4_3926));
+    }
+
+    #[test]
+    fn validates_packages() {
+        let dir = package("update-ok", "26 39 F4 CB");
+        let missing = Bundle::from_directory(&dir);
+        std::fs::write(dir.join("resources.tar"), b"").unwrap();
+        let bundle = Bundle::from_directory(&dir);
+        let from_dfu = Bundle::from_dfu(dir.join("firmware.dfu"));
+        let broken = package("update-bad", "00 00 00 00");
+        let corrupted = Bundle::from_directory(&broken);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&broken).unwrap();
+
+        assert!(matches!(
+            missing,
+            Err(BundleError::MissingFile {
+                key: "Resources",
+                ..
+            })
+        ));
+        assert_eq!(
+            bundle.unwrap().files(),
+            [
+                MANIFEST_NAME,
+                "updater.bin",
+                "firmware.dfu",
+                "resources.tar"
+            ]
+        );
+        assert!(from_dfu.is_ok());
+        assert!(matches!(
+            corrupted,
+            Err(BundleError::StageIntegrity {
+                expected: 0,
+                actual: 0xCBF4_3926
+            })
+        ));
+        assert!(matches!(
+            Bundle::from_directory(std::env::temp_dir().join("flipper-rpc-no-such-package")),
+            Err(BundleError::MissingManifest(_))
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn installs_plain_and_compressed_resource_archives() {
+        use std::io::Write;
+
+        use flate2::{Compression, write::GzEncoder};
+
+        use crate::testing::EmulatedFlipper;
+
+        let mut tar = [0; 512];
+        tar[..10].copy_from_slice(b"Manifest\0\0");
+        tar[124..135].copy_from_slice(b"00000000005");
+        tar[156] = b'0';
+        let mut tar = tar.to_vec();
+        tar.extend_from_slice(b"hello");
+        tar.resize(2048, 0);
+
+        let dir =
+            std::env::temp_dir().join(format!("flipper-rpc-resources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("resources.tar"), &tar).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+        std::fs::write(dir.join("resources.tgz"), gz.finish().unwrap()).unwrap();
+        std::fs::write(dir.join("broken.tgz"), [0x1f, 0x8b, 8, 0]).unwrap();
+
+        let mut flipper = EmulatedFlipper::new();
+        let summary = install_resources(
+            &mut flipper,
+            dir.join("resources.tar"),
+            WriteOptions::default(),
+        );
+        assert_eq!(summary.unwrap().bytes, tar.len());
+        assert_eq!(flipper.file("/ext/Manifest"), Some(&b"hello"[..]));
+        assert_eq!(flipper.file("/ext/update/resources.tar"), None);
+
+        let mut flipper = EmulatedFlipper::new();
+        let compressed = install_resources(
+            &mut flipper,
+            dir.join("resources.tgz"),
+            WriteOptions::default(),
+        );
+        let broken = install_resources(
+            &mut flipper,
+            dir.join("broken.tgz"),
+            WriteOptions::default(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Uploaded as the plain tar
+        assert_eq!(compressed.unwrap().bytes, tar.len());
+        assert_eq!(flipper.file("/ext/Manifest"), Some(&b"hello"[..]));
+        assert_eq!(flipper.file("/ext/update/resources.tar"), None);
+        assert!(matches!(
+            broken,
+            Err(Error::InvalidBundle(BundleError::InvalidGzip { .. }))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+//! Transfer edge cases against the emulated device: empty files and sizes on and around chunk
+//! boundaries, where `has_next` is easy to get wrong.
+//!
+//! ```sh
+//! cargo test --features testing,fs-all --test edge_cases
+//! ```
+
+use std::io::{Read, Write};
+
+use flipper_rpc::{
+    fs::{FsMd5, FsRead, FsReadDir, FsWrite, WriteOptions},
+    proto::main::Content,
+    rpc::res::ReadDirItem,
+    testing::EmulatedFlipper,
+};
+
+/// Bytes per write chunk
+const WRITE_CHUNK: usize = 1024;
+
+/// Bytes per read chunk the emulator sends, as the firmware does
+const READ_CHUNK: usize = 512;
+
+fn sizes() -> Vec<usize> {
+    vec![
+        0,
+        1,
+        READ_CHUNK - 1,
+        READ_CHUNK,
+        READ_CHUNK + 1,
+        WRITE_CHUNK - 1,
+        WRITE_CHUNK,
+        WRITE_CHUNK + 1,
+        2 * WRITE_CHUNK,
+        3 * WRITE_CHUNK + READ_CHUNK,
+    ]
+}
+
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7) as u8).collect()
+}
+
+/// `(chunk length, has_next)` of every write chunk the device received
+fn write_chunks(flipper: &EmulatedFlipper) -> Vec<(usize, bool)> {
+    flipper
+        .requests()
+        .iter()
+        .filter_map(|main| match &main.content {
+            Some(Content::StorageWriteRequest(req)) => {
+                Some((req.file.as_ref()?.data.len(), main.has_next))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn write_chains_end_on_the_last_chunk() {
+    for len in sizes() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper
+            .fs_write_with("/ext/a.bin", data(len), WriteOptions::default())
+            .unwrap();
+
+        let chunks = write_chunks(&flipper);
+        let expected = len.div_ceil(WRITE_CHUNK).max(1);
+        assert_eq!(chunks.len(), expected, "{len} bytes");
+        assert_eq!(
+            chunks.iter().map(|&(len, _)| len).sum::<usize>(),
+            len,
+            "{len} bytes"
+        );
+        // Only the last chunk ends the chain, and only an empty file has an empty chunk
+        assert!(chunks[..expected - 1].iter().all(|&(_, has_next)| has_next));
+        assert!(!chunks[expected - 1].1, "{len} bytes");
+        assert!(len == 0 || chunks.iter().all(|&(len, _)| len > 0));
+
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data(len).as_slice()));
+    }
+}
+
+#[test]
+fn streamed_writes_match_buffered_ones() {
+    for len in sizes() {
+        let mut writer = EmulatedFlipper::new().fs_open_write("/ext/a.bin").unwrap();
+        // Odd-sized pieces, so writes straddle the chunk boundaries
+        for piece in data(len).chunks(300) {
+            writer.write_all(piece).unwrap();
+        }
+        let flipper = writer.finish().unwrap();
+
+        let chunks = write_chunks(&flipper);
+        assert_eq!(
+            chunks.len(),
+            len.div_ceil(WRITE_CHUNK).max(1),
+            "{len} bytes"
+        );
+        assert_eq!(chunks.last().map(|&(_, has_next)| has_next), Some(false));
+        assert_eq!(flipper.file("/ext/a.bin"), Some(data(len).as_slice()));
+    }
+}
+
+#[test]
+fn reads_return_every_byte_and_keep_the_stream_in_sync() {
+    for len in sizes() {
+        let mut flipper = EmulatedFlipper::new();
+        flipper.insert_file("/ext/a.bin", data(len));
+        flipper.insert_file("/ext/next.txt", b"next".to_vec());
+
+        assert_eq!(
+            flipper.fs_read("/ext/a.bin").unwrap().as_ref(),
+            data(len),
+            "{len} bytes"
+        );
+
+        let mut reader = flipper.fs_open_read("/ext/a.bin").unwrap();
+        let mut streamed = Vec::new();
+        reader.read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, data(len), "{len} bytes");
+        let mut flipper = reader.into_inner().unwrap();
+
+        // Nothing of the chains was left behind for the next request
+        assert_eq!(flipper.fs_read("/ext/next.txt").unwrap().as_ref(), b"next");
+    }
+}
+
+#[test]
+fn empty_files_list_and_hash_like_any_other() {
+    let mut flipper = EmulatedFlipper::new();
+    flipper
+        .fs_write_with("/ext/empty.bin", [], WriteOptions::default())
+        .unwrap();
+
+    let items = flipper
+        .fs_read_dir("/ext", true)
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        items,
+        [ReadDirItem::File(
+            "empty.bin".to_string(),
+            0,
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        )]
+    );
+    assert_eq!(
+        flipper.fs_md5("/ext/empty.bin").unwrap(),
+        "d41d8cd98f00b204e9800998ecf8427e"
+    );
+    assert!(flipper.fs_read("/ext/empty.bin").unwrap().is_empty());
+}
@@ -0,0 +1,112 @@
+//! Runs the examples against the emulated device, so they keep compiling and working.
+//!
+//! ```sh
+//! cargo test --features testing,flipper,fs-all,transport-serial-optimized --test examples
+//! ```
+
+use std::time::Duration;
+
+use flipper_rpc::{
+    FlipperZero,
+    proto::gpio::{GpioPin, GpioPinMode},
+    testing::EmulatedFlipper,
+};
+
+#[allow(dead_code)]
+#[path = "../examples/serial/badusb.rs"]
+mod badusb;
+#[allow(dead_code)]
+#[path = "../examples/serial/blink.rs"]
+mod blink;
+#[allow(dead_code)]
+#[path = "../examples/serial/mirror.rs"]
+mod mirror;
+#[allow(dead_code)]
+#[path = "../examples/serial/sync.rs"]
+mod sync;
+
+#[test]
+fn mirror_renders_frames() {
+    // Top left pixel and the pixel below it
+    let mut frame = vec![0; mirror::WIDTH * mirror::HEIGHT / 8];
+    frame[0] = 0b11;
+
+    let mut flipper = FlipperZero::new(EmulatedFlipper::new().with_screen(frame, 3));
+    let mut out = Vec::new();
+
+    mirror::mirror(&mut flipper, 2, &mut out).unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("\x1b[H").count(), 2);
+    assert!(out.starts_with("\x1b[H█ "));
+}
+
+#[test]
+fn sync_uploads_only_changed_files() {
+    let local = std::env::temp_dir().join(format!("flipper-rpc-sync-{}", std::process::id()));
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::write(local.join("same.txt"), "same").unwrap();
+    std::fs::write(local.join("changed.txt"), "new").unwrap();
+
+    let mut emulated = EmulatedFlipper::new();
+    emulated.insert_file("/ext/sync/same.txt", "same");
+    emulated.insert_file("/ext/sync/changed.txt", "old");
+    let mut flipper = FlipperZero::new(emulated);
+
+    let uploaded = sync::sync(&mut flipper, &local, "/ext/sync");
+    std::fs::remove_dir_all(&local).unwrap();
+
+    assert_eq!(uploaded.unwrap(), ["changed.txt"]);
+    assert_eq!(
+        flipper.get_ref().file("/ext/sync/changed.txt"),
+        Some(&b"new"[..])
+    );
+}
+
+#[test]
+fn sync_uploads_every_file_of_a_larger_directory() {
+    let local = std::env::temp_dir().join(format!("flipper-rpc-sync-many-{}", std::process::id()));
+    std::fs::create_dir_all(&local).unwrap();
+    for i in 0..8 {
+        std::fs::write(local.join(format!("{i}.txt")), i.to_string()).unwrap();
+    }
+
+    let mut flipper = FlipperZero::new(EmulatedFlipper::new());
+    let uploaded = sync::sync(&mut flipper, &local, "/ext/sync");
+    std::fs::remove_dir_all(&local).unwrap();
+
+    assert_eq!(uploaded.unwrap().len(), 8);
+    for i in 0..8 {
+        assert_eq!(
+            flipper.get_ref().file(&format!("/ext/sync/{i}.txt")),
+            Some(i.to_string().as_bytes())
+        );
+    }
+}
+
+#[test]
+fn badusb_uploads_and_starts_the_script() {
+    let mut flipper = FlipperZero::new(EmulatedFlipper::new());
+
+    let path = badusb::run_script(&mut flipper, "hello.txt", "STRING hello").unwrap();
+
+    assert_eq!(path, "/ext/badusb/hello.txt");
+    assert_eq!(flipper.get_ref().file(&path), Some(&b"STRING hello"[..]));
+    assert_eq!(
+        flipper.get_ref().running_app(),
+        Some((badusb::APP_NAME, path.as_str()))
+    );
+}
+
+#[test]
+fn blink_toggles_and_releases_the_pin() {
+    let mut flipper = FlipperZero::new(EmulatedFlipper::new());
+
+    blink::blink(&mut flipper, GpioPin::Pc3, 3, Duration::ZERO).unwrap();
+
+    assert!(!flipper.get_ref().pin_level(GpioPin::Pc3));
+    assert_eq!(
+        flipper.gpio().pin_mode(GpioPin::Pc3).unwrap(),
+        GpioPinMode::Input
+    );
+}
@@ -0,0 +1,158 @@
+//! Integration tests against a real Flipper Zero.
+//!
+//! These are `#[ignore]`d so they never run by accident. Point `FLIPPER_PORT` at the device and
+//! run them explicitly:
+//!
+//! ```sh
+//! FLIPPER_PORT=/dev/ttyACM0 cargo test --features it --test hardware -- --ignored --test-threads 1
+//! ```
+//!
+//! Everything is written below [`TEST_DIR`], which is removed at the end of each test.
+
+use flipper_rpc::{
+    fs::{FsCreateDir, FsMd5, FsRead, FsReadDir, FsRemove, FsWrite},
+    rpc::{req::Request, res::ReadDirItem, res::Response},
+    transport::{Transport, serial::rpc::SerialRpcTransport},
+};
+
+use flipper_rpc::proto::gui::{StartScreenStreamRequest, StopScreenStreamRequest};
+
+/// Scratch directory on the SD card
+const TEST_DIR: &str = "/ext/.flipper-rpc-it";
+
+fn connect() -> SerialRpcTransport {
+    let port = std::env::var("FLIPPER_PORT")
+        .expect("set FLIPPER_PORT to the serial port of the device under test");
+
+    let mut rpc = SerialRpcTransport::new(port).expect("failed to open rpc session");
+
+    rpc.fs_create_dir(TEST_DIR)
+        .expect("failed to create test dir");
+
+    rpc
+}
+
+fn cleanup(rpc: &mut SerialRpcTransport) {
+    rpc.fs_remove(TEST_DIR, true)
+        .expect("failed to remove test dir");
+}
+
+fn write(rpc: &mut SerialRpcTransport, path: &str, data: &[u8]) {
+    rpc.fs_write(
+        path,
+        data,
+        #[cfg(feature = "fs-write-progress-mpsc")]
+        None,
+    )
+    .expect("write failed");
+}
+
+/// Deterministic, non-repeating-looking test data
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 7) as u8).collect()
+}
+
+#[test]
+#[ignore = "requires a Flipper Zero, set FLIPPER_PORT"]
+fn fs_roundtrip() {
+    let mut rpc = connect();
+    let path = format!("{TEST_DIR}/roundtrip.bin");
+
+    for len in [0, 1, 1023, 1024, 1025, 4096] {
+        let data = pattern(len);
+
+        write(&mut rpc, &path, &data);
+        let read = rpc.fs_read(&path).expect("read failed");
+
+        assert_eq!(read.as_ref(), data.as_slice(), "mismatch for {len} bytes");
+    }
+
+    cleanup(&mut rpc);
+}
+
+#[test]
+#[ignore = "requires a Flipper Zero, set FLIPPER_PORT"]
+fn md5_matches_host() {
+    let mut rpc = connect();
+    let path = format!("{TEST_DIR}/md5.bin");
+    let data = pattern(10_000);
+
+    write(&mut rpc, &path, &data);
+
+    let device = rpc.fs_md5(&path).expect("md5 failed");
+    let host = format!("{:x}", md5::compute(&data));
+
+    assert_eq!(device, host);
+
+    cleanup(&mut rpc);
+}
+
+#[test]
+#[ignore = "requires a Flipper Zero, set FLIPPER_PORT"]
+fn large_file() {
+    let mut rpc = connect();
+    let path = format!("{TEST_DIR}/large.bin");
+
+    // Long enough to need keepalive pings during the write chain
+    let data = pattern(1024 * 1024);
+
+    write(&mut rpc, &path, &data);
+    let read = rpc.fs_read(&path).expect("read failed");
+
+    assert!(read.as_ref() == data.as_slice(), "large file mismatch");
+
+    cleanup(&mut rpc);
+}
+
+#[test]
+#[ignore = "requires a Flipper Zero, set FLIPPER_PORT"]
+fn read_dir_lists_written_files() {
+    let mut rpc = connect();
+
+    for i in 0..3 {
+        write(&mut rpc, &format!("{TEST_DIR}/file{i}.txt"), b"hi");
+    }
+    rpc.fs_create_dir(format!("{TEST_DIR}/sub"))
+        .expect("mkdir failed");
+
+    let items = rpc
+        .fs_read_dir(TEST_DIR, true)
+        .expect("read_dir failed")
+        .collect::<Vec<_>>();
+
+    for i in 0..3 {
+        assert!(items.iter().any(|item| matches!(
+            item,
+            ReadDirItem::File(name, 2, Some(_)) if *name == format!("file{i}.txt")
+        )));
+    }
+    assert!(items.contains(&ReadDirItem::Dir("sub".to_string())));
+
+    cleanup(&mut rpc);
+}
+
+#[test]
+#[ignore = "requires a Flipper Zero, set FLIPPER_PORT"]
+fn screen_frame() {
+    let mut rpc = connect();
+
+    rpc.send_and_receive(Request::GuiStartScreenStream(StartScreenStreamRequest {}))
+        .expect("failed to start screen stream");
+
+    let frame = loop {
+        if let Response::GuiScreenFrame(frame) = rpc.receive().expect("receive failed") {
+            break frame;
+        }
+    };
+
+    // 128x64 monochrome, one bit per pixel
+    assert_eq!(frame.data.len(), 128 * 64 / 8);
+
+    rpc.send(Request::GuiStopScreenStream(StopScreenStreamRequest {}))
+        .expect("failed to stop screen stream");
+
+    // Frames already in flight arrive before the stop confirmation
+    while rpc.receive().expect("receive failed") != Response::Empty {}
+
+    cleanup(&mut rpc);
+}